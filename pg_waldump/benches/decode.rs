@@ -0,0 +1,64 @@
+// Decoding throughput over the bundled test segment.
+//
+//     cargo bench --bench decode
+//
+// The segment is served from memory, so this measures page validation and
+// record decoding rather than I/O.
+use pg_waldump::constant::*;
+use pg_waldump::pgtypes::*;
+use pg_waldump::state::XLogReaderState;
+use pg_waldump::waldec::{xlog_find_next_record, xlog_read_record, WalConfig};
+use pg_waldump::walsource::MemorySource;
+use std::time::Instant;
+
+const WAL_FILE: &[u8] = include_bytes!("../test/000000010000000000000001");
+const ITERATIONS: u32 = 20000;
+
+fn decode_segment(config: WalConfig, segstart: XLogRecPtr) -> (u64, u64) {
+    let private = XLogDumpPrivate {
+        timeline: 1,
+        startptr: segstart,
+        endptr: segstart + WAL_FILE.len() as u64,
+        ..Default::default()
+    };
+    let source = MemorySource::new(segstart, WAL_FILE.to_vec());
+    let mut state = XLogReaderState::new(config, Box::new(source), private);
+
+    let mut records = 0;
+    let mut bytes = 0;
+    if xlog_find_next_record(&mut state) == INVALID_XLOG_RECPTR {
+        panic!("no record found in test segment");
+    }
+    while xlog_read_record(&mut state) {
+        records += 1;
+        bytes += state.record.as_ref().unwrap().header.xl_tot_len as u64;
+    }
+    (records, bytes)
+}
+
+fn main() {
+    let config = WalConfig::from_first_page(&WAL_FILE[..XLOG_BLCKSZ as usize]).unwrap();
+    let segstart = XLogRecPtr::from_le_bytes(WAL_FILE[8..16].try_into().unwrap());
+
+    // warm up
+    decode_segment(config, segstart);
+
+    let mut records = 0;
+    let mut bytes = 0;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let (r, b) = decode_segment(config, segstart);
+        records += r;
+        bytes += b;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!(
+        "decoded {} records ({} bytes) in {:.3}s: {:.0} records/s, {:.1} MB/s",
+        records,
+        bytes,
+        elapsed,
+        records as f64 / elapsed,
+        bytes as f64 / elapsed / (1024.0 * 1024.0)
+    );
+}
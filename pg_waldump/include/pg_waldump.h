@@ -0,0 +1,86 @@
+/* C interface of the pg_waldump decoder, see src/ffi.rs.  Generated by cbindgen; do not edit. */
+
+#ifndef PG_WALDUMP_H
+#define PG_WALDUMP_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum PgWaldumpStatus {
+  PG_WALDUMP_STATUS_OK = 0,
+  PG_WALDUMP_STATUS_END = 1,
+  PG_WALDUMP_STATUS_ERR_INVALID_ARGUMENT = -1,
+  PG_WALDUMP_STATUS_ERR_NOT_FOUND = -2,
+  PG_WALDUMP_STATUS_ERR_IO = -3,
+  PG_WALDUMP_STATUS_ERR_DECODE = -4,
+  PG_WALDUMP_STATUS_ERR_NO_RECORD = -5,
+} PgWaldumpStatus;
+
+typedef struct PgWaldumpReader PgWaldumpReader;
+
+typedef struct PgWaldumpRecordHeader {
+  uint64_t lsn;
+  uint64_t next_lsn;
+  uint64_t prev;
+  uint32_t xid;
+  uint32_t tot_len;
+  uint8_t rmid;
+  uint8_t info;
+  uint8_t nblocks;
+} PgWaldumpRecordHeader;
+
+typedef struct PgWaldumpBlockRef {
+  uint32_t spc_oid;
+  uint32_t db_oid;
+  uint32_t rel_oid;
+  int32_t forknum;
+  uint32_t blkno;
+  bool has_image;
+  bool apply_image;
+  uint8_t bimg_info;
+  uint16_t bimg_len;
+  uint16_t hole_offset;
+  uint16_t hole_length;
+  bool has_data;
+  uint16_t data_len;
+} PgWaldumpBlockRef;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+enum PgWaldumpStatus pg_waldump_open_dir(const char *dir,
+                                         uint32_t timeline,
+                                         uint64_t startptr,
+                                         uint64_t endptr,
+                                         struct PgWaldumpReader **reader);
+
+void pg_waldump_close(struct PgWaldumpReader *reader);
+
+enum PgWaldumpStatus pg_waldump_next(struct PgWaldumpReader *reader);
+
+enum PgWaldumpStatus pg_waldump_record_header(const struct PgWaldumpReader *reader,
+                                              struct PgWaldumpRecordHeader *header);
+
+enum PgWaldumpStatus pg_waldump_record_block(const struct PgWaldumpReader *reader,
+                                             uint8_t block_id,
+                                             struct PgWaldumpBlockRef *block);
+
+enum PgWaldumpStatus pg_waldump_record_main_data(const struct PgWaldumpReader *reader,
+                                                 const uint8_t **data,
+                                                 size_t *len);
+
+const char *pg_waldump_record_rmgr_name(struct PgWaldumpReader *reader);
+
+const char *pg_waldump_record_desc(struct PgWaldumpReader *reader);
+
+const char *pg_waldump_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PG_WALDUMP_H */
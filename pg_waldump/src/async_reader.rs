@@ -0,0 +1,474 @@
+// Async WAL reading, for the `async` feature.
+//
+// Page validation and record decoding are the same code the blocking reader
+// uses.  The XLogReaderState reads from a page cache that never blocks: when
+// the decoder asks for bytes the cache doesn't hold, the read fails with
+// EWOULDBLOCK and the decoder gives up on the record without moving its read
+// position.  AsyncXLogReader then fetches the missing bytes from an
+// AsyncWalSource and decodes the record again.
+//
+// How far the WAL goes is told to the decoder through the endptr of its
+// private data, exactly as pg_waldump's --end does.  When the decoder hits
+// it, the reader waits on the source for more WAL.
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::Stream;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::watch;
+
+use crate::pgtypes::*;
+use crate::state::XLogReaderState;
+use crate::util::*;
+use crate::waldec::{xlog_find_next_record, xlog_read_record, WalConfig};
+use crate::walsource::{WalSource, EWOULDBLOCK};
+
+// The async counterpart of WalSource.
+pub trait AsyncWalSource: Send {
+    // Read buf.len() bytes starting at startptr on timeline tli.  Only asked
+    // for WAL below the end last returned by wait_for_wal().
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> impl Future<Output = Result<(), WALReadError>> + Send;
+
+    // Wait until there is WAL past `after` on timeline tli, and return the
+    // new end+1 of the available WAL.  None means no more WAL will come.
+    fn wait_for_wal(
+        &mut self,
+        after: XLogRecPtr,
+        tli: TimeLineID,
+    ) -> impl Future<Output = Option<XLogRecPtr>> + Send;
+
+    // The reader won't ask for WAL before upto again, so it may be dropped.
+    fn consumed(&mut self, _upto: XLogRecPtr) {}
+}
+
+// Pages fetched for the decoder, keyed by timeline and page address.  A
+// page may be held only partially, when the WAL ends inside it.
+#[derive(Default)]
+struct PageCache {
+    pages: BTreeMap<(TimeLineID, XLogRecPtr), Vec<u8>>,
+    // the read that missed, to be fetched before decoding again
+    miss: Option<(XLogRecPtr, TimeLineID, usize)>,
+}
+
+// The WalSource of the decoder: serves reads from the page cache only.
+struct CachedSource(Arc<Mutex<PageCache>>);
+
+impl WalSource for CachedSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let mut cache = self.0.lock().unwrap();
+        match cache.pages.get(&(tli, startptr)) {
+            Some(page) if page.len() >= buf.len() => {
+                buf.copy_from_slice(&page[..buf.len()]);
+                Ok(())
+            }
+            _ => {
+                cache.miss = Some((startptr, tli, buf.len()));
+                Err(WALReadError {
+                    errno: EWOULDBLOCK,
+                    off: page_offset(startptr),
+                    req: buf.len() as u32,
+                    read: 0,
+                    seg: WALOpenSegment {
+                        file: None,
+                        segno: 0,
+                        tli,
+                    },
+                    msg: None,
+                })
+            }
+        }
+    }
+}
+
+pub struct AsyncXLogReader<S: AsyncWalSource> {
+    state: XLogReaderState,
+    source: S,
+    cache: Arc<Mutex<PageCache>>,
+    // end+1 of the WAL known to be available
+    available: XLogRecPtr,
+    // found the first record yet?
+    started: bool,
+}
+
+impl<S: AsyncWalSource> AsyncXLogReader<S> {
+    // Read the WAL of timeline tli from source, starting with the first
+    // record at or after startptr.
+    pub fn new(config: WalConfig, source: S, tli: TimeLineID, startptr: XLogRecPtr) -> Self {
+        let cache = Arc::new(Mutex::new(PageCache::default()));
+        let private = XLogDumpPrivate {
+            timeline: tli,
+            startptr,
+            endptr: startptr,
+            endptr_reached: false,
+            ..Default::default()
+        };
+        let state = XLogReaderState::new(config, Box::new(CachedSource(cache.clone())), private);
+        AsyncXLogReader {
+            state,
+            source,
+            cache,
+            available: startptr,
+            started: false,
+        }
+    }
+
+    // Read the next record.  Ok(None) is returned at the end of the WAL,
+    // which for a source that keeps waiting for WAL means never.  The record
+    // is valid until the next call.
+    pub async fn read_record(&mut self) -> Result<Option<&DecodedXLogRecord>, String> {
+        if self.next_record().await? {
+            Ok(self.state.record.as_ref())
+        } else {
+            Ok(None)
+        }
+    }
+
+    // The records as a stream, ending at the end of the WAL or after the
+    // first error.  Each record carries a copy of its bytes in raw, read
+    // with view().
+    pub fn into_stream(self) -> impl Stream<Item = Result<DecodedXLogRecord, String>> + Send
+    where
+        S: 'static,
+    {
+        futures_util::stream::unfold(Some(self), |reader| async move {
+            let mut reader = reader?;
+            match reader.next_record().await {
+                Ok(true) => {
+                    let record = reader.state.take_record().unwrap();
+                    Some((Ok(record), Some(reader)))
+                }
+                Ok(false) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    // Access to the underlying reader state, e.g. for the rmgr desc
+    // routines.
+    pub fn state(&self) -> &XLogReaderState {
+        &self.state
+    }
+
+    async fn next_record(&mut self) -> Result<bool, String> {
+        loop {
+            self.state.errmsg.clear();
+            if self.available > self.state.private_data.startptr {
+                if !self.started {
+                    self.started = xlog_find_next_record(&mut self.state) != INVALID_XLOG_RECPTR;
+                    if self.started {
+                        continue;
+                    }
+                } else if xlog_read_record(&mut self.state) {
+                    self.release();
+                    return Ok(true);
+                }
+            }
+
+            // No record; fetch what the decoder was missing, or wait for more
+            // WAL, or give up.
+            let miss = self.cache.lock().unwrap().miss.take();
+            if let Some((startptr, tli, len)) = miss {
+                let mut page = vec![0; len];
+                self.source
+                    .read(startptr, tli, &mut page)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                self.cache.lock().unwrap().pages.insert((tli, startptr), page);
+            } else if self.state.private_data.endptr_reached
+                || self.available <= self.state.private_data.startptr
+            {
+                let tli = self.state.private_data.timeline;
+                match self.source.wait_for_wal(self.available, tli).await {
+                    Some(end) if end > self.available => {
+                        self.available = end;
+                        self.state.private_data.endptr = end;
+                        self.state.private_data.endptr_reached = false;
+                    }
+                    _ => return Ok(false),
+                }
+            } else if !self.state.errmsg.is_empty() {
+                return Err(self.state.errmsg.clone());
+            } else {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Forget the pages the decoder is done with.  It starts the next record
+    // at next_recptr, and may re-read the first page of its segment.
+    fn release(&mut self) {
+        let next = self.state.next_recptr;
+        let segsz = self.state.config.wal_seg_sz;
+        let segstart = next - xlog_segment_offset(next, segsz) as u64;
+        self.cache
+            .lock()
+            .unwrap()
+            .pages
+            .retain(|&(_, page), _| page >= page_addr(next) || page == segstart);
+        self.source.consumed(segstart);
+    }
+}
+
+// Segment files in a directory, read with tokio::fs.  Segments are expected
+// to appear in order and to only ever grow (archived, shipped or .partial
+// style files; not the recycled, pre-zeroed segments of a live pg_wal).
+pub struct AsyncDirectorySource {
+    dir: PathBuf,
+    segsize: u32,
+    follow: Option<Duration>,
+    seg: Option<(TimeLineID, XLogSegNo, tokio::fs::File)>,
+}
+
+impl AsyncDirectorySource {
+    pub fn new(dir: PathBuf, segsize: u32) -> Self {
+        AsyncDirectorySource {
+            dir,
+            segsize,
+            follow: None,
+            seg: None,
+        }
+    }
+
+    // Keep waiting for WAL at the end, looking for it every interval.
+    pub fn follow(mut self, interval: Duration) -> Self {
+        self.follow = Some(interval);
+        self
+    }
+
+    // end+1 of the WAL in the segment holding ptr, if it exists
+    async fn segment_end(&self, ptr: XLogRecPtr, tli: TimeLineID) -> Option<XLogRecPtr> {
+        let segno = xlog_byte_to_seg(ptr, self.segsize);
+        let path = self.dir.join(xlog_filename(tli, segno, self.segsize));
+        let meta = tokio::fs::metadata(path).await.ok()?;
+        let segstart = segno * self.segsize as u64;
+        Some(segstart + meta.len().min(self.segsize as u64))
+    }
+}
+
+impl AsyncWalSource for AsyncDirectorySource {
+    async fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let mut recptr = startptr;
+        let mut done = 0;
+        let req = buf.len() as u32;
+
+        while done < buf.len() {
+            let segno = xlog_byte_to_seg(recptr, self.segsize);
+            let startoff = xlog_segment_offset(recptr, self.segsize);
+            let err = |errno: i32, read: u32| WALReadError {
+                errno,
+                off: startoff,
+                req,
+                read,
+                seg: WALOpenSegment {
+                    file: None,
+                    segno,
+                    tli,
+                },
+                msg: None,
+            };
+
+            if !matches!(&self.seg, Some((t, s, _)) if *t == tli && *s == segno) {
+                let path = self.dir.join(xlog_filename(tli, segno, self.segsize));
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|e| err(e.raw_os_error().unwrap_or(0), done as u32))?;
+                self.seg = Some((tli, segno, file));
+            }
+            let file = &mut self.seg.as_mut().unwrap().2;
+
+            let segbytes = std::cmp::min(buf.len() - done, (self.segsize - startoff) as usize);
+            let res = match file.seek(SeekFrom::Start(startoff as u64)).await {
+                Ok(_) => file.read_exact(&mut buf[done..done + segbytes]).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            res.map_err(|e| err(e.raw_os_error().unwrap_or(0), done as u32))?;
+
+            recptr += segbytes as u64;
+            done += segbytes;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_wal(&mut self, after: XLogRecPtr, tli: TimeLineID) -> Option<XLogRecPtr> {
+        loop {
+            if let Some(end) = self.segment_end(after, tli).await {
+                if end > after {
+                    return Some(end);
+                }
+            }
+            tokio::time::sleep(self.follow?).await;
+        }
+    }
+}
+
+struct FeedBuffer {
+    // WAL position of data[0]
+    startptr: XLogRecPtr,
+    data: Vec<u8>,
+}
+
+// Producer side of a WAL feed: WAL pushed here, in order, is handed to the
+// FeedSource as soon as it arrives.  Dropping the feed ends the WAL.
+pub struct WalFeed {
+    buf: Arc<Mutex<FeedBuffer>>,
+    end: watch::Sender<XLogRecPtr>,
+}
+
+// Consumer side of a WAL feed.  The reader waits for pushes without
+// polling.
+pub struct FeedSource {
+    buf: Arc<Mutex<FeedBuffer>>,
+    end: watch::Receiver<XLogRecPtr>,
+}
+
+// A WAL feed whose first byte is at startptr.
+pub fn wal_feed(startptr: XLogRecPtr) -> (WalFeed, FeedSource) {
+    let buf = Arc::new(Mutex::new(FeedBuffer {
+        startptr,
+        data: Vec::new(),
+    }));
+    let (tx, rx) = watch::channel(startptr);
+    (
+        WalFeed {
+            buf: buf.clone(),
+            end: tx,
+        },
+        FeedSource { buf, end: rx },
+    )
+}
+
+impl WalFeed {
+    pub fn push(&self, bytes: &[u8]) {
+        let mut buf = self.buf.lock().unwrap();
+        buf.data.extend_from_slice(bytes);
+        let end = buf.startptr + buf.data.len() as u64;
+        drop(buf);
+        self.end.send_replace(end);
+    }
+}
+
+impl AsyncWalSource for FeedSource {
+    async fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let feed = self.buf.lock().unwrap();
+        let endptr = feed.startptr + feed.data.len() as u64;
+        if startptr < feed.startptr || startptr + buf.len() as u64 > endptr {
+            return Err(WALReadError {
+                errno: 0,
+                off: page_offset(startptr),
+                req: buf.len() as u32,
+                read: 0,
+                seg: WALOpenSegment {
+                    file: None,
+                    segno: 0,
+                    tli,
+                },
+                msg: None,
+            });
+        }
+        let off = (startptr - feed.startptr) as usize;
+        buf.copy_from_slice(&feed.data[off..off + buf.len()]);
+        Ok(())
+    }
+
+    async fn wait_for_wal(&mut self, after: XLogRecPtr, _tli: TimeLineID) -> Option<XLogRecPtr> {
+        loop {
+            let end = *self.end.borrow_and_update();
+            if end > after {
+                return Some(end);
+            }
+            if self.end.changed().await.is_err() {
+                // the feed is gone; whatever it pushed last is all there is
+                let end = *self.end.borrow();
+                return (end > after).then_some(end);
+            }
+        }
+    }
+
+    fn consumed(&mut self, upto: XLogRecPtr) {
+        let mut feed = self.buf.lock().unwrap();
+        if upto > feed.startptr {
+            let n = ((upto - feed.startptr) as usize).min(feed.data.len());
+            feed.data.drain(..n);
+            feed.startptr += n as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walsource::tests::{synthetic_page, SEGSIZE};
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn feed_delivers_records_as_they_arrive() {
+        let segstart = SEGSIZE as u64;
+        let (page, lsns) = synthetic_page(segstart, SEGSIZE, 5);
+        let config = WalConfig::from_first_page(&page).unwrap();
+        let walend = (lsns[4] - segstart) as usize + 40;
+
+        let (feed, source) = wal_feed(segstart);
+        let mut reader = AsyncXLogReader::new(config, source, 1, segstart);
+
+        // the long page header and the first record
+        let first = (lsns[1] - segstart) as usize;
+        feed.push(&page[..first]);
+        let record = reader.read_record().await.unwrap().unwrap();
+        assert_eq!(record.lsn, lsns[0]);
+
+        // the rest, in pieces that split records, from another task
+        let rest = page[first..walend].to_vec();
+        let producer = tokio::spawn(async move {
+            for chunk in rest.chunks(13) {
+                feed.push(chunk);
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let stream = reader.into_stream();
+        let got: Vec<XLogRecPtr> = stream.map(|r| r.unwrap().lsn).collect().await;
+        producer.await.unwrap();
+        assert_eq!(got, &lsns[1..]);
+    }
+
+    #[tokio::test]
+    async fn directory_source_reads_segment_files() {
+        let dir = std::env::temp_dir().join(format!("pg_waldump_async_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let segstart = 2 * SEGSIZE as u64;
+        let (page, lsns) = synthetic_page(segstart, SEGSIZE, 3);
+        let walend = (lsns[2] - segstart) as usize + 40;
+        std::fs::write(dir.join(xlog_filename(1, 2, SEGSIZE)), &page[..walend]).unwrap();
+
+        let config = WalConfig::from_first_page(&page).unwrap();
+        let source = AsyncDirectorySource::new(dir.clone(), SEGSIZE);
+        let reader = AsyncXLogReader::new(config, source, 1, segstart);
+        let got: Vec<XLogRecPtr> = reader.into_stream().map(|r| r.unwrap().lsn).collect().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(got, lsns);
+    }
+}
@@ -1,5 +1,7 @@
-use crate::pgtypes::*;
-use crate::rmgr::*;
+use pg_waldump::archive::is_tar_archive;
+use pg_waldump::pgtypes::*;
+use pg_waldump::rmgr::*;
+use pg_waldump::version::PgVersion;
 use clap::Parser;
 use std::path::PathBuf;
 
@@ -26,6 +28,35 @@ pub(crate) struct Cli {
     #[arg(short = 'B', long, value_name = "N")]
     pub block: Option<u32>,
 
+    #[arg(
+        long,
+        value_enum,
+        value_name = "upstream",
+        hide_possible_values = true,
+        help = "\
+print the records, statistics and errors byte for byte
+as pg_waldump does, including -b; only for WAL of
+PostgreSQL 15, the version it is checked against;
+text format only"
+    )]
+    pub compat: Option<Compat>,
+
+    /// print the pg_control of --pgdata, like pg_controldata, and exit
+    #[arg(long, action=clap::ArgAction::SetTrue, requires = "pgdata")]
+    pub controldata: Option<bool>,
+
+    #[arg(
+        short = 'd',
+        long,
+        value_name = "CONNSTR",
+        conflicts_with_all = ["path", "startseg"],
+        help = "\
+stream the WAL from the server at CONNSTR over the
+replication protocol instead of reading files,
+from its current flush location unless --start is given"
+    )]
+    pub dbname: Option<String>,
+
     /// start reading at WAL location RECPTR
     #[arg(short, long, value_name = "RECPTR", value_parser=parse_lsn)]
     pub start: Option<XLogRecPtr>,
@@ -38,6 +69,20 @@ pub(crate) struct Cli {
     #[arg(short, long, action=clap::ArgAction::SetTrue)]
     pub follow: Option<bool>,
 
+    /// start reading at the latest checkpoint record in the pg_control of --pgdata
+    #[arg(
+        long,
+        action=clap::ArgAction::SetTrue,
+        requires = "pgdata",
+        conflicts_with_all = ["start", "from_redo"]
+    )]
+    pub from_checkpoint: Option<bool>,
+
+    /// start reading at the REDO location of the latest checkpoint in the
+    /// pg_control of --pgdata
+    #[arg(long, action=clap::ArgAction::SetTrue, requires = "pgdata", conflicts_with = "start")]
+    pub from_redo: Option<bool>,
+
     #[arg(
         short = 'F',
         long,
@@ -55,6 +100,83 @@ valid names are main, fsm, vm, init"
     )]
     pub fork: Option<String>,
 
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Text,
+        hide_default_value = true,
+        hide_possible_values = true,
+        help = "\
+output format: text (default), json (an array of
+records, or the statistics as an object), ndjson
+(one object per record or statistics row per line),
+both needing the serde feature, csv or copy (one row
+per record, with a header), or parquet (written to
+--output; needs the parquet feature)"
+    )]
+    pub format: OutputFormat,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "\
+print each record as TEMPLATE, e.g.
+'{lsn} {rmgr}/{type} {blocks:rel}/{blocks:blk}'.
+Fields: lsn end_lsn prev rmgr rmid type info xid
+toplevel_xid origin rec_len fpi_len tot_len desc
+nblocks; per block reference, blocks:NAME with id rel
+spc db relnode fork blk data_len image hole_offset
+hole_length apply compression.  {?NAME}...{/NAME}
+is shown only if NAME is neither empty nor 0"
+    )]
+    pub template: Option<String>,
+
+    #[arg(
+        long,
+        action = clap::ArgAction::SetTrue,
+        help = "\
+after each record, hex dump its bytes part by part:
+the headers, block images, block data and main data"
+    )]
+    pub raw: Option<bool>,
+
+    /// with --format=parquet, the file to write
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "\
+with --format=csv or copy, also write one row per
+block reference to FILE"
+    )]
+    pub blocks_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "\
+write the records to the SQLite database FILE, in
+tables records, blocks, checkpoints, transactions
+and segments, appending to an existing one; without
+a start, carry on after its last record (needs the
+sqlite feature)"
+    )]
+    pub sqlite: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "\
+write a report of the records to FILE, a page with
+the rmgrs and record types, WAL volume over LSN and
+time, full page images, top relations and blocks,
+checkpoints and errors, instead of printing them"
+    )]
+    pub html: Option<PathBuf>,
+
     /// number of records to display
     #[arg(short = 'n', long, value_name = "N")]
     pub limit: Option<u32>,
@@ -62,14 +184,36 @@ valid names are main, fsm, vm, init"
     #[arg(
         short,
         long,
-        value_parser=verify_directory,
+        value_parser=verify_path,
+        action=clap::ArgAction::Append,
         hide_default_value=true,
         help = "\
 directory in which to find log segment files or a
-directory with a ./pg_wal that contains such files
+directory with a ./pg_wal that contains such files,
+or a tar archive (.tar, .tar.gz, .tgz) holding them;
+may be repeated to read each segment from the first
+directory with a valid copy
 (default: current directory, ./pg_wal, $PGDATA/pg_wal)"
     )]
-    pub path: Option<PathBuf>,
+    pub path: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DATADIR",
+        help = "\
+data directory whose pg_control the WAL must belong to;
+its pg_wal is read unless --path is given"
+    )]
+    pub pgdata: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "VERSION",
+        help = "\
+decode the WAL as written by PostgreSQL VERSION (13-17)
+(default: detected from the WAL page header)"
+    )]
+    pub pg_version: Option<PgVersion>,
 
     /// do not print any output, except for errors
     #[arg(short, long, action=clap::ArgAction::SetTrue)]
@@ -81,23 +225,38 @@ directory with a ./pg_wal that contains such files
         value_parser=parse_rmgr_name,
         help = "\
 only show records generated by resource manager RMGR;
+may be repeated, or RMGR be a comma-separated list;
 use --rmgr=list to list valid resource manager names"
     )]
-    pub rmgr: Option<[u8; 256]>,
+    pub rmgr: Vec<[u8; 256]>,
 
     /// only show records that modify blocks in relation T/D/R
     #[arg(short = 'R', long, value_name = "T/D/R")]
     pub relation: Option<String>,
 
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        conflicts_with = "dbname",
+        help = "\
+restore segments missing from the WAL directory with
+COMMAND, a restore_command using %f, %p and %r"
+    )]
+    pub restore_command: Option<String>,
+
+    /// with --dbname, stream through replication slot SLOTNAME
+    #[arg(long, value_name = "SLOTNAME", requires = "dbname")]
+    pub slot: Option<String>,
+
     #[arg(
         short,
         long,
         value_name = "TLI",
-        default_value = "1",
-        hide_default_value = true,
         help = "\
-timeline from which to read log records
-(default: 1 or the value used in STARTSEG)"
+timeline from which to read log records, following
+the timelines in its history up to it
+(default: 1 or the value used in ENDSEG or STARTSEG,
+or the server's with --dbname)"
     )]
     pub timeline: Option<TimeLineID>,
 
@@ -112,6 +271,8 @@ timeline from which to read log records
     #[arg(
         short = 'z',
         long,
+        value_parser = ["rmgr", "record"],
+        hide_possible_values = true,
         value_name = "record",
         help = "\
 show statistics instead of records
@@ -121,12 +282,12 @@ show statistics instead of records
     pub stats: Option<Option<String>>,
 }
 
-fn verify_directory(p: &str) -> Result<std::path::PathBuf, String> {
+fn verify_path(p: &str) -> Result<std::path::PathBuf, String> {
     let path = std::path::PathBuf::from(p);
-    if path.is_dir() {
+    if path.is_dir() || (path.is_file() && is_tar_archive(&path)) {
         Ok(path)
     } else {
-        Err(format!("no such directory: {}", path.display()))
+        Err(format!("no such directory or tar archive: {}", path.display()))
     }
 }
 
@@ -175,6 +336,24 @@ fn parse_rmgr_name(s: &str) -> Result<[u8; 256], String> {
     Ok(rst)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    Text,
+    #[cfg(feature = "serde")]
+    Json,
+    #[cfg(feature = "serde")]
+    Ndjson,
+    Csv,
+    Copy,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Compat {
+    Upstream,
+}
+
 impl Cli {
     pub fn new() -> Self {
         Self::parse()
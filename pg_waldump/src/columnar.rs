@@ -0,0 +1,279 @@
+// Records as a Parquet file, for --format=parquet.
+//
+// One row per record, with the block references as a list of structs in
+// the blocks column.  LSNs are UInt64, xids UInt32 and the parts of a
+// RelFileLocator UInt32 (Oid), so that readers such as DuckDB, Polars and
+// pandas compare and join them as numbers.
+//
+// Records are gathered into Arrow record batches of BATCH_SIZE rows, and
+// the writer starts a new row group every ROW_GROUP_SIZE rows, which is
+// all it keeps in memory.
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BooleanBuilder, ListBuilder, StringBuilder, StructBuilder, UInt16Builder, UInt32Builder,
+    UInt64Builder, UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::summary::RecordSummary;
+
+const BATCH_SIZE: usize = 8192;
+const ROW_GROUP_SIZE: usize = 128 * 1024;
+
+fn block_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("block_id", DataType::UInt8, false),
+        Field::new("spc_oid", DataType::UInt32, false),
+        Field::new("db_oid", DataType::UInt32, false),
+        Field::new("rel_oid", DataType::UInt32, false),
+        Field::new("fork", DataType::Utf8, false),
+        Field::new("blkno", DataType::UInt32, false),
+        Field::new("data_len", DataType::UInt16, false),
+        // the image, if the block has one
+        Field::new("fpi_len", DataType::UInt16, true),
+        Field::new("fpi_hole_offset", DataType::UInt16, true),
+        Field::new("fpi_hole_length", DataType::UInt16, true),
+        Field::new("fpi_apply", DataType::Boolean, true),
+        Field::new("fpi_compression", DataType::Utf8, true),
+    ])
+}
+
+fn block_item() -> Arc<Field> {
+    Arc::new(Field::new("item", DataType::Struct(block_fields()), false))
+}
+
+// The schema of the file.
+pub fn record_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("lsn", DataType::UInt64, false),
+        Field::new("end_lsn", DataType::UInt64, false),
+        Field::new("prev_lsn", DataType::UInt64, false),
+        Field::new("rmid", DataType::UInt8, false),
+        Field::new("rmgr", DataType::Utf8, false),
+        Field::new("info", DataType::UInt8, false),
+        // null if the rmgr doesn't know the record type
+        Field::new("record_type", DataType::Utf8, true),
+        Field::new("xid", DataType::UInt32, false),
+        Field::new("toplevel_xid", DataType::UInt32, false),
+        Field::new("origin", DataType::UInt16, false),
+        Field::new("rec_len", DataType::UInt32, false),
+        Field::new("main_data_len", DataType::UInt32, false),
+        Field::new("fpi_len", DataType::UInt32, false),
+        Field::new("tot_len", DataType::UInt32, false),
+        Field::new("description", DataType::Utf8, false),
+        Field::new("blocks", DataType::List(block_item()), false),
+    ]))
+}
+
+// The columns of the batch being gathered.
+struct Columns {
+    lsn: UInt64Builder,
+    end_lsn: UInt64Builder,
+    prev_lsn: UInt64Builder,
+    rmid: UInt8Builder,
+    rmgr: StringBuilder,
+    info: UInt8Builder,
+    record_type: StringBuilder,
+    xid: UInt32Builder,
+    toplevel_xid: UInt32Builder,
+    origin: UInt16Builder,
+    rec_len: UInt32Builder,
+    main_data_len: UInt32Builder,
+    fpi_len: UInt32Builder,
+    tot_len: UInt32Builder,
+    description: StringBuilder,
+    blocks: ListBuilder<StructBuilder>,
+}
+
+impl Columns {
+    fn new() -> Columns {
+        Columns {
+            lsn: UInt64Builder::with_capacity(BATCH_SIZE),
+            end_lsn: UInt64Builder::with_capacity(BATCH_SIZE),
+            prev_lsn: UInt64Builder::with_capacity(BATCH_SIZE),
+            rmid: UInt8Builder::with_capacity(BATCH_SIZE),
+            rmgr: StringBuilder::new(),
+            info: UInt8Builder::with_capacity(BATCH_SIZE),
+            record_type: StringBuilder::new(),
+            xid: UInt32Builder::with_capacity(BATCH_SIZE),
+            toplevel_xid: UInt32Builder::with_capacity(BATCH_SIZE),
+            origin: UInt16Builder::with_capacity(BATCH_SIZE),
+            rec_len: UInt32Builder::with_capacity(BATCH_SIZE),
+            main_data_len: UInt32Builder::with_capacity(BATCH_SIZE),
+            fpi_len: UInt32Builder::with_capacity(BATCH_SIZE),
+            tot_len: UInt32Builder::with_capacity(BATCH_SIZE),
+            description: StringBuilder::new(),
+            blocks: ListBuilder::new(StructBuilder::from_fields(block_fields(), 0))
+                .with_field(block_item()),
+        }
+    }
+
+    fn append(&mut self, record: &RecordSummary) {
+        self.lsn.append_value(record.lsn);
+        self.end_lsn.append_value(record.end_lsn);
+        self.prev_lsn.append_value(record.prev);
+        self.rmid.append_value(record.rmid);
+        self.rmgr.append_value(record.rmgr);
+        self.info.append_value(record.info);
+        self.record_type.append_option(record.identify.as_deref());
+        self.xid.append_value(record.xid);
+        self.toplevel_xid.append_value(record.toplevel_xid);
+        self.origin.append_value(record.origin);
+        self.rec_len.append_value(record.rec_len);
+        self.main_data_len.append_value(record.main_data_len);
+        self.fpi_len.append_value(record.fpi_len);
+        self.tot_len.append_value(record.tot_len);
+        self.description.append_value(&record.desc);
+
+        let blocks = self.blocks.values();
+        for block in &record.blocks {
+            let image = block.image.as_ref();
+            field::<UInt8Builder>(blocks, 0).append_value(block.block_id);
+            field::<UInt32Builder>(blocks, 1).append_value(block.locator.spc_oid);
+            field::<UInt32Builder>(blocks, 2).append_value(block.locator.db_oid);
+            field::<UInt32Builder>(blocks, 3).append_value(block.locator.rel_oid);
+            field::<StringBuilder>(blocks, 4).append_value(block.fork_name());
+            field::<UInt32Builder>(blocks, 5).append_value(block.blkno);
+            field::<UInt16Builder>(blocks, 6).append_value(block.data_len);
+            field::<UInt16Builder>(blocks, 7).append_option(image.map(|i| i.length));
+            field::<UInt16Builder>(blocks, 8).append_option(image.map(|i| i.hole_offset));
+            field::<UInt16Builder>(blocks, 9).append_option(image.map(|i| i.hole_length));
+            field::<BooleanBuilder>(blocks, 10).append_option(image.map(|i| i.apply));
+            field::<StringBuilder>(blocks, 11)
+                .append_option(image.and_then(|i| i.compression).map(|m| m.to_string()));
+            blocks.append(true);
+        }
+        self.blocks.append(true);
+    }
+
+    fn finish(&mut self, schema: SchemaRef) -> RecordBatch {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.lsn.finish()),
+            Arc::new(self.end_lsn.finish()),
+            Arc::new(self.prev_lsn.finish()),
+            Arc::new(self.rmid.finish()),
+            Arc::new(self.rmgr.finish()),
+            Arc::new(self.info.finish()),
+            Arc::new(self.record_type.finish()),
+            Arc::new(self.xid.finish()),
+            Arc::new(self.toplevel_xid.finish()),
+            Arc::new(self.origin.finish()),
+            Arc::new(self.rec_len.finish()),
+            Arc::new(self.main_data_len.finish()),
+            Arc::new(self.fpi_len.finish()),
+            Arc::new(self.tot_len.finish()),
+            Arc::new(self.description.finish()),
+            Arc::new(self.blocks.finish()),
+        ];
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+}
+
+fn field<T: arrow_array::builder::ArrayBuilder>(builder: &mut StructBuilder, i: usize) -> &mut T {
+    builder.field_builder::<T>(i).unwrap()
+}
+
+pub struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    schema: SchemaRef,
+    columns: Columns,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(out: W) -> Result<Self, String> {
+        let schema = record_schema();
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let writer = ArrowWriter::try_new(out, schema.clone(), Some(props))
+            .map_err(|e| format!("could not write parquet: {}", e))?;
+        Ok(ParquetWriter {
+            writer,
+            schema,
+            columns: Columns::new(),
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &RecordSummary) -> Result<(), String> {
+        self.columns.append(record);
+        self.rows += 1;
+        if self.rows == BATCH_SIZE {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> Result<(), String> {
+        let batch = self.columns.finish(self.schema.clone());
+        self.rows = 0;
+        self.writer
+            .write(&batch)
+            .map_err(|e| format!("could not write parquet: {}", e))
+    }
+
+    // Write the last batch and the footer.
+    pub fn finish(mut self) -> Result<W, String> {
+        if self.rows > 0 {
+            self.flush_batch()?;
+        }
+        self.writer
+            .into_inner()
+            .map_err(|e| format!("could not write parquet: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgtypes::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::bundled_segment_reader;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{UInt32Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn records_are_written_as_parquet() {
+        let mut state = bundled_segment_reader();
+
+        let path =
+            std::env::temp_dir().join(format!("pg_waldump_parquet_{}.parquet", std::process::id()));
+        let mut writer = ParquetWriter::new(std::fs::File::create(&path).unwrap()).unwrap();
+        let mut blocks = 0;
+        while xlog_read_record(&mut state) {
+            let record = RecordSummary::from_state(&state);
+            blocks += record.blocks.len();
+            writer.write(&record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches[0].schema(), record_schema());
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 85);
+
+        let lsn = batches[0].column(0).as_primitive::<UInt64Type>();
+        assert_eq!(lsn.value(0), 0x1000028);
+        let lists = batches[0].column(15).as_list::<i32>();
+        assert_eq!(lists.values().len(), blocks);
+        let rel_oids = lists
+            .values()
+            .as_struct()
+            .column(3)
+            .as_primitive::<UInt32Type>();
+        assert!(rel_oids.values().iter().all(|&oid| oid > 0));
+    }
+}
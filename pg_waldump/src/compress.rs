@@ -0,0 +1,330 @@
+// Segment files compressed by an archive_command, e.g.
+// 000000010000000A000000F3.zst next to or instead of the bare segment.
+//
+// The reader seeks to arbitrary pages within a segment, which a compressed
+// stream can't do, so a compressed segment is decompressed into memory
+// once when it is opened and read from there.  Nothing is written to disk.
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Lz4,
+    Bzip2,
+}
+
+impl Compression {
+    // in the order segments are looked for when the bare name is missing
+    pub const ALL: [Compression; 4] = [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Lz4,
+        Compression::Bzip2,
+    ];
+
+    // file name suffix, including the dot
+    pub const fn suffix(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Lz4 => ".lz4",
+            Compression::Bzip2 => ".bz2",
+        }
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Compression> {
+        Compression::ALL
+            .iter()
+            .copied()
+            .find(|c| c.suffix() == suffix)
+    }
+
+    // Decompress all of `input`.  Concatenated members/frames are read
+    // through, as the command line tools write them.  Without the
+    // compression feature, compressed segments are still found by name but
+    // can't be read.
+    #[cfg(feature = "compression")]
+    pub fn decompress<R: Read>(self, input: R) -> std::io::Result<Vec<u8>> {
+        let mut decoder: Box<dyn Read> = match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+            Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(input)),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+        };
+        let mut data = Vec::new();
+        decoder.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub fn decompress<R: Read>(self, input: R) -> std::io::Result<Vec<u8>> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "reading {} files needs the compression feature",
+                self.suffix()
+            ),
+        ))
+    }
+}
+
+// Split a file name into the segment name and the compression its suffix
+// names, if any.
+pub fn split_compression(fname: &str) -> (&str, Option<Compression>) {
+    if let Some(dot) = fname.rfind('.') {
+        if let Some(c) = Compression::from_suffix(&fname[dot..]) {
+            return (&fname[..dot], Some(c));
+        }
+    }
+    (fname, None)
+}
+
+// An open segment: the file itself, the part of a file it is stored in, or
+// its contents held in memory, e.g. after decompressing it or taking it out
+// of a gzipped archive.
+pub enum SegmentFile {
+    Plain(File),
+    // size bytes of file from offset on, e.g. a member of a plain tar
+    Range(FileRange),
+    // shared, so that handing out a member kept in memory doesn't copy it
+    Memory(Cursor<Arc<[u8]>>),
+}
+
+impl SegmentFile {
+    // Open the file at path, decompressing it if its name says it is
+    // compressed.
+    pub fn open(path: &Path) -> std::io::Result<SegmentFile> {
+        let fname = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+        let file = File::open(path)?;
+        match split_compression(fname).1 {
+            None => Ok(SegmentFile::Plain(file)),
+            Some(c) => Ok(SegmentFile::from(c.decompress(file)?)),
+        }
+    }
+
+    // Length of the (decompressed) segment.
+    pub fn size(&self) -> std::io::Result<u64> {
+        match self {
+            SegmentFile::Plain(f) => Ok(f.metadata()?.len()),
+            SegmentFile::Range(r) => Ok(r.size),
+            SegmentFile::Memory(c) => Ok(c.get_ref().len() as u64),
+        }
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<SegmentFile> {
+        match self {
+            SegmentFile::Plain(f) => Ok(SegmentFile::Plain(f.try_clone()?)),
+            SegmentFile::Range(r) => Ok(SegmentFile::Range(FileRange::new(
+                r.file.try_clone()?,
+                r.offset,
+                r.size,
+            ))),
+            SegmentFile::Memory(c) => Ok(SegmentFile::Memory(Cursor::new(c.get_ref().clone()))),
+        }
+    }
+}
+
+impl From<Vec<u8>> for SegmentFile {
+    fn from(data: Vec<u8>) -> Self {
+        SegmentFile::Memory(Cursor::new(data.into()))
+    }
+}
+
+impl From<Arc<[u8]>> for SegmentFile {
+    fn from(data: Arc<[u8]>) -> Self {
+        SegmentFile::Memory(Cursor::new(data))
+    }
+}
+
+impl From<File> for SegmentFile {
+    fn from(file: File) -> Self {
+        SegmentFile::Plain(file)
+    }
+}
+
+impl Read for SegmentFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            SegmentFile::Plain(f) => f.read(buf),
+            SegmentFile::Range(r) => r.read(buf),
+            SegmentFile::Memory(c) => c.read(buf),
+        }
+    }
+}
+
+impl Seek for SegmentFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            SegmentFile::Plain(f) => f.seek(pos),
+            SegmentFile::Range(r) => r.seek(pos),
+            SegmentFile::Memory(c) => c.seek(pos),
+        }
+    }
+}
+
+impl std::fmt::Debug for SegmentFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentFile::Plain(file) => f.debug_tuple("Plain").field(file).finish(),
+            SegmentFile::Range(r) => f
+                .debug_struct("Range")
+                .field("file", &r.file)
+                .field("offset", &r.offset)
+                .field("size", &r.size)
+                .finish(),
+            SegmentFile::Memory(c) => f
+                .debug_struct("Memory")
+                .field("len", &c.get_ref().len())
+                .finish(),
+        }
+    }
+}
+
+// A part of a file read as a file of its own, offsets being relative to
+// its start.
+pub struct FileRange {
+    file: File,
+    offset: u64,
+    size: u64,
+    pos: u64,
+}
+
+impl FileRange {
+    pub fn new(file: File, offset: u64, size: u64) -> FileRange {
+        FileRange {
+            file,
+            offset,
+            size,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for FileRange {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.size.saturating_sub(self.pos) as usize);
+        if n == 0 {
+            return Ok(0);
+        }
+        self.file.seek(SeekFrom::Start(self.offset + self.pos))?;
+        let n = self.file.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for FileRange {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.size.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+// The names file fname may be stored under, in order of preference: as
+// is, compressed with any of the supported methods, and then the .partial
+// copy of a segment left behind by promotion, bare or compressed.  A name
+// that already carries a suffix is only looked up as given.
+pub fn candidate_names(fname: &str) -> Vec<String> {
+    if split_compression(fname).1.is_some() || fname.ends_with(".partial") {
+        return vec![fname.to_string()];
+    }
+    let mut names = Vec::new();
+    for base in [fname.to_string(), format!("{}.partial", fname)] {
+        names.push(base.clone());
+        for c in Compression::ALL {
+            names.push(format!("{}{}", base, c.suffix()));
+        }
+    }
+    names
+}
+
+// Find segment fname in dir, see candidate_names.
+pub fn find_segment(dir: &Path, fname: &Path) -> Option<PathBuf> {
+    candidate_names(fname.to_str()?)
+        .into_iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+// Open segment fname in dir, see find_segment.
+pub fn open_segment(dir: &Path, fname: &Path) -> std::io::Result<SegmentFile> {
+    match find_segment(dir, fname) {
+        Some(path) => SegmentFile::open(&path),
+        None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn compress(c: Compression, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match c {
+            Compression::Gzip => {
+                let mut e = flate2::write::GzEncoder::new(&mut out, flate2::Compression::fast());
+                e.write_all(data).unwrap();
+                e.finish().unwrap();
+            }
+            Compression::Zstd => out = zstd::encode_all(data, 1).unwrap(),
+            Compression::Lz4 => {
+                let mut e = lz4_flex::frame::FrameEncoder::new(&mut out);
+                e.write_all(data).unwrap();
+                e.finish().unwrap();
+            }
+            Compression::Bzip2 => {
+                let mut e = bzip2::write::BzEncoder::new(&mut out, bzip2::Compression::fast());
+                e.write_all(data).unwrap();
+                e.finish().unwrap();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn compressed_segments_are_found_and_read() {
+        let seg = std::fs::read("test/000000010000000000000001").unwrap();
+        let dir = std::env::temp_dir().join(format!("pg_waldump_compress_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (segno, c) in Compression::ALL.iter().enumerate() {
+            let name = format!("0000000100000000000000{:02X}", segno + 2);
+            std::fs::write(
+                dir.join(format!("{}{}", name, c.suffix())),
+                compress(*c, &seg),
+            )
+            .unwrap();
+
+            let mut file = open_segment(&dir, Path::new(&name)).unwrap();
+            assert_eq!(file.size().unwrap(), seg.len() as u64);
+            // read a page from the middle, then go back to the start
+            let mut page = [0u8; 8192];
+            file.seek(SeekFrom::Start(8192)).unwrap();
+            file.read_exact(&mut page).unwrap();
+            assert_eq!(&page[..], &seg[8192..]);
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_exact(&mut page).unwrap();
+            assert_eq!(&page[..], &seg[..8192]);
+        }
+        assert!(open_segment(&dir, Path::new("000000010000000000000009")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
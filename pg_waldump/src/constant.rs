@@ -1,54 +1,58 @@
 #![allow(unused)]
+use crate::version::PgVersion;
 use enumname_derive::EnumName;
 
-pub(crate) const XLOG_BLCKSZ: u32 = 8192;
-pub(crate) const XLOGDIR: &str = "pg_wal";
-pub(crate) const XLOG_INVALID_RECPTR: u64 = 0;
+pub const XLOG_BLCKSZ: u32 = 8192;
+pub const XLOGDIR: &str = "pg_wal";
+pub const XLOG_INVALID_RECPTR: u64 = 0;
 // These macros encapsulate knowledge about the exact layout of XLog file
 // names, timeline history file names, and archive-status file names.
-pub(crate) const MAXFNAMELEN: usize = 64;
-pub(crate) const XLOG_FNAME_LEN: usize = 24;
+pub const MAXFNAMELEN: usize = 64;
+pub const XLOG_FNAME_LEN: usize = 24;
 
-pub(crate) const XLOG_PAGE_MAGIC: u16 = 0xD114;
-pub(crate) const XLR_INFO_MASK: u8 = 0x0F;
-pub(crate) const XLR_RMGR_INFO_MASK: u8 = 0xF0;
+// page magic of the newest version supported; WAL of older versions is
+// recognized by PgVersion::from_page_magic()
+pub const XLOG_PAGE_MAGIC: u16 = PgVersion::LATEST.page_magic();
+pub const XLR_INFO_MASK: u8 = 0x0F;
+pub const XLR_RMGR_INFO_MASK: u8 = 0xF0;
 
-pub(crate) const XLR_MAX_BLOCK_ID: u8 = 32;
-pub(crate) const XLR_BLOCK_ID_DATA_SHORT: u8 = 255;
-pub(crate) const XLR_BLOCK_ID_DATA_LONG: u8 = 254;
-pub(crate) const XLR_BLOCK_ID_ORIGIN: u8 = 253;
-pub(crate) const XLR_BLOCK_ID_TOPLEVEL_XID: u8 = 252;
+pub const XLR_MAX_BLOCK_ID: u8 = 32;
+pub const XLR_BLOCK_ID_DATA_SHORT: u8 = 255;
+pub const XLR_BLOCK_ID_DATA_LONG: u8 = 254;
+pub const XLR_BLOCK_ID_ORIGIN: u8 = 253;
+pub const XLR_BLOCK_ID_TOPLEVEL_XID: u8 = 252;
 
-pub(crate) const WAL_SEG_MIN_SIZE: u32 = 1024 * 1024;
-pub(crate) const WAL_SEG_MAX_SIZE: u32 = 1024 * 1024 * 1024;
-pub(crate) const DEFAULT_MIN_WAL_SEGS: u32 = 64;
-pub(crate) const DEFAULT_MAX_WAL_SEGS: u32 = 1024;
+pub const DEFAULT_XLOG_SEG_SIZE: u32 = 16 * 1024 * 1024;
+pub const WAL_SEG_MIN_SIZE: u32 = 1024 * 1024;
+pub const WAL_SEG_MAX_SIZE: u32 = 1024 * 1024 * 1024;
+pub const DEFAULT_MIN_WAL_SEGS: u32 = 64;
+pub const DEFAULT_MAX_WAL_SEGS: u32 = 1024;
 
 // for xlp_info
-pub(crate) const XLP_FIRST_IS_CONTRECORD: u16 = 0x0001;
-pub(crate) const XLP_LONG_HEADER: u16 = 0x0002;
-pub(crate) const XLP_BKP_REMOVABLE: u16 = 0x0004;
-pub(crate) const XLP_FIRST_IS_OVERWRITE_CONTRECORD: u16 = 0x0008;
-pub(crate) const XLP_ALL_FLAGS: u16 = 0x000F;
+pub const XLP_FIRST_IS_CONTRECORD: u16 = 0x0001;
+pub const XLP_LONG_HEADER: u16 = 0x0002;
+pub const XLP_BKP_REMOVABLE: u16 = 0x0004;
+pub const XLP_FIRST_IS_OVERWRITE_CONTRECORD: u16 = 0x0008;
+pub const XLP_ALL_FLAGS: u16 = 0x000F;
 
 // page image has "hole"
-pub(crate) const BKPIMAGE_HAS_HOLE: u8 = 0x01;
+pub const BKPIMAGE_HAS_HOLE: u8 = 0x01;
 // page image should be restored during replay
-pub(crate) const BKPIMAGE_APPLY: u8 = 0x02;
+pub const BKPIMAGE_APPLY: u8 = 0x02;
 
-pub(crate) const BKPBLOCK_FORK_MASK: u8 = 0x0F;
-pub(crate) const BKPBLOCK_FLAG_MASK: u8 = 0xF0;
+pub const BKPBLOCK_FORK_MASK: u8 = 0x0F;
+pub const BKPBLOCK_FLAG_MASK: u8 = 0xF0;
 // block data is an XLogRecordBlockImage
-pub(crate) const BKPBLOCK_HAS_IMAGE: u8 = 0x10;
-pub(crate) const BKPBLOCK_HAS_DATA: u8 = 0x20;
+pub const BKPBLOCK_HAS_IMAGE: u8 = 0x10;
+pub const BKPBLOCK_HAS_DATA: u8 = 0x20;
 // redo will re-init the page
-pub(crate) const BKPBLOCK_WILL_INIT: u8 = 0x40;
+pub const BKPBLOCK_WILL_INIT: u8 = 0x40;
 // RelFileNode omitted,
-pub(crate) const BKPBLOCK_SAME_REL: u8 = 0x80;
+pub const BKPBLOCK_SAME_REL: u8 = 0x80;
 
 #[repr(u8)]
 #[derive(EnumName, PartialEq)]
-pub(crate) enum XLogInfo {
+pub enum XLogInfo {
     CheckpointShutdown = 0x00,
     CheckpointOnline = 0x10,
     NoOp = 0x20,
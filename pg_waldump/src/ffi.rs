@@ -0,0 +1,621 @@
+// C ABI over the decoder, built into the cdylib.
+//
+// A reader is an opaque handle created by pg_waldump_open_dir() and freed by
+// pg_waldump_close().  pg_waldump_next() steps it to the next record, which
+// the pg_waldump_record_*() accessors then describe.  Every entry point
+// returns a PgWaldumpStatus rather than unwinding into the caller: decoder
+// panics are caught and turned into PG_WALDUMP_STATUS_ERR_DECODE, with the
+// message available from pg_waldump_last_error() and nothing written to
+// stderr.
+//
+// include/pg_waldump.h is generated from this file by cbindgen; see
+// cbindgen.toml.  Only primitive types may appear in the signatures here, as
+// cbindgen is only shown this file.
+//
+// Safety: pointer arguments must be NULL or valid for the access described,
+// and a reader must not be used from two threads at once or after
+// pg_waldump_close().  The entry points check for NULL and nothing more.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, CStr, CString};
+use std::io::Read;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use crate::compress::SegmentFile;
+use crate::constant::*;
+use crate::pgtypes::*;
+use crate::rmgr::get_rmgr_desc;
+use crate::state::XLogReaderState;
+use crate::util::*;
+use crate::waldec::{lsn_out, xlog_find_next_record, xlog_read_record, WalConfig};
+use crate::walsource::DirectorySource;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgWaldumpStatus {
+    // a record is available
+    Ok = 0,
+    // no more records
+    End = 1,
+    // a NULL pointer or otherwise unusable argument
+    ErrInvalidArgument = -1,
+    // no WAL segment, or no such block reference
+    ErrNotFound = -2,
+    // a WAL file could not be read
+    ErrIo = -3,
+    // the WAL could not be decoded
+    ErrDecode = -4,
+    // no current record; pg_waldump_next() hasn't returned
+    // PG_WALDUMP_STATUS_OK
+    ErrNoRecord = -5,
+}
+
+// The fixed fields of the current record.
+#[repr(C)]
+pub struct PgWaldumpRecordHeader {
+    pub lsn: u64,
+    // end+1 of the record
+    pub next_lsn: u64,
+    pub prev: u64,
+    pub xid: u32,
+    pub tot_len: u32,
+    pub rmid: u8,
+    pub info: u8,
+    // block ids run from 0 to nblocks - 1; not all need to be in use
+    pub nblocks: u8,
+}
+
+// A block reference of the current record.
+#[repr(C)]
+pub struct PgWaldumpBlockRef {
+    pub spc_oid: u32,
+    pub db_oid: u32,
+    pub rel_oid: u32,
+    pub forknum: i32,
+    pub blkno: u32,
+    pub has_image: bool,
+    pub apply_image: bool,
+    pub bimg_info: u8,
+    pub bimg_len: u16,
+    pub hole_offset: u16,
+    pub hole_length: u16,
+    pub has_data: bool,
+    pub data_len: u16,
+}
+
+pub struct PgWaldumpReader {
+    state: XLogReaderState,
+    started: bool,
+    finished: bool,
+    rmgr_name: CString,
+    desc: Option<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+    // whether the thread is in an entry point, whose panics guard() reports
+    static IN_ENTRY_POINT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn set_last_error(msg: &str) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+fn fail(status: PgWaldumpStatus, msg: &str) -> PgWaldumpStatus {
+    set_last_error(msg);
+    status
+}
+
+// The panic hook is the process's, not ours to replace for the length of a
+// call: another thread could panic meanwhile.  So one hook is installed,
+// quiet in entry points and passing other panics on to the hook before it.
+fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !IN_ENTRY_POINT.with(|e| e.get()) {
+                previous(info);
+            }
+        }));
+    });
+}
+
+// Run f, turning a panic into ErrDecode.
+fn guard<F: FnOnce() -> PgWaldumpStatus>(f: F) -> PgWaldumpStatus {
+    install_panic_hook();
+    let outer = IN_ENTRY_POINT.with(|e| e.replace(true));
+    let res = catch_unwind(AssertUnwindSafe(f));
+    IN_ENTRY_POINT.with(|e| e.set(outer));
+    match res {
+        Ok(status) => status,
+        Err(payload) => {
+            let msg = if let Some(s) = payload.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                String::from("decoder failed")
+            };
+            fail(PgWaldumpStatus::ErrDecode, &msg)
+        }
+    }
+}
+
+// The segment files of timeline tli in dir, in order.  A tli of 0 picks
+// the timeline of the first segment.
+fn list_segments(dir: &Path, tli: &mut TimeLineID) -> std::io::Result<Vec<PathBuf>> {
+    let mut names: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| PathBuf::from(e.file_name()))
+        .filter(is_xlog_filename)
+        .collect();
+    names.sort();
+    if *tli == 0 {
+        if let Some(first) = names.first() {
+            *tli = u32::from_str_radix(&first.to_str().unwrap()[0..8], 16).unwrap_or(0);
+        }
+    }
+    let prefix = format!("{:08X}", tli);
+    names.retain(|n| n.to_str().unwrap().starts_with(&prefix));
+    Ok(names)
+}
+
+fn open_dir(
+    dir: &Path,
+    mut tli: TimeLineID,
+    startptr: XLogRecPtr,
+    endptr: XLogRecPtr,
+) -> Result<PgWaldumpReader, (PgWaldumpStatus, String)> {
+    let io_err = |e: std::io::Error| (PgWaldumpStatus::ErrIo, format!("{}: {}", dir.display(), e));
+    let segments = list_segments(dir, &mut tli).map_err(io_err)?;
+    let first = dir.join(segments.first().ok_or((
+        PgWaldumpStatus::ErrNotFound,
+        format!("could not find any WAL file in \"{}\"", dir.display()),
+    ))?);
+
+    // the segment may end within its first page; the long header is enough
+    let mut page = Vec::new();
+    SegmentFile::open(&first)
+        .and_then(|f| f.take(XLOG_BLCKSZ as u64).read_to_end(&mut page))
+        .map_err(io_err)?;
+    let config = WalConfig::from_first_page(&page)
+        .map_err(|e| (PgWaldumpStatus::ErrDecode, format!("{}: {}", first.display(), e)))?;
+    let segsz = config.wal_seg_sz as u64;
+
+    // Default the range to the WAL present: from the first segment through
+    // the run of consecutive segments after it.
+    let segments: Vec<(XLogSegNo, PathBuf)> = segments
+        .into_iter()
+        .map(|fname| {
+            let (mut t, mut segno) = (0, 0);
+            xlog_from_file_name(&fname, &mut t, &mut segno, config.wal_seg_sz);
+            (segno, dir.join(fname))
+        })
+        .collect();
+    let startptr = if startptr == 0 {
+        segments[0].0 * segsz
+    } else {
+        startptr
+    };
+    let endptr = if endptr == 0 {
+        let mut segno = xlog_byte_to_seg(startptr, config.wal_seg_sz);
+        let mut end = startptr;
+        while let Some((_, path)) = segments.iter().find(|(s, _)| *s == segno) {
+            let len = SegmentFile::open(path)
+                .and_then(|f| f.size())
+                .map_err(io_err)?
+                .min(segsz);
+            end = segno * segsz + len;
+            if len < segsz {
+                break;
+            }
+            segno += 1;
+        }
+        end
+    } else {
+        endptr
+    };
+    if endptr <= startptr {
+        return Err((
+            PgWaldumpStatus::ErrNotFound,
+            format!("no WAL at {} in \"{}\"", lsn_out(startptr), dir.display()),
+        ));
+    }
+
+    let private = XLogDumpPrivate {
+        timeline: tli,
+        startptr,
+        endptr,
+        endptr_reached: false,
+        ..Default::default()
+    };
+    let source = DirectorySource::new(dir.to_path_buf(), config.wal_seg_sz);
+    Ok(PgWaldumpReader {
+        state: XLogReaderState::new(config, Box::new(source), private),
+        started: false,
+        finished: false,
+        rmgr_name: CString::default(),
+        desc: None,
+    })
+}
+
+// Open the WAL of timeline `timeline` in directory `dir`, to be read from
+// the first record at or after `startptr` up to `endptr`.  Zero for the
+// timeline means that of the first segment found, zero for `startptr` the
+// start of the first segment, and zero for `endptr` the end of the WAL
+// present.  On success *reader is set to a handle to be freed with
+// pg_waldump_close().
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_open_dir(
+    dir: *const c_char,
+    timeline: u32,
+    startptr: u64,
+    endptr: u64,
+    reader: *mut *mut PgWaldumpReader,
+) -> PgWaldumpStatus {
+    if dir.is_null() || reader.is_null() {
+        return fail(PgWaldumpStatus::ErrInvalidArgument, "NULL argument");
+    }
+    let dir = match CStr::from_ptr(dir).to_str() {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => return fail(PgWaldumpStatus::ErrInvalidArgument, "directory is not UTF-8"),
+    };
+    *reader = std::ptr::null_mut();
+    guard(|| match open_dir(&dir, timeline, startptr, endptr) {
+        Ok(r) => {
+            *reader = Box::into_raw(Box::new(r));
+            PgWaldumpStatus::Ok
+        }
+        Err((status, msg)) => fail(status, &msg),
+    })
+}
+
+// Free a reader.  NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_close(reader: *mut PgWaldumpReader) {
+    if !reader.is_null() {
+        drop(Box::from_raw(reader));
+    }
+}
+
+// Step to the next record.  Returns PG_WALDUMP_STATUS_OK if there is one,
+// PG_WALDUMP_STATUS_END at the end of the WAL, or an error.
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_next(reader: *mut PgWaldumpReader) -> PgWaldumpStatus {
+    let Some(r) = reader.as_mut() else {
+        return fail(PgWaldumpStatus::ErrInvalidArgument, "NULL reader");
+    };
+    if r.finished {
+        return PgWaldumpStatus::End;
+    }
+    r.desc = None;
+    r.state.record = None;
+    let status = guard(|| {
+        if !r.started {
+            r.started = true;
+            if xlog_find_next_record(&mut r.state) == INVALID_XLOG_RECPTR {
+                return PgWaldumpStatus::End;
+            }
+        }
+        if xlog_read_record(&mut r.state) {
+            PgWaldumpStatus::Ok
+        } else if !r.state.errmsg.is_empty() {
+            fail(PgWaldumpStatus::ErrDecode, &r.state.errmsg)
+        } else {
+            PgWaldumpStatus::End
+        }
+    });
+    if status != PgWaldumpStatus::Ok {
+        // errors aren't recoverable; the decoder may be in any state
+        r.finished = true;
+        r.state.record = None;
+    }
+    status
+}
+
+fn current<'a>(reader: *const PgWaldumpReader) -> Result<&'a PgWaldumpReader, PgWaldumpStatus> {
+    match unsafe { reader.as_ref() } {
+        None => Err(fail(PgWaldumpStatus::ErrInvalidArgument, "NULL reader")),
+        Some(r) if r.state.record.is_none() => {
+            Err(fail(PgWaldumpStatus::ErrNoRecord, "no current record"))
+        }
+        Some(r) => Ok(r),
+    }
+}
+
+// Fill *header with the fixed fields of the current record.
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_record_header(
+    reader: *const PgWaldumpReader,
+    header: *mut PgWaldumpRecordHeader,
+) -> PgWaldumpStatus {
+    let r = match current(reader) {
+        Ok(r) => r,
+        Err(status) => return status,
+    };
+    let Some(header) = header.as_mut() else {
+        return fail(PgWaldumpStatus::ErrInvalidArgument, "NULL header");
+    };
+    let record = r.state.record.as_ref().unwrap();
+    *header = PgWaldumpRecordHeader {
+        lsn: record.lsn,
+        next_lsn: record.next_lsn,
+        prev: record.header.xl_prev,
+        xid: record.header.xl_xid,
+        tot_len: record.header.xl_tot_len,
+        rmid: record.header.xl_rmid,
+        info: record.header.xl_info,
+        nblocks: (record.max_block_id + 1) as u8,
+    };
+    PgWaldumpStatus::Ok
+}
+
+// Fill *block with block reference block_id of the current record.  Returns
+// PG_WALDUMP_STATUS_ERR_NOT_FOUND if the record has no such block reference.
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_record_block(
+    reader: *const PgWaldumpReader,
+    block_id: u8,
+    block: *mut PgWaldumpBlockRef,
+) -> PgWaldumpStatus {
+    let r = match current(reader) {
+        Ok(r) => r,
+        Err(status) => return status,
+    };
+    let Some(block) = block.as_mut() else {
+        return fail(PgWaldumpStatus::ErrInvalidArgument, "NULL block");
+    };
+    let Some(blk) = r.state.record.as_ref().unwrap().block(block_id) else {
+        return fail(PgWaldumpStatus::ErrNotFound, "no such block reference");
+    };
+    *block = PgWaldumpBlockRef {
+        spc_oid: blk.rlocator.spc_oid,
+        db_oid: blk.rlocator.db_oid,
+        rel_oid: blk.rlocator.rel_oid,
+        forknum: i8::from(blk.forknum) as i32,
+        blkno: blk.blkno,
+        has_image: blk.has_image,
+        apply_image: blk.apply_image,
+        bimg_info: blk.bimg_info,
+        bimg_len: blk.bimg_len,
+        hole_offset: blk.hole_offset,
+        hole_length: blk.hole_length,
+        has_data: blk.has_data,
+        data_len: blk.data_len,
+    };
+    PgWaldumpStatus::Ok
+}
+
+// Point *data and *len at the main data of the current record; *len is 0
+// if it has none.  Valid until the next pg_waldump_next().
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_record_main_data(
+    reader: *const PgWaldumpReader,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> PgWaldumpStatus {
+    let r = match current(reader) {
+        Ok(r) => r,
+        Err(status) => return status,
+    };
+    if data.is_null() || len.is_null() {
+        return fail(PgWaldumpStatus::ErrInvalidArgument, "NULL argument");
+    }
+    let main_data = r.state.record().unwrap().main_data().unwrap_or(&[]);
+    *data = main_data.as_ptr();
+    *len = main_data.len();
+    PgWaldumpStatus::Ok
+}
+
+// The resource manager name of the current record, e.g. "Heap", or NULL
+// without a current record.  Valid until the next pg_waldump_next().
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_record_rmgr_name(reader: *mut PgWaldumpReader) -> *const c_char {
+    if current(reader).is_err() {
+        return std::ptr::null();
+    }
+    let r = &mut *reader;
+    let rmid = r.state.record.as_ref().unwrap().header.xl_rmid;
+    let mut name = std::ptr::null();
+    guard(|| {
+        r.rmgr_name = CString::new(get_rmgr_desc(rmid).rm_name).unwrap();
+        name = r.rmgr_name.as_ptr();
+        PgWaldumpStatus::Ok
+    });
+    name
+}
+
+// The description of the current record as pg_waldump prints it after
+// "desc: ", e.g. "INSERT+INIT off: 1, flags: 0x08", or NULL without a
+// current record or if it can't be described.  Valid until the next
+// pg_waldump_next().
+#[no_mangle]
+pub unsafe extern "C" fn pg_waldump_record_desc(reader: *mut PgWaldumpReader) -> *const c_char {
+    if current(reader).is_err() {
+        return std::ptr::null();
+    }
+    let r = &mut *reader;
+    if r.desc.is_none() {
+        let status = guard(|| {
+            let record = r.state.record.as_ref().unwrap();
+            let rmgr = get_rmgr_desc(record.header.xl_rmid);
+            let info = record.header.xl_info;
+            let id = (rmgr.rm_identify)(info, r.state.config.version);
+            let id = if id.is_empty() {
+                format!("UNKNOWN ({:X})", info & !XLR_INFO_MASK)
+            } else {
+                id
+            };
+            let desc = format!("{} {}", id, (rmgr.rm_desc)(&r.state));
+            r.desc = Some(CString::new(desc.replace('\0', " ")).unwrap());
+            PgWaldumpStatus::Ok
+        });
+        if status != PgWaldumpStatus::Ok {
+            return std::ptr::null();
+        }
+    }
+    r.desc.as_ref().unwrap().as_ptr()
+}
+
+// The message of the last error on this thread.  Valid until the next call
+// into the library on this thread.
+#[no_mangle]
+pub extern "C" fn pg_waldump_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walsource::tests::{synthetic_page, SEGSIZE};
+
+    struct Record {
+        header: PgWaldumpRecordHeader,
+        rmgr: String,
+        block0: Option<PgWaldumpBlockRef>,
+    }
+
+    fn read_all(dir: &Path) -> Vec<Record> {
+        let cdir = CString::new(dir.to_str().unwrap()).unwrap();
+        let mut reader = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                pg_waldump_open_dir(cdir.as_ptr(), 0, 0, 0, &mut reader),
+                PgWaldumpStatus::Ok
+            );
+            let mut records = Vec::new();
+            loop {
+                match pg_waldump_next(reader) {
+                    PgWaldumpStatus::Ok => (),
+                    PgWaldumpStatus::End => break,
+                    status => panic!(
+                        "{:?}: {}",
+                        status,
+                        CStr::from_ptr(pg_waldump_last_error()).to_str().unwrap()
+                    ),
+                }
+                let mut header = std::mem::zeroed();
+                assert_eq!(pg_waldump_record_header(reader, &mut header), PgWaldumpStatus::Ok);
+                assert!(!pg_waldump_record_desc(reader).is_null());
+                let name = CStr::from_ptr(pg_waldump_record_rmgr_name(reader));
+                let mut block = std::mem::zeroed();
+                let block0 = match pg_waldump_record_block(reader, 0, &mut block) {
+                    PgWaldumpStatus::Ok => Some(block),
+                    PgWaldumpStatus::ErrNotFound => None,
+                    status => panic!("{:?}", status),
+                };
+                records.push(Record {
+                    header,
+                    rmgr: name.to_str().unwrap().to_string(),
+                    block0,
+                });
+            }
+            // no current record once the end is reached
+            let mut header = std::mem::zeroed();
+            assert_eq!(
+                pg_waldump_record_header(reader, &mut header),
+                PgWaldumpStatus::ErrNoRecord
+            );
+            assert_eq!(pg_waldump_next(reader), PgWaldumpStatus::End);
+            pg_waldump_close(reader);
+            records
+        }
+    }
+
+    #[test]
+    fn abi_reads_directory() {
+        let dir = std::env::temp_dir().join(format!("pg_waldump_ffi_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let segstart = SEGSIZE as u64;
+        let (page, lsns) = synthetic_page(segstart, SEGSIZE, 3);
+        let walend = (lsns[2] - segstart) as usize + 40;
+        std::fs::write(dir.join(xlog_filename(1, 1, SEGSIZE)), &page[..walend]).unwrap();
+
+        let records = read_all(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let got: Vec<u64> = records.iter().map(|r| r.header.lsn).collect();
+        assert_eq!(got, lsns);
+        assert_eq!(records[0].header.rmid, 0);
+        assert_eq!(records[0].header.nblocks, 0);
+        assert_eq!(records[0].rmgr, "XLOG");
+        assert!(records[0].block0.is_none());
+    }
+
+    #[test]
+    fn abi_reads_bundled_segment() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+        let records = read_all(&dir);
+        assert_eq!(records.len(), 85);
+        assert_eq!(records[0].header.lsn, 0x1000028);
+        assert_eq!(records[0].rmgr, "XLOG");
+
+        // rmgr: XLOG len (rec/tot): 49/137, tx: 1, lsn: 0/01003D18,
+        // prev 0/010032B8, desc: FPI , blkref #0: rel 1663/1/2670 blk 0 FPW
+        let last = records.last().unwrap();
+        assert_eq!(last.header.lsn, 0x1003D18);
+        assert_eq!(last.header.prev, 0x10032B8);
+        assert_eq!(last.header.tot_len, 137);
+        let blk = last.block0.as_ref().unwrap();
+        assert_eq!((blk.spc_oid, blk.db_oid, blk.rel_oid, blk.blkno), (1663, 1, 2670, 0));
+        assert!(blk.has_image);
+    }
+
+    #[test]
+    fn abi_reports_errors() {
+        let mut reader = std::ptr::null_mut();
+        let missing = CString::new("/nonexistent/pg_wal").unwrap();
+        unsafe {
+            assert_eq!(
+                pg_waldump_open_dir(std::ptr::null(), 0, 0, 0, &mut reader),
+                PgWaldumpStatus::ErrInvalidArgument
+            );
+            assert_eq!(
+                pg_waldump_open_dir(missing.as_ptr(), 0, 0, 0, &mut reader),
+                PgWaldumpStatus::ErrIo
+            );
+            assert!(reader.is_null());
+            let msg = CStr::from_ptr(pg_waldump_last_error()).to_str().unwrap();
+            assert!(msg.starts_with("/nonexistent/pg_wal"));
+            assert_eq!(pg_waldump_next(reader), PgWaldumpStatus::ErrInvalidArgument);
+            assert!(pg_waldump_record_desc(reader).is_null());
+        }
+    }
+
+    #[test]
+    fn panics_become_decode_errors() {
+        assert_eq!(
+            guard(|| panic!("no record at {}", lsn_out(0x1000028))),
+            PgWaldumpStatus::ErrDecode
+        );
+        let msg = unsafe { CStr::from_ptr(pg_waldump_last_error()) };
+        assert_eq!(msg.to_str().unwrap(), "no record at 0/01000028");
+        assert!(!IN_ENTRY_POINT.with(|e| e.get()));
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", crate_dir))
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+        let header = format!("{}/include/pg_waldump.h", crate_dir);
+        if std::env::var_os("PG_WALDUMP_UPDATE_HEADER").is_some() {
+            std::fs::write(&header, &generated).unwrap();
+        }
+        let checked_in = std::fs::read(&header).unwrap();
+        assert!(
+            generated == checked_in,
+            "include/pg_waldump.h is stale; rerun with PG_WALDUMP_UPDATE_HEADER=1"
+        );
+    }
+}
@@ -0,0 +1,33 @@
+#![allow(unused)]
+pub mod archive;
+#[cfg(feature = "parquet")]
+pub mod columnar;
+#[cfg(feature = "async")]
+pub mod async_reader;
+pub mod compress;
+pub mod constant;
+pub mod ffi;
+pub mod guc;
+pub mod pg_control;
+pub mod pgtypes;
+#[cfg(feature = "replication")]
+pub mod replication;
+pub mod report;
+pub mod restore;
+pub mod rmgr;
+pub mod scan;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "serde")]
+pub mod serde_util;
+pub mod state;
+pub mod stats;
+pub mod summary;
+pub mod table;
+pub mod template;
+pub mod timeline;
+pub mod util;
+pub mod version;
+pub mod waldec;
+pub mod walsource;
+pub mod xlog;
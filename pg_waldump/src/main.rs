@@ -1,39 +1,64 @@
 #![allow(unused)]
 mod cli;
-mod constant;
-mod guc;
-mod pg_control;
-mod pgtypes;
-mod rmgr;
-mod state;
-mod util;
-mod waldec;
-mod xlog;
-
-use constant::*;
-use pgtypes::*;
-use rmgr::*;
-use state::*;
-use std::io::Read;
-use std::mem::size_of;
-use std::path::PathBuf;
-use util::*;
 
-use crate::waldec::{bkpimage_compressed, lsn_out, BkpImageCompressMethod, XLogPageHeaderData};
+use pg_waldump::archive::{is_tar_archive, TarArchive};
+#[cfg(feature = "parquet")]
+use pg_waldump::columnar::ParquetWriter;
+#[cfg(feature = "sqlite")]
+use pg_waldump::sqlite::{resume_point, SqliteWriter};
+use pg_waldump::compress::{find_segment, open_segment, split_compression, SegmentFile};
+use pg_waldump::constant::*;
+use pg_waldump::pg_control::ControlFileData;
+use pg_waldump::pgtypes::*;
+use pg_waldump::report::HtmlReport;
+#[cfg(feature = "replication")]
+use pg_waldump::replication::{ConnParams, ReplicationConnection, StreamSource};
+use pg_waldump::restore::RestoreCommand;
+use pg_waldump::rmgr::*;
+use pg_waldump::scan::scan_segments;
+use pg_waldump::stats::XLogStats;
+use pg_waldump::state::*;
+use pg_waldump::summary::RecordSummary;
+use pg_waldump::template::Template;
+use pg_waldump::table::{
+    block_rows, format_header, format_row, record_row, TableFormat, BLOCK_COLUMNS, RECORD_COLUMNS,
+};
+use pg_waldump::timeline::{read_timeline_history, tli_of_point_in_history};
+use pg_waldump::util::*;
+use pg_waldump::version::PgVersion;
+use pg_waldump::waldec;
+use pg_waldump::waldec::{
+    lsn_out, xlog_find_next_record, xlog_read_record,
+    BkpImageCompressMethod, SystemIdentifierSource, WalConfig, XLogPageHeaderData,
+};
+use pg_waldump::walsource::{
+    check_segment_copy, ByteStreamSource, DirectorySource, TarSource, WalSource,
+};
+use std::io::{BufWriter, Read, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
 
-fn search_directory(waldir: &std::path::PathBuf, fname: &std::path::PathBuf) -> bool {
+fn search_directory(
+    waldir: &std::path::PathBuf,
+    fname: &std::path::PathBuf,
+    version: Option<PgVersion>,
+) -> Option<WalConfig> {
     let mut srched = std::path::PathBuf::new();
     if fname.as_os_str().is_empty() {
+        // the first segment in order, so that the same one is used every
+        // time
         match std::fs::read_dir(waldir) {
-            Err(_) => return false,
+            Err(_) => return None,
             Ok(dentries) => {
                 for de in dentries {
                     let de = de.unwrap();
                     let path = de.path();
-                    // println!("path: {}", path.display());
-                    if path.is_file() && is_xlog_filename(&path) {
-                        srched = path;
-                        break;
+                    let name = PathBuf::from(de.file_name());
+                    if path.is_file()
+                        && is_xlog_filename(&path)
+                        && (srched.as_os_str().is_empty() || name < srched)
+                    {
+                        srched = name;
                     }
                 }
             }
@@ -42,68 +67,282 @@ fn search_directory(waldir: &std::path::PathBuf, fname: &std::path::PathBuf) ->
         srched = fname.clone();
     }
     if srched.as_os_str().is_empty() {
-        return false;
+        return None;
     }
 
-    let mut fpath = waldir.clone();
-    fpath.push(srched);
-    let mut file = match std::fs::File::open(fpath) {
-        Err(_) => return false,
+    let mut file = match open_segment(waldir, &srched) {
+        Err(_) => return None,
         Ok(file) => file,
     };
 
-    waldec::check_first_page_header(&mut file, &fname)
+    Some(waldec::check_first_page_header(&mut file, &srched, version))
 }
 
-fn identify_target_directory(waldir: PathBuf, fname: &PathBuf) -> PathBuf {
-    if !waldir.as_os_str().is_empty() {
-        if search_directory(&waldir, &fname) {
-            return waldir;
-        }
-
-        let mut waldir = waldir.clone();
-        waldir.push(XLOGDIR);
-        if search_directory(&waldir, &fname) {
-            return waldir;
+// Find the directory holding the WAL, and discover the configuration of the
+// cluster that wrote it from the first segment found there.  `version`, if
+// given, overrides the server version detected from the page magic.  None
+// if no directory has the WAL.
+//
+// With several locations, they are all returned in order, each resolved to
+// the directory holding its segments, and the configuration is that of the
+// first location with the segment.
+fn locate_target_directory(
+    waldirs: &[PathBuf],
+    fname: &PathBuf,
+    version: Option<PgVersion>,
+) -> Option<(Vec<PathBuf>, WalConfig)> {
+    if !waldirs.is_empty() {
+        let mut dirs: Vec<PathBuf> = waldirs.iter().map(|d| wal_directory(d)).collect();
+        for (i, waldir) in waldirs.iter().enumerate() {
+            for dir in [waldir.clone(), waldir.join(XLOGDIR)] {
+                if let Some(config) = search_directory(&dir, &fname, version) {
+                    dirs[i] = dir;
+                    return Some((dirs, config));
+                }
+            }
         }
     } else {
         let dir = std::path::PathBuf::from(".");
-        if search_directory(&dir, &fname) {
-            return dir;
+        if let Some(config) = search_directory(&dir, &fname, version) {
+            return Some((vec![dir], config));
         }
 
         let dir = std::path::PathBuf::from(XLOGDIR);
-        if search_directory(&dir, &fname) {
-            return dir;
+        if let Some(config) = search_directory(&dir, &fname, version) {
+            return Some((vec![dir], config));
         }
 
-        let datadir = std::env::var("PGDATA").unwrap();
+        let datadir = std::env::var("PGDATA").unwrap_or_default();
         if !datadir.is_empty() {
             let mut dir = std::path::PathBuf::from(datadir);
             dir.push(XLOGDIR);
-            if search_directory(&dir, &fname) {
-                return dir;
+            if let Some(config) = search_directory(&dir, &fname, version) {
+                return Some((vec![dir], config));
             }
         }
     }
 
+    None
+}
+
+// The directory of location path that holds the segments: path itself, or
+// its pg_wal if path has none.
+fn wal_directory(path: &Path) -> PathBuf {
+    let has_segments = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .any(|e| is_xlog_filename(&e.path())),
+        Err(_) => false,
+    };
+    if !has_segments && path.join(XLOGDIR).is_dir() {
+        path.join(XLOGDIR)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+// Open file fname from the first of dirs that has it, see open_segment.
+fn open_in_dirs(dirs: &[PathBuf], fname: &Path) -> std::io::Result<SegmentFile> {
+    match dirs.iter().find_map(|dir| find_segment(dir, fname)) {
+        Some(path) => SegmentFile::open(&path),
+        None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+    }
+}
+
+// Open segment file name from the first of dirs whose copy has a valid
+// first page, or else from the first that has it.
+fn open_valid_in_dirs(dirs: &[PathBuf], name: &str, segsize: u32) -> std::io::Result<SegmentFile> {
+    let mut tli: TimeLineID = 0;
+    let mut segno: XLogSegNo = 0;
+    xlog_from_file_name(
+        &PathBuf::from(split_compression(name).0),
+        &mut tli,
+        &mut segno,
+        segsize,
+    );
+    for dir in dirs {
+        let mut page = vec![0u8; XLOG_BLCKSZ as usize];
+        let valid = SegmentFile::open(&dir.join(name))
+            .and_then(|mut file| file.read_exact(&mut page))
+            .is_ok_and(|_| check_segment_copy(&page, segno * segsize as u64, segsize).is_ok());
+        if valid {
+            return SegmentFile::open(&dir.join(name));
+        }
+    }
+    open_in_dirs(dirs, Path::new(name))
+}
+
+// Restore segment fname with the restore command for a WAL directory that
+// doesn't have it, and discover the configuration from it.
+fn identify_restored(
+    restore: &mut RestoreCommand,
+    fname: &PathBuf,
+    version: Option<PgVersion>,
+) -> WalConfig {
+    match restore
+        .fetch(fname.to_str().unwrap())
+        .and_then(|path| SegmentFile::open(&path))
+    {
+        Ok(mut file) => waldec::check_first_page_header(&mut file, fname, version),
+        Err(e) => panic!("could not locate WAL file {}: {}", fname.display(), e),
+    }
+}
+
+// Restore the segment holding start for a WAL directory without any
+// segments, from the timeline that the history of timeline tli has at
+// start.  Its name depends on the segment size, which is only known once
+// it is read, so each valid size is tried, the default first.
+fn identify_restored_start(
+    restore: &mut RestoreCommand,
+    start: XLogRecPtr,
+    tli: TimeLineID,
+    version: Option<PgVersion>,
+) -> WalConfig {
+    let history = read_timeline_history(tli, |fname| {
+        restore
+            .fetch(fname.to_str().unwrap())
+            .and_then(|path| SegmentFile::open(&path))
+    });
+    let tli = match history {
+        Ok(history) => tli_of_point_in_history(start, &history),
+        Err(e) => panic!("{}", e),
+    };
+    let sizes = (0..=WAL_SEG_MAX_SIZE.trailing_zeros() - WAL_SEG_MIN_SIZE.trailing_zeros())
+        .map(|i| WAL_SEG_MIN_SIZE << i)
+        .filter(|&size| size != DEFAULT_XLOG_SEG_SIZE);
+    for segsize in std::iter::once(DEFAULT_XLOG_SEG_SIZE).chain(sizes) {
+        let fname = xlog_filename(tli, byte_to_seg(start, segsize), segsize);
+        let file = restore
+            .fetch(fname.to_str().unwrap())
+            .and_then(|path| SegmentFile::open(&path));
+        if let Ok(mut file) = file {
+            let config = waldec::check_first_page_header(&mut file, &fname, version);
+            if config.wal_seg_sz == segsize {
+                return config;
+            }
+        }
+    }
+    panic!(
+        "could not restore WAL file holding start WAL location {} on timeline {}",
+        lsn_out(start),
+        tli
+    );
+}
+
+// Like locate_target_directory, within a tar archive: the segments are
+// either at the top of the archive (pg_wal.tar) or in its pg_wal directory
+// (base.tar).  Returns the directory within the archive.
+fn identify_target_archive(
+    archive: &TarArchive,
+    fname: &PathBuf,
+    version: Option<PgVersion>,
+) -> (PathBuf, WalConfig) {
+    for dir in [PathBuf::new(), PathBuf::from(XLOGDIR)] {
+        let srched = if fname.as_os_str().is_empty() {
+            match archive.segments(&dir).first() {
+                Some(name) => PathBuf::from(name),
+                None => continue,
+            }
+        } else if archive.contains(&dir, fname) {
+            fname.clone()
+        } else {
+            continue;
+        };
+        let mut file = match archive.open_segment(&dir, &srched) {
+            Ok(file) => file,
+            Err(e) => panic!(
+                "could not read file {} in {}: {}",
+                dir.join(&srched).display(),
+                archive.path().display(),
+                e
+            ),
+        };
+        return (dir, waldec::check_first_page_header(&mut file, &srched, version));
+    }
+
     if !fname.as_os_str().is_empty() {
-        panic!("could not locate WAL file {}", fname.display());
+        panic!(
+            "could not locate WAL file {} in {}",
+            fname.display(),
+            archive.path().display()
+        );
     } else {
-        panic!("could not find any WAL file");
+        panic!("could not find any WAL file in {}", archive.path().display());
+    }
+}
+
+// Connect to the server of --dbname and learn the configuration of its WAL
+// and the history of the timeline to read.  Streaming starts when the
+// reader first asks for WAL.
+#[cfg(feature = "replication")]
+fn connect_stream(
+    conninfo: &str,
+    args: &cli::Cli,
+    private: &mut XLogDumpPrivate,
+) -> (WalConfig, StreamSource) {
+    let params = match ConnParams::parse(conninfo) {
+        Ok(params) => params,
+        Err(e) => panic!("{}", e),
+    };
+    let mut conn = match ReplicationConnection::connect(&params) {
+        Ok(conn) => conn,
+        Err(e) => panic!("could not connect to server: {}", e),
+    };
+    let system = match conn.identify_system() {
+        Ok(system) => system,
+        Err(e) => panic!("{}", e),
+    };
+    let config = match conn.wal_config(system.system_identifier, args.pg_version) {
+        Ok(config) => config,
+        Err(e) => panic!("{}", e),
+    };
+
+    private.timeline = args.timeline.unwrap_or(system.timeline);
+    private.history = match conn.timeline_history(private.timeline) {
+        Ok(history) => history,
+        Err(e) => panic!("{}", e),
+    };
+    private.startptr = args.start.unwrap_or(system.xlogpos);
+    private.endptr = args.end.unwrap_or(INVALID_XLOG_RECPTR);
+
+    let source = StreamSource::new(
+        conn,
+        args.slot.clone(),
+        config.wal_seg_sz,
+        private.history.clone(),
+    );
+    (config, source)
+}
+
+// Read the first page of the WAL on standard input, for STARTSEG "-".  It
+// must be the start of a segment, whose long header gives the segment
+// size, the timeline and the LSN the WAL starts at.
+fn read_stdin_segment(version: Option<PgVersion>) -> (WalConfig, TimeLineID, ByteStreamSource) {
+    let mut input = std::io::stdin();
+    let mut page = vec![0u8; XLOG_BLCKSZ as usize];
+    if let Err(e) = input.read_exact(&mut page) {
+        panic!("could not read the first page of WAL from standard input: {}", e);
     }
-    // not reached
-    std::path::PathBuf::new()
+    let config = match WalConfig::from_first_page_as(&page, version) {
+        Ok(config) => config,
+        Err(e) => panic!("standard input: {}", e),
+    };
+    let hdr = waldec::page_header(&page).unwrap().1;
+    let source = ByteStreamSource::new(
+        Box::new(input),
+        hdr.xlp_pageaddr,
+        page,
+        config.wal_seg_sz,
+    );
+    (config, hdr.xlp_tli, source)
 }
 
 fn xlog_rec_has_block_image(record: &DecodedXLogRecord, blk_id: i8) -> bool {
-    let blocks = record.blocks.as_ref().unwrap();
-    blocks[blk_id as usize].borrow().has_image
+    record.blocks[blk_id as usize].has_image
 }
 
 fn xlog_rec_has_block_ref(record: &DecodedXLogRecord, blk_id: i8) -> bool {
-    let blocks = record.blocks.as_ref().unwrap();
-    record.max_block_id >= blk_id && blocks[blk_id as usize].borrow().in_use
+    record.max_block_id >= blk_id && record.blocks[blk_id as usize].in_use
 }
 
 fn xlog_rec_get_len(record: &DecodedXLogRecord) -> (u32, u32) {
@@ -115,8 +354,7 @@ fn xlog_rec_get_len(record: &DecodedXLogRecord) -> (u32, u32) {
         }
 
         if xlog_rec_has_block_image(record, blk_id) {
-            let blocks = record.blocks.as_ref().unwrap();
-            fpi_len += blocks[blk_id as usize].borrow().bimg_len as u32;
+            fpi_len += record.blocks[blk_id as usize].bimg_len as u32;
         }
     }
 
@@ -124,17 +362,37 @@ fn xlog_rec_get_len(record: &DecodedXLogRecord) -> (u32, u32) {
 }
 
 // Returns a string giving information about all the blocks in an
-// XLogRecord.
-fn xlog_rec_get_block_ref_info(state: &XLogReaderState) -> String {
+// XLogRecord: with detailed_format one line per block, each starting with a
+// tab, else on the line of the record, ended by a newline.
+fn xlog_rec_get_block_ref_info(state: &XLogReaderState, detailed_format: bool) -> String {
     let mut retval = String::new();
-    retval.push('\n');
+    if detailed_format {
+        retval.push('\n');
+    }
 
     let record = state.record.as_ref().unwrap();
-    let blocks = record.blocks.as_ref().unwrap();
 
     for bid in 0..=record.max_block_id {
-        if xlog_rec_has_block_ref(record, bid) {
-            let blk = blocks[bid as usize].borrow();
+        if xlog_rec_has_block_ref(record, bid) && !detailed_format {
+            // the short format, leaving out the main fork
+            let blk = &record.blocks[bid as usize];
+            retval += &format!(
+                ", blkref #{}: rel {}/{}/{}",
+                bid, blk.rlocator.spc_oid, blk.rlocator.db_oid, blk.rlocator.rel_oid
+            );
+            if blk.forknum as i32 != ForkNumber::Main as i32 {
+                retval += &format!(" fork {}", FORK_NAMES[blk.forknum as usize]);
+            }
+            retval += &format!(" blk {}", blk.blkno);
+            if xlog_rec_has_block_image(record, bid) {
+                if blk.apply_image {
+                    retval.push_str(" FPW");
+                } else {
+                    retval.push_str(" FPW for WAL verification");
+                }
+            }
+        } else if xlog_rec_has_block_ref(record, bid) {
+            let blk = &record.blocks[bid as usize];
 
             retval.push('\t');
             assert!(blk.forknum as i8 >= 0);
@@ -151,7 +409,6 @@ fn xlog_rec_get_block_ref_info(state: &XLogReaderState) -> String {
             retval.push_str(&s);
 
             if xlog_rec_has_block_image(record, bid) {
-                let blk = blocks[bid as usize].borrow();
                 // if fpi_len {
                 //     fpi_len += record.blocks[bid as usize].bimg_len;
                 // }
@@ -161,15 +418,18 @@ fn xlog_rec_get_block_ref_info(state: &XLogReaderState) -> String {
                     " for WAL verification"
                 };
 
-                let bimg_info = blk.bimg_info;
-                let s = if bkpimage_compressed(bimg_info) {
+                let method = BkpImageCompressMethod::from_bimg_info(
+                    blk.bimg_info,
+                    state.config.version,
+                );
+                let s = if let Some(method) = method {
                     format!(
                         " (FPW{}); hole: offset: {}, length: {}, compression saved: {}, method: {}",
                         target,
                         blk.hole_offset,
                         blk.hole_length,
                         (XLOG_BLCKSZ - blk.hole_length as u32 - blk.bimg_len as u32),
-                        BkpImageCompressMethod::from(bimg_info)
+                        method
                     )
                 } else {
                     format!(
@@ -182,10 +442,15 @@ fn xlog_rec_get_block_ref_info(state: &XLogReaderState) -> String {
             retval.push('\n');
         }
     }
+    if !detailed_format {
+        retval.push('\n');
+    }
     retval
 }
 
-fn xlog_show_record(state: &XLogReaderState) {
+// Print the record; upstream as pg_waldump does, with the blocks in detail
+// only with bkp_details.
+fn xlog_show_record(state: &XLogReaderState, upstream: bool, bkp_details: bool) {
     let record = state.record.as_ref().unwrap();
     let desc = get_rmgr_desc(record.header.xl_rmid);
     let info = record.header.xl_info;
@@ -203,8 +468,10 @@ fn xlog_show_record(state: &XLogReaderState) {
         lsn_out(xl_prev)
     );
 
-    let id = (desc.rm_identify)(info);
-    if id.len() == 0 {
+    let id = (desc.rm_identify)(info, state.config.version);
+    if id.is_empty() && upstream {
+        print!("desc: UNKNOWN ({:x}) ", info & !XLR_INFO_MASK);
+    } else if id.is_empty() {
         print!("desc UNKNOWN ({:X}) ", info & !XLR_INFO_MASK);
     } else {
         print!("desc: {} ", id);
@@ -213,230 +480,348 @@ fn xlog_show_record(state: &XLogReaderState) {
     let s = (desc.rm_desc)(state);
     print!("{}", s);
 
-    let s = xlog_rec_get_block_ref_info(&state);
-    println!("{}", s);
-}
-
-fn reset_decoder(state: &mut XLogReaderState) {
-    state.decode_queue.clear();
-}
-
-fn xlog_begin_read(state: &mut XLogReaderState, rec_ptr: XLogRecPtr) {
-    assert!(!xlog_recptr_is_invalid(rec_ptr));
-
-    reset_decoder(state);
-
-    // Begin at the passed-in record pointer.
-    state.end_recptr = rec_ptr;
-    state.next_recptr = rec_ptr;
-    state.read_recptr = INVALID_XLOG_RECPTR;
-    state.decode_recptr = INVALID_XLOG_RECPTR;
-}
-
-// Try to decode the next available record, and return it.  The record will
-// also be returned to XLogNextRecord(), which must be called to 'consume'
-// each record.
-//
-// If nonblocking is true, may return NULL due to lack of data or WAL decoding
-// space.
-
-fn xlog_read_ahead(state: &mut XLogReaderState) -> bool {
-    waldec::xlog_decode_next_record(state)
-}
-
-fn xlog_next_record(state: &mut XLogReaderState) {
-    state.record = state.decode_queue.pop_front();
-    if let Some(record) = &state.record {
-        state.read_recptr = record.lsn;
-        state.end_recptr = record.next_lsn;
-    }
-    state.cross_page_record_buf.clear();
-}
-
-// Attempt to read an XLOG record.
-//
-// XLogBeginRead() or XLogFindNextRecord() must be called before the first call
-// to XLogReadRecord().
-//
-// If the page_read callback fails to read the requested data, NULL is
-// returned.  The callback is expected to have reported the error; errormsg
-// is set to NULL.
-//
-// If the reading fails for some other reason, NULL is also returned, and
-// *errormsg is set to a string with details of the failure.
-//
-// The returned pointer (or *errormsg) points to an internal buffer that's
-// valid until the next call to XLogReadRecord.
-
-fn xlog_read_record(state: &mut XLogReaderState) -> bool {
-    if !xlog_read_ahead(state) {
-        return false;
+    if upstream {
+        print!("{}", xlog_rec_get_block_ref_info(state, bkp_details));
+    } else {
+        let s = xlog_rec_get_block_ref_info(&state, true);
+        println!("{}", s);
     }
-
-    // Consume the head record or error.
-    xlog_next_record(state);
-    return true;
 }
 
-fn xlog_find_next_record(state: &mut XLogReaderState) -> XLogRecPtr {
-    let rec_ptr = state.private_data.startptr;
-    assert!(!xlog_recptr_is_invalid(rec_ptr));
-
-    // skip over potential continuation data, keeping in mind that it may span
-    // multiple pages
-    let mut tmp_rec_ptr = rec_ptr;
-    loop {
-        let target_rec_off = page_offset(tmp_rec_ptr);
-        let target_page_ptr = page_addr(tmp_rec_ptr);
-
-        /* Read the page containing the record */
-        let read_len = waldec::read_page(state, target_page_ptr, target_rec_off);
-
-        let (_, header) = waldec::page_header(&state.read_buf).unwrap();
-
-        let page_header_size = waldec::xlog_page_header_size(&header);
-
-        // make sure we have enough data for the page header
-        // readLen = ReadPageInternal(state, targetPagePtr, pageHeaderSize);
-        // if (readLen < 0)
-        // 	goto err;
-
-        /* skip over potential continuation data */
-        if header.xlp_info & XLP_FIRST_IS_CONTRECORD != 0 {
-            // If the length of the remaining continuation data is more than
-            // what can fit in this page, the continuation record crosses over
-            // this page. Read the next page and try again. xlp_rem_len in the
-            // next page header will contain the remaining length of the
-            // continuation data
-            //
-            // Note that record headers are MAXALIGN'ed
-            if max_align(header.xlp_rem_len) >= (XLOG_BLCKSZ - page_header_size as u32) {
-                tmp_rec_ptr = target_page_ptr.saturating_add(XLOG_BLCKSZ as u64);
-            } else {
-                // The previous continuation record ends in this page. Set
-                // tmpRecPtr to point to the first valid record
-                tmp_rec_ptr = target_page_ptr
-                    + page_header_size as u64
-                    + max_align(header.xlp_rem_len) as u64;
-                break;
-            }
-        } else {
-            tmp_rec_ptr = target_page_ptr + page_header_size as u64;
-            break;
-        }
-    }
-
-    // we know now that tmpRecPtr is an address pointing to a valid XLogRecord
-    // because either we're at the first record after the beginning of a page
-    // or we just jumped over the remaining data of a continuation.
-    xlog_begin_read(state, tmp_rec_ptr);
-    while xlog_read_record(state) {
-        // past the record we've found, break out
-        if rec_ptr <= state.read_recptr {
-            // Rewind the reader to the beginning of the last record.
-            let found = state.read_recptr;
-            xlog_begin_read(state, found);
-            return found;
-        }
+// Hex dump the record part by part, as the decoder found the parts, with
+// their offsets from the start of the record.
+fn xlog_show_raw(state: &XLogReaderState) {
+    let record = state.record.as_ref().unwrap();
+    for span in &record.layout {
+        let bytes = &record.raw[span.off as usize..(span.off + span.len) as usize];
+        println!("\t{} (offset {}, {} bytes)", span.part, span.off, span.len);
+        print!("{}", hex_dump(bytes, span.off, "\t\t"));
     }
-
-    state.invalidate();
-
-    return INVALID_XLOG_RECPTR;
 }
 
 fn main() {
     let args = cli::Cli::new();
 
     let mut private = XLogDumpPrivate::default();
-    private.timeline = args.timeline.unwrap();
+    private.timeline = args.timeline.unwrap_or(1);
+
+    let mut waldirs: Vec<PathBuf> = Vec::new();
+    let mut config = WalConfig::default();
+    let mut archive = None;
+    let mut stream = None;
 
-    let mut waldir = std::path::PathBuf::new();
-    if let Some(path) = args.path {
-        waldir = path;
+    let control = args.pgdata.as_ref().map(|pgdata| {
+        match ControlFileData::read(pgdata) {
+            Ok(control) => control,
+            Err(e) => panic!("{}", e),
+        }
+    });
+    if let Some(control) = &control {
+        if args.controldata.unwrap_or(false) {
+            print!("{}", control.describe());
+            return;
+        }
+        waldirs = vec![args.pgdata.clone().unwrap()];
+    }
+    if !args.path.is_empty() {
+        waldirs = args.path.clone();
+    }
+    if let Some(path) = waldirs.iter().find(|p| is_tar_archive(p)) {
+        if waldirs.len() > 1 {
+            panic!("tar archive {} cannot be given with other --path locations", path.display());
+        }
+        match TarArchive::open(path) {
+            Ok(a) => archive = Some(a),
+            Err(e) => panic!("could not read archive {}: {}", path.display(), e),
+        }
+        waldirs.clear();
     }
 
-    if let Some(startseg) = args.startseg {
+    let mut restore = args.restore_command.as_ref().map(|command| {
+        if archive.is_some() {
+            panic!("--restore-command cannot be used with a tar archive");
+        }
+        match RestoreCommand::new(command) {
+            Ok(restore) => restore,
+            Err(e) => panic!("{}", e),
+        }
+    });
+
+    if let Some(conninfo) = &args.dbname {
+        #[cfg(not(feature = "replication"))]
+        panic!("--dbname needs the replication feature");
+        #[cfg(feature = "replication")]
+        {
+            let source;
+            (config, source) = connect_stream(conninfo, &args, &mut private);
+            stream = Some(Box::new(source) as Box<dyn WalSource>);
+        }
+    } else if args.startseg.as_deref() == Some(Path::new("-")) {
+        if args.endseg.is_some() {
+            panic!("ENDSEG cannot be given when reading from standard input");
+        }
+        let (c, tli, source) = read_stdin_segment(args.pg_version);
+        config = c;
+        if args.timeline.is_none() {
+            private.timeline = tli;
+        }
+        let segstart = source.startptr();
+        private.startptr = match args.start {
+            Some(start) if start < segstart => panic!(
+                "start WAL location {} is before the WAL on standard input, which starts at {}",
+                lsn_out(start),
+                lsn_out(segstart)
+            ),
+            Some(start) => start,
+            None => segstart,
+        };
+        private.endptr = args.end.unwrap_or(INVALID_XLOG_RECPTR);
+        stream = Some(Box::new(source));
+    } else if let Some(startseg) = &args.startseg {
         let mut segno: XLogSegNo = 0;
 
         let fname = startseg.file_name().unwrap().into();
         if let Some(dir) = startseg.parent() {
-            if waldir.as_os_str().is_empty() {
-                waldir = dir.to_path_buf();
+            if waldirs.is_empty() && archive.is_none() && !dir.as_os_str().is_empty() {
+                waldirs = vec![dir.to_path_buf()];
             }
         }
-        waldir = identify_target_directory(waldir, &fname);
-        // println!("Bytes per WAL segment: {}", waldec::get_wal_seg_sz());
+        (waldirs, config) = match &archive {
+            Some(a) => {
+                let (dir, config) = identify_target_archive(a, &fname, args.pg_version);
+                (vec![dir], config)
+            }
+            None => match locate_target_directory(&waldirs, &fname, args.pg_version) {
+                Some(found) => found,
+                None => match restore.as_mut() {
+                    Some(r) => (
+                        restored_waldirs(waldirs),
+                        identify_restored(r, &fname, args.pg_version),
+                    ),
+                    None => panic!("could not locate WAL file {}", fname.display()),
+                },
+            },
+        };
+        // println!("Bytes per WAL segment: {}", config.wal_seg_sz);
 
         // parse position from file
-        xlog_from_file_name(
-            &fname,
-            &mut private.timeline,
-            &mut segno,
-            waldec::get_wal_seg_sz(),
-        );
+        let mut tli: TimeLineID = 0;
+        xlog_from_file_name(&fname, &mut tli, &mut segno, config.wal_seg_sz);
+        if args.timeline.is_none() {
+            private.timeline = tli;
+        }
 
         match args.start {
             Some(start) => {
-                if start / waldec::get_wal_seg_sz() as u64 != segno {
-                    panic!("start WAL location {} is not in file {}", start, segno);
+                if byte_to_seg(start, config.wal_seg_sz) != segno {
+                    panic!(
+                        "start WAL location {} is not inside file \"{}\"",
+                        lsn_out(start),
+                        fname.display()
+                    );
                 }
                 private.startptr = start;
             }
             None => {
-                private.startptr = segno * waldec::get_wal_seg_sz() as u64;
+                private.startptr = segno * config.wal_seg_sz as u64;
             }
         }
 
-        if let Some(endseg) = args.endseg {
+        if let Some(endseg) = &args.endseg {
             let fname: PathBuf = endseg.file_name().unwrap().into();
-            let fpath = PathBuf::from(&waldir).join(&fname);
-            if std::fs::File::open(fpath).is_err() {
+            let found = match &archive {
+                Some(a) => a.contains(&waldirs[0], &fname),
+                None => {
+                    waldirs.iter().any(|dir| find_segment(dir, &fname).is_some())
+                        || restore.is_some()
+                }
+            };
+            if !found {
                 panic!("could not open file {}", endseg.display());
             }
 
             let mut endsegno: XLogSegNo = 0;
-            xlog_from_file_name(
-                &fname,
-                &mut private.timeline,
-                &mut endsegno,
-                waldec::get_wal_seg_sz(),
-            );
+            xlog_from_file_name(&fname, &mut tli, &mut endsegno, config.wal_seg_sz);
+            if args.timeline.is_none() {
+                private.timeline = tli;
+            }
             if endsegno < segno {
-                panic!("ENDSEG {} is before STARTSEG {}", endsegno, segno);
+                panic!(
+                    "ENDSEG {} is before STARTSEG {}",
+                    endseg.display(),
+                    startseg.display()
+                );
             }
             match args.end {
                 Some(end) => {
-                    if end / waldec::get_wal_seg_sz() as u64 != endsegno {
+                    // the end of the last record may be the end of the file
+                    if end != (endsegno + 1) * config.wal_seg_sz as u64
+                        && byte_to_seg(end, config.wal_seg_sz) != endsegno
+                    {
                         panic!(
-                            "end WAL location {} is not in file {}",
-                            end,
+                            "end WAL location {} is not inside file \"{}\"",
+                            lsn_out(end),
                             fname.display()
                         );
                     }
                     private.endptr = end;
                 }
                 None => {
-                    private.endptr = (endsegno + 1) * waldec::get_wal_seg_sz() as u64;
+                    private.endptr = (endsegno + 1) * config.wal_seg_sz as u64;
                 }
             }
+        } else if args.end.is_none() && args.compat == Some(cli::Compat::Upstream) {
+            // pg_waldump stops at the end of the only file given
+            private.endptr = (segno + 1) * config.wal_seg_sz as u64;
         }
     } else {
-        waldir = identify_target_directory(waldir, &PathBuf::new());
+        let found = match &archive {
+            Some(a) => {
+                let (dir, config) = identify_target_archive(a, &PathBuf::new(), args.pg_version);
+                Some((vec![dir], config))
+            }
+            None => locate_target_directory(&waldirs, &PathBuf::new(), args.pg_version),
+        };
+        (waldirs, config) = match (found, restore.as_mut(), args.start) {
+            (Some(found), _, _) => found,
+            (None, Some(r), Some(start)) => (
+                restored_waldirs(waldirs),
+                identify_restored_start(r, start, private.timeline, args.pg_version),
+            ),
+            (None, _, _) => panic!("could not find any WAL file"),
+        };
+
+        // Only locations given: the segment files to read are named after
+        // them, on the timeline asked for or else the newest one the start
+        // segment is found on.
+        if let Some(start) = args.start {
+            if args.timeline.is_none() {
+                let names = directory_names(archive.as_ref(), &waldirs);
+                match newest_timeline_at(&names, start, config.wal_seg_sz) {
+                    Some(tli) => private.timeline = tli,
+                    // restored on the default timeline
+                    None if restore.is_some() => {}
+                    None => panic!(
+                        "could not find WAL file holding start WAL location {} in \"{}\"",
+                        lsn_out(start),
+                        waldirs[0].display()
+                    ),
+                }
+            }
+            private.startptr = start;
+            if let Some(end) = args.end {
+                if end <= start {
+                    panic!(
+                        "end WAL location {} is not after start WAL location {}",
+                        lsn_out(end),
+                        lsn_out(start)
+                    );
+                }
+                private.endptr = end;
+            }
+        }
+    }
+
+    // The WAL must come from the cluster of --pgdata, which also tells
+    // where its latest checkpoint is.
+    if let Some(control) = &control {
+        if config.system_identifier != control.system_identifier {
+            panic!(
+                "WAL file is from different database system: WAL file database system identifier is {}, pg_control database system identifier is {}",
+                config.system_identifier, control.system_identifier
+            );
+        }
+        config.system_identifier_source = SystemIdentifierSource::PgControl;
+        let ckpt = &control.check_point_copy;
+        if args.from_checkpoint.unwrap_or(false) {
+            private.startptr = control.check_point;
+        } else if args.from_redo.unwrap_or(false) {
+            private.startptr = ckpt.redo;
+        }
+        if args.timeline.is_none() && args.startseg.is_none() && stream.is_none() {
+            private.timeline = ckpt.time_line_id;
+        }
+    }
+
+    // --sqlite carries on after the records in the database
+    let mut resumed = false;
+    #[cfg(feature = "sqlite")]
+    if let (XLOG_INVALID_RECPTR, None, Some(path)) = (private.startptr, &stream, &args.sqlite) {
+        if let Some((end, tli)) = resume_point(path).unwrap_or_else(|e| panic!("{}", e)) {
+            private.startptr = end;
+            if args.timeline.is_none() {
+                private.timeline = tli;
+            }
+            private.endptr = args.end.unwrap_or(INVALID_XLOG_RECPTR);
+            resumed = true;
+        }
     }
 
     if private.startptr == XLOG_INVALID_RECPTR {
-        panic!("no start WAL location given");
+        if stream.is_some() {
+            panic!("no start WAL location given");
+        }
+        let mut output = Output::new(&args, &config);
+        dump_directory(&args, archive, waldirs, config, &private, &mut output);
+        output.finish();
+        return;
+    }
+
+    // follow the timelines leading to the one asked for; streamed WAL
+    // comes in timeline order already
+    if stream.is_none() {
+        let history = read_timeline_history(private.timeline, |fname| match &archive {
+            Some(a) => a.open_segment(&waldirs[0], fname),
+            None => open_in_dirs(&waldirs, fname).or_else(|e| match restore.as_mut() {
+                Some(r) => r
+                    .fetch(fname.to_str().unwrap())
+                    .and_then(|path| SegmentFile::open(&path)),
+                None => Err(e),
+            }),
+        });
+        private.history = match history {
+            Ok(history) => history,
+            Err(e) => panic!("{}", e),
+        };
     }
 
-    let mut xlogreader_state =
-        XLogReaderState::new(waldec::get_wal_seg_sz(), waldir, private.clone());
+    let source: Box<dyn WalSource> = match (stream, archive) {
+        (Some(s), _) => s,
+        (None, Some(a)) => Box::new(TarSource::new(a, waldirs.remove(0), config.wal_seg_sz)),
+        (None, None) => {
+            let source = DirectorySource::from_dirs(waldirs, config.wal_seg_sz);
+            match restore.take() {
+                Some(r) => Box::new(source.with_restore_command(r)),
+                None => Box::new(source),
+            }
+        }
+    };
+    let mut output = Output::new(&args, &config);
+    let mut xlogreader_state = XLogReaderState::new(config, source, private.clone());
+    xlogreader_state.keep_raw = output.raw;
+    xlogreader_state.upstream = output.upstream;
     let first_record = xlog_find_next_record(&mut xlogreader_state);
 
+    if first_record == INVALID_XLOG_RECPTR && resumed {
+        output.message(&format!(
+            "no new records after {}",
+            waldec::lsn_out(private.startptr)
+        ));
+        output.finish();
+        return;
+    }
     if first_record == INVALID_XLOG_RECPTR {
+        // pg_waldump leaves out why
+        if output.upstream || xlogreader_state.errmsg.is_empty() {
+            panic!(
+                "could not find a valid record after {}",
+                waldec::lsn_out(private.startptr)
+            );
+        }
         panic!(
-            "could not find a valid record after {}",
-            waldec::lsn_out(private.startptr.clone())
+            "could not find a valid record after {}: {}",
+            waldec::lsn_out(private.startptr),
+            xlogreader_state.errmsg
         );
     }
 
@@ -444,43 +829,489 @@ fn main() {
     // to the start of a record and also wasn't a pointer to the beginning of
     // a segment (e.g. we were used in file mode).
     if first_record != private.startptr
-        && xlog_segment_offset(private.startptr, waldec::get_wal_seg_sz()) != 0
+        && xlog_segment_offset(private.startptr, config.wal_seg_sz) != 0
     {
-        println!(
-            "first record is after {}, at {}, skipping over {} byte(s)",
-            waldec::lsn_out(private.startptr),
-            waldec::lsn_out(first_record),
-            (first_record - private.startptr) as u32
-        );
+        let skipped = (first_record - private.startptr) as u32;
+        output.message(&if output.upstream {
+            format!(
+                "first record is after {}, at {}, skipping over {} byte{}",
+                waldec::lsn_format(private.startptr),
+                waldec::lsn_format(first_record),
+                skipped,
+                if skipped == 1 { "" } else { "s" }
+            )
+        } else {
+            format!(
+                "first record is after {}, at {}, skipping over {} byte(s)",
+                waldec::lsn_out(private.startptr),
+                waldec::lsn_out(first_record),
+                skipped
+            )
+        });
     }
 
     let mut records_displayed: u32 = 0;
+    output.start(first_record);
+    dump_records(&mut xlogreader_state, &args, &mut records_displayed, &mut output);
+    output.finish();
+}
+
+// Display the records from where the reader is until the WAL ends or the
+// limit is reached, counting them in records_displayed.  Returns false if
+// the limit was reached.
+fn dump_records(
+    xlogreader_state: &mut XLogReaderState,
+    args: &cli::Cli,
+    records_displayed: &mut u32,
+    output: &mut Output,
+) -> bool {
     loop {
-        if !xlog_read_record(&mut xlogreader_state) {
+        if *records_displayed >= args.limit.unwrap_or(u32::MAX) {
+            return false;
+        }
+        if !xlog_read_record(xlogreader_state) {
             break;
         }
-        if let Some(rmgr) = args.rmgr.as_ref() {
-            if 0 == rmgr[xlogreader_state.record.as_ref().unwrap().header.xl_rmid as usize] {
+        if !args.rmgr.is_empty() {
+            let rmid = xlogreader_state.record.as_ref().unwrap().header.xl_rmid as usize;
+            if args.rmgr.iter().all(|rmgr| rmgr[rmid] == 0) {
                 continue;
             }
         }
 
-        if let Some(quiet) = args.quiet {
-            if !quiet {
-                xlog_show_record(&xlogreader_state);
-            }
-        }
-        records_displayed += 1;
-        if records_displayed >= args.limit.unwrap_or(u32::MAX) {
-            break;
-        }
+        output.record(xlogreader_state);
+        *records_displayed += 1;
     }
     if xlogreader_state.errmsg.len() > 0 {
-        println!(
+        let lsn = if output.upstream {
+            waldec::lsn_format
+        } else {
+            waldec::lsn_out
+        };
+        output.error(&format!(
             "error in WAL record at {}: {}",
-            waldec::lsn_out(xlogreader_state.read_recptr),
+            lsn(xlogreader_state.read_recptr),
             xlogreader_state.errmsg
-        );
+        ));
+    }
+    true
+}
+
+// Where the records go: printed as text, JSON objects or table rows, or
+// counted for --stats.
+struct Output {
+    format: cli::OutputFormat,
+    quiet: bool,
+    // the statistics and whether they are per record
+    stats: Option<(XLogStats, bool)>,
+    // the rows for the block references, with --blocks-file
+    blocks: Option<BufWriter<std::fs::File>>,
+    template: Option<Template>,
+    // hex dump each record after it
+    raw: bool,
+    // print as pg_waldump does, with -b's detailed block references
+    upstream: bool,
+    bkp_details: bool,
+    #[cfg(feature = "parquet")]
+    parquet: Option<ParquetWriter<std::fs::File>>,
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<SqliteWriter>,
+    // the report and the file to write it to, with --html
+    report: Option<(HtmlReport, PathBuf)>,
+    version: PgVersion,
+    written: u64,
+    // errors wait for the statistics, as in pg_waldump
+    errors: Vec<String>,
+}
+
+impl Output {
+    fn new(args: &cli::Cli, config: &WalConfig) -> Output {
+        let mut output = Output {
+            format: args.format,
+            quiet: args.quiet.unwrap_or(false),
+            stats: args
+                .stats
+                .as_ref()
+                .map(|per| (XLogStats::default(), per.as_deref() == Some("record"))),
+            blocks: None,
+            template: None,
+            raw: args.raw.unwrap_or(false),
+            upstream: args.compat == Some(cli::Compat::Upstream),
+            bkp_details: args.bkp_details.unwrap_or(false),
+            #[cfg(feature = "parquet")]
+            parquet: None,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+            report: None,
+            version: config.version,
+            written: 0,
+            errors: Vec::new(),
+        };
+        let table = output.table_format();
+        if table.is_some() && output.stats.is_some() {
+            panic!("--stats cannot be written as csv or copy");
+        }
+        if let Some(path) = &args.blocks_file {
+            let Some(table) = table else {
+                panic!("--blocks-file needs --format=csv or copy");
+            };
+            let file = std::fs::File::create(path).unwrap_or_else(|e| {
+                panic!("could not create file \"{}\": {}", path.display(), e)
+            });
+            let mut blocks = BufWriter::new(file);
+            writeln!(blocks, "{}", format_header(table, BLOCK_COLUMNS)).unwrap();
+            output.blocks = Some(blocks);
+        }
+        if let (Some(table), false) = (table, output.quiet) {
+            println!("{}", format_header(table, RECORD_COLUMNS));
+        }
+        #[cfg(feature = "parquet")]
+        if output.format == cli::OutputFormat::Parquet {
+            if output.stats.is_some() {
+                panic!("--stats cannot be written as parquet");
+            }
+            let Some(path) = &args.output else {
+                panic!("--format=parquet needs --output");
+            };
+            let file = std::fs::File::create(path).unwrap_or_else(|e| {
+                panic!("could not create file \"{}\": {}", path.display(), e)
+            });
+            output.parquet = Some(ParquetWriter::new(file).unwrap_or_else(|e| panic!("{}", e)));
+        }
+        if let Some(template) = &args.template {
+            if output.format != cli::OutputFormat::Text {
+                panic!("--template is only for --format=text");
+            }
+            output.template = Some(Template::parse(template).unwrap_or_else(|e| panic!("{}", e)));
+        }
+        if output.raw && output.format != cli::OutputFormat::Text {
+            panic!("--raw is only for --format=text");
+        }
+        if output.upstream
+            && (output.format != cli::OutputFormat::Text
+                || output.template.is_some()
+                || output.raw)
+        {
+            panic!("--compat=upstream is only for --format=text, without --template or --raw");
+        }
+        if output.upstream && !PgVersion::COMPAT_CHECKED.contains(&config.version) {
+            panic!(
+                "--compat=upstream is only checked against pg_waldump {}, not {}",
+                PgVersion::COMPAT_CHECKED.map(|v| v.to_string()).join(", "),
+                config.version
+            );
+        }
+        if let Some(path) = &args.sqlite {
+            #[cfg(not(feature = "sqlite"))]
+            panic!("--sqlite needs the sqlite feature");
+            #[cfg(feature = "sqlite")]
+            {
+                if output.stats.is_some()
+                    || output.format != cli::OutputFormat::Text
+                    || output.template.is_some()
+                    || output.raw
+                    || output.upstream
+                {
+                    panic!("--sqlite cannot be used with --stats, --format, --template, --raw or --compat");
+                }
+                output.sqlite = Some(
+                    SqliteWriter::open(path, config.system_identifier, config.wal_seg_sz)
+                        .unwrap_or_else(|e| panic!("{}", e)),
+                );
+            }
+        }
+        if let Some(path) = &args.html {
+            if output.stats.is_some()
+                || output.format != cli::OutputFormat::Text
+                || output.template.is_some()
+                || output.raw
+                || output.upstream
+                || args.sqlite.is_some()
+            {
+                panic!("--html cannot be used with --stats, --format, --template, --raw, --compat or --sqlite");
+            }
+            let report = HtmlReport::new(config.version, config.system_identifier, config.wal_seg_sz);
+            output.report = Some((report, path.clone()));
+        }
+        if args.output.is_some() && output.writes_to_stdout() {
+            panic!("--output is only for --format=parquet");
+        }
+        output
+    }
+
+    // whether the records go to stdout
+    fn writes_to_stdout(&self) -> bool {
+        if self.report.is_some() {
+            return false;
+        }
+        #[cfg(feature = "sqlite")]
+        if self.sqlite.is_some() {
+            return false;
+        }
+        #[cfg(feature = "parquet")]
+        if self.format == cli::OutputFormat::Parquet {
+            return false;
+        }
+        true
+    }
+
+    fn table_format(&self) -> Option<TableFormat> {
+        match self.format {
+            cli::OutputFormat::Csv => Some(TableFormat::Csv),
+            cli::OutputFormat::Copy => Some(TableFormat::Copy),
+            _ => None,
+        }
+    }
+
+    // The statistics start at the first record found.
+    fn start(&mut self, first_record: XLogRecPtr) {
+        if let Some((report, _)) = &mut self.report {
+            report.start(first_record);
+        }
+        if let Some((stats, _)) = &mut self.stats {
+            if stats.startptr == INVALID_XLOG_RECPTR {
+                stats.startptr = first_record;
+            }
+        }
+    }
+
+    fn record(&mut self, state: &XLogReaderState) {
+        if let Some((stats, _)) = &mut self.stats {
+            stats.store(&RecordSummary::from_state(state));
+            return;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(writer) = &mut self.sqlite {
+            writer.write(state).unwrap_or_else(|e| panic!("{}", e));
+            self.written += 1;
+            return;
+        }
+        if let Some((report, _)) = &mut self.report {
+            report.add(state);
+            self.written += 1;
+            return;
+        }
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            cli::OutputFormat::Text => {
+                match &self.template {
+                    Some(template) => {
+                        println!("{}", template.render(&RecordSummary::from_state(state)))
+                    }
+                    None => xlog_show_record(state, self.upstream, self.bkp_details),
+                }
+                if self.raw {
+                    xlog_show_raw(state);
+                }
+            }
+            #[cfg(feature = "serde")]
+            cli::OutputFormat::Json => {
+                let sep = if self.written == 0 { "[" } else { "," };
+                let record = RecordSummary::from_state(state);
+                println!("{}\n{}", sep, serde_json::to_string(&record).unwrap());
+            }
+            #[cfg(feature = "serde")]
+            cli::OutputFormat::Ndjson => {
+                let record = RecordSummary::from_state(state);
+                println!("{}", serde_json::to_string(&record).unwrap());
+            }
+            cli::OutputFormat::Csv | cli::OutputFormat::Copy => {
+                let table = self.table_format().unwrap();
+                let record = RecordSummary::from_state(state);
+                println!("{}", format_row(table, &record_row(&record)));
+                if let Some(blocks) = &mut self.blocks {
+                    for row in block_rows(&record) {
+                        writeln!(blocks, "{}", format_row(table, &row)).unwrap();
+                    }
+                }
+            }
+            #[cfg(feature = "parquet")]
+            cli::OutputFormat::Parquet => {
+                let record = RecordSummary::from_state(state);
+                let writer = self.parquet.as_mut().unwrap();
+                writer.write(&record).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+        self.written += 1;
+    }
+
+    // Notes in between the records, kept out of structured output.
+    fn message(&self, msg: &str) {
+        match self.format {
+            cli::OutputFormat::Text if self.writes_to_stdout() => println!("{}", msg),
+            _ => eprintln!("pg_waldump: {}", msg),
+        }
+    }
+
+    fn error(&mut self, msg: &str) {
+        if let Some((report, _)) = &mut self.report {
+            report.error(msg);
+        }
+        if self.stats.is_some() || self.upstream {
+            self.errors.push(msg.to_string());
+        } else {
+            self.message(msg);
+        }
+    }
+
+    fn finish(mut self) {
+        if let Some(blocks) = &mut self.blocks {
+            blocks.flush().unwrap();
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(writer) = self.parquet.take() {
+            writer.finish().unwrap_or_else(|e| panic!("{}", e));
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(writer) = self.sqlite.take() {
+            writer.finish().unwrap_or_else(|e| panic!("{}", e));
+        }
+        if let Some((report, path)) = self.report.take() {
+            std::fs::write(&path, report.render()).unwrap_or_else(|e| {
+                panic!("could not write file \"{}\": {}", path.display(), e)
+            });
+        }
+        if let Some((stats, per_record)) = &self.stats {
+            if !self.quiet {
+                match self.format {
+                    cli::OutputFormat::Text => print!("{}", stats.display(*per_record, self.version)),
+                    #[cfg(feature = "serde")]
+                    cli::OutputFormat::Json => {
+                        let json = stats.to_json(*per_record, self.version);
+                        println!("{}", serde_json::to_string(&json).unwrap())
+                    }
+                    #[cfg(feature = "serde")]
+                    cli::OutputFormat::Ndjson => {
+                        for row in stats.json_rows(*per_record, self.version) {
+                            println!("{}", serde_json::to_string(&row).unwrap());
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        #[cfg(feature = "serde")]
+        if self.stats.is_none() && self.format == cli::OutputFormat::Json && !self.quiet {
+            println!("{}", if self.written == 0 { "[]" } else { "]" });
+        }
+        // pg_waldump fails with the error
+        if self.upstream && !self.errors.is_empty() {
+            std::io::stdout().flush().unwrap();
+            for msg in &self.errors {
+                eprintln!("pg_waldump: error: {}", msg);
+            }
+            std::process::exit(1);
+        }
+        for msg in &self.errors {
+            self.message(msg);
+        }
+    }
+}
+
+// The WAL directories when the segments are restored rather than found:
+// those given, if any, as segments there are still read first.
+fn restored_waldirs(waldirs: Vec<PathBuf>) -> Vec<PathBuf> {
+    if waldirs.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        waldirs.iter().map(|d| wal_directory(d)).collect()
+    }
+}
+
+// The names of the files in the WAL directories, or in the directory within
+// archive.
+fn directory_names(archive: Option<&TarArchive>, waldirs: &[PathBuf]) -> Vec<String> {
+    if let Some(a) = archive {
+        return a.segments(&waldirs[0]).iter().map(|n| n.to_string()).collect();
+    }
+    let mut names = Vec::new();
+    for waldir in waldirs {
+        match std::fs::read_dir(waldir) {
+            Ok(entries) => names.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().into_owned()),
+            ),
+            Err(e) => panic!("could not open directory \"{}\": {}", waldir.display(), e),
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+// The newest timeline on which the segment holding ptr is in the WAL
+// directory, if any.
+fn newest_timeline_at(names: &[String], ptr: XLogRecPtr, segsize: u32) -> Option<TimeLineID> {
+    let segno = byte_to_seg(ptr, segsize);
+    names
+        .iter()
+        .map(|name| PathBuf::from(split_compression(name).0))
+        .filter(is_xlog_filename)
+        .filter_map(|name| {
+            let mut tli: TimeLineID = 0;
+            let mut n: XLogSegNo = 0;
+            xlog_from_file_name(&name, &mut tli, &mut n, segsize);
+            (n == segno).then_some(tli)
+        })
+        .max()
+}
+
+// Without a start location, dump every segment in the WAL directories
+// (within the archive, if there is one), run after run of consecutive
+// segments.  Missing, duplicate and mismatched segments are warned about.
+fn dump_directory(
+    args: &cli::Cli,
+    archive: Option<TarArchive>,
+    waldirs: Vec<PathBuf>,
+    config: WalConfig,
+    private: &XLogDumpPrivate,
+    output: &mut Output,
+) {
+    let names = directory_names(archive.as_ref(), &waldirs);
+    let scan = scan_segments(&names, &config, |name| match &archive {
+        Some(a) => a.open_segment(&waldirs[0], Path::new(name)),
+        None => open_valid_in_dirs(&waldirs, name, config.wal_seg_sz),
+    });
+    for warning in &scan.warnings {
+        eprintln!("pg_waldump: warning: {}", warning);
+    }
+
+    let segsize = config.wal_seg_sz as u64;
+    let history = scan.history(config.wal_seg_sz);
+    let mut source: Option<Box<dyn WalSource>> = match archive {
+        Some(a) => Some(Box::new(TarSource::new(a, waldirs[0].clone(), config.wal_seg_sz))),
+        None => Some(Box::new(DirectorySource::from_dirs(waldirs, config.wal_seg_sz))),
+    };
+    let mut records_displayed: u32 = 0;
+    for run in scan.runs(config.wal_seg_sz) {
+        let last = &run[run.len() - 1];
+        let mut private = private.clone();
+        private.startptr = run[0].segno * segsize;
+        private.endptr = last.segno * segsize + last.size;
+        if let Some(end) = args.end {
+            if end <= private.startptr {
+                break;
+            }
+            private.endptr = private.endptr.min(end);
+        }
+        private.timeline = run[0].tli;
+        private.history = history.clone();
+
+        let mut xlogreader_state = XLogReaderState::new(config, source.take().unwrap(), private);
+        xlogreader_state.keep_raw = output.raw;
+        xlogreader_state.upstream = output.upstream;
+        // a run may hold nothing but the end of a record from before it
+        let first_record = xlog_find_next_record(&mut xlogreader_state);
+        if first_record != INVALID_XLOG_RECPTR {
+            output.start(first_record);
+            if !dump_records(&mut xlogreader_state, args, &mut records_displayed, output) {
+                break;
+            }
+        }
+        source = xlogreader_state.source.take();
     }
 }
 
@@ -507,7 +1338,7 @@ mod tests {
             &mut segno,
             16 * 1024 * 1024,
         );
-        assert_eq!(tli, 1);
-        assert_eq!(segno, 0x40001);
+        assert_eq!(tli, 2);
+        assert_eq!(segno, 0x100 + 1);
     }
 }
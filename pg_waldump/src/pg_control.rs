@@ -1,11 +1,278 @@
 use crate::pgtypes::*;
+use crate::util::{crc32c, xlog_byte_to_seg, xlog_filename};
+use crate::version::PgVersion;
+use crate::waldec::lsn_format;
+use crate::xlog::WalLevel;
+use std::path::Path;
 
 // Version identifier for this pg_control format
 pub const PG_CONTROL_VERSION: u64 = 1300;
+// PostgreSQL 17 only added wal_level to the checkpoint, in what used to be
+// padding, so the layout is otherwise the same
+pub const PG_CONTROL_VERSION_17: u64 = 1700;
 
 // Nonce key length, see below
 pub const MOCK_AUTH_NONCE_LEN: u64 = 32;
 
+// Physical size of the pg_control file.  Note that this is considerably
+// bigger than the actually used size (ie, sizeof(ControlFileData)).
+pub const PG_CONTROL_FILE_SIZE: usize = 8192;
+
+// path of the control file within the data directory
+pub const XLOG_CONTROL_FILE: &str = "global/pg_control";
+
+// System status indicator.  Note this is stored in pg_control; if you change
+// it, you must bump PG_CONTROL_VERSION
+pub const DB_STARTUP: i32 = 0;
+pub const DB_SHUTDOWNED: i32 = 1;
+pub const DB_SHUTDOWNED_IN_RECOVERY: i32 = 2;
+pub const DB_SHUTDOWNING: i32 = 3;
+pub const DB_IN_CRASH_RECOVERY: i32 = 4;
+pub const DB_IN_ARCHIVE_RECOVERY: i32 = 5;
+pub const DB_IN_PRODUCTION: i32 = 6;
+
+// Contents of pg_control.
+#[repr(C)]
+pub struct ControlFileData {
+    // Unique system identifier --- to ensure we match up xlog files with the
+    // installation that produced them.
+    pub system_identifier: u64,
+
+    // Version identifier information.  Keep these fields at the same offset,
+    // especially pg_control_version; they won't be real useful if they move
+    // around.  (For historical reasons they must be 8 bytes into the file
+    // rather than immediately at the front.)
+    pub pg_control_version: u32, // PG_CONTROL_VERSION
+    pub catalog_version_no: u32, // see catversion.h
+
+    // System status data
+    pub state: i32,                  // see enum above
+    pub time: PgTime,                // time stamp of last pg_control update
+    pub check_point: XLogRecPtr,     // last check point record ptr
+    pub check_point_copy: CheckPoint, // copy of last check point record
+
+    pub unlogged_lsn: XLogRecPtr, // current fake LSN value, for unlogged rels
+
+    // These two values determine the minimum point we must recover up to
+    // before starting up.
+    pub min_recovery_point: XLogRecPtr,
+    pub min_recovery_point_tli: TimeLineID,
+    pub backup_start_point: XLogRecPtr,
+    pub backup_end_point: XLogRecPtr,
+    pub backup_end_required: bool,
+
+    // Parameter settings that determine if the WAL can be used for archival
+    // or hot standby.
+    pub wal_level: i32,
+    pub wal_log_hints: bool,
+    pub max_connections: i32,
+    pub max_worker_processes: i32,
+    pub max_wal_senders: i32,
+    pub max_prepared_xacts: i32,
+    pub max_locks_per_xact: i32,
+    pub track_commit_timestamp: bool,
+
+    // This data is used to check for hardware-architecture compatibility of
+    // the database and the backend executable.
+    pub max_align: u32,    // alignment requirement for tuples
+    pub float_format: f64, // constant 1234567.0
+
+    pub blcksz: u32,      // data block size for this DB
+    pub relseg_size: u32, // blocks per segment of large relation
+
+    pub xlog_blcksz: u32,   // block size within WAL files
+    pub xlog_seg_size: u32, // size of each WAL segment
+
+    pub name_data_len: u32,  // catalog name field width
+    pub index_max_keys: u32, // max number of columns in an index
+
+    pub toast_max_chunk_size: u32, // chunk size in TOAST tables
+    pub loblksize: u32,            // chunk size in pg_largeobject
+
+    pub float8_by_val: bool, // float8, int8, etc pass-by-value?
+
+    // Are data pages protected by checksums? Zero if no checksum version
+    pub data_checksum_version: u32,
+
+    // Random nonce, used in authentication requests that need to proceed
+    // based on values that are cluster-unique, like a SASL exchange that
+    // failed at an early stage.
+    pub mock_authentication_nonce: [u8; MOCK_AUTH_NONCE_LEN as usize],
+
+    // CRC of all above ... MUST BE LAST!
+    pub crc: PgCrc32c,
+}
+
+impl ControlFileData {
+    // Parse the contents of a pg_control file, checking its version and
+    // CRC.
+    pub fn from_bytes(buf: &[u8]) -> Result<ControlFileData, String> {
+        let size = std::mem::size_of::<ControlFileData>();
+        if buf.len() < size {
+            return Err(format!(
+                "could not read pg_control: read {} of {} bytes",
+                buf.len(),
+                size
+            ));
+        }
+
+        let version = u32::from_ne_bytes(buf[8..12].try_into().unwrap()) as u64;
+        if version.is_multiple_of(65536) && version / 65536 != 0 {
+            return Err(String::from(
+                "possible byte ordering mismatch: the byte ordering used to store \
+                 the pg_control file might not match the one used by this program",
+            ));
+        }
+        if version != PG_CONTROL_VERSION && version != PG_CONTROL_VERSION_17 {
+            return Err(format!(
+                "pg_control version {} is not supported, expected {} or {}",
+                version, PG_CONTROL_VERSION, PG_CONTROL_VERSION_17
+            ));
+        }
+
+        let crc_off = std::mem::offset_of!(ControlFileData, crc);
+        let crc = crc32c(&buf[..crc_off]);
+        let stored = u32::from_ne_bytes(buf[crc_off..crc_off + 4].try_into().unwrap());
+        if crc != stored {
+            return Err(String::from(
+                "calculated CRC checksum does not match value stored in pg_control",
+            ));
+        }
+
+        // the version and the CRC check out, so this is a real control file
+        Ok(unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const ControlFileData) })
+    }
+
+    // Read global/pg_control of the data directory pgdata.
+    pub fn read(pgdata: &Path) -> Result<ControlFileData, String> {
+        let path = pgdata.join(XLOG_CONTROL_FILE);
+        let buf = std::fs::read(&path)
+            .map_err(|e| format!("could not open file \"{}\": {}", path.display(), e))?;
+        ControlFileData::from_bytes(&buf).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // The server version that wrote the control file, if it's one we know.
+    pub fn version(&self) -> Option<PgVersion> {
+        PgVersion::ALL
+            .iter()
+            .copied()
+            .find(|v| v.catalog_version() == self.catalog_version_no)
+    }
+
+    // The contents in the format of pg_controldata.
+    pub fn describe(&self) -> String {
+        let ckpt = &self.check_point_copy;
+        let redo_segno = xlog_byte_to_seg(ckpt.redo, self.xlog_seg_size);
+        let redo_file = xlog_filename(ckpt.time_line_id, redo_segno, self.xlog_seg_size);
+        let nonce: String = self
+            .mock_authentication_nonce
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let on_off = |b: bool| if b { "on" } else { "off" };
+
+        let lines: Vec<(&str, String)> = vec![
+            ("pg_control version number:", self.pg_control_version.to_string()),
+            ("Catalog version number:", self.catalog_version_no.to_string()),
+            ("Database system identifier:", self.system_identifier.to_string()),
+            ("Database cluster state:", db_state(self.state).to_string()),
+            ("pg_control last modified:", time_str(self.time)),
+            ("Latest checkpoint location:", lsn_format(self.check_point)),
+            ("Latest checkpoint's REDO location:", lsn_format(ckpt.redo)),
+            ("Latest checkpoint's REDO WAL file:", redo_file.display().to_string()),
+            ("Latest checkpoint's TimeLineID:", ckpt.time_line_id.to_string()),
+            ("Latest checkpoint's PrevTimeLineID:", ckpt.prev_time_line_id.to_string()),
+            ("Latest checkpoint's full_page_writes:", on_off(ckpt.full_page_writes).to_string()),
+            (
+                "Latest checkpoint's NextXID:",
+                format!("{}:{}", ckpt.next_xid >> 32, ckpt.next_xid as u32),
+            ),
+            ("Latest checkpoint's NextOID:", ckpt.next_oid.to_string()),
+            ("Latest checkpoint's NextMultiXactId:", ckpt.next_multi.to_string()),
+            ("Latest checkpoint's NextMultiOffset:", ckpt.next_multi_offset.to_string()),
+            ("Latest checkpoint's oldestXID:", ckpt.oldest_xid.to_string()),
+            ("Latest checkpoint's oldestXID's DB:", ckpt.oldest_xid_db.to_string()),
+            ("Latest checkpoint's oldestActiveXID:", ckpt.oldest_active_xid.to_string()),
+            ("Latest checkpoint's oldestMultiXid:", ckpt.oldest_multi.to_string()),
+            ("Latest checkpoint's oldestMulti's DB:", ckpt.oldest_multi_db.to_string()),
+            ("Latest checkpoint's oldestCommitTsXid:", ckpt.oldest_commit_ts_xid.to_string()),
+            ("Latest checkpoint's newestCommitTsXid:", ckpt.newest_commit_ts_xid.to_string()),
+            ("Time of latest checkpoint:", time_str(ckpt.time)),
+            ("Fake LSN counter for unlogged rels:", lsn_format(self.unlogged_lsn)),
+            ("Minimum recovery ending location:", lsn_format(self.min_recovery_point)),
+            ("Min recovery ending loc's timeline:", self.min_recovery_point_tli.to_string()),
+            ("Backup start location:", lsn_format(self.backup_start_point)),
+            ("Backup end location:", lsn_format(self.backup_end_point)),
+            (
+                "End-of-backup record required:",
+                String::from(if self.backup_end_required { "yes" } else { "no" }),
+            ),
+            ("wal_level setting:", wal_level_str(self.wal_level).to_string()),
+            ("wal_log_hints setting:", on_off(self.wal_log_hints).to_string()),
+            ("max_connections setting:", self.max_connections.to_string()),
+            ("max_worker_processes setting:", self.max_worker_processes.to_string()),
+            ("max_wal_senders setting:", self.max_wal_senders.to_string()),
+            ("max_prepared_xacts setting:", self.max_prepared_xacts.to_string()),
+            ("max_locks_per_xact setting:", self.max_locks_per_xact.to_string()),
+            ("track_commit_timestamp setting:", on_off(self.track_commit_timestamp).to_string()),
+            ("Maximum data alignment:", self.max_align.to_string()),
+            // we don't print floatFormat since can't say much useful about it
+            ("Database block size:", self.blcksz.to_string()),
+            ("Blocks per segment of large relation:", self.relseg_size.to_string()),
+            ("WAL block size:", self.xlog_blcksz.to_string()),
+            ("Bytes per WAL segment:", self.xlog_seg_size.to_string()),
+            ("Maximum length of identifiers:", self.name_data_len.to_string()),
+            ("Maximum columns in an index:", self.index_max_keys.to_string()),
+            ("Maximum size of a TOAST chunk:", self.toast_max_chunk_size.to_string()),
+            ("Size of a large-object chunk:", self.loblksize.to_string()),
+            // This is no longer configurable, but users may still expect to see it:
+            ("Date/time type storage:", String::from("64-bit integers")),
+            (
+                "Float8 argument passing:",
+                String::from(if self.float8_by_val { "by value" } else { "by reference" }),
+            ),
+            ("Data page checksum version:", self.data_checksum_version.to_string()),
+            ("Mock authentication nonce:", nonce),
+        ];
+
+        let mut rst = String::new();
+        for (label, value) in lines {
+            rst += &format!("{:<38}{}\n", label, value);
+        }
+        rst
+    }
+}
+
+fn db_state(state: i32) -> &'static str {
+    match state {
+        DB_STARTUP => "starting up",
+        DB_SHUTDOWNED => "shut down",
+        DB_SHUTDOWNED_IN_RECOVERY => "shut down in recovery",
+        DB_SHUTDOWNING => "shutting down",
+        DB_IN_CRASH_RECOVERY => "in crash recovery",
+        DB_IN_ARCHIVE_RECOVERY => "in archive recovery",
+        DB_IN_PRODUCTION => "in production",
+        _ => "unrecognized status code",
+    }
+}
+
+fn wal_level_str(wal_level: i32) -> &'static str {
+    match wal_level {
+        x if x == WalLevel::Minimal as i32 => "minimal",
+        x if x == WalLevel::Replica as i32 => "replica",
+        x if x == WalLevel::Logical as i32 => "logical",
+        _ => "unrecognized wal_level",
+    }
+}
+
+// pg_controldata prints times with strftime's %c in the C locale
+fn time_str(t: PgTime) -> String {
+    match chrono::DateTime::from_timestamp(t, 0) {
+        Some(t) => t.format("%a %b %e %H:%M:%S %Y").to_string(),
+        None => String::from("???"),
+    }
+}
+
 // XLOG info values for XLOG rmgr
 pub const XLOG_CHECKPOINT_SHUTDOWN: u8 = 0x00;
 pub const XLOG_CHECKPOINT_ONLINE: u8 = 0x10;
@@ -22,3 +289,34 @@ pub const XLOG_FPI: u8 = 0xB0;
 // 0xC0 is used in Postgres 9.5-11
 pub const XLOG_OVERWRITE_CONTRECORD: u8 = 0xD0;
 pub const XLOG_CHECKPOINT_REDO: u8 = 0xE0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_file_is_parsed_and_checked() {
+        assert_eq!(std::mem::size_of::<CheckPoint>(), 88);
+        assert_eq!(std::mem::offset_of!(ControlFileData, crc), 288);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+
+        let mut buf = std::fs::read("test/pg_control").unwrap();
+        assert_eq!(buf.len(), PG_CONTROL_FILE_SIZE);
+        let control = ControlFileData::from_bytes(&buf).unwrap();
+        assert_eq!(control.system_identifier, 7698075873487632299);
+        assert_eq!(control.version(), Some(PgVersion::V15));
+        assert_eq!(control.state, DB_SHUTDOWNED);
+        assert_eq!(control.check_point, 0x5000028);
+        assert_eq!(control.check_point_copy.time_line_id, 2);
+        assert_eq!(control.xlog_seg_size, 16 * 1024 * 1024);
+        assert!(control.describe().contains(
+            "Latest checkpoint's REDO WAL file:    000000020000000000000005\n"
+        ));
+
+        buf[16] ^= 1;
+        match ControlFileData::from_bytes(&buf) {
+            Err(e) => assert!(e.contains("CRC")),
+            Ok(_) => panic!("corrupted pg_control accepted"),
+        }
+    }
+}
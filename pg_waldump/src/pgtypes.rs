@@ -1,25 +1,24 @@
 #![allow(unused)]
 use crate::constant::*;
 use std::path::PathBuf;
-use std::cell::RefCell;
-
-pub(crate) type Oid = u32;
-pub(crate) type TransactionId = u32;
-pub(crate) type RmgrId = u8;
-pub(crate) type PgCrc32c = u32;
-pub(crate) type TimeLineID = u32;
-pub(crate) type XLogRecPtr = u64;
-pub(crate) type BlockNumber = u32;
+
+pub type Oid = u32;
+pub type TransactionId = u32;
+pub type RmgrId = u8;
+pub type PgCrc32c = u32;
+pub type TimeLineID = u32;
+pub type XLogRecPtr = u64;
+pub type BlockNumber = u32;
 // A 64 bit value that contains an epoch and a TransactionId
-pub(crate) type FullTransactionId = u64;
-pub(crate) type MultiXactId = TransactionId;
-pub(crate) type MultiXactOffset = u32;
-pub(crate) type PgTime = i64;
+pub type FullTransactionId = u64;
+pub type MultiXactId = TransactionId;
+pub type MultiXactOffset = u32;
+pub type PgTime = i64;
 // Replication origin id - this is located in this file to avoid having to
 // include origin.h in a bunch of xlog related places.
-pub(crate) type RepOriginId = u16;
+pub type RepOriginId = u16;
 // physical log file sequence number.
-pub(crate) type XLogSegNo = u64;
+pub type XLogSegNo = u64;
 
 // Stuff for fork names.
 //
@@ -27,8 +26,9 @@ pub(crate) type XLogSegNo = u64;
 // The main fork is always created, but in addition to that there can be
 // additional forks for storing various metadata. ForkNumber is used when
 // we need to refer to a specific fork in a relation.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Copy)]
-pub(crate) enum ForkNumber {
+pub enum ForkNumber {
     Invalid = -1,
     Main = 0,
     Fsm,
@@ -104,6 +104,7 @@ pub type RelFileNumber = Oid;
 // there *must not* be any unused padding bytes in this struct.  That
 // should be safe as long as all the fields are of type Oid.
 #[repr(align(1))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Default, Copy, Clone)]
 pub struct RelFileLocator {
     pub spc_oid: Oid, // tablespace
@@ -111,8 +112,9 @@ pub struct RelFileLocator {
     pub rel_oid: Oid, // relation
 }
 
-#[derive(Default, Clone)]
-pub(crate) struct DecodedBkpBlock {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Default, Clone, Copy)]
+pub struct DecodedBkpBlock {
     // Is this block ref in use?
     pub in_use: bool,
 
@@ -130,25 +132,24 @@ pub(crate) struct DecodedBkpBlock {
     // Information on full-page image, if any
     pub has_image: bool,   // has image, even for consistency checking
     pub apply_image: bool, // has image that should be restored
-    pub bkp_image: Vec<u8>,
+    pub bkp_image_off: u32, // offset of the image from the start of the record
     pub hole_offset: u16,
     pub hole_length: u16,
     pub bimg_len: u16,
     pub bimg_info: u8,
 
-    // Buffer holding the rmgr-specific data associated with this block
+    // rmgr-specific data associated with this block
     pub has_data: bool,
-    pub data: Vec<u8>,
+    pub data_off: u32, // offset of the data from the start of the record
     pub data_len: u16,
-    pub data_bufsz: u16,
 }
 
 // WALOpenSegment represents a WAL segment being read.
 #[derive(Default, Debug)]
-pub(crate) struct WALOpenSegment {
-    pub file: Option<std::fs::File>, // segment file descriptor
-    pub segno: XLogSegNo,            // segment number
-    pub tli: TimeLineID,             // timeline ID of the currently open file
+pub struct WALOpenSegment {
+    pub file: Option<crate::compress::SegmentFile>, // segment file
+    pub segno: XLogSegNo,                          // segment number
+    pub tli: TimeLineID,                           // timeline ID of the currently open file
 }
 
 impl Clone for WALOpenSegment {
@@ -167,21 +168,16 @@ impl std::fmt::Display for WALOpenSegment {
     }
 }
 
-// WALSegmentContext carries context information about WAL segments to read
-#[derive(Default)]
-pub(crate) struct WALSegmentContext {
-    pub ws_dir: PathBuf,
-    pub ws_segsize: u32,
-}
-
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 #[repr(align(8))]
-pub(crate) struct XLogRecord {
+pub struct XLogRecord {
     // total len of entire record
     pub xl_tot_len: u32,
     // xact id
     pub xl_xid: TransactionId,
     // ptr to previous record in log
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::lsn"))]
     pub xl_prev: XLogRecPtr,
     // flag bits, see below
     pub xl_info: u8,
@@ -193,49 +189,196 @@ pub(crate) struct XLogRecord {
 }
 
 #[derive(Default, Clone)]
-pub(crate) struct XLogDumpPrivate {
+pub struct XLogDumpPrivate {
     pub timeline: TimeLineID,
     pub startptr: XLogRecPtr,
     pub endptr: XLogRecPtr,
     pub endptr_reached: bool,
+    // history of timeline, newest first, to follow the timelines leading
+    // to it; empty to read only timeline
+    pub history: Vec<crate::timeline::TimeLineHistoryEntry>,
 }
 
-#[derive(Default)]
-pub(crate) struct DecodedXLogRecord {
-    // Private member used for resource management.
-    pub size: usize,     // total size of decoded record
-    pub oversized: bool, // outside the regular decode buffer?
+// What a range of a record's bytes holds.  Block parts carry the block id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordPart {
+    Header,
+    BlockHeader(u8),
+    ImageHeader(u8),
+    CompressHeader(u8),
+    RelFileLocator(u8),
+    BlockNumber(u8),
+    Origin,
+    ToplevelXid,
+    DataHeaderShort,
+    DataHeaderLong,
+    Image(u8),
+    BlockData(u8),
+    MainData,
+}
+
+impl std::fmt::Display for RecordPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordPart::Header => write!(f, "XLogRecord"),
+            RecordPart::BlockHeader(id) => write!(f, "XLogRecordBlockHeader #{}", id),
+            RecordPart::ImageHeader(id) => write!(f, "XLogRecordBlockImageHeader #{}", id),
+            RecordPart::CompressHeader(id) => write!(f, "XLogRecordBlockCompressHeader #{}", id),
+            RecordPart::RelFileLocator(id) => write!(f, "RelFileLocator #{}", id),
+            RecordPart::BlockNumber(id) => write!(f, "BlockNumber #{}", id),
+            RecordPart::Origin => write!(f, "XLR_BLOCK_ID_ORIGIN"),
+            RecordPart::ToplevelXid => write!(f, "XLR_BLOCK_ID_TOPLEVEL_XID"),
+            RecordPart::DataHeaderShort => write!(f, "XLogRecordDataHeaderShort"),
+            RecordPart::DataHeaderLong => write!(f, "XLogRecordDataHeaderLong"),
+            RecordPart::Image(id) => write!(f, "block image #{}", id),
+            RecordPart::BlockData(id) => write!(f, "block data #{}", id),
+            RecordPart::MainData => write!(f, "main data"),
+        }
+    }
+}
+
+// A range of a record's bytes, as an offset from the start of the record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordSpan {
+    pub part: RecordPart,
+    pub off: u32,
+    pub len: u32,
+}
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Default)]
+pub struct DecodedXLogRecord {
     // location
-    pub lsn: XLogRecPtr,     
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::lsn"))]
+    pub lsn: XLogRecPtr,
     // location of next record
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::lsn"))]
     pub next_lsn: XLogRecPtr,
-    pub header: XLogRecord,  
+    pub header: XLogRecord,
     pub record_origin: RepOriginId,
     pub toplevel_xid: TransactionId,
-    pub main_data: Option<Vec<u8>>,         
+    // offset of the main data from the start of the record
+    pub main_data_off: u32,
     pub main_data_len: u32,
     // highest block_id in use (-1 if none)
     pub max_block_id: i8,
-    // Design considerations:
-    // 1. block data is Optional
-    // 2. block data is allocated once at initialization of DecodedXLogRecord
-    // 3. thus, Vec<...> is borrowed as immutable, but the element inside is mutable
-    pub blocks: Option<Vec<RefCell<DecodedBkpBlock>>>,
+    // Block references, indexed by block id up to max_block_id.  Slots of
+    // ids the record doesn't use have in_use unset.
+    pub blocks: Vec<DecodedBkpBlock>,
+    // The record as it is in the WAL, if the record has a copy of its own:
+    // with XLogReaderState's keep_raw, or once taken from the reader with
+    // take_record().  Otherwise its bytes, block images, block data and main
+    // data included, are only in the reader's buffers, and are read from
+    // there through XLogReaderState::record().
+    pub raw: Vec<u8>,
+    // With keep_raw, what the parts of raw are, in the order the decoder met
+    // them; empty otherwise.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub layout: Vec<RecordSpan>,
+}
+
+impl DecodedXLogRecord {
+    // Reset for decoding a new record, keeping the buffers' capacity.
+    pub fn reset(&mut self) {
+        let mut blocks = std::mem::take(&mut self.blocks);
+        let mut raw = std::mem::take(&mut self.raw);
+        let mut layout = std::mem::take(&mut self.layout);
+        blocks.clear();
+        raw.clear();
+        layout.clear();
+        *self = DecodedXLogRecord {
+            max_block_id: -1,
+            blocks,
+            raw,
+            layout,
+            ..Default::default()
+        };
+    }
+
+    // Returns the block reference with the given id, if it's in use.
+    pub fn block(&self, block_id: u8) -> Option<&DecodedBkpBlock> {
+        match self.blocks.get(block_id as usize) {
+            Some(blk) if blk.in_use => Some(blk),
+            _ => None,
+        }
+    }
+
+    // The record read from its own copy of its bytes, raw, which it must
+    // have.
+    pub fn view(&self) -> XLogRecordRef<'_> {
+        assert_eq!(self.raw.len(), self.header.xl_tot_len as usize);
+        XLogRecordRef {
+            decoded: self,
+            bytes: &self.raw,
+        }
+    }
 }
 
-pub(crate) const INVALID_XLOG_RECPTR: XLogRecPtr = 0;
+// A decoded record with the bytes it was decoded from: the page in the
+// reader's read_buf, the reader's cross_page_record_buf if it crosses
+// pages, or its own raw copy.  The block images, block data and main data
+// are slices of those, not copies; they are as unaligned as in the WAL.
+#[derive(Clone, Copy)]
+pub struct XLogRecordRef<'a> {
+    pub decoded: &'a DecodedXLogRecord,
+    pub bytes: &'a [u8],
+}
+
+impl std::ops::Deref for XLogRecordRef<'_> {
+    type Target = DecodedXLogRecord;
+
+    fn deref(&self) -> &DecodedXLogRecord {
+        self.decoded
+    }
+}
+
+impl<'a> XLogRecordRef<'a> {
+    fn slice(&self, off: u32, len: usize) -> &'a [u8] {
+        &self.bytes[off as usize..off as usize + len]
+    }
+
+    pub fn main_data(&self) -> Option<&'a [u8]> {
+        if self.main_data_len == 0 {
+            return None;
+        }
+        Some(self.slice(self.main_data_off, self.main_data_len as usize))
+    }
+
+    // Returns the data associated with a block reference, or None if there
+    // is no data (e.g. because a full-page image was taken instead).
+    pub fn block_data(&self, block_id: u8) -> Option<&'a [u8]> {
+        let blk = self.decoded.block(block_id)?;
+        if !blk.has_data {
+            return None;
+        }
+        Some(self.slice(blk.data_off, blk.data_len as usize))
+    }
+
+    // Returns the (possibly compressed, hole-less) page image of a block
+    // reference, or None if it has none.
+    pub fn block_image(&self, block_id: u8) -> Option<&'a [u8]> {
+        let blk = self.decoded.block(block_id)?;
+        if !blk.has_image {
+            return None;
+        }
+        Some(self.slice(blk.bkp_image_off, blk.bimg_len as usize))
+    }
+}
+
+pub const INVALID_XLOG_RECPTR: XLogRecPtr = 0;
 
 #[inline]
-pub(crate) fn xlog_recptr_is_invalid(r: XLogRecPtr) -> bool {
+pub fn xlog_recptr_is_invalid(r: XLogRecPtr) -> bool {
     r == INVALID_XLOG_RECPTR
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct CheckPoint {
     // next RecPtr available when we began to
     // create CheckPoint (i.e. REDO start point)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::lsn"))]
     pub redo: XLogRecPtr,
     // current TLI
     pub time_line_id: TimeLineID,
@@ -244,6 +387,8 @@ pub struct CheckPoint {
     pub prev_time_line_id: TimeLineID,
     // current full_page_writes
     pub full_page_writes: bool,
+    // current wal_level; PostgreSQL 17 and later only, padding before
+    pub wal_level: i32,
     // next free transaction ID
     pub next_xid: FullTransactionId,
     // next free OID
@@ -261,6 +406,7 @@ pub struct CheckPoint {
     // database with minimum datminmxid
     // time stamp of checkpoint
     pub oldest_multi_db: Oid,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::pg_time"))]
     pub time: PgTime,
     // oldest Xid with valid commit timestamp
     pub oldest_commit_ts_xid: TransactionId,
@@ -274,8 +420,10 @@ pub struct CheckPoint {
     pub oldest_active_xid: TransactionId,
 }
 
-impl std::fmt::Display for CheckPoint {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl CheckPoint {
+    // The checkpoint as xlog_desc() prints it.  wal_level is given as a
+    // string for checkpoints that carry it.
+    pub fn fmt_with(&self, wal_level: Option<&str>) -> String {
         let mut s = String::new();
 
         s.push_str(&format!(
@@ -286,6 +434,9 @@ impl std::fmt::Display for CheckPoint {
         s.push_str(&format!("tli {}; ", self.time_line_id));
         s.push_str(&format!("prev tli {}; ", self.prev_time_line_id));
         s.push_str(&format!("fpw {}; ", self.full_page_writes));
+        if let Some(wal_level) = wal_level {
+            s.push_str(&format!("wal_level {}; ", wal_level));
+        }
         s.push_str(&format!(
             "xid {}:{}; ",
             (self.next_xid >> 32) as u32,
@@ -308,41 +459,66 @@ impl std::fmt::Display for CheckPoint {
             self.oldest_commit_ts_xid, self.newest_commit_ts_xid
         ));
         s.push_str(&format!("oldest running xid {};", self.oldest_active_xid));
+        s
+    }
+}
 
-        write!(f, "{}", s)
+impl std::fmt::Display for CheckPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.fmt_with(None))
     }
 }
 
 pub type TimestampTz = i64;
 
-const UNIX_EPOCH_JDATE: i32 = 2451545; // == date2j(1970, 1, 1)
-const POSTGRES_EPOCH_JDATE: i32 = 2453283; // == date2j(2000, 1, 1)
+const UNIX_EPOCH_JDATE: i32 = 2440588; // == date2j(1970, 1, 1)
+const POSTGRES_EPOCH_JDATE: i32 = 2451545; // == date2j(2000, 1, 1)
 const SECS_PER_DAY: i64 = 60 * 60 * 24;
 const MAXDATELEN: usize = 128;
 const USECS_PER_SEC: i64 = 1000_000;
 
 pub fn timestamptz_to_time_t(t: TimestampTz) -> PgTime {
-    t / USECS_PER_SEC + ((POSTGRES_EPOCH_JDATE - UNIX_EPOCH_JDATE) as i64 * SECS_PER_DAY)
+    t.div_euclid(USECS_PER_SEC) + ((POSTGRES_EPOCH_JDATE - UNIX_EPOCH_JDATE) as i64 * SECS_PER_DAY)
+}
+
+// microseconds between the Unix epoch and the PostgreSQL epoch, 2000-01-01
+pub const POSTGRES_EPOCH_USECS: i64 =
+    (POSTGRES_EPOCH_JDATE - UNIX_EPOCH_JDATE) as i64 * SECS_PER_DAY * USECS_PER_SEC;
+
+// ISO 8601 in UTC with microseconds, as in JSON and SQLite; None if out of
+// chrono's range
+pub fn timestamptz_to_iso(t: TimestampTz) -> Option<String> {
+    let usecs = t.checked_add(POSTGRES_EPOCH_USECS)?;
+    chrono::DateTime::from_timestamp(
+        usecs.div_euclid(USECS_PER_SEC),
+        (usecs.rem_euclid(USECS_PER_SEC) * 1000) as u32,
+    )
+    .map(|ts| ts.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
 }
 
 pub fn timestamptz_to_str(t: TimestampTz) -> String {
     let result = timestamptz_to_time_t(t);
 
-    let ltime = chrono::NaiveDateTime::from_timestamp_opt(result, 0).unwrap();
+    // a naive time has no zone for %Z to print, so show it in UTC
+    let ltime = chrono::DateTime::from_timestamp(result, 0).unwrap();
     let ts = ltime.format("%Y-%m-%d %H:%M:%S").to_string();
-    let zone = ltime.format("%Z").to_string();
 
-    format!("{}.{:06} {}", ts, (t % USECS_PER_SEC), zone)
+    format!("{}.{:06} UTC", ts, t.rem_euclid(USECS_PER_SEC))
 }
 
 // logs restore point */
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XlRestorePoint {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::timestamptz"))]
     pub rp_time: TimestampTz,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::cstring"))]
     pub rp_name: [u8; MAXFNAMELEN],
 }
 
 // Information logged when we detect a change in one of the parameters
 // important for Hot Standby.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XlParameterChange {
     pub max_connections: i32,
     pub max_worker_processes: i32,
@@ -0,0 +1,1284 @@
+// Streaming WAL from a running server over the replication protocol, the
+// way pg_receivewal does (see src/bin/pg_basebackup/receivelog.c and the
+// "Streaming Replication Protocol" chapter of the documentation).
+//
+// A replication connection is a frontend/backend protocol connection with
+// replication=true in its startup packet.  The commands used here are
+//
+//   IDENTIFY_SYSTEM              system identifier, timeline, flush position
+//   SHOW name                    wal_segment_size and wal_block_size
+//   TIMELINE_HISTORY tli         contents of a timeline history file
+//   START_REPLICATION [SLOT s] PHYSICAL X/X TIMELINE tli
+//
+// after which the server switches to copy-both mode and sends XLogData
+// ('w') and keepalive ('k') messages, and the client answers with standby
+// status updates ('r').  Only the parts of the protocol needed for that
+// are implemented; there is no TLS.
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use md5::{Digest, Md5};
+
+use crate::constant::XLOG_BLCKSZ;
+use crate::pgtypes::*;
+use crate::timeline::{
+    parse_lsn, parse_timeline_history, single_timeline, tli_of_point_in_history,
+    TimeLineHistoryEntry,
+};
+use crate::util::{xlog_byte_to_seg, xlog_segment_offset, WALReadError};
+use crate::version::PgVersion;
+use crate::waldec::{lsn_out, SystemIdentifierSource, WalConfig};
+use crate::walsource::WalSource;
+
+const PG_PROTOCOL_3_0: u32 = 3 << 16;
+
+// authentication requests, see src/include/libpq/protocol.h
+const AUTH_REQ_OK: u32 = 0;
+const AUTH_REQ_PASSWORD: u32 = 3;
+const AUTH_REQ_MD5: u32 = 5;
+const AUTH_REQ_SASL: u32 = 10;
+const AUTH_REQ_SASL_CONT: u32 = 11;
+const AUTH_REQ_SASL_FIN: u32 = 12;
+
+// how often to tell the server how far we are, as pg_receivewal's
+// --status-interval default
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Connection parameters, from a libpq connection string.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnParams {
+    // host name, address, or directory holding the Unix socket
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    pub application_name: String,
+}
+
+impl ConnParams {
+    // Parse a connection string, either keyword/value pairs ("host=db
+    // port=5433 user=rep") or a URI ("postgresql://rep@db:5433").
+    // Parameters not given come from PGHOST, PGPORT, PGUSER and PGPASSWORD
+    // as with libpq; the host defaults to the Unix socket directory the
+    // server's socket is found in, else localhost.
+    pub fn parse(conninfo: &str) -> Result<ConnParams, String> {
+        let pairs = if conninfo.starts_with("postgresql://") || conninfo.starts_with("postgres://")
+        {
+            parse_uri(conninfo)?
+        } else {
+            parse_keyword_value(conninfo)?
+        };
+
+        let env = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        let mut host = env("PGHOST");
+        let mut port = env("PGPORT");
+        let mut user = env("PGUSER");
+        let mut password = env("PGPASSWORD");
+        let mut application_name = String::from("pg_waldump");
+        for (key, value) in pairs {
+            match key.as_str() {
+                "host" | "hostaddr" => host = Some(value),
+                "port" => port = Some(value),
+                "user" => user = Some(value),
+                "password" => password = Some(value),
+                "application_name" => application_name = value,
+                // physical replication is not bound to a database
+                "dbname" | "connect_timeout" => {}
+                "sslmode" => {
+                    if !["disable", "allow", "prefer"].contains(&value.as_str()) {
+                        return Err(format!("sslmode value \"{}\" is not supported", value));
+                    }
+                }
+                _ => return Err(format!("invalid connection option \"{}\"", key)),
+            }
+        }
+
+        let port = match port {
+            Some(p) => p
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port number: \"{}\"", p))?,
+            None => 5432,
+        };
+        let host = host.unwrap_or_else(|| {
+            ["/var/run/postgresql", "/tmp"]
+                .iter()
+                .find(|dir| Path::new(dir).join(format!(".s.PGSQL.{}", port)).exists())
+                .map(|dir| dir.to_string())
+                .unwrap_or_else(|| String::from("localhost"))
+        });
+        let user = user
+            .or_else(|| env("USER"))
+            .unwrap_or_else(|| String::from("postgres"));
+        Ok(ConnParams {
+            host,
+            port,
+            user,
+            password,
+            application_name,
+        })
+    }
+}
+
+// "key = value" pairs separated by whitespace; values may be single-quoted,
+// and backslash escapes the next character.
+fn parse_keyword_value(conninfo: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut chars = conninfo.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(pairs);
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && !c.is_whitespace()) {
+            key.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next() != Some('=') {
+            return Err(format!(
+                "missing \"=\" after \"{}\" in connection info string",
+                key
+            ));
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'\'').is_some() {
+            loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some('\\') if chars.peek().is_some() => value.push(chars.next().unwrap()),
+                    Some(c) => value.push(c),
+                    None => {
+                        return Err(String::from(
+                            "unterminated quoted string in connection info string",
+                        ))
+                    }
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        }
+        pairs.push((key, value));
+    }
+}
+
+// postgresql://[user[:password]@][host][:port][/dbname][?key=value&...]
+fn parse_uri(uri: &str) -> Result<Vec<(String, String)>, String> {
+    let rest = uri.split_once("://").unwrap().1;
+    let (rest, query) = match rest.split_once('?') {
+        Some((r, q)) => (r, Some(q)),
+        None => (rest, None),
+    };
+    let (authority, dbname) = match rest.split_once('/') {
+        Some((a, d)) => (a, Some(d)),
+        None => (rest, None),
+    };
+    let (userinfo, hostport) = match authority.rsplit_once('@') {
+        Some((u, h)) => (Some(u), h),
+        None => (None, authority),
+    };
+
+    let mut pairs = Vec::new();
+    if let Some(userinfo) = userinfo {
+        let (user, password) = match userinfo.split_once(':') {
+            Some((u, p)) => (u, Some(p)),
+            None => (userinfo, None),
+        };
+        if !user.is_empty() {
+            pairs.push((String::from("user"), percent_decode(user)?));
+        }
+        if let Some(password) = password {
+            pairs.push((String::from("password"), percent_decode(password)?));
+        }
+    }
+    // an IPv6 address is written in brackets
+    let (host, port) = match hostport.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((h, p)) => (h, p.strip_prefix(':')),
+            None => {
+                return Err(format!(
+                    "missing \"]\" in IPv6 host address in URI: \"{}\"",
+                    uri
+                ))
+            }
+        },
+        None => match hostport.rsplit_once(':') {
+            Some((h, p)) => (h, Some(p)),
+            None => (hostport, None),
+        },
+    };
+    if !host.is_empty() {
+        pairs.push((String::from("host"), percent_decode(host)?));
+    }
+    if let Some(port) = port.filter(|p| !p.is_empty()) {
+        pairs.push((String::from("port"), String::from(port)));
+    }
+    if let Some(dbname) = dbname.filter(|d| !d.is_empty()) {
+        pairs.push((String::from("dbname"), percent_decode(dbname)?));
+    }
+    for param in query.into_iter().flat_map(|q| q.split('&')) {
+        match param.split_once('=') {
+            Some((k, v)) => pairs.push((percent_decode(k)?, percent_decode(v)?)),
+            None => {
+                return Err(format!(
+                    "missing key/value separator \"=\" in URI query parameter: \"{}\"",
+                    param
+                ))
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+fn percent_decode(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| format!("invalid percent-encoded token: \"{}\"", s))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| format!("invalid percent-encoded token: \"{}\"", s))
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    // A host starting with a slash is the directory of a Unix socket, as
+    // in libpq.
+    fn connect(host: &str, port: u16) -> std::io::Result<Socket> {
+        #[cfg(unix)]
+        if host.starts_with('/') {
+            let path = Path::new(host).join(format!(".s.PGSQL.{}", port));
+            return Ok(Socket::Unix(UnixStream::connect(path)?));
+        }
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_nodelay(true)?;
+        Ok(Socket::Tcp(stream))
+    }
+
+    fn try_clone(&self) -> std::io::Result<Socket> {
+        match self {
+            Socket::Tcp(s) => Ok(Socket::Tcp(s.try_clone()?)),
+            #[cfg(unix)]
+            Socket::Unix(s) => Ok(Socket::Unix(s.try_clone()?)),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Socket::Unix(s) => s.flush(),
+        }
+    }
+}
+
+// a row of a result, in text format, NULLs as None
+pub type Row = Vec<Option<String>>;
+
+// Result of IDENTIFY_SYSTEM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemInfo {
+    pub system_identifier: u64,
+    pub timeline: TimeLineID,
+    // current WAL flush location
+    pub xlogpos: XLogRecPtr,
+}
+
+pub struct ReplicationConnection {
+    reader: BufReader<Socket>,
+    writer: Socket,
+    // run-time parameters reported by the server, e.g. server_version
+    parameters: HashMap<String, String>,
+}
+
+impl ReplicationConnection {
+    // Connect and authenticate, by password, md5 or SCRAM-SHA-256 if the
+    // server asks for one.
+    pub fn connect(params: &ConnParams) -> Result<ReplicationConnection, String> {
+        let socket = Socket::connect(&params.host, params.port).map_err(|e| {
+            format!(
+                "connection to server at \"{}\", port {} failed: {}",
+                params.host, params.port, e
+            )
+        })?;
+        let writer = socket
+            .try_clone()
+            .map_err(|e| format!("could not set up connection: {}", e))?;
+        let mut conn = ReplicationConnection {
+            reader: BufReader::new(socket),
+            writer,
+            parameters: HashMap::new(),
+        };
+
+        // the startup packet is the only message without a type byte
+        let mut body = PG_PROTOCOL_3_0.to_be_bytes().to_vec();
+        for (name, value) in [
+            ("user", params.user.as_str()),
+            ("replication", "true"),
+            ("application_name", params.application_name.as_str()),
+        ] {
+            body.extend_from_slice(&cstring(name));
+            body.extend_from_slice(&cstring(value));
+        }
+        body.push(0);
+        let mut packet = (body.len() as u32 + 4).to_be_bytes().to_vec();
+        packet.extend_from_slice(&body);
+        conn.writer
+            .write_all(&packet)
+            .map_err(|e| format!("could not send startup packet: {}", e))?;
+
+        conn.authenticate(params)?;
+
+        // wait for the backend to be ready for queries
+        loop {
+            match conn.receive()? {
+                (b'K', _) => {}
+                (b'Z', _) => return Ok(conn),
+                (tag, _) => return Err(unexpected_message(tag)),
+            }
+        }
+    }
+
+    fn authenticate(&mut self, params: &ConnParams) -> Result<(), String> {
+        let password = || {
+            params
+                .password
+                .as_deref()
+                .ok_or_else(|| String::from("fe_sendauth: no password supplied"))
+        };
+        loop {
+            let (tag, body) = self.receive()?;
+            if tag != b'R' || body.len() < 4 {
+                return Err(unexpected_message(tag));
+            }
+            match be_u32(&body) {
+                AUTH_REQ_OK => return Ok(()),
+                AUTH_REQ_PASSWORD => self.send(b'p', &cstring(password()?))?,
+                AUTH_REQ_MD5 if body.len() >= 8 => {
+                    let inner = md5_hex(&[password()?.as_bytes(), params.user.as_bytes()].concat());
+                    let outer = md5_hex(&[inner.as_bytes(), &body[4..8]].concat());
+                    self.send(b'p', &cstring(&format!("md5{}", outer)))?
+                }
+                AUTH_REQ_SASL => {
+                    let mechanisms: Vec<&[u8]> = body[4..].split(|b| *b == 0).collect();
+                    if !mechanisms.contains(&&b"SCRAM-SHA-256"[..]) {
+                        return Err(String::from(
+                            "none of the server's SASL authentication mechanisms are supported",
+                        ));
+                    }
+                    self.scram_authenticate(password()?)?
+                }
+                code => {
+                    return Err(format!(
+                        "authentication method requested by server ({}) is not supported",
+                        code
+                    ))
+                }
+            }
+        }
+    }
+
+    // SCRAM-SHA-256 exchange, RFC 7677.  The user name in the messages is
+    // left empty, the server uses the one from the startup packet.
+    fn scram_authenticate(&mut self, password: &str) -> Result<(), String> {
+        let mut nonce = [0u8; 18];
+        getrandom::getrandom(&mut nonce).map_err(|e| format!("could not generate nonce: {}", e))?;
+        let client_first_bare = format!("n=,r={}", base64_encode(&nonce));
+
+        let client_first = format!("n,,{}", client_first_bare);
+        let mut body = cstring("SCRAM-SHA-256");
+        body.extend_from_slice(&(client_first.len() as u32).to_be_bytes());
+        body.extend_from_slice(client_first.as_bytes());
+        self.send(b'p', &body)?;
+
+        let server_first = self.sasl_data(AUTH_REQ_SASL_CONT)?;
+        let (client_final, server_signature) =
+            scram_client_final(password, &client_first_bare, &server_first)?;
+        self.send(b'p', client_final.as_bytes())?;
+
+        let server_final = self.sasl_data(AUTH_REQ_SASL_FIN)?;
+        if server_final != format!("v={}", base64_encode(&server_signature)) {
+            return Err(String::from("incorrect server signature"));
+        }
+        Ok(())
+    }
+
+    fn sasl_data(&mut self, expected: u32) -> Result<String, String> {
+        match self.receive()? {
+            (b'R', body) if body.len() >= 4 && be_u32(&body) == expected => {
+                Ok(String::from_utf8_lossy(&body[4..]).into_owned())
+            }
+            (tag, _) => Err(unexpected_message(tag)),
+        }
+    }
+
+    // Run parameter name as reported by the server at startup.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    pub fn send(&mut self, tag: u8, body: &[u8]) -> Result<(), String> {
+        let mut msg = Vec::with_capacity(body.len() + 5);
+        msg.push(tag);
+        msg.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        msg.extend_from_slice(body);
+        self.writer
+            .write_all(&msg)
+            .map_err(|e| format!("could not send data to server: {}", e))
+    }
+
+    // Receive the next message.  ErrorResponse becomes an Err, notices
+    // are printed and parameter status messages remembered.
+    pub fn receive(&mut self) -> Result<(u8, Vec<u8>), String> {
+        loop {
+            let mut hdr = [0u8; 5];
+            self.reader.read_exact(&mut hdr).map_err(lost_connection)?;
+            let len = be_u32(&hdr[1..]) as usize;
+            if len < 4 {
+                return Err(format!("invalid message length {}", len));
+            }
+            let mut body = vec![0u8; len - 4];
+            self.reader.read_exact(&mut body).map_err(lost_connection)?;
+            match hdr[0] {
+                b'E' => return Err(error_fields(&body)),
+                b'N' => eprintln!("{}", error_fields(&body)),
+                b'S' => {
+                    let mut fields = body.split(|b| *b == 0);
+                    if let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+                        self.parameters.insert(
+                            String::from_utf8_lossy(name).into_owned(),
+                            String::from_utf8_lossy(value).into_owned(),
+                        );
+                    }
+                }
+                tag => return Ok((tag, body)),
+            }
+        }
+    }
+
+    // Run a replication command and return the rows of its result.
+    pub fn query(&mut self, command: &str) -> Result<Vec<Row>, String> {
+        self.send(b'Q', &cstring(command))?;
+        self.read_result()
+            .map_err(|e| format!("could not send replication command \"{}\": {}", command, e))
+    }
+
+    // Collect the rows of a result up to ReadyForQuery.
+    fn read_result(&mut self) -> Result<Vec<Row>, String> {
+        let mut rows = Vec::new();
+        loop {
+            match self.receive()? {
+                (b'T', _) | (b'C', _) | (b'I', _) => {}
+                (b'D', body) => rows.push(parse_data_row(&body)?),
+                (b'Z', _) => return Ok(rows),
+                (tag, _) => return Err(unexpected_message(tag)),
+            }
+        }
+    }
+
+    pub fn identify_system(&mut self) -> Result<SystemInfo, String> {
+        let rows = self.query("IDENTIFY_SYSTEM")?;
+        let invalid = || {
+            format!(
+                "could not identify system: got {} rows and {} fields, expected 1 rows and 3 or more fields",
+                rows.len(),
+                rows.first().map_or(0, |r| r.len())
+            )
+        };
+        let row = match rows.as_slice() {
+            [row] if row.len() >= 3 => row,
+            _ => return Err(invalid()),
+        };
+        let field = |i: usize| row[i].as_deref().unwrap_or("");
+        match (
+            field(0).parse::<u64>(),
+            field(1).parse::<TimeLineID>(),
+            parse_lsn(field(2)),
+        ) {
+            (Ok(system_identifier), Ok(timeline), Some(xlogpos)) => Ok(SystemInfo {
+                system_identifier,
+                timeline,
+                xlogpos,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    // Value of a server setting, by SHOW.
+    pub fn show(&mut self, name: &str) -> Result<String, String> {
+        let rows = self.query(&format!("SHOW {}", name))?;
+        match rows.first().and_then(|r| r.first()) {
+            Some(Some(value)) => Ok(value.clone()),
+            _ => Err(format!("could not fetch {}: no value returned", name)),
+        }
+    }
+
+    // The history of timeline tli, fetched from the server.
+    pub fn timeline_history(
+        &mut self,
+        tli: TimeLineID,
+    ) -> Result<Vec<TimeLineHistoryEntry>, String> {
+        // Timeline 1 does not have a history file
+        if tli == 1 {
+            return Ok(single_timeline(tli));
+        }
+        let rows = self.query(&format!("TIMELINE_HISTORY {}", tli))?;
+        match rows.as_slice() {
+            [row] if row.len() == 2 => {
+                let fname = row[0].as_deref().unwrap_or("");
+                let content = row[1].as_deref().unwrap_or("");
+                parse_timeline_history(content, tli).map_err(|e| format!("{}: {}", fname, e))
+            }
+            _ => Err(format!(
+                "unexpected response to TIMELINE_HISTORY command: got {} rows, expected 1",
+                rows.len()
+            )),
+        }
+    }
+
+    // The configuration of the server's WAL: segment and block size from
+    // its settings, and the version from server_version.  `version`
+    // overrides the version the records are decoded as.
+    pub fn wal_config(
+        &mut self,
+        system_identifier: u64,
+        version: Option<PgVersion>,
+    ) -> Result<WalConfig, String> {
+        let server_version = self.parameter("server_version").unwrap_or("").to_string();
+        let major: String = server_version
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        let detected = major.parse::<PgVersion>().ok();
+        let version = match version.or(detected) {
+            Some(v) => v,
+            None => {
+                return Err(format!(
+                    "unsupported server version \"{}\", expected one of 13, 14, 15, 16, 17",
+                    server_version
+                ))
+            }
+        };
+
+        let wal_segment_size = self.show("wal_segment_size")?;
+        let wal_seg_sz = parse_size(&wal_segment_size)
+            .ok_or_else(|| format!("invalid wal_segment_size \"{}\"", wal_segment_size))?;
+        let xlog_blcksz = self.show("wal_block_size")?;
+        let xlog_blcksz = xlog_blcksz
+            .parse::<u32>()
+            .map_err(|_| format!("invalid wal_block_size \"{}\"", xlog_blcksz))?;
+        if xlog_blcksz != XLOG_BLCKSZ {
+            return Err(format!(
+                "WAL block size {} is not supported, expected {}",
+                xlog_blcksz, XLOG_BLCKSZ
+            ));
+        }
+        Ok(WalConfig {
+            wal_seg_sz,
+            xlog_blcksz,
+            system_identifier,
+            system_identifier_source: SystemIdentifierSource::Server,
+            version,
+            page_magic: detected.unwrap_or(version).page_magic(),
+        })
+    }
+}
+
+impl Drop for ReplicationConnection {
+    fn drop(&mut self) {
+        // Terminate; the server may be gone already
+        let _ = self.send(b'X', &[]);
+    }
+}
+
+// WAL streamed from a server, for the reader.  Streaming starts at the
+// first page the reader asks for, on the timeline in use there according
+// to the history, and carries on into the following timelines as the
+// server ends each one.  The WAL received is kept from the start of the
+// segment before the one being read, since the reader goes back to the
+// first page of a segment to check its header.
+pub struct StreamSource {
+    conn: ReplicationConnection,
+    slot: Option<String>,
+    segsize: u32,
+    history: Vec<TimeLineHistoryEntry>,
+    streaming: bool,
+    // WAL received, starting at startptr
+    startptr: XLogRecPtr,
+    data: Vec<u8>,
+    // end+1 of the WAL handed to the reader; reported to the server as
+    // flushed, which lets a slot release the WAL before it
+    consumed: XLogRecPtr,
+    last_status: Instant,
+    // the error wait_for() ran into, for the read that follows to return
+    error: Option<String>,
+}
+
+impl StreamSource {
+    pub fn new(
+        conn: ReplicationConnection,
+        slot: Option<String>,
+        segsize: u32,
+        history: Vec<TimeLineHistoryEntry>,
+    ) -> Self {
+        StreamSource {
+            conn,
+            slot,
+            segsize,
+            history,
+            streaming: false,
+            startptr: INVALID_XLOG_RECPTR,
+            data: Vec::new(),
+            consumed: INVALID_XLOG_RECPTR,
+            last_status: Instant::now(),
+            error: None,
+        }
+    }
+
+    // end+1 of the WAL received
+    fn endptr(&self) -> XLogRecPtr {
+        self.startptr + self.data.len() as u64
+    }
+
+    fn start_streaming(&mut self, startptr: XLogRecPtr, tli: TimeLineID) -> Result<(), String> {
+        let slot = match &self.slot {
+            Some(slot) => format!("SLOT \"{}\" ", slot),
+            None => String::new(),
+        };
+        let command = format!(
+            "START_REPLICATION {}PHYSICAL {} TIMELINE {}",
+            slot,
+            lsn_out(startptr),
+            tli
+        );
+        self.conn.send(b'Q', &cstring(&command))?;
+        match self.conn.receive() {
+            Ok((b'W', _)) => {}
+            Ok((tag, _)) => {
+                return Err(format!(
+                    "could not send replication command \"{}\": {}",
+                    command,
+                    unexpected_message(tag)
+                ))
+            }
+            Err(e) => {
+                return Err(format!(
+                    "could not send replication command \"{}\": {}",
+                    command, e
+                ))
+            }
+        }
+        if !self.streaming && self.data.is_empty() {
+            self.startptr = startptr;
+        }
+        self.streaming = true;
+        Ok(())
+    }
+
+    // Process one message from the server.  Returns false once the server
+    // has stopped streaming for good.
+    fn receive_more(&mut self) -> Result<bool, String> {
+        let (tag, body) = self.conn.receive()?;
+        match (tag, body.first()) {
+            (b'd', Some(b'w')) if body.len() >= 25 => {
+                let start = be_u64(&body[1..]);
+                if start < self.startptr || start > self.endptr() {
+                    return Err(format!(
+                        "received WAL at {}, expected {}",
+                        lsn_out(start),
+                        lsn_out(self.endptr())
+                    ));
+                }
+                // a restarted stream may send again what we have
+                self.data.truncate((start - self.startptr) as usize);
+                self.data.extend_from_slice(&body[25..]);
+            }
+            (b'd', Some(b'k')) if body.len() >= 18 => {
+                // the server wants a reply right away
+                if body[17] != 0 {
+                    self.send_status()?;
+                }
+            }
+            (b'd', Some(c)) => {
+                return Err(format!("unrecognized streaming header: \"{}\"", *c as char))
+            }
+            (b'c', _) => return self.end_of_timeline(),
+            (tag, _) => return Err(unexpected_message(tag)),
+        }
+        if self.last_status.elapsed() >= STATUS_INTERVAL {
+            self.send_status()?;
+        }
+        Ok(true)
+    }
+
+    // The server ended the copy stream.  After streaming a timeline that
+    // is not its latest, it tells where the next timeline starts, and
+    // streaming carries on there.
+    fn end_of_timeline(&mut self) -> Result<bool, String> {
+        self.conn.send(b'c', &[])?;
+        self.streaming = false;
+        let rows = self.conn.read_result()?;
+        let next = rows.first().and_then(|row| {
+            let tli = row.first()?.as_deref()?.parse::<TimeLineID>().ok()?;
+            let start = parse_lsn(row.get(1)?.as_deref()?)?;
+            Some((tli, start))
+        });
+        match next {
+            Some((tli, start)) => {
+                self.start_streaming(start, tli)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Send a standby status update, like sendFeedback() in receivelog.c.
+    fn send_status(&mut self) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64)
+            - POSTGRES_EPOCH_USECS;
+        let mut body = Vec::with_capacity(34);
+        body.push(b'r');
+        body.extend_from_slice(&self.endptr().to_be_bytes()); // write
+        body.extend_from_slice(&self.consumed.to_be_bytes()); // flush
+        body.extend_from_slice(&INVALID_XLOG_RECPTR.to_be_bytes()); // apply
+        body.extend_from_slice(&now.to_be_bytes());
+        body.push(0); // no reply requested
+        self.conn.send(b'd', &body)?;
+        self.last_status = Instant::now();
+        Ok(())
+    }
+
+    // Receive until the WAL up to upto is there.
+    fn fill(&mut self, startptr: XLogRecPtr, upto: XLogRecPtr) -> Result<(), String> {
+        if !self.streaming && self.data.is_empty() {
+            let tli = tli_of_point_in_history(startptr, &self.history);
+            self.start_streaming(startptr, tli)?;
+        }
+        if startptr < self.startptr {
+            return Err(format!(
+                "WAL at {} is no longer available",
+                lsn_out(startptr)
+            ));
+        }
+        while self.endptr() < upto {
+            if !self.streaming || !self.receive_more()? {
+                return Err(String::from(
+                    "replication stream was terminated before stop point",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WalSource for StreamSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let endptr = startptr + buf.len() as u64;
+        let res = match self.error.take() {
+            Some(e) => Err(e),
+            None => self.fill(startptr, endptr),
+        };
+        if let Err(e) = res {
+            return Err(WALReadError {
+                errno: 0,
+                off: xlog_segment_offset(startptr, self.segsize),
+                req: buf.len() as u32,
+                read: 0,
+                seg: WALOpenSegment {
+                    file: None,
+                    segno: xlog_byte_to_seg(startptr, self.segsize),
+                    tli,
+                },
+                msg: Some(e),
+            });
+        }
+        let off = (startptr - self.startptr) as usize;
+        buf.copy_from_slice(&self.data[off..off + buf.len()]);
+        self.consumed = self.consumed.max(endptr);
+
+        // drop what is before the previous segment
+        let keep = xlog_byte_to_seg(startptr, self.segsize).saturating_sub(1) * self.segsize as u64;
+        if keep > self.startptr {
+            self.data.drain(..(keep - self.startptr) as usize);
+            self.startptr = keep;
+        }
+        Ok(())
+    }
+
+    fn wait_for(&mut self, startptr: XLogRecPtr, upto: XLogRecPtr) -> Option<XLogRecPtr> {
+        if let Err(e) = self.fill(startptr, upto) {
+            self.error = Some(e);
+            return None;
+        }
+        Some(self.endptr())
+    }
+}
+
+// The client-final-message of a SCRAM-SHA-256 exchange, and the server
+// signature to expect in the server-final-message.
+fn scram_client_final(
+    password: &str,
+    client_first_bare: &str,
+    server_first: &str,
+) -> Result<(String, [u8; 32]), String> {
+    let invalid = || String::from("malformed SCRAM message");
+    let attr = |name: char| {
+        server_first
+            .split(',')
+            .find_map(|a| a.strip_prefix(name)?.strip_prefix('='))
+    };
+    let nonce = attr('r').ok_or_else(invalid)?;
+    let salt = attr('s').and_then(base64_decode).ok_or_else(invalid)?;
+    let iterations = attr('i')
+        .and_then(|i| i.parse::<u32>().ok())
+        .filter(|i| *i > 0)
+        .ok_or_else(invalid)?;
+    let client_nonce = client_first_bare
+        .split(',')
+        .find_map(|a| a.strip_prefix("r="))
+        .ok_or_else(invalid)?;
+    if !nonce.starts_with(client_nonce) || nonce.len() == client_nonce.len() {
+        return Err(String::from("invalid SCRAM response (nonce mismatch)"));
+    }
+
+    // Hi(), i.e. PBKDF2 with HMAC-SHA-256 and one block of output
+    let mut u = hmac_sha256::HMAC::mac([&salt[..], &1u32.to_be_bytes()].concat(), password);
+    let mut salted_password = u;
+    for _ in 1..iterations {
+        u = hmac_sha256::HMAC::mac(u, password);
+        salted_password
+            .iter_mut()
+            .zip(u.iter())
+            .for_each(|(s, u)| *s ^= u);
+    }
+
+    let client_key = hmac_sha256::HMAC::mac("Client Key", salted_password);
+    let stored_key = hmac_sha256::Hash::hash(&client_key);
+    // c=biws is the base64 of the GS2 header "n,,"
+    let without_proof = format!("c=biws,r={}", nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let client_signature = hmac_sha256::HMAC::mac(&auth_message, stored_key);
+    let mut proof = client_key;
+    proof
+        .iter_mut()
+        .zip(client_signature.iter())
+        .for_each(|(p, s)| *p ^= s);
+
+    let server_key = hmac_sha256::HMAC::mac("Server Key", salted_password);
+    let server_signature = hmac_sha256::HMAC::mac(&auth_message, server_key);
+    Ok((
+        format!("{},p={}", without_proof, base64_encode(&proof)),
+        server_signature,
+    ))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut n: u32 = 0;
+    for (i, c) in s.bytes().enumerate() {
+        n = n << 6 | BASE64.iter().position(|b| *b == c)? as u32;
+        if i % 4 == 3 {
+            out.extend_from_slice(&n.to_be_bytes()[1..]);
+            n = 0;
+        }
+    }
+    match s.len() % 4 {
+        0 => {}
+        2 => out.push((n >> 4) as u8),
+        3 => out.extend_from_slice(&((n >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+    Some(out)
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", Md5::digest(data))
+}
+
+// Sizes as SHOW reports them: "16MB", "8192", ...
+fn parse_size(s: &str) -> Option<u32> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = s[..digits].parse::<u32>().ok()?;
+    let multiplier = match &s[digits..] {
+        "" | "B" => 1,
+        "kB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    n.checked_mul(multiplier)
+}
+
+fn parse_data_row(body: &[u8]) -> Result<Row, String> {
+    let invalid = || String::from("invalid DataRow message");
+    let n = u16::from_be_bytes(body.get(..2).ok_or_else(invalid)?.try_into().unwrap());
+    let mut off = 2;
+    let mut row = Vec::with_capacity(n as usize);
+    for _ in 0..n {
+        let len = i32::from_be_bytes(
+            body.get(off..off + 4)
+                .ok_or_else(invalid)?
+                .try_into()
+                .unwrap(),
+        );
+        off += 4;
+        if len < 0 {
+            row.push(None);
+            continue;
+        }
+        let value = body.get(off..off + len as usize).ok_or_else(invalid)?;
+        row.push(Some(String::from_utf8_lossy(value).into_owned()));
+        off += len as usize;
+    }
+    Ok(row)
+}
+
+// "SEVERITY:  message" from the fields of an ErrorResponse or
+// NoticeResponse, as libpq shows them.
+fn error_fields(body: &[u8]) -> String {
+    let mut severity = "ERROR".to_string();
+    let mut message = String::new();
+    for field in body.split(|b| *b == 0).filter(|f| !f.is_empty()) {
+        let value = String::from_utf8_lossy(&field[1..]).into_owned();
+        match field[0] {
+            b'S' => severity = value,
+            b'M' => message = value,
+            _ => {}
+        }
+    }
+    format!("{}:  {}", severity, message)
+}
+
+fn unexpected_message(tag: u8) -> String {
+    format!("unexpected message type \"{}\" from server", tag as char)
+}
+
+fn lost_connection(e: std::io::Error) -> String {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        String::from("server closed the connection unexpectedly")
+    } else {
+        format!("could not receive data from server: {}", e)
+    }
+}
+
+fn cstring(s: &str) -> Vec<u8> {
+    let mut v = s.as_bytes().to_vec();
+    v.push(0);
+    v
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes(b[..4].try_into().unwrap())
+}
+
+fn be_u64(b: &[u8]) -> u64 {
+    u64::from_be_bytes(b[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::XLogReaderState;
+    use crate::waldec::{xlog_find_next_record, xlog_read_record};
+    use std::net::TcpListener;
+
+    #[test]
+    fn scram_proof_matches_rfc7677() {
+        let (client_final, server_signature) = scram_client_final(
+            "pencil",
+            "n=user,r=rOprNGfwEbeRWgbNEkqO",
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        )
+        .unwrap();
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert_eq!(
+            base64_encode(&server_signature),
+            "6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        );
+        assert_eq!(base64_decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap().len(), 16);
+
+        let params = ConnParams::parse("host=/tmp port = 5433 user='a b\\'c'").unwrap();
+        assert_eq!((params.host.as_str(), params.port), ("/tmp", 5433));
+        assert_eq!(params.user, "a b'c");
+        let params =
+            ConnParams::parse("postgresql://rep:p%40ss@db:5434/x?application_name=t").unwrap();
+        assert_eq!((params.host.as_str(), params.port), ("db", 5434));
+        assert_eq!(params.password.as_deref(), Some("p@ss"));
+        assert_eq!(params.application_name, "t");
+        assert!(ConnParams::parse("host").is_err());
+        assert!(ConnParams::parse("sslmode=require").is_err());
+    }
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![tag];
+        msg.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    // RowDescription, DataRow, CommandComplete and ReadyForQuery of a
+    // one-row result of text columns
+    fn result(values: &[Option<&str>]) -> Vec<u8> {
+        let mut desc = (values.len() as u16).to_be_bytes().to_vec();
+        let mut row = desc.clone();
+        for (i, value) in values.iter().enumerate() {
+            desc.extend_from_slice(&cstring(&format!("col{}", i)));
+            desc.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 25, 0xFF, 0xFF]);
+            desc.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+            match value {
+                Some(v) => {
+                    row.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                    row.extend_from_slice(v.as_bytes());
+                }
+                None => row.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        [
+            message(b'T', &desc),
+            message(b'D', &row),
+            message(b'C', &cstring("SELECT 1")),
+            message(b'Z', b"I"),
+        ]
+        .concat()
+    }
+
+    // A walsender that serves segment 1 of timeline 1 from memory, and with
+    // hang_up closes the connection once it has sent it.  Returns what the
+    // client sent: the startup parameters, the commands, and the flush
+    // location of each status update.
+    fn mock_walsender(listener: TcpListener, segment: Vec<u8>, hang_up: bool) -> Vec<String> {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut log = Vec::new();
+
+        let mut len = [0u8; 4];
+        sock.read_exact(&mut len).unwrap();
+        let mut startup = vec![0u8; be_u32(&len) as usize - 4];
+        sock.read_exact(&mut startup).unwrap();
+        log.push(String::from_utf8_lossy(&startup[4..]).replace('\0', " "));
+        let sysid = u64::from_le_bytes(segment[24..32].try_into().unwrap());
+        sock.write_all(
+            &[
+                message(b'R', &AUTH_REQ_OK.to_be_bytes()),
+                message(b'S', b"server_version\x0015.4\x00"),
+                message(b'Z', b"I"),
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        loop {
+            let mut hdr = [0u8; 5];
+            if sock.read_exact(&mut hdr).is_err() {
+                break;
+            }
+            let mut body = vec![0u8; be_u32(&hdr[1..]) as usize - 4];
+            sock.read_exact(&mut body).unwrap();
+            match hdr[0] {
+                b'Q' => {
+                    let command = String::from_utf8_lossy(&body[..body.len() - 1]).into_owned();
+                    let response = match command.as_str() {
+                        "IDENTIFY_SYSTEM" => {
+                            result(&[Some(&sysid.to_string()), Some("1"), Some("0/1004000"), None])
+                        }
+                        "SHOW wal_segment_size" => result(&[Some("16MB")]),
+                        "SHOW wal_block_size" => result(&[Some("8192")]),
+                        _ => {
+                            assert!(command.starts_with("START_REPLICATION"));
+                            let segstart = 0x1000000u64;
+                            let mut out = message(b'W', &[0, 0, 0]);
+                            // the segment in uneven pieces, with a keepalive
+                            // asking for a reply before the last one
+                            let chunks: Vec<&[u8]> = segment.chunks(3000).collect();
+                            for (i, chunk) in chunks.iter().enumerate() {
+                                let start = segstart + i as u64 * 3000;
+                                if i == chunks.len() - 1 {
+                                    let mut k = vec![b'k'];
+                                    k.extend_from_slice(&start.to_be_bytes());
+                                    k.extend_from_slice(&[0; 8]);
+                                    k.push(1);
+                                    out.extend(message(b'd', &k));
+                                }
+                                let mut w = vec![b'w'];
+                                w.extend_from_slice(&start.to_be_bytes());
+                                w.extend_from_slice(&(start + chunk.len() as u64).to_be_bytes());
+                                w.extend_from_slice(&[0; 8]);
+                                w.extend_from_slice(chunk);
+                                out.extend(message(b'd', &w));
+                            }
+                            out
+                        }
+                    };
+                    log.push(command.clone());
+                    sock.write_all(&response).unwrap();
+                    if hang_up && command.starts_with("START_REPLICATION") {
+                        sock.shutdown(std::net::Shutdown::Write).unwrap();
+                    }
+                }
+                b'd' if body[0] == b'r' => {
+                    log.push(format!("flush {}", lsn_out(be_u64(&body[9..]))));
+                }
+                b'X' => break,
+                tag => panic!("unexpected message {}", tag as char),
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn wal_is_streamed_from_mock_walsender() {
+        let segment = std::fs::read("test/000000010000000000000001").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let seglen = segment.len() as u64;
+        let mock = std::thread::spawn(move || mock_walsender(listener, segment, false));
+
+        let params = ConnParams::parse(&format!("host=127.0.0.1 port={} user=rep", port)).unwrap();
+        let mut conn = ReplicationConnection::connect(&params).unwrap();
+        let system = conn.identify_system().unwrap();
+        assert_eq!((system.timeline, system.xlogpos), (1, 0x1004000));
+        let config = conn.wal_config(system.system_identifier, None).unwrap();
+        assert_eq!(config.version, PgVersion::V15);
+        assert_eq!(config.wal_seg_sz, 16 * 1024 * 1024);
+        let history = conn.timeline_history(system.timeline).unwrap();
+
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: 0x1000000,
+            endptr: 0x1000000 + seglen,
+            history: history.clone(),
+            ..Default::default()
+        };
+        let source =
+            StreamSource::new(conn, Some(String::from("dump")), config.wal_seg_sz, history);
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        assert_eq!(xlog_find_next_record(&mut state), 0x1000028);
+        let mut n = 0;
+        while xlog_read_record(&mut state) {
+            n += 1;
+        }
+        assert_eq!(n, 85);
+        drop(state);
+
+        let log = mock.join().unwrap();
+        assert!(log[0].contains("user rep replication true"));
+        assert!(log.contains(&String::from(
+            "START_REPLICATION SLOT \"dump\" PHYSICAL 0/01000000 TIMELINE 1"
+        )));
+        assert!(log.iter().any(|l| l.starts_with("flush 0/01")));
+    }
+
+    #[test]
+    fn lost_connection_ends_the_wal() {
+        let segment = std::fs::read("test/000000010000000000000001").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let seglen = segment.len() as u64;
+        let sent = segment[..10000].to_vec();
+        let mock = std::thread::spawn(move || mock_walsender(listener, sent, true));
+
+        let params = ConnParams::parse(&format!("host=127.0.0.1 port={} user=rep", port)).unwrap();
+        let mut conn = ReplicationConnection::connect(&params).unwrap();
+        let system = conn.identify_system().unwrap();
+        let config = conn.wal_config(system.system_identifier, None).unwrap();
+        let history = conn.timeline_history(system.timeline).unwrap();
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: 0x1000000,
+            endptr: 0x1000000 + seglen,
+            history: history.clone(),
+            ..Default::default()
+        };
+        let source = StreamSource::new(conn, None, config.wal_seg_sz, history);
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        assert_eq!(xlog_find_next_record(&mut state), 0x1000028);
+        let mut n = 0;
+        while xlog_read_record(&mut state) {
+            n += 1;
+        }
+        assert!(n > 0 && n < 85);
+        assert_eq!(state.errmsg, "server closed the connection unexpectedly");
+        assert!(state.read_recptr < 0x1000000 + 10000);
+        drop(state);
+        mock.join().unwrap();
+    }
+}
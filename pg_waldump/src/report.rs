@@ -0,0 +1,651 @@
+// A WAL range as one self-contained HTML page, for --html.
+//
+// Everything is gathered while the records are read, in one pass: the
+// statistics of --stats per rmgr and record type, the bytes of records and
+// of full page images per segment and per minute, the block references per
+// relation and block, the checkpoints, and the errors that ended the WAL.
+// The charts are inline SVG and the page has no scripts, so it can be
+// mailed or attached to a ticket as is.
+//
+// Records carry no time themselves; a record is counted in the minute of
+// the last commit, abort, prepare or checkpoint record before it, and the
+// records before the first of those in its minute.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::pgtypes::*;
+use crate::rmgr::{xact_end, xlog_checkpoint, RmgrIds};
+use crate::state::XLogReaderState;
+use crate::stats::{Stats, XLogStats};
+use crate::summary::RecordSummary;
+use crate::util::xlog_byte_to_seg;
+use crate::version::PgVersion;
+use crate::waldec::lsn_format;
+
+// at most this many bars per chart; buckets are merged to fit
+const MAX_BARS: usize = 120;
+// rows of the top relations and blocks
+const TOP_ROWS: usize = 20;
+
+const CHART_WIDTH: u32 = 840;
+const CHART_HEIGHT: u32 = 180;
+// room on the left for the axis labels, and below for the bucket labels
+const MARGIN_LEFT: u32 = 70;
+const MARGIN_BOTTOM: u32 = 20;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; font-size: 0.9em; }
+th, td { padding: 2px 10px; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+tr:nth-child(even) { background: #f4f4f4; }
+.rec { fill: #4e79a7; background: #4e79a7; }
+.fpi { fill: #f28e2b; background: #f28e2b; }
+.online { stroke: #4e79a7; }
+.shutdown { stroke: #e15759; }
+.share { display: flex; width: 400px; height: 12px; }
+.legend span { display: inline-block; width: 10px; height: 10px; margin: 0 4px 0 12px; }
+.error { color: #b00; font-family: monospace; }
+svg text { font-size: 11px; fill: #555; }
+";
+
+// Bytes of records and of their full page images.
+#[derive(Clone, Copy, Default)]
+struct Volume {
+    records: u64,
+    rec_len: u64,
+    fpi_len: u64,
+}
+
+impl Volume {
+    fn add(&mut self, other: &Volume) {
+        self.records += other.records;
+        self.rec_len += other.rec_len;
+        self.fpi_len += other.fpi_len;
+    }
+}
+
+// The block references to a relation fork or to one of its blocks.
+#[derive(Default)]
+struct RefStats {
+    refs: u64,
+    fpis: u64,
+    fpi_len: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct RelKey {
+    spc_oid: Oid,
+    db_oid: Oid,
+    rel_oid: Oid,
+    fork: &'static str,
+}
+
+struct CheckpointMark {
+    lsn: XLogRecPtr,
+    redo: XLogRecPtr,
+    shutdown: bool,
+    time: PgTime,
+    timeline: TimeLineID,
+}
+
+pub struct HtmlReport {
+    version: PgVersion,
+    system_identifier: u64,
+    wal_seg_sz: u32,
+    stats: XLogStats,
+    segments: BTreeMap<XLogSegNo, Volume>,
+    // by minute since the Unix epoch
+    minutes: BTreeMap<i64, Volume>,
+    // the time of the last record that had one, and what came before any
+    clock: Option<PgTime>,
+    untimed: Volume,
+    relations: HashMap<RelKey, RefStats>,
+    blocks: HashMap<(RelKey, BlockNumber), RefStats>,
+    checkpoints: Vec<CheckpointMark>,
+    errors: Vec<String>,
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn size_text(bytes: u64) -> String {
+    let units = ["bytes", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn time_text(t: PgTime) -> String {
+    match chrono::DateTime::from_timestamp(t, 0) {
+        Some(ts) => ts.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => t.to_string(),
+    }
+}
+
+fn pct(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * n as f64 / total as f64
+    }
+}
+
+// A stacked bar chart of the bytes of records and images, one bar per
+// bucket, labelled with the first and last bucket's labels.
+fn volume_chart(buckets: &[(String, Volume)]) -> String {
+    if buckets.is_empty() {
+        return String::from("<p>No records.</p>\n");
+    }
+    let max = buckets
+        .iter()
+        .map(|(_, v)| v.rec_len + v.fpi_len)
+        .max()
+        .unwrap()
+        .max(1);
+    let plot_width = CHART_WIDTH - MARGIN_LEFT;
+    let bar_width = plot_width as f64 / buckets.len() as f64;
+    let scale = |bytes: u64| bytes as f64 * CHART_HEIGHT as f64 / max as f64;
+
+    let mut svg = format!(
+        "<svg width=\"{}\" height=\"{}\" role=\"img\">\n",
+        CHART_WIDTH,
+        CHART_HEIGHT + MARGIN_BOTTOM
+    );
+    writeln!(
+        svg,
+        "<text x=\"{}\" y=\"10\" text-anchor=\"end\">{}</text>",
+        MARGIN_LEFT - 6,
+        size_text(max)
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">0</text>",
+        MARGIN_LEFT - 6,
+        CHART_HEIGHT
+    )
+    .unwrap();
+    for (i, (label, v)) in buckets.iter().enumerate() {
+        let x = MARGIN_LEFT as f64 + i as f64 * bar_width;
+        let rec = scale(v.rec_len);
+        let fpi = scale(v.fpi_len);
+        writeln!(
+            svg,
+            "<g><title>{}: {} records, {} of records, {} of full page images</title>\
+             <rect class=\"rec\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"/>\
+             <rect class=\"fpi\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"/></g>",
+            escape(label),
+            v.records,
+            size_text(v.rec_len),
+            size_text(v.fpi_len),
+            x,
+            CHART_HEIGHT as f64 - rec,
+            bar_width,
+            rec,
+            x,
+            CHART_HEIGHT as f64 - rec - fpi,
+            bar_width,
+            fpi
+        )
+        .unwrap();
+    }
+    writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\">{}</text>",
+        MARGIN_LEFT,
+        CHART_HEIGHT + MARGIN_BOTTOM - 4,
+        escape(&buckets[0].0)
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        CHART_WIDTH,
+        CHART_HEIGHT + MARGIN_BOTTOM - 4,
+        escape(&buckets[buckets.len() - 1].0)
+    )
+    .unwrap();
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Merge the buckets of keys first..=last, missing ones empty, into at most
+// MAX_BARS, each labelled after its first key.
+fn merge_buckets(
+    buckets: &BTreeMap<i64, Volume>,
+    label: impl Fn(i64) -> String,
+) -> Vec<(String, Volume)> {
+    let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().last()) else {
+        return Vec::new();
+    };
+    let width = ((last - first + 1) as usize).div_ceil(MAX_BARS) as i64;
+    let mut merged = Vec::new();
+    let mut key = first;
+    while key <= last {
+        let mut v = Volume::default();
+        for (_, b) in buckets.range(key..key + width) {
+            v.add(b);
+        }
+        merged.push((label(key), v));
+        key += width;
+    }
+    merged
+}
+
+impl HtmlReport {
+    pub fn new(version: PgVersion, system_identifier: u64, wal_seg_sz: u32) -> HtmlReport {
+        HtmlReport {
+            version,
+            system_identifier,
+            wal_seg_sz,
+            stats: XLogStats::default(),
+            segments: BTreeMap::new(),
+            minutes: BTreeMap::new(),
+            clock: None,
+            untimed: Volume::default(),
+            relations: HashMap::new(),
+            blocks: HashMap::new(),
+            checkpoints: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // The range starts at the first record found.
+    pub fn start(&mut self, first_record: XLogRecPtr) {
+        if self.stats.startptr == INVALID_XLOG_RECPTR {
+            self.stats.startptr = first_record;
+        }
+    }
+
+    // Count the record the reader last read.
+    pub fn add(&mut self, state: &XLogReaderState) {
+        let record = RecordSummary::from_state(state);
+        self.stats.store(&record);
+        let volume = Volume {
+            records: 1,
+            rec_len: record.rec_len as u64,
+            fpi_len: record.fpi_len as u64,
+        };
+
+        let time = if record.rmid == RmgrIds::XLOG as u8 {
+            xlog_checkpoint(state).map(|(checkpoint, shutdown)| {
+                self.checkpoints.push(CheckpointMark {
+                    lsn: record.lsn,
+                    redo: checkpoint.redo,
+                    shutdown,
+                    time: checkpoint.time,
+                    timeline: checkpoint.time_line_id,
+                });
+                checkpoint.time
+            })
+        } else if record.rmid == RmgrIds::XACT as u8 {
+            xact_end(state).map(|end| timestamptz_to_time_t(end.time))
+        } else {
+            None
+        };
+        if let Some(time) = time {
+            if self.clock.is_none() {
+                let untimed = std::mem::take(&mut self.untimed);
+                self.minutes
+                    .entry(time.div_euclid(60))
+                    .or_default()
+                    .add(&untimed);
+            }
+            self.clock = Some(time);
+        }
+        match self.clock {
+            Some(clock) => self
+                .minutes
+                .entry(clock.div_euclid(60))
+                .or_default()
+                .add(&volume),
+            None => self.untimed.add(&volume),
+        }
+        self.segments
+            .entry(xlog_byte_to_seg(record.lsn, self.wal_seg_sz))
+            .or_default()
+            .add(&volume);
+
+        for block in &record.blocks {
+            let key = RelKey {
+                spc_oid: block.locator.spc_oid,
+                db_oid: block.locator.db_oid,
+                rel_oid: block.locator.rel_oid,
+                fork: block.fork_name(),
+            };
+            let fpi_len = block.image.as_ref().map_or(0, |i| i.length as u64);
+            for stats in [
+                self.relations.entry(key).or_default(),
+                self.blocks.entry((key, block.blkno)).or_default(),
+            ] {
+                stats.refs += 1;
+                stats.fpis += block.image.is_some() as u64;
+                stats.fpi_len += fpi_len;
+            }
+        }
+    }
+
+    pub fn error(&mut self, msg: &str) {
+        self.errors.push(msg.to_string());
+    }
+
+    fn totals(&self) -> Stats {
+        self.stats
+            .rows(false, self.version)
+            .iter()
+            .fold(Stats::default(), |total, row| Stats {
+                count: total.count + row.stats.count,
+                rec_len: total.rec_len + row.stats.rec_len,
+                fpi_len: total.fpi_len + row.stats.fpi_len,
+            })
+    }
+
+    fn stats_table(&self, per_record: bool, total: Stats) -> String {
+        let mut html = String::from(
+            "<table>\n<tr><th>Type</th><th>Records</th><th>(%)</th><th>Record size</th>\
+             <th>FPI size</th><th>Combined size</th><th>(%)</th></tr>\n",
+        );
+        let total_len = total.rec_len + total.fpi_len;
+        for row in self.stats.rows(per_record, self.version) {
+            let s = row.stats;
+            if s.count == 0 {
+                continue;
+            }
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}</td></tr>",
+                escape(&row.name),
+                s.count,
+                pct(s.count, total.count),
+                size_text(s.rec_len),
+                size_text(s.fpi_len),
+                size_text(s.rec_len + s.fpi_len),
+                pct(s.rec_len + s.fpi_len, total_len)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    // The share of full page images in the bytes of each rmgr.
+    fn fpi_share(&self, total: Stats) -> String {
+        let mut rows: Vec<(String, Stats)> = self
+            .stats
+            .rows(false, self.version)
+            .into_iter()
+            .filter(|row| row.stats.count > 0)
+            .map(|row| (row.name, row.stats))
+            .collect();
+        rows.push((String::from("Total"), total));
+        let mut html = String::from(
+            "<p class=\"legend\"><span class=\"rec\"></span>records\
+             <span class=\"fpi\"></span>full page images</p>\n<table>\n",
+        );
+        for (name, s) in rows {
+            let fpi = pct(s.fpi_len, s.rec_len + s.fpi_len);
+            writeln!(
+                html,
+                "<tr><td>{}</td><td><div class=\"share\"><div class=\"rec\" style=\"width: {:.2}%\"></div>\
+                 <div class=\"fpi\" style=\"width: {:.2}%\"></div></div></td><td>{:.2}% images</td></tr>",
+                escape(&name),
+                100.0 - fpi,
+                fpi,
+                fpi
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    fn top_table<K>(
+        &self,
+        stats: &HashMap<K, RefStats>,
+        name: &str,
+        label: impl Fn(&K) -> String,
+    ) -> String
+    where
+        K: Ord,
+    {
+        let mut top: Vec<(&K, &RefStats)> = stats.iter().collect();
+        top.sort_by(|a, b| b.1.refs.cmp(&a.1.refs).then(a.0.cmp(b.0)));
+        let mut html = format!(
+            "<table>\n<tr><th>{}</th><th>Block references</th><th>Full page images</th><th>FPI size</th></tr>\n",
+            name
+        );
+        for (key, s) in top.into_iter().take(TOP_ROWS) {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&label(key)),
+                s.refs,
+                s.fpis,
+                size_text(s.fpi_len)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    // The checkpoints along the LSNs of the range, each a line at the
+    // checkpoint record from its redo point, and a table of them.
+    fn checkpoint_timeline(&self) -> String {
+        if self.checkpoints.is_empty() {
+            return String::from("<p>No checkpoints.</p>\n");
+        }
+        let (start, end) = (
+            self.stats.startptr,
+            self.stats.endptr.max(self.stats.startptr + 1),
+        );
+        let plot_width = (CHART_WIDTH - MARGIN_LEFT) as f64;
+        let x = |lsn: XLogRecPtr| {
+            MARGIN_LEFT as f64
+                + plot_width * (lsn.clamp(start, end) - start) as f64 / (end - start) as f64
+        };
+        let mut html = format!(
+            "<svg width=\"{}\" height=\"50\" role=\"img\">\n\
+             <line x1=\"{}\" y1=\"25\" x2=\"{}\" y2=\"25\" stroke=\"#ccc\"/>\n",
+            CHART_WIDTH, MARGIN_LEFT, CHART_WIDTH
+        );
+        for c in &self.checkpoints {
+            let class = if c.shutdown { "shutdown" } else { "online" };
+            writeln!(
+                html,
+                "<g class=\"{}\"><title>{} checkpoint at {}, redo {}, {}</title>\
+                 <line x1=\"{:.1}\" y1=\"25\" x2=\"{:.1}\" y2=\"25\" stroke-width=\"4\"/>\
+                 <line x1=\"{:.1}\" y1=\"10\" x2=\"{:.1}\" y2=\"40\" stroke-width=\"2\"/></g>",
+                class,
+                class,
+                lsn_format(c.lsn),
+                lsn_format(c.redo),
+                time_text(c.time),
+                x(c.redo),
+                x(c.lsn),
+                x(c.lsn),
+                x(c.lsn)
+            )
+            .unwrap();
+        }
+        html.push_str("</svg>\n");
+
+        html.push_str(
+            "<table>\n<tr><th>LSN</th><th>Kind</th><th>Redo</th><th>Timeline</th>\
+             <th>Time</th><th>WAL since previous</th></tr>\n",
+        );
+        let mut prev: Option<XLogRecPtr> = None;
+        for c in &self.checkpoints {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                lsn_format(c.lsn),
+                if c.shutdown { "shutdown" } else { "online" },
+                lsn_format(c.redo),
+                c.timeline,
+                time_text(c.time),
+                prev.map_or(String::new(), |prev| size_text(c.lsn - prev))
+            )
+            .unwrap();
+            prev = Some(c.lsn);
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    // The page.
+    pub fn render(&self) -> String {
+        let total = self.totals();
+        let title = format!(
+            "WAL between {} and {}",
+            lsn_format(self.stats.startptr),
+            lsn_format(self.stats.endptr)
+        );
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            title, STYLE, title
+        );
+
+        let times: Vec<i64> = self.minutes.keys().copied().collect();
+        writeln!(
+            html,
+            "<table>\n<tr><td>Database system identifier</td><td>{}</td></tr>\n\
+             <tr><td>WAL of PostgreSQL</td><td>{}</td></tr>\n\
+             <tr><td>Records</td><td>{}</td></tr>\n\
+             <tr><td>Record size</td><td>{}</td></tr>\n\
+             <tr><td>FPI size</td><td>{}</td></tr>\n\
+             <tr><td>Segments</td><td>{}</td></tr>\n\
+             <tr><td>Time</td><td>{}</td></tr>\n\
+             <tr><td>Errors</td><td>{}</td></tr>\n</table>",
+            self.system_identifier,
+            self.version,
+            total.count,
+            size_text(total.rec_len),
+            size_text(total.fpi_len),
+            self.segments.len(),
+            match (times.first(), times.last()) {
+                (Some(first), Some(last)) =>
+                    format!("{} to {}", time_text(first * 60), time_text(last * 60 + 59)),
+                _ => String::from("unknown"),
+            },
+            self.errors.len()
+        )
+        .unwrap();
+
+        html.push_str("<h2>Resource managers</h2>\n");
+        html.push_str(&self.stats_table(false, total));
+        html.push_str("<h2>Record types</h2>\n");
+        html.push_str(&self.stats_table(true, total));
+
+        html.push_str("<h2>WAL volume over LSN</h2>\n");
+        let segments: BTreeMap<i64, Volume> = self
+            .segments
+            .iter()
+            .map(|(&segno, &v)| (segno as i64, v))
+            .collect();
+        let wal_seg_sz = self.wal_seg_sz as u64;
+        html.push_str(&volume_chart(&merge_buckets(&segments, |segno| {
+            lsn_format(segno as u64 * wal_seg_sz)
+        })));
+        html.push_str("<h2>WAL volume over time</h2>\n");
+        html.push_str(&volume_chart(&merge_buckets(&self.minutes, |minute| {
+            time_text(minute * 60)
+        })));
+        html.push_str("<h2>Full page image share</h2>\n");
+        html.push_str(&self.fpi_share(total));
+
+        html.push_str("<h2>Top relations</h2>\n");
+        html.push_str(&self.top_table(&self.relations, "Relation", |k| {
+            format!("{}/{}/{} {}", k.spc_oid, k.db_oid, k.rel_oid, k.fork)
+        }));
+        html.push_str("<h2>Top blocks</h2>\n");
+        html.push_str(&self.top_table(&self.blocks, "Block", |(k, blkno)| {
+            format!(
+                "{}/{}/{} {} blk {}",
+                k.spc_oid, k.db_oid, k.rel_oid, k.fork, blkno
+            )
+        }));
+
+        html.push_str("<h2>Checkpoints</h2>\n");
+        html.push_str(&self.checkpoint_timeline());
+
+        html.push_str("<h2>Errors</h2>\n");
+        if self.errors.is_empty() {
+            html.push_str("<p>None; the WAL was read to its end.</p>\n");
+        }
+        for msg in &self.errors {
+            writeln!(html, "<p class=\"error\">{}</p>", escape(msg)).unwrap();
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::{bundled_segment_reader, BUNDLED_FIRST_RECORD};
+
+    #[test]
+    fn report_has_every_section() {
+        let mut state = bundled_segment_reader();
+        let config = state.config;
+        let mut report =
+            HtmlReport::new(config.version, config.system_identifier, config.wal_seg_sz);
+        report.start(BUNDLED_FIRST_RECORD);
+        while xlog_read_record(&mut state) {
+            report.add(&state);
+        }
+        report.error("invalid <record>");
+
+        let html = report.render();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>WAL between 0/1000028 and 0/1003DA8</h1>"));
+        for section in [
+            "Resource managers",
+            "Record types",
+            "WAL volume over LSN",
+            "WAL volume over time",
+            "Full page image share",
+            "Top relations",
+            "Top blocks",
+            "Checkpoints",
+            "Errors",
+        ] {
+            assert!(
+                html.contains(&format!("<h2>{}</h2>", section)),
+                "{}",
+                section
+            );
+        }
+        assert!(html.contains("<tr><td>Records</td><td>85</td></tr>"));
+        assert!(html.contains("<td>XLOG/CHECKPOINT_SHUTDOWN</td>"));
+        assert!(html.contains("<td>0/1000028</td><td>shutdown</td>"));
+        assert!(html.contains("<p class=\"error\">invalid &lt;record&gt;</p>"));
+        assert!(!html.contains("<script"));
+    }
+}
@@ -0,0 +1,186 @@
+// Fetching files with a restore_command, as archive recovery does in
+// src/backend/access/transam/xlogarchive.c.
+//
+// The command is the one from the recovery configuration, with its %f, %p,
+// %r and %% placeholders.  Files are restored into a scratch directory of
+// our own when the reader asks for them, kept there while they may be read
+// again, and removed with the directory when the RestoreCommand is dropped.
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// how many restored files are kept; the reader goes forward through the
+// WAL, so only the last few segments are ever read again
+const MAX_CACHED: usize = 8;
+
+// %r is the oldest file recovery still needs.  We never remove anything
+// from the archive, so pass the name recovery uses when it doesn't know,
+// with which archive cleanup keeps every file.
+const LAST_RESTART_POINT_FNAME: &str = "000000000000000000000000";
+
+pub struct RestoreCommand {
+    command: String,
+    dir: PathBuf,
+    // restored files, oldest first
+    cached: VecDeque<String>,
+}
+
+impl RestoreCommand {
+    // Create the scratch directory for command.
+    pub fn new(command: &str) -> Result<Self, String> {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "pg_waldump_restore_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&dir)
+            .map_err(|e| format!("could not create directory \"{}\": {}", dir.display(), e))?;
+        Ok(RestoreCommand {
+            command: command.to_string(),
+            dir,
+            cached: VecDeque::new(),
+        })
+    }
+
+    // the scratch directory the files are restored into
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Restore file fname (a segment or a history file) from the archive,
+    // returning where it was put.  A command that fails is taken to mean
+    // the archive doesn't have the file, and gives NotFound; one that was
+    // killed by a signal, or that the shell could not run, is fatal as in
+    // recovery.
+    pub fn fetch(&mut self, fname: &str) -> std::io::Result<PathBuf> {
+        let xlogpath = self.dir.join(fname);
+        if self.cached.iter().any(|f| f == fname) {
+            return Ok(xlogpath);
+        }
+
+        let command = build_restore_command(
+            &self.command,
+            xlogpath.to_str().unwrap(),
+            fname,
+            LAST_RESTART_POINT_FNAME,
+        );
+        let status = Command::new("sh").arg("-c").arg(&command).status()?;
+        if !status.success() {
+            let _ = std::fs::remove_file(&xlogpath);
+            // the shell gives the signal that killed the command as an exit
+            // status above 128, 126 and 127 when it can't run it
+            match status.code() {
+                Some(code) if code <= 125 => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                            "could not restore file \"{}\" from archive: command \"{}\" exited with status {}",
+                            fname, command, code
+                        ),
+                    ))
+                }
+                _ => panic!("restore_command \"{}\" failed: {}", command, status),
+            }
+        }
+        if !xlogpath.is_file() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "could not restore file \"{}\" from archive: command \"{}\" did not create it",
+                    fname, command
+                ),
+            ));
+        }
+
+        self.cached.push_back(fname.to_string());
+        if self.cached.len() > MAX_CACHED {
+            let oldest = self.cached.pop_front().unwrap();
+            let _ = std::fs::remove_file(self.dir.join(oldest));
+        }
+        Ok(xlogpath)
+    }
+}
+
+impl Drop for RestoreCommand {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Build the command to restore xlogfname to xlogpath, as BuildRestoreCommand
+// in src/common/archive.c does: %p is replaced by the path to restore to, %f
+// by the file name, %r by the name of the last restart point's file and %%
+// by %.  Any other % is left as it is.
+pub fn build_restore_command(
+    command: &str,
+    xlogpath: &str,
+    xlogfname: &str,
+    last_restart_point_fname: &str,
+) -> String {
+    let mut result = String::with_capacity(command.len());
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('p') => result.push_str(xlogpath),
+            Some('f') => result.push_str(xlogfname),
+            Some('r') => result.push_str(last_restart_point_fname),
+            Some('%') => result.push('%'),
+            // otherwise treat the % as not special
+            _ => {
+                result.push('%');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_are_restored_on_demand_and_cleaned_up() {
+        assert_eq!(
+            build_restore_command("cp /a/%f %p; echo 100%% %r %x%", "/tmp/x/F", "F", "R"),
+            "cp /a/F /tmp/x/F; echo 100% R %x%"
+        );
+
+        let archive =
+            std::env::temp_dir().join(format!("pg_waldump_restore_test_{}", std::process::id()));
+        std::fs::create_dir_all(&archive).unwrap();
+        std::fs::copy(
+            "test/000000010000000000000001",
+            archive.join("000000010000000000000001"),
+        )
+        .unwrap();
+
+        let mut restore =
+            RestoreCommand::new(&format!("cp \"{}/%f\" \"%p\"", archive.display())).unwrap();
+        let dir = restore.dir().to_path_buf();
+        let path = restore.fetch("000000010000000000000001").unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            std::fs::read("test/000000010000000000000001").unwrap()
+        );
+        // restored once, then read from the cache
+        std::fs::remove_file(archive.join("000000010000000000000001")).unwrap();
+        assert_eq!(restore.fetch("000000010000000000000001").unwrap(), path);
+
+        let e = restore.fetch("000000010000000000000002").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+
+        drop(restore);
+        assert!(!dir.exists());
+        std::fs::remove_dir_all(&archive).unwrap();
+    }
+}
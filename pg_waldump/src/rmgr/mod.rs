@@ -1,13 +1,18 @@
 use crate::pgtypes::*;
 use crate::state::*;
+use crate::version::PgVersion;
 mod rmgrdesc;
 use rmgrdesc::xlogdesc::*;
+pub use rmgrdesc::xlogdesc::xlog_checkpoint;
 use rmgrdesc::dbasedesc::*;
 use rmgrdesc::heapdesc::*;
 use rmgrdesc::btreedesc::*;
+use rmgrdesc::xactdesc::*;
+pub use rmgrdesc::xactdesc::{xact_end, XactEnd};
+pub use rmgrdesc::utils::{DescValue, RecordDesc};
 
 #[repr(u8)]
-pub(crate) enum RmgrIds {
+pub enum RmgrIds {
     XLOG,
     XACT,
     SMGR,
@@ -48,8 +53,8 @@ pub fn rmgr_id_is_valid(rmid: u8) -> bool {
     rmgr_id_is_builtin(rmid) || rmgr_id_is_custom(rmid)
 }
 
-type RmDesc = fn(state: &XLogReaderState) -> String;
-type RmIdentify = fn(info: u8) -> String;
+type RmDesc = fn(state: &XLogReaderState) -> RecordDesc;
+type RmIdentify = fn(info: u8, version: PgVersion) -> String;
 
 #[derive(Debug)]
 pub struct RmgrDescData {
@@ -68,147 +73,131 @@ macro_rules! pg_rmgr {
     };
 }
 
-fn xact_desc(state: &XLogReaderState) -> String {
-    String::from("xact_desc")
+fn smgr_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("smgr_desc")
 }
 
-fn xact_identify(info: u8) -> String {
-    String::from("xact_identify")
-}
-
-fn smgr_desc(state: &XLogReaderState) -> String {
-    String::from("smgr_desc")
-}
-
-fn smgr_identify(info: u8) -> String {
+fn smgr_identify(info: u8, version: PgVersion) -> String {
     String::from("smgr_identify")
 }
 
-fn clog_desc(state: &XLogReaderState) -> String {
-    String::from("clog_desc")
+fn clog_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("clog_desc")
 }
 
-fn clog_identify(info: u8) -> String {
+fn clog_identify(info: u8, version: PgVersion) -> String {
     String::from("clog_identify")
 }
 
-fn tblspc_desc(state: &XLogReaderState) -> String {
-    String::from("tblspc_desc")
+fn tblspc_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("tblspc_desc")
 }
 
-fn tblspc_identify(info: u8) -> String {
+fn tblspc_identify(info: u8, version: PgVersion) -> String {
     String::from("tblspc_identify")
 }
 
-fn multixact_desc(state: &XLogReaderState) -> String {
-    String::from("multixact_desc")
+fn multixact_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("multixact_desc")
 }
 
-fn multixact_identify(info: u8) -> String {
+fn multixact_identify(info: u8, version: PgVersion) -> String {
     String::from("multixact_identify")
 }
 
-fn relmap_desc(state: &XLogReaderState) -> String {
-    String::from("relmap_desc")
+fn relmap_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("relmap_desc")
 }
 
-fn relmap_identify(info: u8) -> String {
+fn relmap_identify(info: u8, version: PgVersion) -> String {
     String::from("relmap_identify")
 }
 
-fn standby_desc(state: &XLogReaderState) -> String {
-    String::from("standby_desc")
+fn standby_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("standby_desc")
 }
 
-fn standby_identify(info: u8) -> String {
+fn standby_identify(info: u8, version: PgVersion) -> String {
     String::from("standby_identify")
 }
 
-fn heap2_desc(state: &XLogReaderState) -> String {
-    String::from("heap2_desc")
-}
-
-fn heap2_identify(info: u8) -> String {
-    String::from("heap2_identify")
-}
-
-fn hash_desc(state: &XLogReaderState) -> String {
-    String::from("hash_desc")
+fn hash_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("hash_desc")
 }
 
-fn hash_identify(info: u8) -> String {
+fn hash_identify(info: u8, version: PgVersion) -> String {
     String::from("hash_identify")
 }
 
-fn gin_desc(state: &XLogReaderState) -> String {
-    String::from("gin_desc")
+fn gin_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("gin_desc")
 }
 
-fn gin_identify(info: u8) -> String {
+fn gin_identify(info: u8, version: PgVersion) -> String {
     String::from("gin_identify")
 }
 
-fn gist_desc(state: &XLogReaderState) -> String {
-    String::from("gist_desc")
+fn gist_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("gist_desc")
 }
 
-fn gist_identify(info: u8) -> String {
+fn gist_identify(info: u8, version: PgVersion) -> String {
     String::from("gist_identify")
 }
 
-fn seq_desc(state: &XLogReaderState) -> String {
-    String::from("seq_desc")
+fn seq_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("seq_desc")
 }
 
-fn seq_identify(info: u8) -> String {
+fn seq_identify(info: u8, version: PgVersion) -> String {
     String::from("seq_identify")
 }
 
-fn spg_desc(state: &XLogReaderState) -> String {
-    String::from("spg_desc")
+fn spg_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("spg_desc")
 }
 
-fn spg_identify(info: u8) -> String {
+fn spg_identify(info: u8, version: PgVersion) -> String {
     String::from("spg_identify")
 }
 
-fn brin_desc(state: &XLogReaderState) -> String {
-    String::from("brin_desc")
+fn brin_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("brin_desc")
 }
 
-fn brin_identify(info: u8) -> String {
+fn brin_identify(info: u8, version: PgVersion) -> String {
     String::from("brin_identify")
 }
 
-fn commit_ts_desc(state: &XLogReaderState) -> String {
-    String::from("commit_ts_desc")
+fn commit_ts_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("commit_ts_desc")
 }
 
-fn commit_ts_identify(info: u8) -> String {
+fn commit_ts_identify(info: u8, version: PgVersion) -> String {
     String::from("commit_ts_identify")
 }
 
-fn replorigin_desc(state: &XLogReaderState) -> String {
-    String::from("replorigin_desc")
+fn replorigin_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("replorigin_desc")
 }
 
-fn replorigin_identify(info: u8) -> String {
+fn replorigin_identify(info: u8, version: PgVersion) -> String {
     String::from("replorigin_identify")
 }
 
-fn generic_desc(state: &XLogReaderState) -> String {
-    String::from("generic_desc")
+fn generic_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("generic_desc")
 }
 
-fn generic_identify(info: u8) -> String {
+fn generic_identify(info: u8, version: PgVersion) -> String {
     String::from("generic_identify")
 }
 
-fn logicalmsg_desc(state: &XLogReaderState) -> String {
-    String::from("logicalmsg_desc")
+fn logicalmsg_desc(state: &XLogReaderState) -> RecordDesc {
+    RecordDesc::from("logicalmsg_desc")
 }
 
-fn logicalmsg_identify(info: u8) -> String {
+fn logicalmsg_identify(info: u8, version: PgVersion) -> String {
     String::from("logicalmsg_identify")
 }
 
@@ -3,8 +3,8 @@ use crate::constant::*;
 use crate::pgtypes::*;
 use crate::state::*;
 use crate::util;
+use crate::version::PgVersion;
 use std::mem;
-use std::cell::RefCell;
 
 #[repr(C)]
 struct XLogBtreeInsert {
@@ -45,6 +45,22 @@ fn size_of_btree_dedup() -> usize {
     mem::size_of::<XLogBtreeDedup>()
 }
 
+// xl_btree_reuse_page of PostgreSQL 13
+#[repr(C)]
+struct XLogBtreeReusePage13 {
+    node: RelFileLocator,
+    block: BlockNumber,
+    latest_removed_xid: TransactionId,
+}
+
+// xl_btree_reuse_page of PostgreSQL 14 and 15
+#[repr(C)]
+struct XLogBtreeReusePage14 {
+    node: RelFileLocator,
+    block: BlockNumber,
+    latest_removed_full_xid: FullTransactionId,
+}
+
 #[repr(C)]
 struct XLogBtreeReusePage {
     locator: RelFileLocator,
@@ -77,6 +93,24 @@ fn size_of_btree_vacuum() -> usize {
     mem::size_of::<XLogBtreeVacuum>()
 }
 
+// xl_btree_delete of PostgreSQL 13; the deleted offsets follow in the
+// main data rather than in the payload of blk 0
+#[repr(C)]
+struct XLogBtreeDelete13 {
+    latest_removed_xid: TransactionId,
+    ndeleted: u32,
+}
+
+const SIZE_OF_BTREE_DELETE_13: usize = 8;
+
+// xl_btree_delete of PostgreSQL 14 and 15
+#[repr(C)]
+struct XLogBtreeDelete14 {
+    latest_removed_xid: TransactionId,
+    ndeleted: u16,
+    nupdated: u16,
+}
+
 #[repr(C)]
 struct XLogBtreeDelete {
     snapshot_conflict_horizon: TransactionId,
@@ -121,6 +155,21 @@ fn size_of_btree_mark_page_half_dead() -> usize {
     mem::size_of::<XLogBtreeMarkPageHalfDead>()
 }
 
+// xl_btree_unlink_page of PostgreSQL 13
+#[repr(C)]
+struct XLogBtreeUnlinkPage13 {
+    leftsib: BlockNumber,  //  target block's left sibling, if any
+    rightsib: BlockNumber, //  target block's right sibling
+
+    // Information needed to recreate the leaf page, when target is an
+    // internal page.
+    leafleftsib: BlockNumber,
+    leafrightsib: BlockNumber,
+    topparent: BlockNumber, //  next child down in the branch
+
+    btpo_xact: TransactionId, //  value of btpo.xact for use in recovery
+}
+
 #[repr(C)]
 struct XLogBtreeUnlinkPage {
     leftsib: BlockNumber,       //  target block's left sibling, if any
@@ -158,6 +207,19 @@ fn size_of_btree_newroot() -> usize {
     mem::size_of::<XLogBtreeNewroot>()
 }
 
+// xl_btree_metadata of PostgreSQL 13
+#[repr(C)]
+struct XLogBtreeMetadata13 {
+    version: u32,
+    root: BlockNumber,
+    level: u32,
+    fastroot: BlockNumber,
+    fastlevel: u32,
+    oldest_btpo_xact: TransactionId,
+    last_cleanup_num_heap_tuples: f64,
+    allequalimage: bool,
+}
+
 #[repr(C)]
 struct XLogBtreeMetadata {
     version: u32,
@@ -208,45 +270,28 @@ const XLOG_BTREE_META_CLEANUP: u8 = 0xE0;
 #[inline]
 fn xlog_rec_has_block_data(decoder: &XLogReaderState, block_id: u8) -> bool {
     let record = decoder.record.as_ref().unwrap();
-    let blocks = record.blocks.as_ref().unwrap();
-    blocks[block_id as usize].borrow().has_data
+    record.block(block_id).is_some_and(|blk| blk.has_data)
 }
 
-fn delvacuum_desc(block_data: &[u8], ndeleted: u16, nupdated: u16) -> String {
-    let mut rst = String::new();
+fn delvacuum_desc(rst: &mut RecordDesc, block_data: &[u8], ndeleted: u16, nupdated: u16) {
     // Output deleted page offset number array
     rst.push_str(", deleted:");
-    let deletedoffsets = vec![OffsetNumber::default(); ndeleted as usize];
-    unsafe {
-        std::ptr::copy_nonoverlapping(
-            block_data.as_ptr(),
-            deletedoffsets.as_ptr() as *mut u8,
-            ndeleted as usize * mem::size_of::<OffsetNumber>(),
-        );
-    }
-    rst += &array_desc(deletedoffsets.as_slice(), offset_elem_desc);
+    let deletedoffsets = offsets_at(block_data, 0, ndeleted as usize);
+    *rst += &array_desc(deletedoffsets.as_slice(), offset_elem_desc);
+    rst.set("deleted", deletedoffsets);
 
     // Output updates as an array of "update objects", where each element
     // contains a page offset number from updated array.  (This is not the
     // most literal representation of the underlying physical data structure
     // that we could use.  Readability seems more important here.)
     rst.push_str(", updated: [");
-    let updatedoffsets = unsafe {
-        std::slice::from_raw_parts(
-            block_data
-                .as_ptr()
-                .add(ndeleted as usize * mem::size_of::<OffsetNumber>())
-                as *const OffsetNumber,
-            nupdated as usize,
-        )
-    };
-    let mut updates = unsafe {
-        &*((updatedoffsets.as_ptr() as *const u8)
-            .add((nupdated as usize) * mem::size_of::<OffsetNumber>())
-            as *mut XLogBtreeUpdate)
-    };
+    let mut cursor = ndeleted as usize * mem::size_of::<OffsetNumber>();
+    let updatedoffsets = offsets_at(block_data, cursor, nupdated as usize);
+    cursor += nupdated as usize * mem::size_of::<OffsetNumber>();
+    let mut updated = Vec::new();
     for i in 0..nupdated as usize {
         let off = updatedoffsets[i];
+        let updates = read_struct::<XLogBtreeUpdate>(block_data.get(cursor..).unwrap_or_default());
 
         assert!(offset_number_is_valid(off));
         assert!(updates.ndeletedtids > 0);
@@ -254,99 +299,134 @@ fn delvacuum_desc(block_data: &[u8], ndeleted: u16, nupdated: u16) -> String {
         // "ptid" is the symbol name used when building each xl_btree_update's
         // array of offsets into a posting list tuple's ItemPointerData array.
         // xl_btree_update describes a subset of the existing TIDs to delete.
-        rst += &format!(
+        *rst += &format!(
             "{{ off: {}, nptids: {}, ptids: [",
             off, updates.ndeletedtids
         );
-        for p in 0..updates.ndeletedtids {
-            let ptid = unsafe {
-                *((updates as *const XLogBtreeUpdate as *const u8)
-                    .add(SIZE_OF_BTREE_UPDATE) as *const u16).add(p as usize)
-            };
-            rst += &format!("{}", ptid);
-
-            if (p < updates.ndeletedtids - 1) {
+        let ptids = offsets_at(
+            block_data,
+            cursor + SIZE_OF_BTREE_UPDATE,
+            updates.ndeletedtids as usize,
+        );
+        for (p, ptid) in ptids.iter().enumerate() {
+            *rst += &format!("{}", ptid);
+
+            if p < updates.ndeletedtids as usize - 1 {
                 rst.push_str(", ");
             }
         }
         rst.push_str("] }");
+        updated.push(DescValue::object([
+            ("off", off.into()),
+            ("nptids", updates.ndeletedtids.into()),
+            ("ptids", ptids.into()),
+        ]));
         if i < nupdated as usize - 1 {
             rst.push_str(", ");
         }
 
-        updates = unsafe {
-            &*((updates as *const XLogBtreeUpdate as *const u8)
-                .add(SIZE_OF_BTREE_UPDATE + updates.ndeletedtids as usize * mem::size_of::<u16>())
-                as *mut XLogBtreeUpdate)
-        };
+        cursor += SIZE_OF_BTREE_UPDATE + updates.ndeletedtids as usize * mem::size_of::<u16>();
     }
-    rst.push(']');
-    rst
+    rst.push_str("]");
+    rst.set("updated", updated);
 }
 
 // Returns the data associated with a block reference, or NULL if there is
-// no data (e.g. because a full-page image was taken instead). The returned
-// pointer points to a MAXALIGNed buffer.
-fn xlog_rec_get_block_data(state: &XLogReaderState, block_id: u8) -> Option<&RefCell<DecodedBkpBlock>> {
-    let record = state.record.as_ref().unwrap();
-    let blocks = record.blocks.as_ref().unwrap();
-    let bkpb = blocks[block_id as usize].borrow();
-
-    if block_id > record.max_block_id as u8 || !bkpb.in_use || !bkpb.has_data {
-        None
-    } else {
-        // Some(bkpb.data.as_slice())
-        Some(&blocks[block_id as usize])
-    }
+// no data (e.g. because a full-page image was taken instead).
+fn xlog_rec_get_block_data(state: &XLogReaderState, block_id: u8) -> Option<&[u8]> {
+    state.record().unwrap().block_data(block_id)
 }
 
-pub fn btree_desc(state: &XLogReaderState) -> String {
-    let record = state.record.as_ref().unwrap();
-    if record.main_data.is_none() {
-        return String::new();
-    }
-    let main_data = record.main_data.as_ref().unwrap();
-    let rec = main_data.as_slice();
+pub fn btree_desc(state: &XLogReaderState) -> RecordDesc {
+    let version = state.config.version;
+    let record = state.record().unwrap();
     let info = record.header.xl_info & !XLR_INFO_MASK;
-    let mut rst = String::new();
+    // META_CLEANUP has only the metapage in its block data
+    let rec = match record.main_data() {
+        Some(rec) => rec,
+        None if info == XLOG_BTREE_META_CLEANUP => &[],
+        None => return RecordDesc::default(),
+    };
+
+    if state.upstream && version < PgVersion::V16 {
+        return RecordDesc::from(btree_desc_15(state, rec, info, version));
+    }
+
+    let mut rst = RecordDesc::default();
 
     match info {
         XLOG_BTREE_INSERT_LEAF
         | XLOG_BTREE_INSERT_UPPER
         | XLOG_BTREE_INSERT_META
         | XLOG_BTREE_INSERT_POST => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeInsert) };
+            let xlrec = read_struct::<XLogBtreeInsert>(rec);
 
             rst += &format!("off: {}", xlrec.offnum);
+            rst.set("off", xlrec.offnum);
         }
         XLOG_BTREE_SPLIT_L | XLOG_BTREE_SPLIT_R => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeSplit) };
+            let xlrec = read_struct::<XLogBtreeSplit>(rec);
 
             rst += &format!(
                 "level: {}, firstrightoff: {}, newitemoff: {}, postingoff: {}",
                 xlrec.level, xlrec.firstrightoff, xlrec.newitemoff, xlrec.postingoff
             );
+            rst.set("level", xlrec.level);
+            rst.set("firstrightoff", xlrec.firstrightoff);
+            rst.set("newitemoff", xlrec.newitemoff);
+            rst.set("postingoff", xlrec.postingoff);
         }
         XLOG_BTREE_DEDUP => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeDedup) };
+            let xlrec = read_struct::<XLogBtreeDedup>(rec);
 
             rst += &format!("nintervals: {}", xlrec.nintervals);
+            rst.set("nintervals", xlrec.nintervals);
         }
         XLOG_BTREE_VACUUM => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeVacuum) };
+            let xlrec = read_struct::<XLogBtreeVacuum>(rec);
 
             rst += &format!("ndeleted: {}, nupdated: {}", xlrec.ndeleted, xlrec.nupdated);
+            rst.set("ndeleted", xlrec.ndeleted);
+            rst.set("nupdated", xlrec.nupdated);
+
+            if xlog_rec_has_block_data(state, 0) {
+                if let Some(data) = xlog_rec_get_block_data(state, 0) {
+                    delvacuum_desc(&mut rst, data, xlrec.ndeleted, xlrec.nupdated);
+                }
+            }
+        }
+        XLOG_BTREE_DELETE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeDelete13>(rec);
+
+            rst += &format!(
+                "latestRemovedXid: {}, ndeleted: {}, deleted:",
+                xlrec.latest_removed_xid, xlrec.ndeleted
+            );
+            rst.set("latestRemovedXid", xlrec.latest_removed_xid);
+            rst.set("ndeleted", xlrec.ndeleted);
+            let deleted = offsets_at(rec, SIZE_OF_BTREE_DELETE_13, xlrec.ndeleted as usize);
+            rst += &array_desc(&deleted, offset_elem_desc);
+            rst.set("deleted", deleted);
+        }
+        XLOG_BTREE_DELETE if version < PgVersion::V16 => {
+            let xlrec = read_struct::<XLogBtreeDelete14>(rec);
+
+            rst += &format!(
+                "latestRemovedXid: {}, ndeleted: {}, nupdated: {}",
+                xlrec.latest_removed_xid, xlrec.ndeleted, xlrec.nupdated
+            );
+            rst.set("latestRemovedXid", xlrec.latest_removed_xid);
+            rst.set("ndeleted", xlrec.ndeleted);
+            rst.set("nupdated", xlrec.nupdated);
 
             if xlog_rec_has_block_data(state, 0) {
                 if let Some(data) = xlog_rec_get_block_data(state, 0) {
-                    let data = data.borrow();
-                    let data = data.data.as_slice();
-                    rst += &delvacuum_desc(data, xlrec.ndeleted, xlrec.nupdated);
+                    delvacuum_desc(&mut rst, data, xlrec.ndeleted, xlrec.nupdated);
                 }
             }
         }
         XLOG_BTREE_DELETE => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeDelete) };
+            let xlrec = read_struct::<XLogBtreeDelete>(rec);
 
             rst += &format!(
                 "snapshotConflictHorizon: {}, ndeleted: {}, nupdated: {}, isCatalogRel: {}",
@@ -355,25 +435,50 @@ pub fn btree_desc(state: &XLogReaderState) -> String {
                 xlrec.nupdated,
                 if xlrec.is_catalog_rel { 'T' } else { 'F' }
             );
+            rst.set("snapshotConflictHorizon", xlrec.snapshot_conflict_horizon);
+            rst.set("ndeleted", xlrec.ndeleted);
+            rst.set("nupdated", xlrec.nupdated);
+            rst.set("isCatalogRel", xlrec.is_catalog_rel);
 
             if xlog_rec_has_block_data(state, 0) {
                 if let Some(data) = xlog_rec_get_block_data(state, 0) {
-                    let data = data.borrow();
-                    let data = data.data.as_slice();
-                    rst += &delvacuum_desc(data, xlrec.ndeleted, xlrec.nupdated);
+                    delvacuum_desc(&mut rst, data, xlrec.ndeleted, xlrec.nupdated);
                 }
             }
         }
         XLOG_BTREE_MARK_PAGE_HALFDEAD => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeMarkPageHalfDead) };
+            let xlrec = read_struct::<XLogBtreeMarkPageHalfDead>(rec);
 
             rst += &format!(
                 "topparent: {}, leaf: {}, left: {}, right: {}",
                 xlrec.topparent, xlrec.leafblk, xlrec.leftblk, xlrec.rightblk
             );
+            rst.set("topparent", xlrec.topparent);
+            rst.set("leaf", xlrec.leafblk);
+            rst.set("left", xlrec.leftblk);
+            rst.set("right", xlrec.rightblk);
+        }
+        XLOG_BTREE_UNLINK_PAGE_META | XLOG_BTREE_UNLINK_PAGE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeUnlinkPage13>(rec);
+
+            rst += &format!(
+                "left: {}, right: {}, btpo_xact: {}, leafleft: {}, leafright: {}, topparent: {}",
+                xlrec.leftsib,
+                xlrec.rightsib,
+                xlrec.btpo_xact,
+                xlrec.leafleftsib,
+                xlrec.leafrightsib,
+                xlrec.topparent
+            );
+            rst.set("left", xlrec.leftsib);
+            rst.set("right", xlrec.rightsib);
+            rst.set("btpo_xact", xlrec.btpo_xact);
+            rst.set("leafleft", xlrec.leafleftsib);
+            rst.set("leafright", xlrec.leafrightsib);
+            rst.set("topparent", xlrec.topparent);
         }
         XLOG_BTREE_UNLINK_PAGE_META | XLOG_BTREE_UNLINK_PAGE => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeUnlinkPage) };
+            let xlrec = read_struct::<XLogBtreeUnlinkPage>(rec);
 
             rst += &format!(
                 "left: {}, right: {}, level: {}, safexid: {}:{}, ",
@@ -387,14 +492,46 @@ pub fn btree_desc(state: &XLogReaderState) -> String {
                 "leafleft: {}, leafright: {}, leaftopparent: {}",
                 xlrec.leafleftsib, xlrec.leafrightsib, xlrec.leaftopparent
             );
+            rst.set("left", xlrec.leftsib);
+            rst.set("right", xlrec.rightsib);
+            rst.set("level", xlrec.level);
+            rst.set("safexid", xlrec.safexid);
+            rst.set("leafleft", xlrec.leafleftsib);
+            rst.set("leafright", xlrec.leafrightsib);
+            rst.set("leaftopparent", xlrec.leaftopparent);
         }
         XLOG_BTREE_NEWROOT => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeNewroot) };
+            let xlrec = read_struct::<XLogBtreeNewroot>(rec);
 
             rst += &format!("level: {}", xlrec.level);
+            rst.set("level", xlrec.level);
+        }
+        XLOG_BTREE_REUSE_PAGE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeReusePage13>(rec);
+
+            rst += &format!(
+                "rel: {}/{}/{}, latestRemovedXid: {}",
+                xlrec.node.spc_oid, xlrec.node.db_oid, xlrec.node.rel_oid, xlrec.latest_removed_xid
+            );
+            rst.set("rel", rel_value(&xlrec.node));
+            rst.set("latestRemovedXid", xlrec.latest_removed_xid);
+        }
+        XLOG_BTREE_REUSE_PAGE if version < PgVersion::V16 => {
+            let xlrec = read_struct::<XLogBtreeReusePage14>(rec);
+
+            rst += &format!(
+                "rel: {}/{}/{}, latestRemovedXid: {}:{}",
+                xlrec.node.spc_oid,
+                xlrec.node.db_oid,
+                xlrec.node.rel_oid,
+                util::epoch_from_full_transaction_id(xlrec.latest_removed_full_xid),
+                util::xid_from_full_transaction_id(xlrec.latest_removed_full_xid)
+            );
+            rst.set("rel", rel_value(&xlrec.node));
+            rst.set("latestRemovedXid", xlrec.latest_removed_full_xid);
         }
         XLOG_BTREE_REUSE_PAGE => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XLogBtreeReusePage) };
+            let xlrec = read_struct::<XLogBtreeReusePage>(rec);
 
             rst += &format!(
                 "rel: {}/{}/{}, snapshotConflictHorizon: {}:{}, isCatalogRel: {}",
@@ -405,14 +542,32 @@ pub fn btree_desc(state: &XLogReaderState) -> String {
                 util::xid_from_full_transaction_id(xlrec.snapshot_conflict_horizon),
                 if xlrec.is_catalog_rel { 'T' } else { 'F' }
             );
+            rst.set("rel", rel_value(&xlrec.locator));
+            rst.set("snapshotConflictHorizon", xlrec.snapshot_conflict_horizon);
+            rst.set("isCatalogRel", xlrec.is_catalog_rel);
+        }
+        XLOG_BTREE_META_CLEANUP if version == PgVersion::V13 => {
+            if let Some(data) = xlog_rec_get_block_data(state, 0) {
+                let xlrec = read_struct::<XLogBtreeMetadata13>(data);
+                rst += &format!(
+                    "oldest_btpo_xact: {}, last_cleanup_num_heap_tuples: {:.6}",
+                    xlrec.oldest_btpo_xact, xlrec.last_cleanup_num_heap_tuples
+                );
+                rst.set("oldest_btpo_xact", xlrec.oldest_btpo_xact);
+                rst.set(
+                    "last_cleanup_num_heap_tuples",
+                    xlrec.last_cleanup_num_heap_tuples,
+                );
+            }
         }
         XLOG_BTREE_META_CLEANUP => {
             if let Some(data) = xlog_rec_get_block_data(state, 0) {
-                let xlrec = unsafe { &*(data.as_ptr() as *const XLogBtreeMetadata) };
+                let xlrec = read_struct::<XLogBtreeMetadata>(data);
                 rst += &format!(
                     "last_cleanup_num_delpages: {}",
                     xlrec.last_cleanup_num_delpages
                 );
+                rst.set("last_cleanup_num_delpages", xlrec.last_cleanup_num_delpages);
             }
         }
         _ => panic!("invalid info for btree: {}", info),
@@ -420,7 +575,136 @@ pub fn btree_desc(state: &XLogReaderState) -> String {
     rst
 }
 
-pub fn btree_identify(info: u8) -> String {
+// btree_desc() of PostgreSQL 15 and before, which separated the fields with
+// semicolons and left out the offsets in the block data.
+fn btree_desc_15(state: &XLogReaderState, rec: &[u8], info: u8, version: PgVersion) -> String {
+    match info {
+        XLOG_BTREE_INSERT_LEAF
+        | XLOG_BTREE_INSERT_UPPER
+        | XLOG_BTREE_INSERT_META
+        | XLOG_BTREE_INSERT_POST => {
+            let xlrec = read_struct::<XLogBtreeInsert>(rec);
+
+            format!("off {}", xlrec.offnum)
+        }
+        XLOG_BTREE_SPLIT_L | XLOG_BTREE_SPLIT_R => {
+            let xlrec = read_struct::<XLogBtreeSplit>(rec);
+
+            format!(
+                "level {}, firstrightoff {}, newitemoff {}, postingoff {}",
+                xlrec.level, xlrec.firstrightoff, xlrec.newitemoff, xlrec.postingoff
+            )
+        }
+        XLOG_BTREE_DEDUP => {
+            let xlrec = read_struct::<XLogBtreeDedup>(rec);
+
+            format!("nintervals {}", xlrec.nintervals)
+        }
+        XLOG_BTREE_VACUUM => {
+            let xlrec = read_struct::<XLogBtreeVacuum>(rec);
+
+            format!("ndeleted {}; nupdated {}", xlrec.ndeleted, xlrec.nupdated)
+        }
+        XLOG_BTREE_DELETE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeDelete13>(rec);
+
+            format!(
+                "latestRemovedXid {}; ndeleted {}",
+                xlrec.latest_removed_xid, xlrec.ndeleted
+            )
+        }
+        XLOG_BTREE_DELETE => {
+            let xlrec = read_struct::<XLogBtreeDelete14>(rec);
+
+            format!(
+                "latestRemovedXid {}; ndeleted {}; nupdated {}",
+                xlrec.latest_removed_xid, xlrec.ndeleted, xlrec.nupdated
+            )
+        }
+        XLOG_BTREE_MARK_PAGE_HALFDEAD => {
+            let xlrec = read_struct::<XLogBtreeMarkPageHalfDead>(rec);
+
+            format!(
+                "topparent {}; leaf {}; left {}; right {}",
+                xlrec.topparent, xlrec.leafblk, xlrec.leftblk, xlrec.rightblk
+            )
+        }
+        XLOG_BTREE_UNLINK_PAGE_META | XLOG_BTREE_UNLINK_PAGE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeUnlinkPage13>(rec);
+
+            format!(
+                "left {}; right {}; btpo_xact {}; leafleft {}; leafright {}; topparent {}",
+                xlrec.leftsib,
+                xlrec.rightsib,
+                xlrec.btpo_xact,
+                xlrec.leafleftsib,
+                xlrec.leafrightsib,
+                xlrec.topparent
+            )
+        }
+        XLOG_BTREE_UNLINK_PAGE_META | XLOG_BTREE_UNLINK_PAGE => {
+            let xlrec = read_struct::<XLogBtreeUnlinkPage>(rec);
+
+            format!(
+                "left {}; right {}; level {}; safexid {}:{}; \
+                 leafleft {}; leafright {}; leaftopparent {}",
+                xlrec.leftsib,
+                xlrec.rightsib,
+                xlrec.level,
+                util::epoch_from_full_transaction_id(xlrec.safexid),
+                util::xid_from_full_transaction_id(xlrec.safexid),
+                xlrec.leafleftsib,
+                xlrec.leafrightsib,
+                xlrec.leaftopparent
+            )
+        }
+        XLOG_BTREE_NEWROOT => {
+            let xlrec = read_struct::<XLogBtreeNewroot>(rec);
+
+            format!("lev {}", xlrec.level)
+        }
+        XLOG_BTREE_REUSE_PAGE if version == PgVersion::V13 => {
+            let xlrec = read_struct::<XLogBtreeReusePage13>(rec);
+
+            format!(
+                "rel {}/{}/{}; latestRemovedXid {}",
+                xlrec.node.spc_oid, xlrec.node.db_oid, xlrec.node.rel_oid, xlrec.latest_removed_xid
+            )
+        }
+        XLOG_BTREE_REUSE_PAGE => {
+            let xlrec = read_struct::<XLogBtreeReusePage14>(rec);
+
+            format!(
+                "rel {}/{}/{}; latestRemovedXid {}:{}",
+                xlrec.node.spc_oid,
+                xlrec.node.db_oid,
+                xlrec.node.rel_oid,
+                util::epoch_from_full_transaction_id(xlrec.latest_removed_full_xid),
+                util::xid_from_full_transaction_id(xlrec.latest_removed_full_xid)
+            )
+        }
+        XLOG_BTREE_META_CLEANUP => match xlog_rec_get_block_data(state, 0) {
+            Some(data) if version == PgVersion::V13 => {
+                let xlrec = read_struct::<XLogBtreeMetadata13>(data);
+                format!(
+                    "oldest_btpo_xact {}; last_cleanup_num_heap_tuples: {:.6}",
+                    xlrec.oldest_btpo_xact, xlrec.last_cleanup_num_heap_tuples
+                )
+            }
+            Some(data) => {
+                let xlrec = read_struct::<XLogBtreeMetadata>(data);
+                format!(
+                    "last_cleanup_num_delpages {}",
+                    xlrec.last_cleanup_num_delpages
+                )
+            }
+            None => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+pub fn btree_identify(info: u8, version: PgVersion) -> String {
     let info2 = info & !XLR_INFO_MASK;
 
     match info2 {
@@ -443,3 +727,40 @@ pub fn btree_identify(info: u8) -> String {
     }
     .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btree_delete_layouts() {
+        let deleted: Vec<u8> = [3u16, 4].iter().flat_map(|x| x.to_ne_bytes()).collect();
+
+        // PostgreSQL 13 keeps the offsets in the main data
+        let mut main = 7u32.to_ne_bytes().to_vec();
+        main.extend(2u32.to_ne_bytes());
+        main.extend(&deleted);
+        let state = test_state(PgVersion::V13, XLOG_BTREE_DELETE, &main, None);
+        assert_eq!(
+            btree_desc(&state).text,
+            "latestRemovedXid: 7, ndeleted: 2, deleted: [3, 4]"
+        );
+
+        let mut main = 7u32.to_ne_bytes().to_vec();
+        main.extend(2u16.to_ne_bytes());
+        main.extend(0u16.to_ne_bytes());
+        let state = test_state(PgVersion::V15, XLOG_BTREE_DELETE, &main, Some(&deleted));
+        assert_eq!(
+            btree_desc(&state).text,
+            "latestRemovedXid: 7, ndeleted: 2, nupdated: 0, deleted: [3, 4], updated: []"
+        );
+
+        main.push(1);
+        let state = test_state(PgVersion::V16, XLOG_BTREE_DELETE, &main, Some(&deleted));
+        assert_eq!(
+            btree_desc(&state).text,
+            "snapshotConflictHorizon: 7, ndeleted: 2, nupdated: 0, isCatalogRel: T, \
+             deleted: [3, 4], updated: []"
+        );
+    }
+}
@@ -1,9 +1,8 @@
+use super::utils::*;
 use crate::constant::*;
 use crate::pgtypes::*;
 use crate::state::*;
-use enumname_derive::EnumName;
-
-#[derive(EnumName)]
+use crate::version::PgVersion;
 enum XLogInfoDbase {
     CreateFileCopy = 0x00,
     CreateWalLog = 0x10,
@@ -21,6 +20,15 @@ impl From<u8> for XLogInfoDbase {
     }
 }
 
+// PostgreSQL 15 added the WAL_LOG strategy of CREATE DATABASE, logging
+// CREATE_WAL_LOG where DROP was before and DROP after it.
+fn dbase_info(info: u8, version: PgVersion) -> XLogInfoDbase {
+    match info {
+        0x10 if version < PgVersion::V15 => XLogInfoDbase::Drop,
+        info => XLogInfoDbase::from(info),
+    }
+}
+
 // Single WAL record for an entire CREATE DATABASE operation. This is used
 // by the FILE_COPY strategy.
 #[repr(C)]
@@ -48,33 +56,38 @@ struct XlDbaseDropRec {
     // tablespace_ids: [Oid; 0],
 }
 
-pub fn dbase_desc(state: &XLogReaderState) -> String {
-    let record = state.record.as_ref().unwrap();
-    if record.main_data.is_none() {
-        return String::new();
-    }
-    let main_data = record.main_data.as_ref().unwrap();
-    let rec = main_data.as_slice();
+pub fn dbase_desc(state: &XLogReaderState) -> RecordDesc {
+    let record = state.record().unwrap();
+    let rec = match record.main_data() {
+        Some(rec) => rec,
+        None => return RecordDesc::default(),
+    };
     let info = record.header.xl_info & !XLR_INFO_MASK;
-    let info = XLogInfoDbase::from(info);
-    let mut rst = String::new();
+    let info = dbase_info(info, state.config.version);
+    let mut rst = RecordDesc::default();
 
     match info {
         XLogInfoDbase::CreateFileCopy => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlDbaseCreateFileCopyRec) };
+            let xlrec = read_struct::<XlDbaseCreateFileCopyRec>(rec);
 
             rst += &format!(
                 "copy dir {}/{} to {}/{}",
                 xlrec.src_tablespace_id, xlrec.src_db_id, xlrec.tablespace_id, xlrec.db_id
             );
+            rst.set("src_tablespace_id", xlrec.src_tablespace_id);
+            rst.set("src_db_id", xlrec.src_db_id);
+            rst.set("tablespace_id", xlrec.tablespace_id);
+            rst.set("db_id", xlrec.db_id);
         }
         XLogInfoDbase::CreateWalLog => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlDbaseCreateWalLogRec) };
+            let xlrec = read_struct::<XlDbaseCreateWalLogRec>(rec);
 
             rst += &format!("create dir {}/{}", xlrec.tablespace_id, xlrec.db_id);
+            rst.set("tablespace_id", xlrec.tablespace_id);
+            rst.set("db_id", xlrec.db_id);
         }
         XLogInfoDbase::Drop => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlDbaseDropRec) };
+            let xlrec = read_struct::<XlDbaseDropRec>(rec);
 
             rst.push_str("dir");
 
@@ -90,13 +103,20 @@ pub fn dbase_desc(state: &XLogReaderState) -> String {
             for i in 0..xlrec.ntablespaces as usize {
                 rst += &format!(" {}/{}", tblsps[i], xlrec.db_id);
             }
+            rst.set("db_id", xlrec.db_id);
+            rst.set("tablespace_ids", tblsps);
         }
     }
 
     rst
 }
 
-pub fn dbase_identify(info: u8) -> String {
-    let info = XLogInfoDbase::from(info & !XLR_INFO_MASK);
-    format!("{}", info)
+pub fn dbase_identify(info: u8, version: PgVersion) -> String {
+    let infostr = match dbase_info(info & !XLR_INFO_MASK, version) {
+        XLogInfoDbase::CreateFileCopy if version < PgVersion::V15 => "CREATE",
+        XLogInfoDbase::CreateFileCopy => "CREATE_FILE_COPY",
+        XLogInfoDbase::CreateWalLog => "CREATE_WAL_LOG",
+        XLogInfoDbase::Drop => "DROP",
+    };
+    String::from(infostr)
 }
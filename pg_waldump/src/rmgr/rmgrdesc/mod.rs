@@ -3,3 +3,4 @@ pub mod xlogdesc;
 pub mod dbasedesc;
 pub mod heapdesc;
 pub mod btreedesc;
+pub mod xactdesc;
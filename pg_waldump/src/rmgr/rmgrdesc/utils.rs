@@ -1,7 +1,138 @@
 use crate::pgtypes::*;
+use crate::version::PgVersion;
+use std::fmt;
 
-pub fn array_desc<T, F>(array: &[T], elem_desc: F) -> String 
-    where F: Fn(&T) -> String
+// What a desc routine says about a record: the description pg_waldump
+// prints, and the values in it by name for the structured output formats.
+// The routines set each field from the decoded record as they write the
+// text, so nothing ever has to be read back out of the text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RecordDesc {
+    pub text: String,
+    pub fields: Vec<(String, DescValue)>,
+}
+
+// A value in a record description.  Flags are numbers here, however the
+// text shows them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DescValue {
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<DescValue>),
+    Object(Vec<(String, DescValue)>),
+}
+
+impl RecordDesc {
+    pub fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    // Set field key, replacing the value of an earlier one of that name.
+    pub fn set(&mut self, key: &str, value: impl Into<DescValue>) {
+        let value = value.into();
+        match self.fields.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn field(&self, key: &str) -> Option<&DescValue> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+impl std::ops::AddAssign<&str> for RecordDesc {
+    fn add_assign(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+}
+
+// A description with no fields, as the routines that only write text give.
+impl From<String> for RecordDesc {
+    fn from(text: String) -> Self {
+        RecordDesc {
+            text,
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl From<&str> for RecordDesc {
+    fn from(text: &str) -> Self {
+        RecordDesc::from(text.to_string())
+    }
+}
+
+impl fmt::Display for RecordDesc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl DescValue {
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, DescValue)>) -> DescValue {
+        DescValue::Object(
+            members
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! desc_value_from_int {
+    ($variant:ident, $as:ty, $($t:ty),*) => {
+        $(impl From<$t> for DescValue {
+            fn from(v: $t) -> Self {
+                DescValue::$variant(v as $as)
+            }
+        })*
+    };
+}
+desc_value_from_int!(UInt, u64, u8, u16, u32, u64, usize);
+desc_value_from_int!(Int, i64, i8, i16, i32, i64);
+
+impl From<f64> for DescValue {
+    fn from(v: f64) -> Self {
+        DescValue::Float(v)
+    }
+}
+
+impl From<bool> for DescValue {
+    fn from(v: bool) -> Self {
+        DescValue::Bool(v)
+    }
+}
+
+impl From<String> for DescValue {
+    fn from(v: String) -> Self {
+        DescValue::Str(v)
+    }
+}
+
+impl From<&str> for DescValue {
+    fn from(v: &str) -> Self {
+        DescValue::Str(v.to_string())
+    }
+}
+
+impl<T: Into<DescValue>> From<Vec<T>> for DescValue {
+    fn from(v: Vec<T>) -> Self {
+        DescValue::List(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Clone + Into<DescValue>> From<&[T]> for DescValue {
+    fn from(v: &[T]) -> Self {
+        DescValue::List(v.iter().cloned().map(Into::into).collect())
+    }
+}
+
+pub fn array_desc<T, F>(array: &[T], mut elem_desc: F) -> String 
+    where F: FnMut(&T) -> String
 {
     let mut buf = String::new();
     if array.len() == 0 {
@@ -28,3 +159,102 @@ pub fn offset_elem_desc(offset: &OffsetNumber) -> String
 {
 	format!("{}", offset)
 }
+
+pub fn redirect_elem_desc(pair: &[OffsetNumber; 2]) -> String
+{
+	format!("{}->{}", pair[0], pair[1])
+}
+
+// n OffsetNumbers stored at byte off of data, fewer if data ends before
+pub fn offsets_at(data: &[u8], off: usize, n: usize) -> Vec<OffsetNumber>
+{
+	data.get(off..)
+		.unwrap_or_default()
+		.chunks_exact(std::mem::size_of::<OffsetNumber>())
+		.take(n)
+		.map(|c| OffsetNumber::from_ne_bytes([c[0], c[1]]))
+		.collect()
+}
+
+// A T read from the start of data, which is as unaligned as the WAL has
+// it.  Bytes past the end of data read as zeros: records are written with
+// the SizeOf length of their struct, which leaves out trailing padding.
+pub fn read_struct<T>(data: &[u8]) -> T
+{
+	if data.len() >= std::mem::size_of::<T>() {
+		return unsafe { std::ptr::read_unaligned(data.as_ptr() as *const T) };
+	}
+	let mut v = std::mem::MaybeUninit::<T>::zeroed();
+	unsafe {
+		std::ptr::copy_nonoverlapping(data.as_ptr(), v.as_mut_ptr() as *mut u8, data.len());
+		v.assume_init()
+	}
+}
+
+// A relation by the oids of its locator.
+pub fn rel_value(rlocator: &RelFileLocator) -> DescValue {
+    DescValue::object([
+        ("spc_oid", rlocator.spc_oid.into()),
+        ("db_oid", rlocator.db_oid.into()),
+        ("rel_oid", rlocator.rel_oid.into()),
+    ])
+}
+
+const DEFAULTTABLESPACE_OID: Oid = 1663;
+const GLOBALTABLESPACE_OID: Oid = 1664;
+
+// Path of the main fork of a relation, relative to the data directory.
+// Tablespaces keep a per-version directory named after the catalog version.
+pub fn relpathperm(rlocator: &RelFileLocator, version: PgVersion) -> String
+{
+	if rlocator.spc_oid == GLOBALTABLESPACE_OID {
+		format!("global/{}", rlocator.rel_oid)
+	} else if rlocator.spc_oid == DEFAULTTABLESPACE_OID {
+		format!("base/{}/{}", rlocator.db_oid, rlocator.rel_oid)
+	} else {
+		format!(
+			"pg_tblspc/{}/PG_{}_{}/{}/{}",
+			rlocator.spc_oid,
+			version.major(),
+			version.catalog_version(),
+			rlocator.db_oid,
+			rlocator.rel_oid
+		)
+	}
+}
+
+// A reader positioned on a record with the given main data and payload
+// of blk 0, for testing the desc routines.
+#[cfg(test)]
+pub fn test_state(
+	version: PgVersion,
+	info: u8,
+	main: &[u8],
+	block0: Option<&[u8]>,
+) -> crate::state::XLogReaderState
+{
+	let mut rec = DecodedXLogRecord::default();
+	rec.reset();
+	rec.header.xl_info = info;
+	// the record's bytes are just the payloads, unaligned as in the WAL
+	if let Some(data) = block0 {
+		rec.max_block_id = 0;
+		rec.blocks.push(DecodedBkpBlock {
+			in_use: true,
+			has_data: true,
+			data_off: 0,
+			data_len: data.len() as u16,
+			..Default::default()
+		});
+		rec.raw.extend_from_slice(data);
+	}
+	rec.main_data_off = rec.raw.len() as u32;
+	rec.main_data_len = main.len() as u32;
+	rec.raw.extend_from_slice(main);
+	rec.header.xl_tot_len = rec.raw.len() as u32;
+
+	let mut state = crate::state::XLogReaderState::default();
+	state.config.version = version;
+	state.record = Some(rec);
+	state
+}
@@ -1,3 +1,4 @@
+use super::utils::*;
 use crate::constant::*;
 use crate::guc::*;
 use crate::pg_control::*;
@@ -6,59 +7,71 @@ use crate::state::XLogReaderState;
 use crate::util::*;
 use crate::waldec;
 use crate::xlog::*;
+use crate::version::PgVersion;
 
-pub fn xlog_desc(state: &XLogReaderState) -> String {
-    let record = state.record.as_ref().unwrap();
-    if record.main_data.is_none() {
-        return String::new();
-    }
-    let rec = record.main_data.as_ref().unwrap().as_slice();
+pub fn xlog_desc(state: &XLogReaderState) -> RecordDesc {
+    let version = state.config.version;
+    let record = state.record().unwrap();
+    let rec = match record.main_data() {
+        Some(rec) => rec,
+        None => return RecordDesc::default(),
+    };
     let info = record.header.xl_info & !XLR_INFO_MASK;
     let info = XLogInfo::from(info);
-    let mut rst = String::new();
+    let mut rst = RecordDesc::default();
 
     match info {
         XLogInfo::CheckpointShutdown | XLogInfo::CheckpointOnline => {
-            let checkpoint = unsafe { &*(rec.as_ptr() as *const CheckPoint) };
+            let checkpoint = read_struct::<CheckPoint>(rec);
+            // wal_level was added to the checkpoint in PostgreSQL 17
+            let wal_level = if version >= PgVersion::V17 {
+                Some(get_wal_level_string(checkpoint.wal_level))
+            } else {
+                None
+            };
             rst += &format!(
                 "{} {}",
-                checkpoint,
+                checkpoint.fmt_with(wal_level),
                 if info == XLogInfo::CheckpointShutdown {
                     "shutdown"
                 } else {
                     "online"
                 }
             );
+            checkpoint_fields(&mut rst, &checkpoint, wal_level);
+            rst.set("shutdown", info == XLogInfo::CheckpointShutdown);
         }
         XLogInfo::NextOid => {
-            let next_oid = unsafe { *(rec.as_ptr() as *const Oid) };
+            let next_oid = read_struct::<Oid>(rec);
 
             rst += &format!("{}", next_oid);
+            rst.set("next_oid", next_oid);
         }
         XLogInfo::RestorePoint => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlRestorePoint) };
+            let xlrec = read_struct::<XlRestorePoint>(rec);
 
-            rst += &format!("{}", to_string(&xlrec.rp_name));
+            let len = xlrec
+                .rp_name
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(MAXFNAMELEN);
+            let rp_name = to_string(&xlrec.rp_name[..len]);
+            rst += &rp_name;
+            rst.set("rp_name", rp_name);
         }
         XLogInfo::FPI | XLogInfo::FPIForHint => {
             // no further information to print
         }
         XLogInfo::BackupEnd => {
-            let startpoint = unsafe { *(rec.as_ptr() as *const XLogRecPtr) };
+            let startpoint = read_struct::<XLogRecPtr>(rec);
 
-            rst += &format!("{}", waldec::lsn_out(startpoint));
+            rst += &waldec::lsn_format(startpoint);
+            rst.set("startpoint", waldec::lsn_format(startpoint));
         }
         XLogInfo::ParameterChange => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlParameterChange) };
+            let xlrec = read_struct::<XlParameterChange>(rec);
 
-            // Find a string representation for wal_level
-            let mut wal_level_str = "";
-            for entry in WAL_LEVEL_OPTIONS.iter() {
-                if entry.val == xlrec.wal_level {
-                    wal_level_str = entry.name;
-                    break;
-                }
-            }
+            let wal_level_str = get_wal_level_string(xlrec.wal_level);
 
             let s = format!(
                 "max_connections={} max_worker_processes={} \
@@ -79,13 +92,22 @@ pub fn xlog_desc(state: &XLogReaderState) -> String {
                 }
             );
             rst += &s;
+            rst.set("max_connections", xlrec.max_connections);
+            rst.set("max_worker_processes", xlrec.max_worker_processes);
+            rst.set("max_wal_senders", xlrec.max_wal_senders);
+            rst.set("max_prepared_xacts", xlrec.max_prepared_xacts);
+            rst.set("max_locks_per_xact", xlrec.max_locks_per_xact);
+            rst.set("wal_level", wal_level_str);
+            rst.set("wal_log_hints", xlrec.wal_log_hints);
+            rst.set("track_commit_timestamp", xlrec.track_commit_timestamp);
         }
         XLogInfo::FpwChange => {
-            let fpw = unsafe { *(rec.as_ptr() as *const bool) };
+            let fpw = read_struct::<bool>(rec);
             rst += &format!("{}", if fpw { "true" } else { "false" });
+            rst.set("fpw", fpw);
         }
         XLogInfo::EndOfRecovery => {
-            let xlrec = unsafe { &*(rec.as_ptr() as *const XlEndOfRecovery) };
+            let xlrec = read_struct::<XlEndOfRecovery>(rec);
             let s = format!(
                 "tli {}; prev tli {}; time {}",
                 xlrec.this_timeline_id,
@@ -93,6 +115,15 @@ pub fn xlog_desc(state: &XLogReaderState) -> String {
                 timestamptz_to_str(xlrec.end_time)
             );
             rst += &s;
+            rst.set("tli", xlrec.this_timeline_id);
+            rst.set("prev_tli", xlrec.prev_timeline_id);
+            rst.set("time", timestamptz_to_str(xlrec.end_time));
+            // and to the end-of-recovery record too
+            if version >= PgVersion::V17 && rec.len() >= 20 {
+                let wal_level = i32::from_ne_bytes(rec[16..20].try_into().unwrap());
+                rst += &format!("; wal_level {}", get_wal_level_string(wal_level));
+                rst.set("wal_level", get_wal_level_string(wal_level));
+            }
         }
         XLogInfo::OverwriteContrecord => {
             // TODO: implement
@@ -111,9 +142,72 @@ pub fn xlog_desc(state: &XLogReaderState) -> String {
     rst
 }
 
-pub fn xlog_identify(info: u8) -> String {
-    let info = XLogInfo::from(info);
-    format!("{}", info)
+// The fields of a checkpoint, named as in CheckPoint.
+fn checkpoint_fields(rst: &mut RecordDesc, checkpoint: &CheckPoint, wal_level: Option<&str>) {
+    rst.set("redo", waldec::lsn_format(checkpoint.redo));
+    rst.set("time_line_id", checkpoint.time_line_id);
+    rst.set("prev_time_line_id", checkpoint.prev_time_line_id);
+    rst.set("full_page_writes", checkpoint.full_page_writes);
+    if let Some(wal_level) = wal_level {
+        rst.set("wal_level", wal_level);
+    }
+    rst.set("next_xid", checkpoint.next_xid);
+    rst.set("next_oid", checkpoint.next_oid);
+    rst.set("next_multi", checkpoint.next_multi);
+    rst.set("next_multi_offset", checkpoint.next_multi_offset);
+    rst.set("oldest_xid", checkpoint.oldest_xid);
+    rst.set("oldest_xid_db", checkpoint.oldest_xid_db);
+    rst.set("oldest_multi", checkpoint.oldest_multi);
+    rst.set("oldest_multi_db", checkpoint.oldest_multi_db);
+    rst.set("oldest_commit_ts_xid", checkpoint.oldest_commit_ts_xid);
+    rst.set("newest_commit_ts_xid", checkpoint.newest_commit_ts_xid);
+    rst.set("oldest_active_xid", checkpoint.oldest_active_xid);
+}
+
+// The checkpoint of a checkpoint record, and whether it was a shutdown one.
+pub fn xlog_checkpoint(state: &XLogReaderState) -> Option<(CheckPoint, bool)> {
+    let record = state.record().unwrap();
+    let info = XLogInfo::from(record.header.xl_info & !XLR_INFO_MASK);
+    if info != XLogInfo::CheckpointShutdown && info != XLogInfo::CheckpointOnline {
+        return None;
+    }
+    let rec = record.main_data()?;
+    if rec.len() < std::mem::size_of::<CheckPoint>() {
+        return None;
+    }
+    let checkpoint = unsafe { std::ptr::read_unaligned(rec.as_ptr() as *const CheckPoint) };
+    Some((checkpoint, info == XLogInfo::CheckpointShutdown))
+}
+
+pub fn xlog_identify(info: u8, version: PgVersion) -> String {
+    match info & !XLR_INFO_MASK {
+        0x00 => "CHECKPOINT_SHUTDOWN",
+        0x10 => "CHECKPOINT_ONLINE",
+        0x20 => "NOOP",
+        0x30 => "NEXTOID",
+        0x40 => "SWITCH",
+        0x50 => "BACKUP_END",
+        0x60 => "PARAMETER_CHANGE",
+        0x70 => "RESTORE_POINT",
+        0x80 => "FPW_CHANGE",
+        0x90 => "END_OF_RECOVERY",
+        0xA0 => "FPI_FOR_HINT",
+        0xB0 => "FPI",
+        0xD0 => "OVERWRITE_CONTRECORD",
+        0xE0 if version >= PgVersion::V17 => "CHECKPOINT_REDO",
+        _ => "",
+    }
+    .to_string()
+}
+
+// Find a string representation for wal_level
+fn get_wal_level_string(wal_level: i32) -> &'static str {
+    for entry in WAL_LEVEL_OPTIONS.iter() {
+        if entry.val == wal_level {
+            return entry.name;
+        }
+    }
+    "?"
 }
 
 // GUC support
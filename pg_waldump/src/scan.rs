@@ -0,0 +1,411 @@
+// Every segment in a directory, for dumping all of it.
+//
+// The segment files are ordered by segment number and timeline, and the
+// first page of each is checked against its name and against the first
+// segment.  Where a segment exists on several timelines the newest one is
+// read, as that is the copy a promotion leaves with the WAL up to and
+// after the switch.  What doesn't fit is reported as a ScanWarning rather
+// than stopping the dump: the segments that can be read are split into
+// runs of consecutive segments, each of which is decoded on its own.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::compress::{split_compression, SegmentFile};
+use crate::constant::XLOG_BLCKSZ;
+use crate::pgtypes::*;
+use crate::timeline::TimeLineHistoryEntry;
+use crate::util::{is_xlog_filename, xlog_filename, xlog_from_file_name};
+use crate::waldec::{lsn_out, WalConfig};
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanWarning {
+    // no segment between two that exist: segments first to last are missing
+    Gap {
+        first: XLogSegNo,
+        last: XLogSegNo,
+        start: XLogRecPtr,
+        end: XLogRecPtr,
+    },
+    // segment segno exists on all of tlis; it is read from the newest
+    Duplicate {
+        segno: XLogSegNo,
+        start: XLogRecPtr,
+        tlis: Vec<TimeLineID>,
+    },
+    // the first page of file doesn't agree with its name or with the other
+    // segments; the file is skipped
+    HeaderMismatch { file: String, reason: String },
+    // the copies of segment segno in used and other, in two directories,
+    // differ from byte offset on; used is read
+    CopiesDiffer {
+        segno: XLogSegNo,
+        start: XLogRecPtr,
+        used: String,
+        other: String,
+        offset: u64,
+    },
+    // file, for segment segno, is shorter than a segment, e.g. a copy
+    // still being written; its run of segments ends at end, after its
+    // last whole page
+    Truncated {
+        segno: XLogSegNo,
+        file: String,
+        size: u64,
+        end: XLogRecPtr,
+    },
+}
+
+impl fmt::Display for ScanWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanWarning::Gap {
+                first,
+                last,
+                start,
+                end,
+            } => write!(
+                f,
+                "WAL from {} to {} is missing ({} segment(s)); records in it are not shown",
+                lsn_out(*start),
+                lsn_out(*end),
+                last - first + 1
+            ),
+            ScanWarning::Duplicate { start, tlis, .. } => write!(
+                f,
+                "segment at {} exists on timelines {}; reading it from timeline {}",
+                lsn_out(*start),
+                tlis.iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                tlis.last().unwrap()
+            ),
+            ScanWarning::HeaderMismatch { file, reason } => {
+                write!(f, "skipping file \"{}\": {}", file, reason)
+            }
+            ScanWarning::CopiesDiffer {
+                start,
+                used,
+                other,
+                offset,
+                ..
+            } => write!(
+                f,
+                "copies of segment at {} differ from offset {}: reading \"{}\", not \"{}\"",
+                lsn_out(*start),
+                offset,
+                used,
+                other
+            ),
+            ScanWarning::Truncated {
+                file, size, end, ..
+            } => write!(
+                f,
+                "file \"{}\" is only {} bytes; WAL from {} to the end of its segment is not shown",
+                file,
+                size,
+                lsn_out(*end)
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScannedSegment {
+    pub tli: TimeLineID,
+    pub segno: XLogSegNo,
+    pub name: String,
+    // bytes of the segment to read: the segment size, or the whole pages
+    // of a truncated file
+    pub size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct DirectoryScan {
+    // the segments to read, one per segment number, in order
+    pub segments: Vec<ScannedSegment>,
+    pub warnings: Vec<ScanWarning>,
+}
+
+impl DirectoryScan {
+    // The segments split into runs of consecutive segment numbers; a
+    // truncated segment ends its run.
+    pub fn runs(&self, segsize: u32) -> Vec<&[ScannedSegment]> {
+        let mut runs = Vec::new();
+        let mut begin = 0;
+        for i in 1..=self.segments.len() {
+            if i == self.segments.len()
+                || self.segments[i].segno != self.segments[i - 1].segno + 1
+                || self.segments[i - 1].size < segsize as u64
+            {
+                runs.push(&self.segments[begin..i]);
+                begin = i;
+            }
+        }
+        runs
+    }
+
+    // A timeline history under which the reader reads each segment from
+    // the timeline chosen for it.
+    pub fn history(&self, segsize: u32) -> Vec<TimeLineHistoryEntry> {
+        let mut history: Vec<TimeLineHistoryEntry> = Vec::new();
+        for seg in &self.segments {
+            match history.first_mut() {
+                Some(tle) if tle.tli == seg.tli => {}
+                _ => {
+                    let begin = match history.first_mut() {
+                        Some(prev) => {
+                            prev.end = seg.segno * segsize as u64;
+                            prev.end
+                        }
+                        None => INVALID_XLOG_RECPTR,
+                    };
+                    history.insert(
+                        0,
+                        TimeLineHistoryEntry {
+                            tli: seg.tli,
+                            begin,
+                            end: INVALID_XLOG_RECPTR,
+                        },
+                    );
+                }
+            }
+        }
+        history
+    }
+}
+
+// Scan the files `names` of a directory, opening them with open to check
+// their first page against config, which is that of the first segment.
+// Names other than segment names are ignored.
+pub fn scan_segments<F>(names: &[String], config: &WalConfig, mut open: F) -> DirectoryScan
+where
+    F: FnMut(&str) -> std::io::Result<SegmentFile>,
+{
+    let segsize = config.wal_seg_sz;
+    let mut scan = DirectoryScan::default();
+
+    // every timeline's file, and its length, for each segment number; a
+    // bare file is preferred over compressed ones, as when opening by name
+    let mut found: BTreeMap<XLogSegNo, BTreeMap<TimeLineID, (&str, u64)>> = BTreeMap::new();
+    let mut names: Vec<&String> = names
+        .iter()
+        .filter(|n| is_xlog_filename(&PathBuf::from(n)))
+        .collect();
+    names.sort_by_key(|n| (split_compression(n).0, split_compression(n).1.is_some()));
+    for name in names {
+        let mut tli: TimeLineID = 0;
+        let mut segno: XLogSegNo = 0;
+        xlog_from_file_name(
+            &PathBuf::from(split_compression(name).0),
+            &mut tli,
+            &mut segno,
+            segsize,
+        );
+        if found.get(&segno).is_some_and(|t| t.contains_key(&tli)) {
+            continue;
+        }
+        let size = match check_segment(&mut open, name, tli, segno, config) {
+            Ok(size) => size,
+            Err(reason) => {
+                scan.warnings.push(ScanWarning::HeaderMismatch {
+                    file: name.clone(),
+                    reason,
+                });
+                continue;
+            }
+        };
+        found.entry(segno).or_default().insert(tli, (name, size));
+    }
+
+    for (segno, files) in found {
+        if files.len() > 1 {
+            scan.warnings.push(ScanWarning::Duplicate {
+                segno,
+                start: segno * segsize as u64,
+                tlis: files.keys().copied().collect(),
+            });
+        }
+        let (tli, (name, size)) = files.into_iter().next_back().unwrap();
+        if let Some(prev) = scan.segments.last() {
+            if segno > prev.segno + 1 {
+                scan.warnings.push(ScanWarning::Gap {
+                    first: prev.segno + 1,
+                    last: segno - 1,
+                    start: (prev.segno + 1) * segsize as u64,
+                    end: segno * segsize as u64,
+                });
+            }
+        }
+        // the reader reads whole pages, up to the end of the last one
+        let whole = size.min(segsize as u64) / XLOG_BLCKSZ as u64 * XLOG_BLCKSZ as u64;
+        if whole < segsize as u64 {
+            scan.warnings.push(ScanWarning::Truncated {
+                segno,
+                file: name.to_string(),
+                size,
+                end: segno * segsize as u64 + whole,
+            });
+        }
+        scan.segments.push(ScannedSegment {
+            tli,
+            segno,
+            name: name.to_string(),
+            size: whole,
+        });
+    }
+    scan
+}
+
+// Check the long header on the first page of segment file name, and
+// return the length of the file.
+fn check_segment<F>(
+    open: &mut F,
+    name: &str,
+    tli: TimeLineID,
+    segno: XLogSegNo,
+    config: &WalConfig,
+) -> Result<u64, String>
+where
+    F: FnMut(&str) -> std::io::Result<SegmentFile>,
+{
+    let mut page = vec![0u8; XLOG_BLCKSZ as usize];
+    let size = open(name)
+        .and_then(|mut file| file.read_exact(&mut page).and_then(|_| file.size()))
+        .map_err(|e| format!("could not read first page: {}", e))?;
+    let found = WalConfig::from_first_page(&page)?;
+    let hdr = crate::waldec::page_header(&page).unwrap().1;
+
+    let segstart = segno * config.wal_seg_sz as u64;
+    if found.wal_seg_sz != config.wal_seg_sz {
+        return Err(format!(
+            "WAL segment size in page header is {} bytes, expected {}",
+            found.wal_seg_sz, config.wal_seg_sz
+        ));
+    }
+    if hdr.xlp_pageaddr != segstart {
+        return Err(format!(
+            "page address in page header is {}, expected {} from the file name",
+            lsn_out(hdr.xlp_pageaddr),
+            lsn_out(segstart)
+        ));
+    }
+    // pages before a switch point carry the old timeline, but never a
+    // newer one than the file's
+    if hdr.xlp_tli > tli {
+        return Err(format!(
+            "timeline in page header is {}, newer than {} from the file name",
+            hdr.xlp_tli, tli
+        ));
+    }
+    if config.system_identifier != 0 && found.system_identifier != config.system_identifier {
+        return Err(format!(
+            "database system identifier in page header is {}, expected {}",
+            found.system_identifier, config.system_identifier
+        ));
+    }
+    if found.page_magic != config.page_magic {
+        return Err(format!(
+            "magic number in page header is {:04X}, expected {:04X}",
+            found.page_magic, config.page_magic
+        ));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_duplicates_and_mismatches_are_reported() {
+        // the bundled 16 kB as the start of 1 MB segments, which keeps the
+        // files small
+        let segsize: u32 = 1024 * 1024;
+        let mut seg = std::fs::read("test/000000010000000000000001").unwrap();
+        seg[32..36].copy_from_slice(&segsize.to_le_bytes());
+        seg.resize(segsize as usize, 0);
+        let config = WalConfig::from_first_page(&seg).unwrap();
+
+        // make copies that claim to be segments 1, 2, 4 and 5
+        let with_pageaddr = |segno: u64| {
+            let mut data = seg.clone();
+            data[8..16].copy_from_slice(&(segno * segsize as u64).to_le_bytes());
+            data
+        };
+        let mut files: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        // cut off in the middle of its fourth page
+        let mut truncated = with_pageaddr(1);
+        truncated.truncate(3 * XLOG_BLCKSZ as usize + 100);
+        files.insert(String::from("000000010000000000000001"), truncated);
+        files.insert(String::from("000000010000000000000002"), with_pageaddr(2));
+        files.insert(String::from("000000020000000000000002"), with_pageaddr(2));
+        files.insert(String::from("000000020000000000000004"), with_pageaddr(4));
+        // named 5, but the header says 6
+        files.insert(String::from("000000020000000000000005"), with_pageaddr(6));
+        files.insert(String::from("00000002.history"), vec![]);
+
+        let names: Vec<String> = files.keys().cloned().collect();
+        let scan = scan_segments(&names, &config, |name| {
+            Ok(SegmentFile::from(files[name].clone()))
+        });
+
+        assert_eq!(
+            scan.segments
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "000000010000000000000001",
+                "000000020000000000000002",
+                "000000020000000000000004"
+            ]
+        );
+        assert_eq!(scan.segments[0].size, 3 * XLOG_BLCKSZ as u64);
+        // the truncated segment ends a run of its own
+        assert_eq!(
+            scan.runs(segsize)
+                .iter()
+                .map(|r| r.len())
+                .collect::<Vec<_>>(),
+            [1, 1, 1]
+        );
+        assert_eq!(scan.warnings.len(), 4);
+        assert!(matches!(&scan.warnings[0],
+            ScanWarning::HeaderMismatch { file, .. } if file == "000000020000000000000005"));
+        assert_eq!(
+            scan.warnings[1],
+            ScanWarning::Truncated {
+                segno: 1,
+                file: String::from("000000010000000000000001"),
+                size: 3 * XLOG_BLCKSZ as u64 + 100,
+                end: segsize as u64 + 3 * XLOG_BLCKSZ as u64
+            }
+        );
+        assert_eq!(
+            scan.warnings[2],
+            ScanWarning::Duplicate {
+                segno: 2,
+                start: 2 * segsize as u64,
+                tlis: vec![1, 2]
+            }
+        );
+        assert_eq!(
+            scan.warnings[3],
+            ScanWarning::Gap {
+                first: 3,
+                last: 3,
+                start: 3 * segsize as u64,
+                end: 4 * segsize as u64
+            }
+        );
+
+        let history = scan.history(segsize);
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].tli, history[0].begin), (2, 2 * segsize as u64));
+        assert_eq!((history[1].tli, history[1].end), (1, 2 * segsize as u64));
+    }
+}
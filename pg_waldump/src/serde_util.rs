@@ -0,0 +1,253 @@
+// Serialization helpers for the `serde` feature.
+//
+// LSNs and timestamps are written the way PostgreSQL prints them when the
+// format is human readable (JSON and the like): LSNs as "X/X" and
+// timestamps as ISO 8601 in UTC.  Binary formats such as bincode get the
+// plain integers, which is what the Rust side wants anyway.  Either form is
+// accepted when deserializing a human-readable format.
+use crate::pgtypes::*;
+use crate::rmgr::DescValue;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum TextOrInt<'a> {
+    Text(std::borrow::Cow<'a, str>),
+    Int(i64),
+}
+
+fn parse_lsn<E: de::Error>(s: &str) -> Result<XLogRecPtr, E> {
+    let (hi, lo) = s
+        .split_once('/')
+        .ok_or_else(|| E::custom(format!("invalid LSN \"{}\"", s)))?;
+    let hi = u32::from_str_radix(hi, 16).map_err(E::custom)?;
+    let lo = u32::from_str_radix(lo, 16).map_err(E::custom)?;
+    Ok(((hi as u64) << 32) | lo as u64)
+}
+
+fn parse_iso<E: de::Error>(s: &str) -> Result<chrono::DateTime<chrono::Utc>, E> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .map_err(E::custom)
+}
+
+// XLogRecPtr as "X/X"
+pub mod lsn {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(lsn: &XLogRecPtr, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.serialize_str(&format!("{:X}/{:X}", (lsn >> 32) as u32, *lsn as u32))
+        } else {
+            s.serialize_u64(*lsn)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<XLogRecPtr, D::Error> {
+        if d.is_human_readable() {
+            match TextOrInt::deserialize(d)? {
+                TextOrInt::Text(s) => parse_lsn(&s),
+                TextOrInt::Int(v) => Ok(v as XLogRecPtr),
+            }
+        } else {
+            u64::deserialize(d)
+        }
+    }
+}
+
+// TimestampTz (microseconds since 2000-01-01) as ISO 8601
+pub mod timestamptz {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(t: &TimestampTz, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            match timestamptz_to_iso(*t) {
+                Some(iso) => s.serialize_str(&iso),
+                None => s.serialize_i64(*t),
+            }
+        } else {
+            s.serialize_i64(*t)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<TimestampTz, D::Error> {
+        if d.is_human_readable() {
+            match TextOrInt::deserialize(d)? {
+                TextOrInt::Text(s) => {
+                    Ok(parse_iso(&s)?.timestamp_micros() - POSTGRES_EPOCH_USECS)
+                }
+                TextOrInt::Int(v) => Ok(v),
+            }
+        } else {
+            i64::deserialize(d)
+        }
+    }
+}
+
+// pg_time_t (seconds since the Unix epoch) as ISO 8601
+pub mod pg_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(t: &PgTime, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            match chrono::DateTime::from_timestamp(*t, 0) {
+                Some(ts) => {
+                    s.serialize_str(&ts.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                }
+                None => s.serialize_i64(*t),
+            }
+        } else {
+            s.serialize_i64(*t)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PgTime, D::Error> {
+        if d.is_human_readable() {
+            match TextOrInt::deserialize(d)? {
+                TextOrInt::Text(s) => Ok(parse_iso(&s)?.timestamp()),
+                TextOrInt::Int(v) => Ok(v),
+            }
+        } else {
+            i64::deserialize(d)
+        }
+    }
+}
+
+// NUL-terminated char array as a string
+pub mod cstring {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        buf: &[u8; N],
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(N);
+        s.serialize_str(&String::from_utf8_lossy(&buf[..len]))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        d: D,
+    ) -> Result<[u8; N], D::Error> {
+        let s = String::deserialize(d)?;
+        if s.len() >= N {
+            return Err(de::Error::custom(format!(
+                "string longer than {} bytes",
+                N - 1
+            )));
+        }
+        let mut buf = [0; N];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(buf)
+    }
+}
+
+// ForkNumber by its name, e.g. "main"
+pub fn fork_name<S: Serializer>(fork: &ForkNumber, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(crate::constant::FORK_NAMES[*fork as usize])
+}
+
+// The fields of a record description as a map, in the order the desc
+// routine set them.
+pub fn desc_fields<S: Serializer>(fields: &[(String, DescValue)], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_map(fields.iter().map(|(k, v)| (k, v)))
+}
+
+impl Serialize for DescValue {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DescValue::UInt(v) => s.serialize_u64(*v),
+            DescValue::Int(v) => s.serialize_i64(*v),
+            DescValue::Float(v) => s.serialize_f64(*v),
+            DescValue::Bool(v) => s.serialize_bool(*v),
+            DescValue::Str(v) => s.serialize_str(v),
+            DescValue::List(items) => s.collect_seq(items),
+            DescValue::Object(members) => desc_fields(members, s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_record() -> DecodedXLogRecord {
+        let mut rec = DecodedXLogRecord::default();
+        rec.reset();
+        rec.lsn = 0x1_0000_0028;
+        rec.next_lsn = 0x1_0000_0060;
+        rec.header.xl_tot_len = 50;
+        rec.header.xl_prev = 0x0_0FFF_FFD8;
+        rec.header.xl_rmid = 9;
+        rec.max_block_id = 0;
+        rec.blocks.push(DecodedBkpBlock {
+            in_use: true,
+            rlocator: RelFileLocator {
+                spc_oid: 1663,
+                db_oid: 5,
+                rel_oid: 16384,
+            },
+            forknum: ForkNumber::Main,
+            blkno: 7,
+            has_data: true,
+            data_off: 45,
+            data_len: 3,
+            ..Default::default()
+        });
+        rec.main_data_off = 48;
+        rec.main_data_len = 2;
+        rec.raw = vec![0; 50];
+        rec.raw[45..].copy_from_slice(&[1, 2, 3, 0xAB, 0xCD]);
+        rec
+    }
+
+    #[test]
+    fn record_json_uses_lsn_text() {
+        let json = serde_json::to_value(sample_record()).unwrap();
+        assert_eq!(json["lsn"], "1/28");
+        assert_eq!(json["next_lsn"], "1/60");
+        assert_eq!(json["header"]["xl_prev"], "0/FFFFFD8");
+        assert_eq!(json["blocks"][0]["rlocator"]["rel_oid"], 16384);
+        assert_eq!(json["blocks"][0]["forknum"], "Main");
+
+        let back: DecodedXLogRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(back.lsn, 0x1_0000_0028);
+        assert_eq!(back.view().main_data(), Some(&[0xAB, 0xCD][..]));
+        assert_eq!(back.view().block_data(0), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn record_bincode_roundtrip() {
+        let bytes = bincode::serialize(&sample_record()).unwrap();
+        let back: DecodedXLogRecord = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(back.lsn, 0x1_0000_0028);
+        assert_eq!(back.header.xl_prev, 0x0_0FFF_FFD8);
+        assert_eq!(back.block(0).unwrap().blkno, 7);
+        assert_eq!(back.view().main_data(), Some(&[0xAB, 0xCD][..]));
+    }
+
+    #[test]
+    fn timestamps_are_iso() {
+        let checkpoint = CheckPoint {
+            redo: 0x1_05E4_0A60,
+            time: 1_700_000_000,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(checkpoint).unwrap();
+        assert_eq!(json["redo"], "1/5E40A60");
+        assert_eq!(json["time"], "2023-11-14T22:13:20Z");
+        let back: CheckPoint = serde_json::from_value(json).unwrap();
+        assert_eq!(back.time, 1_700_000_000);
+
+        // 2000-01-01 is zero in PostgreSQL's epoch
+        let eor = crate::xlog::XlEndOfRecovery {
+            end_time: 1_500_000,
+            this_timeline_id: 2,
+            prev_timeline_id: 1,
+        };
+        let json = serde_json::to_value(&eor).unwrap();
+        assert_eq!(json["end_time"], "2000-01-01T00:00:01.500000Z");
+        let back: crate::xlog::XlEndOfRecovery = serde_json::from_value(json).unwrap();
+        assert_eq!(back.end_time, 1_500_000);
+    }
+}
@@ -0,0 +1,476 @@
+// Records as an SQLite database, for --sqlite.
+//
+// The tables are
+//
+//     records       one row per record, keyed by LSN
+//     blocks        one row per block reference, keyed by LSN and block id
+//     checkpoints   one row per checkpoint record, with its fields
+//     transactions  one row per commit, abort or prepare record
+//     segments      one row per WAL segment records were taken from, with
+//                   the number taken and the cluster's system identifier
+//
+// LSNs are integers, so printf('%X/%X', lsn >> 32, lsn & 0xFFFFFFFF) shows
+// them as PostgreSQL does, and times ISO 8601 text in UTC.  xid is indexed
+// in records and transactions, and relfilenode in blocks.
+//
+// Writing to an existing database appends: records already in it are left
+// as they are, so dumping overlapping ranges doesn't duplicate them, and
+// resume_point() tells where the last dump stopped.  Rows are committed
+// every BATCH_SIZE records, so an interrupted dump keeps what it wrote.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::pgtypes::*;
+use crate::rmgr::{xact_end, xlog_checkpoint, RmgrIds};
+use crate::state::XLogReaderState;
+use crate::summary::RecordSummary;
+use crate::timeline::tli_of_segment;
+use crate::util::{xlog_byte_to_seg, xlog_filename};
+
+const BATCH_SIZE: usize = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS records (
+    lsn INTEGER PRIMARY KEY,
+    end_lsn INTEGER NOT NULL,
+    prev_lsn INTEGER NOT NULL,
+    timeline INTEGER NOT NULL,
+    rmid INTEGER NOT NULL,
+    rmgr TEXT NOT NULL,
+    info INTEGER NOT NULL,
+    record_type TEXT,
+    xid INTEGER NOT NULL,
+    toplevel_xid INTEGER NOT NULL,
+    origin INTEGER NOT NULL,
+    rec_len INTEGER NOT NULL,
+    main_data_len INTEGER NOT NULL,
+    fpi_len INTEGER NOT NULL,
+    tot_len INTEGER NOT NULL,
+    description TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_xid ON records (xid);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    lsn INTEGER NOT NULL REFERENCES records,
+    block_id INTEGER NOT NULL,
+    spc_oid INTEGER NOT NULL,
+    db_oid INTEGER NOT NULL,
+    relfilenode INTEGER NOT NULL,
+    fork TEXT NOT NULL,
+    blkno INTEGER NOT NULL,
+    data_len INTEGER NOT NULL,
+    fpi_len INTEGER,
+    fpi_hole_offset INTEGER,
+    fpi_hole_length INTEGER,
+    fpi_apply INTEGER,
+    fpi_compression TEXT,
+    PRIMARY KEY (lsn, block_id)
+);
+CREATE INDEX IF NOT EXISTS blocks_relfilenode ON blocks (relfilenode, blkno);
+
+CREATE TABLE IF NOT EXISTS checkpoints (
+    lsn INTEGER PRIMARY KEY REFERENCES records,
+    shutdown INTEGER NOT NULL,
+    redo INTEGER NOT NULL,
+    timeline INTEGER NOT NULL,
+    prev_timeline INTEGER NOT NULL,
+    full_page_writes INTEGER NOT NULL,
+    next_xid INTEGER NOT NULL,
+    next_oid INTEGER NOT NULL,
+    next_multi INTEGER NOT NULL,
+    next_multi_offset INTEGER NOT NULL,
+    oldest_xid INTEGER NOT NULL,
+    oldest_xid_db INTEGER NOT NULL,
+    oldest_multi INTEGER NOT NULL,
+    oldest_multi_db INTEGER NOT NULL,
+    oldest_commit_ts_xid INTEGER NOT NULL,
+    newest_commit_ts_xid INTEGER NOT NULL,
+    oldest_active_xid INTEGER NOT NULL,
+    time TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    lsn INTEGER PRIMARY KEY REFERENCES records,
+    xid INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    time TEXT,
+    db_oid INTEGER NOT NULL,
+    nsubxacts INTEGER NOT NULL,
+    subxacts TEXT NOT NULL,
+    nrels INTEGER NOT NULL,
+    gid TEXT
+);
+CREATE INDEX IF NOT EXISTS transactions_xid ON transactions (xid);
+
+CREATE TABLE IF NOT EXISTS segments (
+    timeline INTEGER NOT NULL,
+    segno INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    system_identifier INTEGER NOT NULL,
+    first_lsn INTEGER NOT NULL,
+    last_lsn INTEGER NOT NULL,
+    records INTEGER NOT NULL,
+    PRIMARY KEY (timeline, segno)
+);
+";
+
+// The records taken from a segment since the last commit.
+struct SegmentStats {
+    first_lsn: XLogRecPtr,
+    last_lsn: XLogRecPtr,
+    records: u64,
+}
+
+pub struct SqliteWriter {
+    conn: Connection,
+    system_identifier: u64,
+    wal_seg_sz: u32,
+    segments: BTreeMap<(TimeLineID, XLogSegNo), SegmentStats>,
+    rows: usize,
+}
+
+fn sql_error(e: rusqlite::Error) -> String {
+    format!("could not write sqlite database: {}", e)
+}
+
+fn pg_time_text(t: PgTime) -> String {
+    match chrono::DateTime::from_timestamp(t, 0) {
+        Some(ts) => ts.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        None => t.to_string(),
+    }
+}
+
+// Where a dump into the database at path should carry on: the end of its
+// last record and that record's timeline, or None if it has no records.
+pub fn resume_point(path: &Path) -> Result<Option<(XLogRecPtr, TimeLineID)>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let conn = Connection::open(path).map_err(sql_error)?;
+    let has_records: bool = conn
+        .query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'records'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(sql_error)?;
+    if !has_records {
+        return Ok(None);
+    }
+    conn.query_row(
+        "SELECT end_lsn, timeline FROM records ORDER BY lsn DESC LIMIT 1",
+        [],
+        |row| Ok((row.get::<_, i64>(0)? as XLogRecPtr, row.get(1)?)),
+    )
+    .optional()
+    .map_err(sql_error)
+}
+
+impl SqliteWriter {
+    // Open the database at path, creating it or the tables missing from
+    // it.  WAL of another cluster than the one already in it is refused.
+    pub fn open(path: &Path, system_identifier: u64, wal_seg_sz: u32) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(sql_error)?;
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        let other: Option<i64> = conn
+            .query_row(
+                "SELECT system_identifier FROM segments WHERE system_identifier != ?1 LIMIT 1",
+                [system_identifier as i64],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        if let Some(other) = other {
+            return Err(format!(
+                "database \"{}\" holds WAL of database system {}, not {}",
+                path.display(),
+                other as u64,
+                system_identifier
+            ));
+        }
+        conn.execute_batch("BEGIN").map_err(sql_error)?;
+        Ok(SqliteWriter {
+            conn,
+            system_identifier,
+            wal_seg_sz,
+            segments: BTreeMap::new(),
+            rows: 0,
+        })
+    }
+
+    // Write the record the reader last read.
+    pub fn write(&mut self, state: &XLogReaderState) -> Result<(), String> {
+        let record = RecordSummary::from_state(state);
+        let private = &state.private_data;
+        let timeline = if private.history.is_empty() {
+            private.timeline
+        } else {
+            tli_of_segment(record.lsn, self.wal_seg_sz, &private.history)
+        };
+
+        let inserted = self
+            .conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO records VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    record.lsn as i64,
+                    record.end_lsn as i64,
+                    record.prev as i64,
+                    timeline,
+                    record.rmid,
+                    record.rmgr,
+                    record.info,
+                    record.identify,
+                    record.xid,
+                    record.toplevel_xid,
+                    record.origin,
+                    record.rec_len,
+                    record.main_data_len,
+                    record.fpi_len,
+                    record.tot_len,
+                    record.desc,
+                ])
+            })
+            .map_err(sql_error)?;
+        // in the database already
+        if inserted == 0 {
+            return Ok(());
+        }
+
+        self.write_blocks(&record)?;
+        if record.rmid == RmgrIds::XLOG as u8 {
+            self.write_checkpoint(state, &record)?;
+        } else if record.rmid == RmgrIds::XACT as u8 {
+            self.write_transaction(state, &record)?;
+        }
+
+        let segno = xlog_byte_to_seg(record.lsn, self.wal_seg_sz);
+        let seg = self
+            .segments
+            .entry((timeline, segno))
+            .or_insert(SegmentStats {
+                first_lsn: record.lsn,
+                last_lsn: record.lsn,
+                records: 0,
+            });
+        seg.last_lsn = record.lsn;
+        seg.records += 1;
+
+        self.rows += 1;
+        if self.rows == BATCH_SIZE {
+            self.commit()?;
+            self.conn.execute_batch("BEGIN").map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, record: &RecordSummary) -> Result<(), String> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO blocks VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )
+            .map_err(sql_error)?;
+        for block in &record.blocks {
+            let image = block.image.as_ref();
+            stmt.execute(params![
+                record.lsn as i64,
+                block.block_id,
+                block.locator.spc_oid,
+                block.locator.db_oid,
+                block.locator.rel_oid,
+                block.fork_name(),
+                block.blkno,
+                block.data_len,
+                image.map(|i| i.length),
+                image.map(|i| i.hole_offset),
+                image.map(|i| i.hole_length),
+                image.map(|i| i.apply),
+                image.and_then(|i| i.compression).map(|m| m.to_string()),
+            ])
+            .map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_checkpoint(
+        &mut self,
+        state: &XLogReaderState,
+        record: &RecordSummary,
+    ) -> Result<(), String> {
+        let Some((checkpoint, shutdown)) = xlog_checkpoint(state) else {
+            return Ok(());
+        };
+        self.conn
+            .prepare_cached(
+                "INSERT INTO checkpoints VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    record.lsn as i64,
+                    shutdown,
+                    checkpoint.redo as i64,
+                    checkpoint.time_line_id,
+                    checkpoint.prev_time_line_id,
+                    checkpoint.full_page_writes,
+                    checkpoint.next_xid as i64,
+                    checkpoint.next_oid,
+                    checkpoint.next_multi,
+                    checkpoint.next_multi_offset,
+                    checkpoint.oldest_xid,
+                    checkpoint.oldest_xid_db,
+                    checkpoint.oldest_multi,
+                    checkpoint.oldest_multi_db,
+                    checkpoint.oldest_commit_ts_xid,
+                    checkpoint.newest_commit_ts_xid,
+                    checkpoint.oldest_active_xid,
+                    pg_time_text(checkpoint.time),
+                ])
+            })
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    fn write_transaction(
+        &mut self,
+        state: &XLogReaderState,
+        record: &RecordSummary,
+    ) -> Result<(), String> {
+        let Some(end) = xact_end(state) else {
+            return Ok(());
+        };
+        let subxacts: Vec<String> = end.subxacts.iter().map(|x| x.to_string()).collect();
+        self.conn
+            .prepare_cached("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    record.lsn as i64,
+                    end.xid,
+                    end.status,
+                    timestamptz_to_iso(end.time),
+                    end.db_oid,
+                    end.subxacts.len(),
+                    subxacts.join(" "),
+                    end.nrels,
+                    (!end.gid.is_empty()).then_some(end.gid),
+                ])
+            })
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    // Count the records taken from each segment, and commit.
+    fn commit(&mut self) -> Result<(), String> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO segments VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (timeline, segno) DO UPDATE SET
+                     first_lsn = min(first_lsn, excluded.first_lsn),
+                     last_lsn = max(last_lsn, excluded.last_lsn),
+                     records = records + excluded.records",
+            )
+            .map_err(sql_error)?;
+        for ((timeline, segno), seg) in std::mem::take(&mut self.segments) {
+            stmt.execute(params![
+                timeline,
+                segno as i64,
+                xlog_filename(timeline, segno, self.wal_seg_sz)
+                    .display()
+                    .to_string(),
+                self.system_identifier as i64,
+                seg.first_lsn as i64,
+                seg.last_lsn as i64,
+                seg.records as i64,
+            ])
+            .map_err(sql_error)?;
+        }
+        drop(stmt);
+        self.rows = 0;
+        self.conn.execute_batch("COMMIT").map_err(sql_error)
+    }
+
+    // Commit the last batch.
+    pub fn finish(mut self) -> Result<(), String> {
+        self.commit()
+    }
+}
+
+// Keep what was written before a panic, for the next dump to carry on from.
+impl Drop for SqliteWriter {
+    fn drop(&mut self) {
+        if !self.conn.is_autocommit() {
+            let _ = self.commit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::bundled_segment_reader;
+
+    fn write_segment(path: &Path, endptr: XLogRecPtr) {
+        let mut state = bundled_segment_reader();
+        state.private_data.endptr = endptr;
+        let config = state.config;
+        let mut writer =
+            SqliteWriter::open(path, config.system_identifier, config.wal_seg_sz).unwrap();
+        while xlog_read_record(&mut state) {
+            writer.write(&state).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn records_are_appended_to_sqlite() {
+        let path =
+            std::env::temp_dir().join(format!("pg_waldump_sqlite_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(resume_point(&path).unwrap(), None);
+
+        // half of the records, then all of them
+        write_segment(&path, 0x1002000);
+        let (end, tli) = resume_point(&path).unwrap().unwrap();
+        assert!(end <= 0x1002000);
+        assert_eq!(tli, 1);
+        write_segment(&path, 0x1004000);
+        assert_eq!(resume_point(&path).unwrap(), Some((0x1003DA8, 1)));
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT count(*) FROM records"), 85);
+        assert_eq!(
+            count("SELECT records FROM segments WHERE name = '000000010000000000000001'"),
+            85
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM blocks"),
+            count("SELECT count(*) FROM blocks b JOIN records r USING (lsn)")
+        );
+        assert!(count("SELECT count(*) FROM blocks") > 0);
+        assert_eq!(
+            count("SELECT count(*) FROM checkpoints WHERE shutdown AND lsn = 16777256"),
+            1
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM transactions"),
+            count("SELECT count(*) FROM records WHERE record_type IN ('COMMIT', 'ABORT')")
+        );
+
+        // WAL of another cluster isn't mixed in
+        assert!(SqliteWriter::open(&path, 42, 16 * 1024 * 1024)
+            .err()
+            .unwrap()
+            .contains("holds WAL of database system"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
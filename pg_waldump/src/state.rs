@@ -1,31 +1,32 @@
-use std::collections::LinkedList;
-use std::path::PathBuf;
+use std::collections::VecDeque;
 
 use crate::constant::*;
 use crate::pgtypes::*;
 use crate::util::*;
+use crate::waldec::WalConfig;
+use crate::walsource::WalSource;
 
 #[derive(Default)]
-pub(crate) struct XLogReaderState {
+pub struct XLogReaderState {
     pub errmsg: String,
-    pub(crate) private_data: XLogDumpPrivate,
+    pub private_data: XLogDumpPrivate,
     // Start and end point of last record read.  EndRecPtr is also used as the
     // position to read next.  Calling XLogBeginRead() sets EndRecPtr to the
     // starting position and ReadRecPtr to invalid.
     //
     // Start and end point of last record returned by XLogReadRecord().  These
     // are also available as record->lsn and record->next_lsn.
-    pub(crate) read_recptr: XLogRecPtr, // start of last record read
-    pub(crate) end_recptr: XLogRecPtr,  // end+1 of last record read
+    pub read_recptr: XLogRecPtr, // start of last record read
+    pub end_recptr: XLogRecPtr,  // end+1 of last record read
 
-    pub(crate) segoff: u32,
+    pub segoff: u32,
 
     // Buffer for currently read page (XLOG_BLCKSZ bytes, valid up to at least
     // read_len bytes)
-    pub(crate) read_buf: Vec<u8>,
-    pub(crate) read_len: u32,
-    pub(crate) page_hdr_size: u32,
-    pub(crate) cross_page_record_buf: Vec<u8>,
+    pub read_buf: Vec<u8>,
+    pub read_len: u32,
+    pub page_hdr_size: u32,
+    pub cross_page_record_buf: Vec<u8>,
 
     // ----------------------------------------
     // Decoded representation of current record
@@ -37,28 +38,44 @@ pub(crate) struct XLogReaderState {
     // XLogReadRecordInternal().  NextRecPtr is also used as the position to
     // decode next.  Calling XLogBeginRead() sets NextRecPtr and EndRecPtr to
     // the requested starting position.
-    pub(crate) decode_recptr: XLogRecPtr, // start of last record decoded
-    pub(crate) next_recptr: XLogRecPtr,   // end+1 of last record decoded
-    pub(crate) prev_recptr: XLogRecPtr,   // start of previous record decoded
+    pub decode_recptr: XLogRecPtr, // start of last record decoded
+    pub next_recptr: XLogRecPtr,   // end+1 of last record decoded
+    pub prev_recptr: XLogRecPtr,   // start of previous record decoded
 
     // Last record returned by XLogReadRecord().
-    pub(crate) record: Option<DecodedXLogRecord>,
+    pub record: Option<DecodedXLogRecord>,
 
-    pub(crate) decode_queue: LinkedList<DecodedXLogRecord>,
+    pub decode_queue: VecDeque<DecodedXLogRecord>,
+
+    // Record handed out before the current one, kept so that its buffers
+    // can be reused by the next record decoded.
+    pub recycled_record: Option<DecodedXLogRecord>,
+
+    // Keep each record's bytes and the layout of its parts in the decoded
+    // record (raw and layout), for --raw
+    pub keep_raw: bool,
+    // Describe records, and the errors ending the WAL, the way pg_waldump of
+    // the PostgreSQL version that wrote them does rather than in the current
+    // format, for --compat=upstream
+    pub upstream: bool,
+    // where the WAL bytes come from
+    pub source: Option<Box<dyn WalSource>>,
+
+    // segment size, block size and system identifier of the WAL being read
+    pub config: WalConfig,
 
     // last read XLOG position for data currently in readBuf
-    pub(crate) segcxt: WALSegmentContext,
-    pub(crate) seg: WALOpenSegment,
+    pub seg: WALOpenSegment,
 
     // beginning of the WAL record being read.
-    pub(crate) curr_recptr: XLogRecPtr,
+    pub curr_recptr: XLogRecPtr,
     // timeline to read it from, 0 if a lookup is required
-    pub(crate) curr_tli: TimeLineID,
+    pub curr_tli: TimeLineID,
 
     // beginning of prior page read, and its TLI.  Doesn't necessarily
     // correspond to what's in readBuf; used for timeline sanity checks.
-    pub(crate) latest_page_ptr: XLogRecPtr,
-    pub(crate) latest_page_tli: TimeLineID,
+    pub latest_page_ptr: XLogRecPtr,
+    pub latest_page_tli: TimeLineID,
 }
 
 impl XLogReaderState {
@@ -69,21 +86,53 @@ impl XLogReaderState {
         self.read_len = 0;
     }
 
-    pub fn new(wal_seg_sz: u32, waldir: PathBuf, private_data: XLogDumpPrivate) -> XLogReaderState {
+    pub fn new(
+        config: WalConfig,
+        source: Box<dyn WalSource>,
+        private_data: XLogDumpPrivate,
+    ) -> XLogReaderState {
         let mut state = XLogReaderState::default();
         state.private_data = private_data;
-        state.segcxt.ws_dir = waldir;
-        state.segcxt.ws_segsize = wal_seg_sz;
+        state.source = Some(source);
+        state.config = config;
 
-        state.read_buf = vec![0; XLOG_BLCKSZ as usize];
+        state.read_buf = vec![0; state.config.xlog_blcksz as usize];
         state
     }
 
-    pub fn get_next_record_buf(&self, lsn: XLogRecPtr) -> &[u8] {
-        if self.cross_page_record_buf.len() > 0 {
-            &self.cross_page_record_buf.as_slice()[..]
+    // The tot_len bytes of the record at lsn, where the decoder left them:
+    // reassembled in cross_page_record_buf if the record crosses pages, and
+    // on the page in read_buf otherwise.
+    pub fn record_bytes(&self, lsn: XLogRecPtr, tot_len: u32) -> &[u8] {
+        let off = page_offset(lsn) as usize;
+        if off + tot_len as usize > XLOG_BLCKSZ as usize {
+            &self.cross_page_record_buf[..tot_len as usize]
         } else {
-            &self.read_buf.as_slice()[page_offset(lsn) as usize..]
+            &self.read_buf[off..off + tot_len as usize]
+        }
+    }
+
+    // The last record returned by XLogReadRecord(), read from the reader's
+    // buffers, or from the record's raw copy if it has one.  Valid until the
+    // next record is read.
+    pub fn record(&self) -> Option<XLogRecordRef<'_>> {
+        let decoded = self.record.as_ref()?;
+        let bytes = if decoded.raw.is_empty() {
+            self.record_bytes(decoded.lsn, decoded.header.xl_tot_len)
+        } else {
+            &decoded.raw
+        };
+        Some(XLogRecordRef { decoded, bytes })
+    }
+
+    // Take the last record returned, with a copy of its bytes in raw, so
+    // that it outlives the reader's buffers.
+    pub fn take_record(&mut self) -> Option<DecodedXLogRecord> {
+        let mut decoded = self.record.take()?;
+        if decoded.raw.is_empty() {
+            let bytes = self.record_bytes(decoded.lsn, decoded.header.xl_tot_len);
+            decoded.raw.extend_from_slice(bytes);
         }
+        Some(decoded)
     }
 }
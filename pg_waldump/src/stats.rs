@@ -0,0 +1,331 @@
+// Statistics over the records read, as pg_waldump --stats shows them.  See
+// XLogRecStoreStats() and XLogDumpDisplayStats() in pg_waldump.c.
+use crate::pgtypes::*;
+use crate::rmgr::{get_rmgr_desc, rmgr_id_is_custom, RmgrIds};
+use crate::summary::RecordSummary;
+use crate::version::PgVersion;
+
+// the upper four bits of xl_info are the rmgr's, so sixteen record types
+// per rmgr
+const MAX_XLINFO_TYPES: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub count: u64,
+    pub rec_len: u64,
+    pub fpi_len: u64,
+}
+
+impl Stats {
+    fn add(&mut self, record: &RecordSummary) {
+        self.count += 1;
+        self.rec_len += record.rec_len as u64;
+        self.fpi_len += record.fpi_len as u64;
+    }
+}
+
+// One line of the statistics: a resource manager or, per record, one of
+// its record types.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsRow {
+    pub name: String,
+    pub stats: Stats,
+}
+
+pub struct XLogStats {
+    pub count: u64,
+    pub startptr: XLogRecPtr,
+    pub endptr: XLogRecPtr,
+    pub rmgr_stats: Vec<Stats>,
+    pub record_stats: Vec<[Stats; MAX_XLINFO_TYPES]>,
+}
+
+impl Default for XLogStats {
+    fn default() -> Self {
+        XLogStats {
+            count: 0,
+            startptr: INVALID_XLOG_RECPTR,
+            endptr: INVALID_XLOG_RECPTR,
+            rmgr_stats: vec![Stats::default(); 256],
+            record_stats: vec![[Stats::default(); MAX_XLINFO_TYPES]; 256],
+        }
+    }
+}
+
+impl XLogStats {
+    // Accumulate the record's lengths.
+    pub fn store(&mut self, record: &RecordSummary) {
+        self.count += 1;
+        self.endptr = record.end_lsn;
+
+        let rmid = record.rmid as usize;
+        self.rmgr_stats[rmid].add(record);
+
+        // Update per-record statistics, where the record is identified by a
+        // combination of the RMID and the upper four bits of the xl_info
+        // field (to give sixteen possible entries per RMID).
+        let mut recid = (record.info >> 4) as usize;
+
+        // XACT records need to be handled differently.  Those records use
+        // the first bit of those four bits for an optional flag variable
+        // and the following three bits for the opcode.  We filter opcode
+        // out of xl_info and use it as the identifier of the record.
+        if record.rmid == RmgrIds::XACT as u8 {
+            recid &= 0x07;
+        }
+        self.record_stats[rmid][recid].add(record);
+    }
+
+    // The rows to display: every built-in rmgr, and custom ones that
+    // occurred, or with per_record the record types that occurred.
+    pub fn rows(&self, per_record: bool, version: PgVersion) -> Vec<StatsRow> {
+        let mut rows = Vec::new();
+        for rmid in 0..=u8::MAX {
+            let builtin = rmid < RmgrIds::MAX as u8;
+            if !builtin && !rmgr_id_is_custom(rmid) {
+                continue;
+            }
+            let name = if builtin {
+                get_rmgr_desc(rmid).rm_name.to_string()
+            } else {
+                format!("custom{:03}", rmid)
+            };
+            if !per_record {
+                let stats = self.rmgr_stats[rmid as usize];
+                if !builtin && stats.count == 0 {
+                    continue;
+                }
+                rows.push(StatsRow { name, stats });
+                continue;
+            }
+            for (recid, stats) in self.record_stats[rmid as usize].iter().enumerate() {
+                // skip undefined combinations and ones that didn't occur
+                if stats.count == 0 {
+                    continue;
+                }
+                let info = (recid << 4) as u8;
+                let id = if builtin {
+                    (get_rmgr_desc(rmid).rm_identify)(info, version)
+                } else {
+                    String::new()
+                };
+                let id = if id.is_empty() {
+                    format!("UNKNOWN ({:x})", info)
+                } else {
+                    id
+                };
+                rows.push(StatsRow {
+                    name: format!("{}/{}", name, id),
+                    stats: *stats,
+                });
+            }
+        }
+        rows
+    }
+
+    fn totals(&self) -> Stats {
+        let mut total = Stats::default();
+        for stats in &self.rmgr_stats {
+            total.count += stats.count;
+            total.rec_len += stats.rec_len;
+            total.fpi_len += stats.fpi_len;
+        }
+        total
+    }
+
+    // The statistics as pg_waldump prints them.
+    pub fn display(&self, per_record: bool, version: PgVersion) -> String {
+        let total = self.totals();
+        let total_len = total.rec_len + total.fpi_len;
+        let mut out = format!(
+            "WAL statistics between {} and {}:\n",
+            crate::waldec::lsn_format(self.startptr),
+            crate::waldec::lsn_format(self.endptr)
+        );
+
+        // 27 is strlen("Transaction/COMMIT_PREPARED"), 20 is strlen(2^64), 8
+        // is strlen("(100.00%)")
+        out += &format!(
+            "{:<27} {:>20} {:>8} {:>20} {:>8} {:>20} {:>8} {:>20} {:>8}\n",
+            "Type", "N", "(%)", "Record size", "(%)", "FPI size", "(%)", "Combined size", "(%)"
+        );
+        out += &format!(
+            "{:<27} {:>20} {:>8} {:>20} {:>8} {:>20} {:>8} {:>20} {:>8}\n",
+            "----", "-", "---", "-----------", "---", "--------", "---", "-------------", "---"
+        );
+        for row in self.rows(per_record, version) {
+            let s = row.stats;
+            let tot_len = s.rec_len + s.fpi_len;
+            out += &format!(
+                "{:<27} {:>20} ({:>6.2}) {:>20} ({:>6.2}) {:>20} ({:>6.2}) {:>20} ({:>6.2})\n",
+                row.name,
+                s.count,
+                pct(s.count, total.count),
+                s.rec_len,
+                pct(s.rec_len, total.rec_len),
+                s.fpi_len,
+                pct(s.fpi_len, total.fpi_len),
+                tot_len,
+                pct(tot_len, total_len)
+            );
+        }
+        out += &format!(
+            "{:<27} {:>20} {:>8} {:>20} {:>8} {:>20} {:>8} {:>20}\n",
+            "", "--------", "", "--------", "", "--------", "", "--------"
+        );
+
+        // The percentages in earlier rows were calculated against the
+        // column total, but the ones that follow are against the row total.
+        // Note that these are displayed with a % symbol to differentiate
+        // them from the earlier ones, and are thus up to 9 characters long.
+        out += &format!(
+            "{:<27} {:>20} {:<9}{:>20} {:<9}{:>20} {:<9}{:>20} {:<6}\n",
+            "Total",
+            self.count,
+            "",
+            total.rec_len,
+            format!("[{:.2}%]", pct(total.rec_len, total_len)),
+            total.fpi_len,
+            format!("[{:.2}%]", pct(total.fpi_len, total_len)),
+            total_len,
+            "[100%]"
+        );
+        out
+    }
+
+    // The rows for the structured output formats, the total last with
+    // type "Total".
+    #[cfg(feature = "serde")]
+    pub fn json_rows(&self, per_record: bool, version: PgVersion) -> Vec<StatsRowJson> {
+        let total = self.totals();
+        let total_len = total.rec_len + total.fpi_len;
+        let mut rows: Vec<StatsRowJson> = self
+            .rows(per_record, version)
+            .iter()
+            .map(|r| {
+                let s = r.stats;
+                let tot_len = s.rec_len + s.fpi_len;
+                StatsRowJson {
+                    name: r.name.clone(),
+                    count: s.count,
+                    count_pct: Some(pct(s.count, total.count)),
+                    rec_len: s.rec_len,
+                    rec_len_pct: pct(s.rec_len, total.rec_len),
+                    fpi_len: s.fpi_len,
+                    fpi_len_pct: pct(s.fpi_len, total.fpi_len),
+                    tot_len,
+                    tot_len_pct: Some(pct(tot_len, total_len)),
+                }
+            })
+            .collect();
+        // against the row total, as in the text
+        rows.push(StatsRowJson {
+            name: String::from("Total"),
+            count: self.count,
+            count_pct: None,
+            rec_len: total.rec_len,
+            rec_len_pct: pct(total.rec_len, total_len),
+            fpi_len: total.fpi_len,
+            fpi_len_pct: pct(total.fpi_len, total_len),
+            tot_len: total_len,
+            tot_len_pct: None,
+        });
+        rows
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self, per_record: bool, version: PgVersion) -> StatsJson {
+        StatsJson {
+            start: self.startptr,
+            end: self.endptr,
+            per_record,
+            rows: self.json_rows(per_record, version),
+        }
+    }
+}
+
+// A row of the statistics in the structured output formats.  The total
+// row has no percentages against the column totals.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+pub struct StatsRowJson {
+    #[serde(rename = "type")]
+    pub name: String,
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_pct: Option<f64>,
+    pub rec_len: u64,
+    pub rec_len_pct: f64,
+    pub fpi_len: u64,
+    pub fpi_len_pct: f64,
+    pub tot_len: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tot_len_pct: Option<f64>,
+}
+
+// The statistics as one object.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+pub struct StatsJson {
+    #[serde(with = "crate::serde_util::lsn")]
+    pub start: XLogRecPtr,
+    #[serde(with = "crate::serde_util::lsn")]
+    pub end: XLogRecPtr,
+    pub per_record: bool,
+    pub rows: Vec<StatsRowJson>,
+}
+
+fn pct(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * n as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::{bundled_segment_reader, BUNDLED_FIRST_RECORD};
+
+    #[test]
+    fn stats_are_counted_per_rmgr_and_record() {
+        let mut state = bundled_segment_reader();
+        let config = state.config;
+        let mut stats = XLogStats {
+            startptr: BUNDLED_FIRST_RECORD,
+            ..Default::default()
+        };
+        while xlog_read_record(&mut state) {
+            stats.store(&RecordSummary::from_state(&state));
+        }
+        assert_eq!(stats.count, 85);
+
+        let text = stats.display(false, config.version);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "WAL statistics between 0/1000028 and 0/1003DA8:"
+        );
+        assert_eq!(lines.len(), 3 + RmgrIds::MAX as usize + 2);
+        assert!(lines
+            .last()
+            .unwrap()
+            .starts_with("Total                                         85"));
+
+        let rows = stats.rows(true, config.version);
+        assert_eq!(
+            rows.iter().map(|r| r.stats.count).sum::<u64>(),
+            85,
+            "{:?}",
+            rows
+        );
+        assert!(rows.iter().any(|r| r.name == "XLOG/CHECKPOINT_SHUTDOWN"));
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&stats.to_json(false, config.version)).unwrap();
+            assert!(json.starts_with(r#"{"start":"0/1000028","end":"0/1003DA8","per_record":false,"rows":[{"type":"XLOG","count":"#));
+        }
+    }
+}
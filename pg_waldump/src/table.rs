@@ -0,0 +1,228 @@
+// Records and block references as rows, for --format=csv and copy.
+//
+// The columns are those of pg_walinspect's pg_get_wal_records_info() and
+// pg_get_wal_block_info(), less the block data and images themselves, so
+// that the output loads into tables of the same shape, e.g.
+//
+//     \copy wal_records from 'records.csv' with (format csv, header)
+//
+// copy is COPY's own text format, tab separated with \N for NULL, which
+// takes the header option from PostgreSQL 15 on.
+use crate::constant::*;
+use crate::pgtypes::*;
+use crate::summary::{BlockSummary, RecordSummary};
+use crate::waldec::{lsn_format, BkpImageCompressMethod};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Copy,
+}
+
+pub const RECORD_COLUMNS: &[&str] = &[
+    "start_lsn",
+    "end_lsn",
+    "prev_lsn",
+    "xid",
+    "resource_manager",
+    "record_type",
+    "record_length",
+    "main_data_length",
+    "fpi_length",
+    "description",
+    "block_ref",
+];
+
+pub const BLOCK_COLUMNS: &[&str] = &[
+    "start_lsn",
+    "end_lsn",
+    "prev_lsn",
+    "block_id",
+    "reltablespace",
+    "reldatabase",
+    "relfilenode",
+    "relforknumber",
+    "relblocknumber",
+    "xid",
+    "resource_manager",
+    "record_type",
+    "record_length",
+    "main_data_length",
+    "block_data_length",
+    "block_fpi_length",
+    "block_fpi_info",
+    "description",
+];
+
+fn record_type(record: &RecordSummary) -> String {
+    match &record.identify {
+        Some(id) => id.clone(),
+        None => format!("UNKNOWN ({:x})", record.info & !XLR_INFO_MASK),
+    }
+}
+
+// The row of pg_get_wal_records_info() for the record; None is NULL.
+pub fn record_row(record: &RecordSummary) -> Vec<Option<String>> {
+    let block_ref: Vec<String> = record.blocks.iter().map(BlockSummary::describe).collect();
+    vec![
+        Some(lsn_format(record.lsn)),
+        Some(lsn_format(record.end_lsn)),
+        Some(lsn_format(record.prev)),
+        Some(record.xid.to_string()),
+        Some(record.rmgr.to_string()),
+        Some(record_type(record)),
+        Some(record.tot_len.to_string()),
+        Some(record.main_data_len.to_string()),
+        Some(record.fpi_len.to_string()),
+        Some(record.desc.clone()),
+        (!block_ref.is_empty()).then(|| block_ref.join(", ")),
+    ]
+}
+
+// The image's flags as a text[] literal, as block_fpi_info has them.
+fn fpi_info(block: &BlockSummary) -> Option<String> {
+    let image = block.image.as_ref()?;
+    let mut flags = Vec::new();
+    if image.hole_length > 0 {
+        flags.push("HAS_HOLE");
+    }
+    if image.apply {
+        flags.push("APPLY");
+    }
+    match image.compression {
+        Some(BkpImageCompressMethod::PGLZ) => flags.push("COMPRESS_PGLZ"),
+        Some(BkpImageCompressMethod::LZ4) => flags.push("COMPRESS_LZ4"),
+        Some(BkpImageCompressMethod::ZSTD) => flags.push("COMPRESS_ZSTD"),
+        None => {}
+    }
+    Some(format!("{{{}}}", flags.join(",")))
+}
+
+// The rows of pg_get_wal_block_info() for the record, one per block
+// reference.
+pub fn block_rows(record: &RecordSummary) -> Vec<Vec<Option<String>>> {
+    record
+        .blocks
+        .iter()
+        .map(|block| {
+            vec![
+                Some(lsn_format(record.lsn)),
+                Some(lsn_format(record.end_lsn)),
+                Some(lsn_format(record.prev)),
+                Some(block.block_id.to_string()),
+                Some(block.locator.spc_oid.to_string()),
+                Some(block.locator.db_oid.to_string()),
+                Some(block.locator.rel_oid.to_string()),
+                Some((block.fork as i16).to_string()),
+                Some(block.blkno.to_string()),
+                Some(record.xid.to_string()),
+                Some(record.rmgr.to_string()),
+                Some(record_type(record)),
+                Some(record.tot_len.to_string()),
+                Some(record.main_data_len.to_string()),
+                (block.data_len > 0).then(|| block.data_len.to_string()),
+                block.image.as_ref().map(|image| image.length.to_string()),
+                fpi_info(block),
+                Some(record.desc.clone()),
+            ]
+        })
+        .collect()
+}
+
+fn csv_field(field: &Option<String>) -> String {
+    match field {
+        // NULL is an unquoted empty field, and so an empty string is quoted
+        None => String::new(),
+        Some(s) if s.is_empty() => String::from("\"\""),
+        Some(s) if s.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        }
+        Some(s) => s.clone(),
+    }
+}
+
+fn copy_field(field: &Option<String>) -> String {
+    match field {
+        None => String::from("\\N"),
+        Some(s) => {
+            let mut out = String::with_capacity(s.len());
+            for c in s.chars() {
+                match c {
+                    '\\' => out.push_str("\\\\"),
+                    '\t' => out.push_str("\\t"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    c => out.push(c),
+                }
+            }
+            out
+        }
+    }
+}
+
+// One line of output, without the newline.
+pub fn format_row(format: TableFormat, row: &[Option<String>]) -> String {
+    let fields: Vec<String> = match format {
+        TableFormat::Csv => row.iter().map(csv_field).collect(),
+        TableFormat::Copy => row.iter().map(copy_field).collect(),
+    };
+    let sep = match format {
+        TableFormat::Csv => ",",
+        TableFormat::Copy => "\t",
+    };
+    fields.join(sep)
+}
+
+// The header line naming the columns.
+pub fn format_header(format: TableFormat, columns: &[&str]) -> String {
+    let row: Vec<Option<String>> = columns.iter().map(|c| Some(c.to_string())).collect();
+    format_row(format, &row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::bundled_segment_reader;
+
+    #[test]
+    fn records_and_blocks_are_written_as_rows() {
+        let row = vec![
+            Some(String::from("a,b")),
+            None,
+            Some(String::new()),
+            Some(String::from("say \"x\"\tand\\y")),
+        ];
+        assert_eq!(
+            format_row(TableFormat::Csv, &row),
+            "\"a,b\",,\"\",\"say \"\"x\"\"\tand\\y\""
+        );
+        assert_eq!(
+            format_row(TableFormat::Copy, &row),
+            "a,b\t\\N\t\tsay \"x\"\\tand\\\\y"
+        );
+
+        let mut state = bundled_segment_reader();
+        let (mut records, mut blocks) = (0, 0);
+        while xlog_read_record(&mut state) {
+            let record = RecordSummary::from_state(&state);
+            let row = record_row(&record);
+            assert_eq!(row.len(), RECORD_COLUMNS.len());
+            assert_eq!(row[10].is_some(), !record.blocks.is_empty());
+            for row in block_rows(&record) {
+                assert_eq!(row.len(), BLOCK_COLUMNS.len());
+                assert_eq!(row[0], record_row(&record)[0]);
+                blocks += 1;
+            }
+            if records == 0 {
+                assert_eq!(
+                    format_row(TableFormat::Csv, &row[..7]),
+                    "0/1000028,0/10000A0,0/0,0,XLOG,CHECKPOINT_SHUTDOWN,114"
+                );
+            }
+            records += 1;
+        }
+        assert_eq!(records, 85);
+        assert!(blocks > 0);
+    }
+}
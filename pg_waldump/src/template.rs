@@ -0,0 +1,335 @@
+// Output templates, for --template.
+//
+// A template is text with fields in braces, rendered once per record:
+//
+//     {lsn} {rmgr}/{type} xid={xid} {blocks:rel}/{blocks:blk} {desc}
+//
+// {name} is a field of the record and {blocks:name} one of a block
+// reference.  The part of the template from the first block field to the
+// last is repeated for each block reference of the record, separated by
+// ", ", and left out if it has none.
+//
+// {?name}...{/name} is a conditional section, shown only if the field is
+// neither empty nor 0, e.g. {?blocks:image} (FPW){/blocks:image}.  {{ and
+// }} are literal braces.
+use crate::summary::{BlockSummary, RecordSummary};
+use crate::waldec::lsn_out;
+
+pub const RECORD_FIELDS: &[(&str, &str)] = &[
+    ("lsn", "start of the record"),
+    ("end_lsn", "end of the record"),
+    ("prev", "start of the previous record"),
+    ("rmgr", "resource manager name"),
+    ("rmid", "resource manager id"),
+    ("type", "record type, e.g. INSERT, or UNKNOWN (info)"),
+    ("info", "xl_info, in hex"),
+    ("xid", "transaction id"),
+    (
+        "toplevel_xid",
+        "top-level transaction id of a subtransaction",
+    ),
+    ("origin", "replication origin"),
+    ("rec_len", "length without the full page images"),
+    ("fpi_len", "length of the full page images"),
+    ("tot_len", "total length"),
+    ("desc", "description"),
+    ("nblocks", "number of block references"),
+];
+
+pub const BLOCK_FIELDS: &[(&str, &str)] = &[
+    ("id", "block reference id"),
+    ("rel", "relation, as spcoid/dboid/relfilenode"),
+    ("spc", "tablespace oid"),
+    ("db", "database oid"),
+    ("relnode", "relfilenode"),
+    ("fork", "fork name"),
+    ("blk", "block number"),
+    ("data_len", "length of the block data"),
+    ("image", "length of the full page image, if there is one"),
+    ("hole_offset", "offset of the image's hole"),
+    ("hole_length", "length of the image's hole"),
+    ("apply", "true if the image is restored at redo"),
+    ("compression", "compression method of the image"),
+];
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Literal(String),
+    Field(String),
+    BlockField(String),
+    // a conditional section on a field or, if block is true, a block field
+    Section {
+        name: String,
+        block: bool,
+        nodes: Vec<Node>,
+    },
+}
+
+impl Node {
+    fn is_block(&self) -> bool {
+        match self {
+            Node::Literal(_) | Node::Field(_) => false,
+            Node::BlockField(_) => true,
+            Node::Section { block, nodes, .. } => *block || nodes.iter().any(Node::is_block),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+fn field_name(name: &str) -> Result<(String, bool), String> {
+    let (fields, field, block) = match name.strip_prefix("blocks:") {
+        Some(field) => (BLOCK_FIELDS, field, true),
+        None => (RECORD_FIELDS, name, false),
+    };
+    if !fields.iter().any(|(f, _)| *f == field) {
+        return Err(format!("unknown field \"{}\" in template", name));
+    }
+    Ok((field.to_string(), block))
+}
+
+// Parse nodes up to the end of the template or, within a section, its
+// closing tag.
+fn parse_nodes(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    closing: Option<&str>,
+) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut literal = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(String::from("unmatched } in template")),
+            '{' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(String::from("unterminated { in template")),
+                    }
+                }
+                if !literal.is_empty() {
+                    nodes.push(Node::Literal(std::mem::take(&mut literal)));
+                }
+                if let Some(name) = tag.strip_prefix('/') {
+                    if closing == Some(name) {
+                        return Ok(nodes);
+                    }
+                    return Err(format!("unexpected {{/{}}} in template", name));
+                }
+                if let Some(name) = tag.strip_prefix('?') {
+                    let (field, block) = field_name(name)?;
+                    let section = parse_nodes(chars, Some(name))?;
+                    nodes.push(Node::Section {
+                        name: field,
+                        block,
+                        nodes: section,
+                    });
+                    continue;
+                }
+                nodes.push(match field_name(&tag)? {
+                    (field, true) => Node::BlockField(field),
+                    (field, false) => Node::Field(field),
+                });
+            }
+            c => literal.push(c),
+        }
+    }
+    if let Some(name) = closing {
+        return Err(format!("missing {{/{}}} in template", name));
+    }
+    if !literal.is_empty() {
+        nodes.push(Node::Literal(literal));
+    }
+    Ok(nodes)
+}
+
+fn record_field(record: &RecordSummary, name: &str) -> String {
+    match name {
+        "lsn" => lsn_out(record.lsn),
+        "end_lsn" => lsn_out(record.end_lsn),
+        "prev" => lsn_out(record.prev),
+        "rmgr" => record.rmgr.to_string(),
+        "rmid" => record.rmid.to_string(),
+        "type" => match &record.identify {
+            Some(id) => id.clone(),
+            None => format!(
+                "UNKNOWN ({:X})",
+                record.info & !crate::constant::XLR_INFO_MASK
+            ),
+        },
+        "info" => format!("0x{:02X}", record.info),
+        "xid" => record.xid.to_string(),
+        "toplevel_xid" => record.toplevel_xid.to_string(),
+        "origin" => record.origin.to_string(),
+        "rec_len" => record.rec_len.to_string(),
+        "fpi_len" => record.fpi_len.to_string(),
+        "tot_len" => record.tot_len.to_string(),
+        "desc" => record.desc.clone(),
+        "nblocks" => record.blocks.len().to_string(),
+        _ => unreachable!("field {} was checked when parsing", name),
+    }
+}
+
+fn block_field(block: &BlockSummary, name: &str) -> String {
+    let image = block.image.as_ref();
+    match name {
+        "id" => block.block_id.to_string(),
+        "rel" => format!(
+            "{}/{}/{}",
+            block.locator.spc_oid, block.locator.db_oid, block.locator.rel_oid
+        ),
+        "spc" => block.locator.spc_oid.to_string(),
+        "db" => block.locator.db_oid.to_string(),
+        "relnode" => block.locator.rel_oid.to_string(),
+        "fork" => block.fork_name().to_string(),
+        "blk" => block.blkno.to_string(),
+        "data_len" => block.data_len.to_string(),
+        "image" => image.map(|i| i.length.to_string()).unwrap_or_default(),
+        "hole_offset" => image.map(|i| i.hole_offset.to_string()).unwrap_or_default(),
+        "hole_length" => image.map(|i| i.hole_length.to_string()).unwrap_or_default(),
+        "apply" => image.map(|i| i.apply.to_string()).unwrap_or_default(),
+        "compression" => image
+            .and_then(|i| i.compression)
+            .map(|m| m.to_string())
+            .unwrap_or_default(),
+        _ => unreachable!("field {} was checked when parsing", name),
+    }
+}
+
+fn is_set(value: &str) -> bool {
+    !value.is_empty() && value != "0" && value != "false"
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let nodes = parse_nodes(&mut template.chars().peekable(), None)?;
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, record: &RecordSummary) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, record, None, &mut out);
+        out
+    }
+}
+
+// Render nodes for record and, within the repeated part, one of its
+// blocks.
+fn render_nodes(
+    nodes: &[Node],
+    record: &RecordSummary,
+    block: Option<&BlockSummary>,
+    out: &mut String,
+) {
+    // the part repeated per block, unless we're in it already
+    let span = match block {
+        Some(_) => None,
+        None => nodes
+            .iter()
+            .position(Node::is_block)
+            .map(|first| (first, nodes.iter().rposition(Node::is_block).unwrap())),
+    };
+    let mut i = 0;
+    while i < nodes.len() {
+        if let Some((first, last)) = span {
+            if i == first {
+                for (n, blk) in record.blocks.iter().enumerate() {
+                    if n > 0 {
+                        out.push_str(", ");
+                    }
+                    render_nodes(&nodes[first..=last], record, Some(blk), out);
+                }
+                i = last + 1;
+                continue;
+            }
+        }
+        match &nodes[i] {
+            Node::Literal(s) => out.push_str(s),
+            Node::Field(name) => out.push_str(&record_field(record, name)),
+            Node::BlockField(name) => out.push_str(&block_field(block.unwrap(), name)),
+            Node::Section {
+                name,
+                block: is_block,
+                nodes,
+            } => {
+                let value = match is_block {
+                    true => block_field(block.unwrap(), name),
+                    false => record_field(record, name),
+                };
+                if is_set(&value) {
+                    render_nodes(nodes, record, block, out);
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgtypes::*;
+    use crate::waldec::xlog_read_record;
+    use crate::walsource::tests::bundled_segment_reader;
+
+    #[test]
+    fn templates_render_fields_blocks_and_sections() {
+        assert!(Template::parse("{lsn} {nosuch}")
+            .unwrap_err()
+            .contains("\"nosuch\""));
+        assert!(Template::parse("{?fpi_len} FPI").is_err());
+        assert!(Template::parse("{xid").is_err());
+
+        let mut state = bundled_segment_reader();
+
+        let template = Template::parse(
+            "{{{lsn}}} {rmgr}/{type} xid={xid} [{blocks:rel}/{blocks:blk}\
+             {?blocks:image} FPW {blocks:image}{/blocks:image}]{?fpi_len} fpi{/fpi_len}",
+        )
+        .unwrap();
+        assert!(xlog_read_record(&mut state));
+        let record = RecordSummary::from_state(&state);
+        assert_eq!(
+            template.render(&record),
+            "{0/01000028} XLOG/CHECKPOINT_SHUTDOWN xid=0 []"
+        );
+
+        // the next one is a full page image
+        assert!(xlog_read_record(&mut state));
+        assert!(xlog_read_record(&mut state));
+        let record = RecordSummary::from_state(&state);
+        assert_eq!(
+            template.render(&record),
+            "{0/010000C0} XLOG/FPI xid=1 [1663/1/6117/0 FPW 88] fpi"
+        );
+
+        // one part per block, none without blocks
+        let mut record = record;
+        let mut second = record.blocks[0].clone();
+        second.block_id = 1;
+        second.blkno = 7;
+        second.image = None;
+        record.blocks.push(second);
+        assert_eq!(
+            template.render(&record),
+            "{0/010000C0} XLOG/FPI xid=1 [1663/1/6117/0 FPW 88, 1663/1/6117/7] fpi"
+        );
+        record.blocks.clear();
+        assert_eq!(
+            Template::parse("<{blocks:id}>").unwrap().render(&record),
+            "<>"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+// Timeline history, as in src/backend/access/transam/timeline.c
+//
+// A timeline history file 0000000N.history lists the ancestors of
+// timeline N, one line per ancestor:
+//
+//   parentTLI	switchpoint	reason
+//
+// switchpoint is the LSN where the timeline on that line ended and the
+// next one began.  Lines starting with # are comments.
+//
+// When a standby is promoted, the segment in which the switch happened is
+// copied to the new timeline, and the old timeline's copy is archived with
+// a .partial suffix.  So the WAL up to a switch point is found on the new
+// timeline for that last segment, and on the old timeline before it.
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::compress::SegmentFile;
+use crate::pgtypes::*;
+use crate::waldec::lsn_out;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeLineHistoryEntry {
+    pub tli: TimeLineID,
+    pub begin: XLogRecPtr, // inclusive
+    pub end: XLogRecPtr,   // exclusive, InvalidXLogRecPtr means infinity
+}
+
+pub fn tl_history_file_name(tli: TimeLineID) -> PathBuf {
+    PathBuf::from(format!("{:08X}.history", tli))
+}
+
+// History of a timeline without ancestors, or whose history file is
+// missing.
+pub fn single_timeline(tli: TimeLineID) -> Vec<TimeLineHistoryEntry> {
+    vec![TimeLineHistoryEntry {
+        tli,
+        begin: INVALID_XLOG_RECPTR,
+        end: INVALID_XLOG_RECPTR,
+    }]
+}
+
+// Parse the contents of the history file of targetTLI.  Returns the list of
+// timelines leading to targetTLI, newest first, like readTimeLineHistory().
+pub fn parse_timeline_history(
+    content: &str,
+    target_tli: TimeLineID,
+) -> Result<Vec<TimeLineHistoryEntry>, String> {
+    let mut result = Vec::new();
+    let mut prevend = INVALID_XLOG_RECPTR;
+    let mut last_tli: TimeLineID = 0;
+
+    for line in content.lines() {
+        let line = line.trim_start();
+        // skip leading whitespace and check for # comment
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let tli = fields.next().and_then(|f| f.parse::<TimeLineID>().ok());
+        let switchpoint = fields.next().and_then(parse_lsn);
+        let (tli, switchpoint) =
+            match (tli, switchpoint) {
+                (Some(t), Some(s)) => (t, s),
+                (Some(_), None) => return Err(String::from(
+                    "syntax error in history file: expected a write-ahead log switchpoint location",
+                )),
+                _ => {
+                    return Err(format!(
+                        "syntax error in history file: {}: expected a numeric timeline ID",
+                        line
+                    ))
+                }
+            };
+
+        if !result.is_empty() && tli <= last_tli {
+            return Err(String::from(
+                "invalid data in history file: timeline IDs must be in increasing sequence",
+            ));
+        }
+        last_tli = tli;
+
+        result.insert(
+            0,
+            TimeLineHistoryEntry {
+                tli,
+                begin: prevend,
+                end: switchpoint,
+            },
+        );
+        prevend = switchpoint;
+    }
+
+    if !result.is_empty() && target_tli <= last_tli {
+        return Err(String::from(
+            "invalid data in history file: timeline IDs must be less than child timeline's ID",
+        ));
+    }
+
+    // Create one more entry for the "tip" of the timeline, which has no entry
+    // in the history file.
+    result.insert(
+        0,
+        TimeLineHistoryEntry {
+            tli: target_tli,
+            begin: prevend,
+            end: INVALID_XLOG_RECPTR,
+        },
+    );
+    Ok(result)
+}
+
+// Read the history of target_tli through open, which opens a file by name
+// wherever the WAL lives.  A missing history file means the timeline has
+// no ancestors we know of.
+pub fn read_timeline_history<F>(
+    target_tli: TimeLineID,
+    open: F,
+) -> Result<Vec<TimeLineHistoryEntry>, String>
+where
+    F: FnOnce(&Path) -> std::io::Result<SegmentFile>,
+{
+    // Timeline 1 does not have a history file
+    if target_tli == 1 {
+        return Ok(single_timeline(target_tli));
+    }
+
+    let fname = tl_history_file_name(target_tli);
+    let mut file = match open(&fname) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(single_timeline(target_tli))
+        }
+        Err(e) => {
+            return Err(format!(
+                "could not open file \"{}\": {}",
+                fname.display(),
+                e
+            ))
+        }
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .map_err(|e| format!("could not read file \"{}\": {}", fname.display(), e))?;
+    parse_timeline_history(&content, target_tli).map_err(|e| format!("{}: {}", fname.display(), e))
+}
+
+// Returns the entry of the timeline in use at a particular point in time,
+// in the given timeline history.
+pub fn timeline_of_point(
+    ptr: XLogRecPtr,
+    history: &[TimeLineHistoryEntry],
+) -> &TimeLineHistoryEntry {
+    for tle in history {
+        if (tle.begin == INVALID_XLOG_RECPTR || tle.begin <= ptr)
+            && (tle.end == INVALID_XLOG_RECPTR || ptr < tle.end)
+        {
+            // found it
+            return tle;
+        }
+    }
+
+    // shouldn't happen.
+    panic!("timeline history was not contiguous at {}", lsn_out(ptr));
+}
+
+// Returns the ID of the timeline in use at a particular point in time, in
+// the given timeline history, like tliOfPointInHistory().
+pub fn tli_of_point_in_history(ptr: XLogRecPtr, history: &[TimeLineHistoryEntry]) -> TimeLineID {
+    timeline_of_point(ptr, history).tli
+}
+
+// The timeline to read the segment holding ptr from: the newest timeline
+// in use at some point within the segment, since a segment with a switch
+// point in it is copied to the new timeline up to the switch.  Like the
+// timeline choice in XLogReadDetermineTimeline().
+pub fn tli_of_segment(
+    ptr: XLogRecPtr,
+    segsize: u32,
+    history: &[TimeLineHistoryEntry],
+) -> TimeLineID {
+    let seg_end = ptr - ptr % segsize as u64 + segsize as u64 - 1;
+    tli_of_point_in_history(seg_end, history)
+}
+
+pub(crate) fn parse_lsn(s: &str) -> Option<XLogRecPtr> {
+    let (hi, lo) = s.split_once('/')?;
+    let hi = u32::from_str_radix(hi, 16).ok()?;
+    let lo = u32::from_str_radix(lo, 16).ok()?;
+    Some((hi as u64) << 32 | lo as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_is_followed() {
+        let content = "1\t0/3000000\tno recovery target specified\n\n\
+                       # comment\n\
+                       2\t0/5000158\tat restore point \"x\"\n";
+        let history = parse_timeline_history(content, 3).unwrap();
+        assert_eq!(
+            history,
+            vec![
+                TimeLineHistoryEntry {
+                    tli: 3,
+                    begin: 0x5000158,
+                    end: 0
+                },
+                TimeLineHistoryEntry {
+                    tli: 2,
+                    begin: 0x3000000,
+                    end: 0x5000158
+                },
+                TimeLineHistoryEntry {
+                    tli: 1,
+                    begin: 0,
+                    end: 0x3000000
+                },
+            ]
+        );
+        assert_eq!(tli_of_point_in_history(0x2FFFFFF, &history), 1);
+        assert_eq!(tli_of_point_in_history(0x3000000, &history), 2);
+        assert_eq!(tli_of_point_in_history(0x5000158, &history), 3);
+
+        // the segments holding the switch points are read from the new
+        // timeline, even before the switch
+        let segsize = 16 * 1024 * 1024;
+        assert_eq!(tli_of_segment(0x2FFE000, segsize, &history), 1);
+        assert_eq!(tli_of_segment(0x3000000, segsize, &history), 2);
+        assert_eq!(tli_of_segment(0x5000000, segsize, &history), 3);
+
+        assert!(parse_timeline_history("2\t0/1\tx\n1\t0/2\ty\n", 3).is_err());
+        assert!(parse_timeline_history("1\t0/1\tx\n", 1).is_err());
+        assert!(parse_timeline_history("1\tnonsense\n", 2).is_err());
+    }
+}
@@ -42,8 +42,39 @@ pub fn byte_to_seg(xlrp: XLogRecPtr, wal_segsz_bytes: u32) -> XLogSegNo {
     xlrp / wal_segsz_bytes as u64
 }
 
+// CRC-32C (Castagnoli), as computed by INIT_CRC32C, COMP_CRC32C and
+// FIN_CRC32C
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32c(data: &[u8]) -> PgCrc32c {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xFFFF_FFFF
+}
+
 // Error information from wal_read that both backend and frontend caller can
-// process.  Currently only errors from pg_pread can be reported.
+// process.  Mostly errors from pg_pread; a source that isn't reading files,
+// e.g. one streaming from a server, can say what went wrong in msg.
 #[derive(Debug)]
 pub struct WALReadError {
     pub errno: i32,          // errno set by the last pg_pread()
@@ -51,10 +82,14 @@ pub struct WALReadError {
     pub req: u32,            // Bytes requested to be read.
     pub read: u32,           // Bytes read by the last read().
     pub seg: WALOpenSegment, // Segment we tried to read from.
+    pub msg: Option<String>, // What went wrong, if errno doesn't tell.
 }
 
 impl std::fmt::Display for WALReadError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            return write!(f, "{}", msg);
+        }
         write!(
             f,
             "WALReadError: errno: {}, offset: {}, requested: {}, read: {}, segment: {}",
@@ -72,9 +107,30 @@ pub fn prefix_length(s: &str, set: &str) -> usize {
 
 #[inline]
 pub fn is_xlog_filename(fname: &std::path::PathBuf) -> bool {
-    let fname = fname.file_name().unwrap();
-    fname.len() == XLOG_FNAME_LEN
-        && prefix_length(fname.to_str().unwrap(), "0123456789ABCDEF") == XLOG_FNAME_LEN
+    let fname = fname.file_name().unwrap().to_str().unwrap_or("");
+    // possibly compressed by the archive_command
+    let (fname, _) = crate::compress::split_compression(fname);
+    fname.len() == XLOG_FNAME_LEN && prefix_length(fname, "0123456789ABCDEF") == XLOG_FNAME_LEN
+}
+
+// A segment left behind by promotion, NAME.partial, possibly compressed.
+pub fn is_partial_xlog_filename(fname: &std::path::Path) -> bool {
+    let fname = fname.file_name().unwrap().to_str().unwrap_or("");
+    let (fname, _) = crate::compress::split_compression(fname);
+    match fname.strip_suffix(".partial") {
+        Some(name) => is_xlog_filename(&PathBuf::from(name)),
+        None => false,
+    }
+}
+
+// A timeline history file, 0000000N.history, possibly compressed.
+pub fn is_tl_history_filename(fname: &std::path::Path) -> bool {
+    let fname = fname.file_name().unwrap().to_str().unwrap_or("");
+    let (fname, _) = crate::compress::split_compression(fname);
+    match fname.strip_suffix(".history") {
+        Some(tli) => tli.len() == 8 && prefix_length(tli, "0123456789ABCDEF") == 8,
+        None => false,
+    }
 }
 
 #[inline]
@@ -102,12 +158,48 @@ pub fn xlog_from_file_name(
     wal_seg_sz: u32,
 ) {
     let fname = fname.to_str().unwrap();
-    *timeline = fname[0..8].parse::<u32>().unwrap();
-    let log = fname[8..16].parse::<u64>().unwrap();
-    let seg = fname[16..24].parse::<u64>().unwrap();
+    *timeline = u32::from_str_radix(&fname[0..8], 16).unwrap();
+    let log = u64::from_str_radix(&fname[8..16], 16).unwrap();
+    let seg = u64::from_str_radix(&fname[16..24], 16).unwrap();
     *segno = log * (0x10000_0000u64 / wal_seg_sz as u64) + seg;
 }
 
+// Compute an XLogRecPtr from a segment number and offset.
+#[inline]
+pub fn xlog_seg_no_offset_to_rec_ptr(
+    segno: XLogSegNo,
+    offset: u32,
+    wal_segsz_bytes: u32,
+) -> XLogRecPtr {
+    segno * wal_segsz_bytes as u64 + offset as u64
+}
+
+// The name of the segment file of timeline tli that holds xlogptr, and the
+// offset of xlogptr within it, as pg_walfile_name_offset() reports them.
+pub fn xlog_file_name_offset(
+    tli: TimeLineID,
+    xlogptr: XLogRecPtr,
+    wal_seg_sz: u32,
+) -> (PathBuf, u32) {
+    (
+        xlog_filename(tli, byte_to_seg(xlogptr, wal_seg_sz), wal_seg_sz),
+        xlog_segment_offset(xlogptr, wal_seg_sz),
+    )
+}
+
+// The reverse: the timeline of segment file fname, and the location at
+// offset within it.
+pub fn xlog_file_name_to_rec_ptr(
+    fname: &PathBuf,
+    offset: u32,
+    wal_seg_sz: u32,
+) -> (TimeLineID, XLogRecPtr) {
+    let mut tli: TimeLineID = 0;
+    let mut segno: XLogSegNo = 0;
+    xlog_from_file_name(fname, &mut tli, &mut segno, wal_seg_sz);
+    (tli, xlog_seg_no_offset_to_rec_ptr(segno, offset, wal_seg_sz))
+}
+
 #[inline]
 pub fn epoch_from_full_transaction_id(x: FullTransactionId) -> u32 {
     (x >> 32) as u32
@@ -122,3 +214,64 @@ pub fn xid_from_full_transaction_id(x: FullTransactionId) -> u32 {
 pub fn to_string(s: &[u8]) -> String {
     String::from_utf8_lossy(s).to_string()
 }
+
+// Hex dump bytes, sixteen to a line, each line starting with prefix and
+// the offset of its first byte (counted from off) and ending with the
+// printable characters.
+pub fn hex_dump(bytes: &[u8], off: u32, prefix: &str) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        out += &format!("{}{:04x}:", prefix, off as usize + i * 16);
+        for (j, b) in line.iter().enumerate() {
+            out += if j == 8 { "  " } else { " " };
+            out += &format!("{:02x}", b);
+        }
+        let pad = (16 - line.len()) * 3 + if line.len() <= 8 { 1 } else { 0 };
+        out += &" ".repeat(pad);
+        out += "  |";
+        for b in line {
+            out.push(if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            });
+        }
+        out += "|\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_cross_the_xlogid_boundary() {
+        let start: XLogRecPtr = 0x1A_FF00_0028;
+        let end: XLogRecPtr = 0x1B_0010_0000;
+        for (segsize, startname, endname) in [
+            (1 << 20, "000000010000001A00000FF0", "000000010000001B00000001"),
+            (16 << 20, "000000010000001A000000FF", "000000010000001B00000000"),
+            (1 << 30, "000000010000001A00000003", "000000010000001B00000000"),
+        ] {
+            let (fname, offset) = xlog_file_name_offset(1, start, segsize);
+            assert_eq!(fname, PathBuf::from(startname));
+            assert_eq!(xlog_file_name_to_rec_ptr(&fname, offset, segsize), (1, start));
+
+            let (fname, offset) = xlog_file_name_offset(1, end, segsize);
+            assert_eq!(fname, PathBuf::from(endname));
+            assert_eq!(xlog_file_name_to_rec_ptr(&fname, offset, segsize), (1, end));
+
+            // the segment after the last of log 1A is the first of log 1B
+            let last = byte_to_seg(0x1B_0000_0000, segsize) - 1;
+            assert_eq!(
+                xlog_filename(1, last + 1, segsize),
+                PathBuf::from("000000010000001B00000000")
+            );
+            assert!(xlog_filename(1, last, segsize)
+                .to_str()
+                .unwrap()
+                .starts_with("000000010000001A"));
+        }
+    }
+}
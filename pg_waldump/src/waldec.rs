@@ -1,10 +1,12 @@
 #![allow(unused)]
-use std::cell::RefCell;
 use crate::constant::*;
 use crate::pgtypes::*;
 use crate::rmgr::*;
 use crate::state::*;
 use crate::util::*;
+use crate::version::PgVersion;
+use crate::timeline::{timeline_of_point, tli_of_segment};
+use crate::walsource::{ENOENT, EWOULDBLOCK};
 use nom::bytes::streaming::take as bytes_take;
 use nom::combinator::map;
 use nom::number::streaming::{le_u16, le_u32, le_u64, le_u8};
@@ -20,29 +22,51 @@ fn is_valid_xlog_record_header(
     prev_recptr: XLogRecPtr,
     record: &XLogRecord,
 ) -> bool {
+    // Like report_invalid_record(): the caller stops reading, and the
+    // message is left for whoever is reading.  Running into the unused end
+    // of the last segment ends up here.
     if record.xl_tot_len < std::mem::size_of::<XLogRecord>() as u32 {
-        panic!(
-            "invalid record length at {}: expected at least {}, got {}",
-            lsn_out(rec_ptr),
-            std::mem::size_of::<XLogRecord>() as u32,
-            record.xl_tot_len,
-        );
+        state.errmsg = if state.upstream {
+            // worded "wanted" before PostgreSQL 16
+            format!(
+                "invalid record length at {}: {} {}, got {}",
+                lsn_format(rec_ptr),
+                if state.config.version < PgVersion::V16 {
+                    "wanted"
+                } else {
+                    "expected at least"
+                },
+                std::mem::size_of::<XLogRecord>() as u32,
+                record.xl_tot_len,
+            )
+        } else {
+            format!(
+                "invalid record length at {}: expected at least {}, got {}",
+                lsn_out(rec_ptr),
+                std::mem::size_of::<XLogRecord>() as u32,
+                record.xl_tot_len,
+            )
+        };
+        return false;
     }
 
+    let lsn = if state.upstream { lsn_format } else { lsn_out };
     if !rmgr_id_is_valid(record.xl_rmid) {
-        panic!(
+        state.errmsg = format!(
             "invalid resource manager ID {} at {}",
             record.xl_rmid,
-            lsn_out(rec_ptr)
+            lsn(rec_ptr)
         );
+        return false;
     }
 
     if prev_recptr != 0 && record.xl_prev != prev_recptr {
-        panic!(
+        state.errmsg = format!(
             "record with incorrect prev-link {} at {}",
-            lsn_out(prev_recptr),
-            lsn_out(rec_ptr)
+            lsn(record.xl_prev),
+            lsn(rec_ptr)
         );
+        return false;
     }
     return true;
 }
@@ -51,23 +75,38 @@ pub fn lsn_out(rec_ptr: XLogRecPtr) -> String {
     format!("{:X}/{:08X}", rec_ptr >> 32, rec_ptr as u32)
 }
 
+// An LSN as PostgreSQL's messages and record descriptions print it, "%X/%X",
+// without the zero padding of the record lines.
+pub fn lsn_format(rec_ptr: XLogRecPtr) -> String {
+    format!("{:X}/{:X}", rec_ptr >> 32, rec_ptr as u32)
+}
+
+// bimg_info flags of PostgreSQL 14 and before, when pglz was the only
+// compression method.  PostgreSQL 15 moved BKPIMAGE_APPLY and gave each
+// compression method its own bit.
+const BKPIMAGE_IS_COMPRESSED_PRE15: u8 = 0x02;
+const BKPIMAGE_APPLY_PRE15: u8 = 0x04;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BkpImageCompressMethod {
     PGLZ = 0x04,
     LZ4 = 0x08,
     ZSTD = 0x10,
 }
 
-impl From<u8> for BkpImageCompressMethod {
-    fn from(value: u8) -> Self {
-        if value == Self::PGLZ as u8 {
-            Self::PGLZ
-        } else if value == Self::LZ4 as u8 {
-            Self::LZ4
-        } else if value == Self::ZSTD as u8 {
-            Self::ZSTD
-        } else {
-            panic!("invalid value for enum BkpImageCompressMethod");
+impl BkpImageCompressMethod {
+    // The compression method of a block image, None if it isn't compressed.
+    pub fn from_bimg_info(info: u8, version: PgVersion) -> Option<Self> {
+        if version < PgVersion::V15 {
+            if info & BKPIMAGE_IS_COMPRESSED_PRE15 != 0 {
+                return Some(Self::PGLZ);
+            }
+            return None;
         }
+        [Self::PGLZ, Self::LZ4, Self::ZSTD]
+            .into_iter()
+            .find(|m| info & *m as u8 != 0)
     }
 }
 
@@ -82,42 +121,81 @@ impl std::fmt::Display for BkpImageCompressMethod {
     }
 }
 
-pub fn bkpimage_compressed(info: u8) -> bool {
-    (info
-        & (BkpImageCompressMethod::PGLZ as u8
-            | BkpImageCompressMethod::LZ4 as u8
-            | BkpImageCompressMethod::ZSTD as u8))
-        != 0
+pub fn bkpimage_compressed(info: u8, version: PgVersion) -> bool {
+    BkpImageCompressMethod::from_bimg_info(info, version).is_some()
+}
+
+pub fn bkpimage_apply(info: u8, version: PgVersion) -> bool {
+    if version < PgVersion::V15 {
+        info & BKPIMAGE_APPLY_PRE15 != 0
+    } else {
+        info & BKPIMAGE_APPLY != 0
+    }
 }
 
 pub fn decode_xlog_record_payload(
     state: &mut XLogReaderState,
     lsn: XLogRecPtr,
+    tot_len: u32,
 ) -> Option<DecodedXLogRecord> {
-    let mut decoded = DecodedXLogRecord::default();
-
-    let (hdr, payload) = {
-        let buf = state.get_next_record_buf(lsn);
-        let (_, hdr) = xlog_record(buf).unwrap();
-        (hdr, &buf[std::mem::size_of::<XLogRecord>()..])
+    // Reuse the buffers of the last record handed out, if any
+    let mut decoded = state.recycled_record.take().unwrap_or_default();
+    decoded.reset();
+
+    // Decode straight out of the page, or out of the buffer the record was
+    // reassembled in if it crosses pages, as record_bytes() finds it later.
+    // The buffer is moved out of the reader state for the duration, and put
+    // back afterwards.
+    let cross_page = page_offset(lsn) + tot_len > XLOG_BLCKSZ;
+    let (src, off) = if cross_page {
+        (std::mem::take(&mut state.cross_page_record_buf), 0)
+    } else {
+        (std::mem::take(&mut state.read_buf), page_offset(lsn) as usize)
     };
+    let ok = decode_xlog_record(state, &mut decoded, &src[off..], lsn);
+    if cross_page {
+        state.cross_page_record_buf = src;
+    } else {
+        state.read_buf = src;
+    }
+
+    if ok {
+        Some(decoded)
+    } else {
+        None
+    }
+}
+
+fn decode_xlog_record(
+    state: &mut XLogReaderState,
+    decoded: &mut DecodedXLogRecord,
+    record: &[u8],
+    lsn: XLogRecPtr,
+) -> bool {
+    let (payload, hdr) = xlog_record(record).unwrap();
 
-    decoded.max_block_id = -1;
     decoded.header = hdr.clone();
     decoded.lsn = lsn;
-    decoded.blocks = Some(vec![RefCell::new(DecodedBkpBlock::default()); XLR_MAX_BLOCK_ID as usize + 1]);
-    let blocks = decoded.blocks.as_ref().unwrap();
 
     // println!("xlog record: {:?}", hdr);
 
     let hdrsz = std::mem::size_of::<XLogRecord>() as u32;
     let mut remaining = hdr.xl_tot_len - hdrsz;
-    let mut buf = &payload[..];
+    let mut buf = payload;
+
+    // the parts of the record for --raw, handed back to decoded at the end
+    let keep = state.keep_raw;
+    let mut layout = std::mem::take(&mut decoded.layout);
+    if keep {
+        decoded.raw.extend_from_slice(&record[..hdr.xl_tot_len as usize]);
+    }
+    note_span(&mut layout, keep, record, record, buf, RecordPart::Header);
 
     let mut datatotal = 0;
     let mut blk_id = 0;
     let mut rlocator: Option<RelFileLocator> = None;
     while remaining > datatotal {
+        let start = buf;
         (buf, blk_id) = byte_to_u8(buf).unwrap();
         remaining -= 1;
 
@@ -125,6 +203,7 @@ pub fn decode_xlog_record_payload(
             let mut main_data_len = 0;
             (buf, main_data_len) = byte_to_u8(buf).unwrap();
             remaining -= 1;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::DataHeaderShort);
 
             decoded.main_data_len = main_data_len as u32;
             datatotal += main_data_len as u32;
@@ -133,6 +212,7 @@ pub fn decode_xlog_record_payload(
             let main_data_len: u32;
             (buf, main_data_len) = byte_to_u32(buf).unwrap();
             remaining -= 4;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::DataHeaderLong);
 
             decoded.main_data_len = main_data_len;
             datatotal += main_data_len;
@@ -141,12 +221,14 @@ pub fn decode_xlog_record_payload(
             let record_origin: u16;
             (buf, record_origin) = byte_to_u16(buf).unwrap();
             remaining -= 2;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::Origin);
 
             decoded.record_origin = record_origin;
         } else if blk_id == XLR_BLOCK_ID_TOPLEVEL_XID {
             let top_level_xid: TransactionId;
             (buf, top_level_xid) = byte_to_u32(buf).unwrap();
             remaining -= 4;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::ToplevelXid);
 
             decoded.toplevel_xid = top_level_xid;
         } else if blk_id <= XLR_MAX_BLOCK_ID {
@@ -158,7 +240,10 @@ pub fn decode_xlog_record_payload(
                 );
             }
             decoded.max_block_id = blk_id as i8;
-            let mut blk = blocks.get(blk_id as usize).unwrap().borrow_mut();
+            decoded
+                .blocks
+                .resize(blk_id as usize + 1, DecodedBkpBlock::default());
+            let blk = &mut decoded.blocks[blk_id as usize];
             blk.in_use = true;
             blk.apply_image = false;
             let mut fork_flags = 0;
@@ -172,13 +257,14 @@ pub fn decode_xlog_record_payload(
             // blk.prefetch_buffer = InvalidBuffer;
             (buf, blk.data_len) = byte_to_u16(buf).unwrap();
             remaining -= 2;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::BlockHeader(blk_id));
 
             if blk.has_data && blk.data_len == 0 {
                 state.errmsg = format!(
                     "BKPBLOCK_HAS_DATA set, but no data included at {}",
                     lsn_out(state.read_recptr)
                 );
-                return None;
+                return false;
             }
 
             if !blk.has_data && blk.data_len != 0 {
@@ -187,24 +273,35 @@ pub fn decode_xlog_record_payload(
                     blk.data_len,
                     lsn_out(state.read_recptr)
                 );
-                return None;
+                return false;
             }
             datatotal += blk.data_len as u32;
 
             if blk.has_image {
+                let start = buf;
                 (buf, blk.bimg_len) = byte_to_u16(buf).unwrap();
                 remaining -= 2;
                 (buf, blk.hole_offset) = byte_to_u16(buf).unwrap();
                 remaining -= 2;
                 (buf, blk.bimg_info) = byte_to_u8(buf).unwrap();
                 remaining -= 1;
+                note_span(&mut layout, keep, record, start, buf, RecordPart::ImageHeader(blk_id));
 
-                blk.apply_image = (blk.bimg_info & BKPIMAGE_APPLY) != 0;
+                blk.apply_image = bkpimage_apply(blk.bimg_info, state.config.version);
 
-                if bkpimage_compressed(blk.bimg_info) {
+                if bkpimage_compressed(blk.bimg_info, state.config.version) {
                     if blk.bimg_info & BKPIMAGE_HAS_HOLE != 0 {
+                        let start = buf;
                         (buf, blk.hole_length) = byte_to_u16(buf).unwrap();
                         remaining -= 2;
+                        note_span(
+                            &mut layout,
+                            keep,
+                            record,
+                            start,
+                            buf,
+                            RecordPart::CompressHeader(blk_id),
+                        );
                     } else {
                         blk.hole_length = 0;
                     }
@@ -241,7 +338,9 @@ pub fn decode_xlog_record_payload(
                 }
 
                 // Cross-check that bimg_len < BLCKSZ if it is compressed.
-                if bkpimage_compressed(blk.bimg_info) && blk.bimg_len == XLOG_BLCKSZ as u16 {
+                if bkpimage_compressed(blk.bimg_info, state.config.version)
+                    && blk.bimg_len == XLOG_BLCKSZ as u16
+                {
                     panic!(
                         "BKPIMAGE_COMPRESSED set, but block image length {} at {}",
                         blk.bimg_len,
@@ -252,7 +351,7 @@ pub fn decode_xlog_record_payload(
                 // cross-check that bimg_len = BLCKSZ if neither HAS_HOLE is
                 // set nor COMPRESSED().
                 if blk.bimg_info & BKPIMAGE_HAS_HOLE == 0
-                    && !bkpimage_compressed(blk.bimg_info)
+                    && !bkpimage_compressed(blk.bimg_info, state.config.version)
                     && blk.bimg_len != XLOG_BLCKSZ as u16
                 {
                     panic!("neither BKPIMAGE_HAS_HOLE nor BKPIMAGE_COMPRESSED set, but block image length is {} at {}",
@@ -262,8 +361,17 @@ pub fn decode_xlog_record_payload(
             }
 
             if fork_flags & BKPBLOCK_SAME_REL == 0 {
+                let start = buf;
                 (buf, blk.rlocator) = parse_rel_file_locator(buf).unwrap();
                 remaining -= std::mem::size_of::<RelFileLocator>() as u32;
+                note_span(
+                    &mut layout,
+                    keep,
+                    record,
+                    start,
+                    buf,
+                    RecordPart::RelFileLocator(blk_id),
+                );
                 rlocator = Some(blk.rlocator.clone());
             } else {
                 if rlocator.is_none() {
@@ -272,8 +380,10 @@ pub fn decode_xlog_record_payload(
                 }
                 blk.rlocator = rlocator.unwrap().clone();
             }
+            let start = buf;
             (buf, blk.blkno) = byte_to_u32(buf).unwrap();
             remaining -= 4;
+            note_span(&mut layout, keep, record, start, buf, RecordPart::BlockNumber(blk_id));
         } else {
             panic!("invalid block id: {}", blk_id);
         }
@@ -286,62 +396,56 @@ pub fn decode_xlog_record_payload(
         );
     }
 
-    let mut decoded_size: usize = 0;
-    // block data first
-    for block_id in 0..=decoded.max_block_id {
-        let mut blk = blocks.get(block_id as usize).unwrap().borrow_mut();
+    // The block images, block data and main data follow the headers in the
+    // same order.  They are left where they are, and only their offsets
+    // from the start of the record noted.
+    for i in 0..decoded.blocks.len() {
+        let blk = &mut decoded.blocks[i];
         if !blk.in_use {
             continue;
         }
         assert!(blk.has_image || !blk.apply_image);
 
         if blk.has_image {
-            blk.bkp_image = buf[decoded_size..(decoded_size + blk.bimg_len as usize)].to_vec();
-            decoded_size += blk.bimg_len as usize;
+            let len = blk.bimg_len as usize;
+            blk.bkp_image_off = (record.len() - buf.len()) as u32;
+            note_span(&mut layout, keep, record, buf, &buf[len..], RecordPart::Image(i as u8));
+            buf = &buf[len..];
         }
         if blk.has_data {
-            blk.data = buf[decoded_size..(decoded_size + blk.data_len as usize)].to_vec();
-            decoded_size += blk.data_len as usize;
+            let len = blk.data_len as usize;
+            blk.data_off = (record.len() - buf.len()) as u32;
+            note_span(&mut layout, keep, record, buf, &buf[len..], RecordPart::BlockData(i as u8));
+            buf = &buf[len..];
         }
     }
-
-    // and finally, the main data
     if decoded.main_data_len > 0 {
-        decoded.main_data = Some(buf[decoded_size..(decoded_size + decoded.main_data_len as usize)].to_vec());
-        decoded_size += decoded.main_data_len as usize;
+        let len = decoded.main_data_len as usize;
+        decoded.main_data_off = (record.len() - buf.len()) as u32;
+        note_span(&mut layout, keep, record, buf, &buf[len..], RecordPart::MainData);
     }
+    decoded.layout = layout;
 
-    // report the actual size we used
-    decoded.size = max_align(decoded_size as u32) as usize;
-    assert!(decode_xlog_record_required_space(hdr.xl_tot_len as usize) >= decoded.size);
-
-    Some(decoded)
+    true
 }
 
-// Compute the maximum possible amount of padding that could be required to
-// decode a record, given xl_tot_len from the record's header.  This is the
-// amount of output buffer space that we need to decode a record, though we
-// might not finish up using it all.
-//
-// This computation is pessimistic and assumes the maximum possible number of
-// blocks, due to lack of better information.
-fn decode_xlog_record_required_space(xl_tot_len: usize) -> usize {
-    let mut size: usize = 0;
-
-    /* Account for the fixed size part of the decoded record struct. */
-    size += std::mem::size_of::<DecodedXLogRecord>();
-    /* Account for the flexible blocks array of maximum possible size. */
-    size += std::mem::size_of::<DecodedBkpBlock>() * (XLR_MAX_BLOCK_ID + 1) as usize;
-    /* Account for all the raw main and block data. */
-    size += xl_tot_len;
-    /* We might insert padding before main_data. */
-    size += 7;
-    /* We might insert padding before each block's data. */
-    size += 7 * (XLR_MAX_BLOCK_ID + 1) as usize;
-    // We might insert padding at the end.
-    size += 7;
-
-    size
+// With keep, note in layout that the bytes of record from from up to to
+// hold part; from and to are what is left of record there.
+fn note_span(
+    layout: &mut Vec<RecordSpan>,
+    keep: bool,
+    record: &[u8],
+    from: &[u8],
+    to: &[u8],
+    part: RecordPart,
+) {
+    if keep {
+        layout.push(RecordSpan {
+            part,
+            off: (record.len() - from.len()) as u32,
+            len: (from.len() - to.len()) as u32,
+        });
+    }
 }
 
 fn parse_rel_file_locator(input: &[u8]) -> IResult<&[u8], RelFileLocator> {
@@ -355,91 +459,28 @@ fn parse_rel_file_locator(input: &[u8]) -> IResult<&[u8], RelFileLocator> {
     )(input)
 }
 
-fn open_segment(state: &mut XLogReaderState, next_seg_no: XLogSegNo, tli: TimeLineID) {
-    let fname = xlog_filename(tli, next_seg_no, state.segcxt.ws_segsize);
-    let path = &mut state.segcxt.ws_dir.clone();
-    path.push(fname);
-    state.seg.file = Some(std::fs::File::open(path).unwrap());
-}
-
-fn close_segment(state: &mut XLogReaderState) {
-    state.seg.file = None;
-}
-
+// Read 'count' bytes into read_buf from the reader's WAL source, starting
+// at location 'startptr', on timeline 'tli'.
 fn wal_read(
     state: &mut XLogReaderState,
     startptr: XLogRecPtr,
     count: usize,
     tli: TimeLineID,
 ) -> Result<bool, WALReadError> {
-    let mut recptr = startptr;
-    let mut nbytes = count;
-
-    while nbytes > 0 {
-        let startoff = xlog_segment_offset(recptr, state.segcxt.ws_segsize);
-
-        // If the data we want is not in a segment we have open, close what we
-        // have (if anything) and open the next one, using the caller's
-        // provided segment_open callback.
-        if state.seg.file.is_none()
-            || !byte_in_seg(recptr, state.seg.segno, state.segcxt.ws_segsize)
-            || tli != state.seg.tli
-        {
-            if state.seg.file.is_some() {
-                close_segment(state);
-            }
-
-            let next_seg_no = byte_to_seg(recptr, state.segcxt.ws_segsize);
-            open_segment(state, next_seg_no, tli);
-
-            // This shouldn't happen -- indicates a bug in segment_open
-            assert!(state.seg.file.is_some());
-
-            /* Update the current segment info. */
-            state.seg.tli = tli;
-            state.seg.segno = next_seg_no;
-        }
-
-        let mut segbytes = 0;
-        // How many bytes are within this segment?
-        if nbytes > (state.segcxt.ws_segsize - startoff) as usize {
-            segbytes = state.segcxt.ws_segsize - startoff;
-        } else {
-            segbytes = nbytes as u32;
-        }
-
-        // Reset errno first; eases reporting non-errno-affecting errors
-        // errno = 0;
-        // readbytes = pg_pread(state.seg.ws_file, p, segbytes, (off_t) startoff);
-        let buf = state.read_buf[0..segbytes as usize].as_mut();
-        state
-            .seg
-            .file
-            .as_mut()
-            .unwrap()
-            .seek(SeekFrom::Start(startoff as u64))
-            .unwrap();
-        match state.seg.file.as_mut().unwrap().read_exact(buf) {
-            Err(_) => {
-                return Err(WALReadError {
-                    errno: 0,
-                    off: startoff,
-                    req: segbytes,
-                    read: segbytes,
-                    seg: state.seg.clone(),
-                })
-            }
-            Ok(_) => (),
-        }
-
-        // Update state for read
-        recptr += segbytes as u64;
-        nbytes -= segbytes as usize;
-    }
-
-    return Ok(true);
+    let buf = &mut state.read_buf[..count];
+    state
+        .source
+        .as_mut()
+        .expect("XLogReaderState has no WAL source")
+        .read(startptr, tli, buf)?;
+
+    Ok(true)
 }
 
+// returned by the page read callback when the source doesn't have the data
+// yet; see EWOULDBLOCK
+const XLREAD_WOULDBLOCK: i32 = -2;
+
 fn wal_dump_read_page(
     state: &mut XLogReaderState,
     target_page_ptr: XLogRecPtr,
@@ -460,14 +501,63 @@ fn wal_dump_read_page(
         }
     }
 
+    // A source whose WAL is still being written may only have part of the
+    // last page so far; read what there is.  If it has ended, so has the
+    // WAL.
+    let upto = target_page_ptr + req_len as u64;
+    let source = state.source.as_mut().expect("XLogReaderState has no WAL source");
+    if let Some(available) = source.wait_for(target_page_ptr, upto) {
+        if available < upto {
+            state.private_data.endptr_reached = true;
+            return -1;
+        }
+        if available < target_page_ptr + count as u64 {
+            count = (available - target_page_ptr) as u32;
+        }
+    }
+
+    // With a timeline history, read the segment from the newest timeline in
+    // use within it.  If that timeline doesn't have the segment, the part of
+    // it from before the switch can be read from the older timeline's copy
+    // of the segment, which promotion may have left as .partial.
     let private = &state.private_data;
+    let (tli, older) = if private.history.is_empty() {
+        (private.timeline, None)
+    } else {
+        let older = timeline_of_point(target_page_ptr, &private.history);
+        (
+            tli_of_segment(target_page_ptr, state.config.wal_seg_sz, &private.history),
+            Some((older.tli, older.end)),
+        )
+    };
 
-    match wal_read(state, target_page_ptr, count as usize, private.timeline) {
+    let res = match wal_read(state, target_page_ptr, count as usize, tli) {
+        Err(errinfo) if errinfo.errno == ENOENT => match older {
+            Some((older_tli, switchpoint))
+                if older_tli != tli && target_page_ptr + req_len as u64 <= switchpoint =>
+            {
+                count = count.min((switchpoint - target_page_ptr) as u32);
+                wal_read(state, target_page_ptr, count as usize, older_tli)
+            }
+            _ => Err(errinfo),
+        },
+        res => res,
+    };
+    match res {
+        Err(errinfo) if errinfo.errno == EWOULDBLOCK => return XLREAD_WOULDBLOCK,
+        // the source knows what went wrong, e.g. it lost its connection;
+        // the WAL ends there, as it does at an invalid record
+        Err(WALReadError { msg: Some(msg), .. }) => {
+            state.errmsg = msg;
+            return -1;
+        }
         Err(errinfo) => {
             let seg = &errinfo.seg;
-            let fname = xlog_filename(seg.tli, seg.segno, state.segcxt.ws_segsize);
+            let fname = xlog_filename(seg.tli, seg.segno, state.config.wal_seg_sz);
 
-            if errinfo.errno != 0 {
+            if errinfo.errno == ENOENT && errinfo.req == 0 {
+                panic!("could not find file \"{}\"", fname.display());
+            } else if errinfo.errno != 0 {
                 panic!(
                     "could not read from file \"{}\", offset {}",
                     fname.display(),
@@ -495,8 +585,8 @@ fn wal_dump_read_page(
 pub fn read_page(state: &mut XLogReaderState, pageptr: XLogRecPtr, req_len: u32) -> u32 {
     assert_eq!((pageptr % XLOG_BLCKSZ as u64), 0);
 
-    let target_seg_no = xlog_byte_to_seg(pageptr, state.segcxt.ws_segsize);
-    let target_page_off = xlog_segment_offset(pageptr, state.segcxt.ws_segsize);
+    let target_seg_no = xlog_byte_to_seg(pageptr, state.config.wal_seg_sz);
+    let target_page_off = xlog_segment_offset(pageptr, state.config.wal_seg_sz);
 
     /* check whether we have all the requested data already */
     if target_seg_no == state.seg.segno
@@ -528,12 +618,14 @@ pub fn read_page(state: &mut XLogReaderState, pageptr: XLogRecPtr, req_len: u32)
         let target_segment_ptr = pageptr - target_page_off as u64;
 
         read_len = wal_dump_read_page(state, target_segment_ptr, XLOG_BLCKSZ, state.curr_recptr);
-        // if (read_len == XLREAD_WOULDBLOCK)
-        // 	return XLREAD_WOULDBLOCK;
-        // else if (readLen < 0)
-        // 	goto err;
+        if read_len == XLREAD_WOULDBLOCK {
+            return 0;
+        }
         if read_len < 0 {
             state.invalidate();
+            if !state.errmsg.is_empty() {
+                return 0;
+            }
             panic!("could not read a page");
         }
 
@@ -541,7 +633,8 @@ pub fn read_page(state: &mut XLogReaderState, pageptr: XLogRecPtr, req_len: u32)
         assert_eq!(read_len as u32, XLOG_BLCKSZ);
 
         if !xlog_reader_validate_page_header(state, target_segment_ptr) {
-            panic!("unexpected page magic number");
+            state.invalidate();
+            return 0;
         }
     }
 
@@ -555,7 +648,8 @@ pub fn read_page(state: &mut XLogReaderState, pageptr: XLogRecPtr, req_len: u32)
 
     // Now that we know we have the full header, validate it.
     if !xlog_reader_validate_page_header(state, pageptr) {
-        panic!("unexpected page magic number");
+        state.invalidate();
+        return 0;
     }
 
     // update read state information
@@ -568,33 +662,46 @@ pub fn read_page(state: &mut XLogReaderState, pageptr: XLogRecPtr, req_len: u32)
     // state.invalidate();
 }
 
-pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
+pub fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
     let mut rec_ptr = state.next_recptr;
     // println!("rec_ptr: {}", lsn_out(rec_ptr));
 
     // state.curr_recptr = rec_ptr;
     let target_page_ptr = page_addr(rec_ptr);
     let mut target_rec_off = page_offset(rec_ptr);
+    let record_hdrsz = std::mem::size_of::<XLogRecord>() as u32;
+
+    // Read the page containing the record into read_buf.  Request enough
+    // bytes to cover the whole record header, but no more; the source may
+    // not have the rest of the page (yet).
+    let read_len = read_page(
+        state,
+        target_page_ptr,
+        std::cmp::min(target_rec_off + record_hdrsz, XLOG_BLCKSZ),
+    );
+    if read_len == 0 {
+        // no more WAL available from the source
+        return false;
+    }
+
+    let (buf, hdr) = page_header(&state.read_buf).unwrap();
+    let page_header_size = xlog_page_header_size(&hdr);
 
     if target_rec_off == 0 {
-        rec_ptr += state.page_hdr_size as u64;
-        target_rec_off = state.page_hdr_size;
-    } else if target_rec_off < state.page_hdr_size {
+        // At page start, so skip over page header.
+        rec_ptr += page_header_size as u64;
+        target_rec_off = page_header_size;
+    } else if target_rec_off < page_header_size {
         panic!(
             "invalid record offset at {}: expected at least {}, got {}",
             lsn_out(rec_ptr),
-            state.page_hdr_size,
+            page_header_size,
             target_rec_off
         );
     }
 
-    let read_len = read_page(state, target_page_ptr, XLOG_BLCKSZ);
-    assert_eq!(read_len, XLOG_BLCKSZ);
-
-    let (buf, hdr) = page_header(&state.read_buf).unwrap();
-
     if (hdr.xlp_info & XLP_FIRST_IS_CONTRECORD) == XLP_FIRST_IS_CONTRECORD
-        && target_rec_off == state.page_hdr_size
+        && target_rec_off == page_header_size
     {
         state.errmsg = format!("contrecord is requested by {}", lsn_out(rec_ptr));
         return false;
@@ -603,7 +710,15 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
     let mut gotheader = false;
     let mut total_len = 0;
 
-    if target_rec_off <= XLOG_BLCKSZ - std::mem::size_of::<XLogRecord>() as u32 {
+    if target_rec_off <= XLOG_BLCKSZ - record_hdrsz {
+        // the page header may have pushed the record header past what we
+        // asked for above.  pg_waldump doesn't read it then, and checks what
+        // the buffer still holds from the page read before, which only
+        // differs when the end given cuts the page short.
+        if !state.upstream && read_page(state, target_page_ptr, target_rec_off + record_hdrsz) == 0
+        {
+            return false;
+        }
         let buf = &state.read_buf[target_rec_off as usize..];
         let (_, record) = xlog_record(buf).unwrap();
         total_len = record.xl_tot_len;
@@ -612,10 +727,23 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
             return false;
         }
         gotheader = true;
+    } else {
+        // The header is split across pages, but xl_tot_len always fits on
+        // this one as records are MAXALIGN'ed.  We'll validate the header
+        // once we have the next page.
+        if read_page(state, target_page_ptr, XLOG_BLCKSZ) == 0 {
+            return false;
+        }
+        let buf = &state.read_buf[target_rec_off as usize..];
+        (_, total_len) = byte_to_u32(buf).unwrap();
     }
 
     let len = XLOG_BLCKSZ - page_offset(rec_ptr);
     if !gotheader || total_len > len {
+        // The record continues on the next page, so we need all of this one.
+        if read_page(state, target_page_ptr, XLOG_BLCKSZ) == 0 {
+            return false;
+        }
         let start = page_offset(rec_ptr) as usize;
         state.cross_page_record_buf.clear();
         state
@@ -626,8 +754,16 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
         let mut target_page_ptr = target_page_ptr;
         while !gotheader || gotlen < total_len {
             target_page_ptr += XLOG_BLCKSZ as u64;
-            let read_len = read_page(state, target_page_ptr, XLOG_BLCKSZ);
-            assert_eq!(read_len, XLOG_BLCKSZ);
+
+            // Wait for the next page to become available
+            let read_len = read_page(
+                state,
+                target_page_ptr,
+                std::cmp::min(total_len - gotlen + SIZE_OF_XLOG_SHORT_PHD, XLOG_BLCKSZ),
+            );
+            if read_len == 0 {
+                return false;
+            }
 
             let (buf, page_hdr) = page_header(&state.read_buf).unwrap();
 
@@ -670,6 +806,12 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
                 len = page_hdr.xlp_rem_len;
             }
 
+            if read_len < page_hdrsz + len
+                && read_page(state, target_page_ptr, page_hdrsz + len) == 0
+            {
+                return false;
+            }
+
             state.cross_page_record_buf.extend_from_slice(
                 &state.read_buf[page_hdrsz as usize..(page_hdrsz + len) as usize],
             );
@@ -683,7 +825,7 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
             if !gotheader {
                 let (_, record) = xlog_record(&state.cross_page_record_buf).unwrap();
                 if !is_valid_xlog_record_header(state, rec_ptr, state.decode_recptr, &record) {
-                    panic!("invalid xlog record header");
+                    return false;
                 }
                 gotheader = true;
                 total_len = record.xl_tot_len;
@@ -701,6 +843,11 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
         state.next_recptr =
             target_page_ptr + page_hdrsz as u64 + max_align(page_hdr.xlp_rem_len) as u64;
     } else {
+        // Wait for the record data to become available
+        if read_page(state, target_page_ptr, target_rec_off + total_len) == 0 {
+            return false;
+        }
+
         // TODO: crc check xlog record
         // if !ValidXLogRecord(state, record, RecPtr)
 
@@ -708,37 +855,168 @@ pub(crate) fn xlog_decode_next_record(state: &mut XLogReaderState) -> bool {
         state.decode_recptr = rec_ptr;
     }
 
-    let (_, record) = xlog_record(state.get_next_record_buf(rec_ptr)).unwrap();
+    if let Some(mut decoded) = decode_xlog_record_payload(state, rec_ptr, total_len) {
+        // Special processing if it's an XLOG SWITCH record
+        if decoded.header.xl_rmid == RmgrIds::XLOG as u8
+            && (decoded.header.xl_info & !XLR_INFO_MASK) == XLogInfo::Switch as u8
+        {
+            // Pretend it extends to end of segment
+            state.next_recptr += (state.config.wal_seg_sz - 1) as u64;
+            state.next_recptr -=
+                xlog_segment_offset(state.next_recptr, state.config.wal_seg_sz) as u64;
+        }
 
-    // Special processing if it's an XLOG SWITCH record
-    if record.xl_rmid == RmgrIds::XLOG as u8
-        && (record.xl_info & !XLR_INFO_MASK) == XLogInfo::Switch as u8
-    {
-        // Pretend it extends to end of segment
-        state
-            .next_recptr
-            .saturating_add((state.segcxt.ws_segsize - 1) as u64);
-        state.next_recptr -= xlog_segment_offset(state.next_recptr, state.segcxt.ws_segsize) as u64;
+        decoded.next_lsn = state.next_recptr;
+        state.decode_queue.push_back(decoded);
+        return true;
     }
+    state.invalidate();
+    return false;
+}
 
-    if let Some(mut decoded) = decode_xlog_record_payload(state, rec_ptr) {
-        decoded.next_lsn = state.next_recptr;
-        if !decoded.oversized {
-            assert_eq!(decoded.size, max_align(decoded.size as u32) as usize);
-            state.decode_queue.push_back(decoded);
-            return true;
+fn reset_decoder(state: &mut XLogReaderState) {
+    state.decode_queue.clear();
+}
+
+pub fn xlog_begin_read(state: &mut XLogReaderState, rec_ptr: XLogRecPtr) {
+    assert!(!xlog_recptr_is_invalid(rec_ptr));
+
+    reset_decoder(state);
+
+    // Begin at the passed-in record pointer.
+    state.end_recptr = rec_ptr;
+    state.next_recptr = rec_ptr;
+    state.read_recptr = INVALID_XLOG_RECPTR;
+    state.decode_recptr = INVALID_XLOG_RECPTR;
+}
+
+// Try to decode the next available record, and return it.  The record will
+// also be returned to XLogNextRecord(), which must be called to 'consume'
+// each record.
+//
+// If nonblocking is true, may return NULL due to lack of data or WAL decoding
+// space.
+
+fn xlog_read_ahead(state: &mut XLogReaderState) -> bool {
+    xlog_decode_next_record(state)
+}
+
+pub fn xlog_next_record(state: &mut XLogReaderState) {
+    // Release the last record returned; its buffers are reused for the next
+    // one decoded
+    if let Some(old) = state.record.take() {
+        state.recycled_record = Some(old);
+    }
+    state.record = state.decode_queue.pop_front();
+    if let Some(record) = &state.record {
+        state.read_recptr = record.lsn;
+        state.end_recptr = record.next_lsn;
+    }
+}
+
+// Attempt to read an XLOG record.
+//
+// XLogBeginRead() or XLogFindNextRecord() must be called before the first call
+// to XLogReadRecord().
+//
+// If the page_read callback fails to read the requested data, NULL is
+// returned.  The callback is expected to have reported the error; errormsg
+// is set to NULL.
+//
+// If the reading fails for some other reason, NULL is also returned, and
+// *errormsg is set to a string with details of the failure.
+//
+// The returned pointer (or *errormsg) points to an internal buffer that's
+// valid until the next call to XLogReadRecord.
+
+pub fn xlog_read_record(state: &mut XLogReaderState) -> bool {
+    if !xlog_read_ahead(state) {
+        return false;
+    }
+
+    // Consume the head record or error.
+    xlog_next_record(state);
+    return true;
+}
+
+pub fn xlog_find_next_record(state: &mut XLogReaderState) -> XLogRecPtr {
+    let rec_ptr = state.private_data.startptr;
+    assert!(!xlog_recptr_is_invalid(rec_ptr));
+
+    // skip over potential continuation data, keeping in mind that it may span
+    // multiple pages
+    let mut tmp_rec_ptr = rec_ptr;
+    loop {
+        let target_rec_off = page_offset(tmp_rec_ptr);
+        let target_page_ptr = page_addr(tmp_rec_ptr);
+
+        /* Read the page containing the record */
+        let read_len = read_page(state, target_page_ptr, target_rec_off);
+        if read_len == 0 {
+            state.invalidate();
+            return INVALID_XLOG_RECPTR;
+        }
+
+        let (_, header) = page_header(&state.read_buf).unwrap();
+
+        let page_header_size = xlog_page_header_size(&header);
+
+        // make sure we have enough data for the page header
+        // readLen = ReadPageInternal(state, targetPagePtr, pageHeaderSize);
+        // if (readLen < 0)
+        // 	goto err;
+
+        /* skip over potential continuation data */
+        if header.xlp_info & XLP_FIRST_IS_CONTRECORD != 0 {
+            // If the length of the remaining continuation data is more than
+            // what can fit in this page, the continuation record crosses over
+            // this page. Read the next page and try again. xlp_rem_len in the
+            // next page header will contain the remaining length of the
+            // continuation data
+            //
+            // Note that record headers are MAXALIGN'ed
+            if max_align(header.xlp_rem_len) >= (XLOG_BLCKSZ - page_header_size as u32) {
+                tmp_rec_ptr = target_page_ptr.saturating_add(XLOG_BLCKSZ as u64);
+            } else {
+                // The previous continuation record ends in this page. Set
+                // tmpRecPtr to point to the first valid record
+                tmp_rec_ptr = target_page_ptr
+                    + page_header_size as u64
+                    + max_align(header.xlp_rem_len) as u64;
+                break;
+            }
+        } else {
+            tmp_rec_ptr = target_page_ptr + page_header_size as u64;
+            break;
         }
-    } else {
-        state.invalidate();
     }
-    return false;
+
+    // we know now that tmpRecPtr is an address pointing to a valid XLogRecord
+    // because either we're at the first record after the beginning of a page
+    // or we just jumped over the remaining data of a continuation.
+    xlog_begin_read(state, tmp_rec_ptr);
+    while xlog_read_record(state) {
+        // past the record we've found, break out
+        if rec_ptr <= state.read_recptr {
+            // Rewind the reader to the beginning of the last record.
+            let found = state.read_recptr;
+            xlog_begin_read(state, found);
+            return found;
+        }
+    }
+
+    state.invalidate();
+
+    return INVALID_XLOG_RECPTR;
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(align(8))]
-pub(crate) struct XLogPageHeaderData {
+pub struct XLogPageHeaderData {
     pub xlp_magic: u16,
     pub xlp_info: u16,
     pub xlp_tli: TimeLineID,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::lsn"))]
     pub xlp_pageaddr: XLogRecPtr,
     // When there is not enough space on current page for whole record, we
     // continue on the next page.  xlp_rem_len is the number of bytes
@@ -762,35 +1040,33 @@ impl XLogPageHeaderData {
     }
 }
 
-pub(crate) type XLogPageHeader = XLogPageHeaderData;
+pub type XLogPageHeader = XLogPageHeaderData;
+
+pub const SIZE_OF_XLOG_SHORT_PHD: u32 = std::mem::size_of::<XLogPageHeaderData>() as u32;
 
 pub fn page_header(input: &[u8]) -> IResult<&[u8], XLogPageHeaderData> {
     map(
         sequence::tuple((le_u16, le_u16, le_u32, le_u64, le_u32, bytes_take(4usize))),
-        |(magic, info, tli, paguaddr, rem_len, _)| {
-            if magic != XLOG_PAGE_MAGIC {
-                panic!("invalid magic: {:#06X}", magic);
-            }
-            XLogPageHeaderData {
-                xlp_magic: XLOG_PAGE_MAGIC,
-                xlp_info: info,
-                xlp_tli: tli,
-                xlp_pageaddr: paguaddr,
-                xlp_rem_len: rem_len,
-            }
+        |(magic, info, tli, paguaddr, rem_len, _)| XLogPageHeaderData {
+            xlp_magic: magic,
+            xlp_info: info,
+            xlp_tli: tli,
+            xlp_pageaddr: paguaddr,
+            xlp_rem_len: rem_len,
         },
     )(input)
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(align(8))]
-pub(crate) struct XLogLongPageHeaderData {
+pub struct XLogLongPageHeaderData {
     std: XLogPageHeaderData,
     xlp_sysid: u64,
     xlp_seg_size: u32,
     xlp_xlog_blcksz: u32,
 }
 
-pub(crate) fn first_page_header(input: &[u8]) -> IResult<&[u8], XLogLongPageHeaderData> {
+pub fn first_page_header(input: &[u8]) -> IResult<&[u8], XLogLongPageHeaderData> {
     map(
         sequence::tuple((page_header, le_u64, le_u32, le_u32)),
         |(hdr, sysid, seg_size, blcksz)| XLogLongPageHeaderData {
@@ -802,7 +1078,7 @@ pub(crate) fn first_page_header(input: &[u8]) -> IResult<&[u8], XLogLongPageHead
     )(input)
 }
 
-pub(crate) fn xlog_record(input: &[u8]) -> IResult<&[u8], XLogRecord> {
+pub fn xlog_record(input: &[u8]) -> IResult<&[u8], XLogRecord> {
     map(
         sequence::tuple((
             le_u32,
@@ -825,7 +1101,7 @@ pub(crate) fn xlog_record(input: &[u8]) -> IResult<&[u8], XLogRecord> {
 }
 
 #[derive(PartialEq, Debug)]
-pub(crate) enum XLogRecordDataHeader {
+pub enum XLogRecordDataHeader {
     Short(XLogRecordDataHeaderShort),
     Long(XLogRecordDataHeaderLong),
     Origin(XLogRecordDataHeaderOrigin),
@@ -833,7 +1109,7 @@ pub(crate) enum XLogRecordDataHeader {
     Block(XLogRecordBlockHeader),
 }
 
-pub(crate) fn xlog_record_data_header(input: &[u8]) -> IResult<&[u8], XLogRecordDataHeader> {
+pub fn xlog_record_data_header(input: &[u8]) -> IResult<&[u8], XLogRecordDataHeader> {
     let (input, id) = le_u8(input)?;
     match id {
         XLR_BLOCK_ID_DATA_SHORT => map(xlog_record_data_header_short(id), |hdr| {
@@ -861,14 +1137,14 @@ pub(crate) fn xlog_record_data_header(input: &[u8]) -> IResult<&[u8], XLogRecord
 // form is used.
 #[repr(packed)]
 #[derive(PartialEq, Debug)]
-pub(crate) struct XLogRecordDataHeaderShort {
+pub struct XLogRecordDataHeaderShort {
     // XLR_BLOCK_ID_DATA_SHORT
     id: u8,
     // number of payload bytes
     data_length: u8,
 }
 
-pub(crate) fn xlog_record_data_header_short(
+pub fn xlog_record_data_header_short(
     id: u8,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XLogRecordDataHeaderShort> {
     move |input: &[u8]| {
@@ -879,13 +1155,13 @@ pub(crate) fn xlog_record_data_header_short(
 
 #[repr(packed)]
 #[derive(PartialEq, Debug)]
-pub(crate) struct XLogRecordDataHeaderLong {
+pub struct XLogRecordDataHeaderLong {
     // XLR_BLOCK_ID_DATA_LONG
     id: u8,
     data_length: u32,
 }
 
-pub(crate) fn xlog_record_data_header_long(
+pub fn xlog_record_data_header_long(
     id: u8,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XLogRecordDataHeaderLong> {
     move |input: &[u8]| {
@@ -896,12 +1172,12 @@ pub(crate) fn xlog_record_data_header_long(
 
 #[repr(packed)]
 #[derive(PartialEq, Debug)]
-pub(crate) struct XLogRecordDataHeaderOrigin {
+pub struct XLogRecordDataHeaderOrigin {
     id: u8,
     record_origin: u16,
 }
 
-pub(crate) fn xlog_record_data_header_origin(
+pub fn xlog_record_data_header_origin(
     id: u8,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XLogRecordDataHeaderOrigin> {
     move |input: &[u8]| {
@@ -912,12 +1188,12 @@ pub(crate) fn xlog_record_data_header_origin(
 
 #[repr(packed)]
 #[derive(PartialEq, Debug)]
-pub(crate) struct XLogRecordDataHeaderTopLevelXid {
+pub struct XLogRecordDataHeaderTopLevelXid {
     id: u8,
     top_level_xid: TransactionId,
 }
 
-pub(crate) fn xlog_record_data_header_top_level_xid(
+pub fn xlog_record_data_header_top_level_xid(
     id: u8,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XLogRecordDataHeaderTopLevelXid> {
     move |input: &[u8]| {
@@ -937,7 +1213,7 @@ pub(crate) fn xlog_record_data_header_top_level_xid(
 ///
 #[repr(packed)]
 #[derive(PartialEq, Debug)]
-pub(crate) struct XLogRecordBlockHeader {
+pub struct XLogRecordBlockHeader {
     // block reference ID
     id: u8,
     // fork within the relation, and flags
@@ -949,7 +1225,7 @@ pub(crate) struct XLogRecordBlockHeader {
     // BlockNumber follows
 }
 
-pub(crate) fn xlog_record_block_header(
+pub fn xlog_record_block_header(
     id: u8,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XLogRecordBlockHeader> {
     move |input: &[u8]| {
@@ -965,7 +1241,7 @@ pub(crate) fn xlog_record_block_header(
 }
 
 #[repr(packed)]
-pub(crate) struct XLogRecordBlockImageHeader {
+pub struct XLogRecordBlockImageHeader {
     // number of page image bytes
     length: u16,
     // number of bytes before "hole"
@@ -976,7 +1252,7 @@ pub(crate) struct XLogRecordBlockImageHeader {
     // XLogRecordBlockCompressHeader struct follows.
 }
 
-pub(crate) fn xlog_record_block_image_header(
+pub fn xlog_record_block_image_header(
     input: &[u8],
 ) -> IResult<&[u8], XLogRecordBlockImageHeader> {
     map(
@@ -1001,12 +1277,12 @@ pub fn byte_to_u32(input: &[u8]) -> IResult<&[u8], u32> {
     le_u32(input)
 }
 
-pub(crate) struct XLogRecordBlockCompressHeader {
+pub struct XLogRecordBlockCompressHeader {
     // number of bytes in "hole"
     hole_length: u16,
 }
 
-pub(crate) fn xlog_page_header_size(hdr: &XLogPageHeaderData) -> u32 {
+pub fn xlog_page_header_size(hdr: &XLogPageHeaderData) -> u32 {
     if hdr.xlp_info & XLP_LONG_HEADER == XLP_LONG_HEADER {
         std::mem::size_of::<XLogLongPageHeaderData>() as u32
     } else {
@@ -1014,15 +1290,104 @@ pub(crate) fn xlog_page_header_size(hdr: &XLogPageHeaderData) -> u32 {
     }
 }
 
-static mut WAL_SEG_SZ: u32 = 16 * 1024 * 1024;
+// Properties of the cluster that wrote the WAL being read.  Each reader owns
+// its copy, so readers over clusters with different settings can coexist in
+// one process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalConfig {
+    pub wal_seg_sz: u32,
+    pub xlog_blcksz: u32,
+    // database system identifier; 0 if not known, in which case it's not
+    // checked against the page headers
+    pub system_identifier: u64,
+    // where system_identifier was taken from, for the message about a page
+    // header carrying another one
+    pub system_identifier_source: SystemIdentifierSource,
+    // version whose record layouts are used to decode the WAL
+    pub version: PgVersion,
+    // magic every page header must carry; that of the version, unless the
+    // version was given explicitly
+    pub page_magic: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SystemIdentifierSource {
+    // the long header of the first segment read
+    #[default]
+    FirstSegment,
+    // pg_control of --pgdata
+    PgControl,
+    // IDENTIFY_SYSTEM of the replication connection
+    Server,
+}
 
-pub(crate) fn get_wal_seg_sz() -> u32 {
-    unsafe { WAL_SEG_SZ }
+impl Default for WalConfig {
+    fn default() -> Self {
+        WalConfig {
+            wal_seg_sz: DEFAULT_XLOG_SEG_SIZE,
+            xlog_blcksz: XLOG_BLCKSZ,
+            system_identifier: 0,
+            system_identifier_source: SystemIdentifierSource::FirstSegment,
+            version: PgVersion::LATEST,
+            page_magic: PgVersion::LATEST.page_magic(),
+        }
+    }
 }
 
-pub(crate) fn set_wal_seg_sz(sz: u32) {
-    unsafe {
-        WAL_SEG_SZ = sz;
+impl WalConfig {
+    // Discover the configuration from the long page header found at the
+    // start of every segment.
+    pub fn from_first_page(page: &[u8]) -> Result<WalConfig, String> {
+        WalConfig::from_first_page_as(page, None)
+    }
+
+    // Same, but decode the WAL as that of `version` if given, whatever the
+    // page magic says.
+    pub fn from_first_page_as(
+        page: &[u8],
+        version: Option<PgVersion>,
+    ) -> Result<WalConfig, String> {
+        let (_, hdr) = match first_page_header(page) {
+            Ok(t) => t,
+            Err(_) => return Err(String::from("could not parse long page header")),
+        };
+        let version = match version.or(PgVersion::from_page_magic(hdr.std.xlp_magic)) {
+            Some(v) => v,
+            None => {
+                return Err(format!(
+                    "invalid magic number {:04X} in first page of WAL segment; \
+                     not WAL of a supported PostgreSQL version",
+                    hdr.std.xlp_magic
+                ))
+            }
+        };
+        if hdr.std.xlp_info & XLP_LONG_HEADER == 0 {
+            return Err(format!(
+                "invalid info bits {:04X} in first page of WAL segment",
+                hdr.std.xlp_info
+            ));
+        }
+        if !is_valid_wal_segment_size(hdr.xlp_seg_size) {
+            return Err(format!(
+                "WAL segment size must be a power of two between 1 MB and 1 GB, \
+                 but the WAL file header specifies {} bytes",
+                hdr.xlp_seg_size
+            ));
+        }
+        if hdr.xlp_xlog_blcksz != XLOG_BLCKSZ {
+            return Err(format!(
+                "WAL block size {} is not supported, expected {}",
+                hdr.xlp_xlog_blcksz, XLOG_BLCKSZ
+            ));
+        }
+        Ok(WalConfig {
+            wal_seg_sz: hdr.xlp_seg_size,
+            xlog_blcksz: hdr.xlp_xlog_blcksz,
+            system_identifier: hdr.xlp_sysid,
+            system_identifier_source: SystemIdentifierSource::FirstSegment,
+            version,
+            page_magic: hdr.std.xlp_magic,
+        })
     }
 }
 
@@ -1030,17 +1395,22 @@ fn is_valid_wal_segment_size(sz: u32) -> bool {
     (sz > 0 && (sz & (sz - 1) == 0)) && sz >= WAL_SEG_MIN_SIZE && sz <= WAL_SEG_MAX_SIZE
 }
 
-pub(crate) fn check_first_page_header(file: &mut File, fname: &PathBuf) -> bool {
+// Read the first page of a segment file and discover the WAL configuration
+// from its long header.  `version` overrides the version detected from the
+// page magic.
+pub fn check_first_page_header<R: Read>(
+    file: &mut R,
+    fname: &PathBuf,
+    version: Option<PgVersion>,
+) -> WalConfig {
     let mut buf = [0u8; XLOG_BLCKSZ as usize];
     match file.read(&mut buf) {
         Ok(n) => {
             if n == XLOG_BLCKSZ as usize {
-                let (_, hdr) = first_page_header(&buf).unwrap();
-                if !is_valid_wal_segment_size(hdr.xlp_seg_size) {
-                    panic!("invalid wal segment size");
+                match WalConfig::from_first_page_as(&buf, version) {
+                    Ok(config) => config,
+                    Err(e) => panic!("{}: {}", fname.display(), e),
                 }
-                set_wal_seg_sz(hdr.xlp_seg_size);
-                return true;
             } else {
                 panic!(
                     "could not read file {}: read {} of {}",
@@ -1054,18 +1424,44 @@ pub(crate) fn check_first_page_header(file: &mut File, fname: &PathBuf) -> bool
             panic!("could not read file {}: {}", fname.display(), e);
         }
     }
-    return false;
 }
 
 fn xlog_segment_offset(xlogptr: XLogRecPtr, wal_segsz_bytes: u32) -> u32 {
     (xlogptr & (wal_segsz_bytes - 1) as u64) as u32
 }
 
+// Where a page is, for the errors about its header: the segment file and
+// offset, worded as pg_waldump of the version does with --compat=upstream.
+fn page_location(state: &XLogReaderState, recptr: XLogRecPtr, offset: u32) -> String {
+    if !state.upstream {
+        return format!("WAL segment, LSN {}, offset {}", lsn_out(recptr), offset);
+    }
+    let private = &state.private_data;
+    let tli = if private.history.is_empty() {
+        private.timeline
+    } else {
+        tli_of_segment(recptr, state.config.wal_seg_sz, &private.history)
+    };
+    let segno = xlog_byte_to_seg(recptr, state.config.wal_seg_sz);
+    let fname = xlog_filename(tli, segno, state.config.wal_seg_sz);
+    // PostgreSQL 17 added the LSN
+    if state.config.version < PgVersion::V17 {
+        format!("log segment {}, offset {}", fname.display(), offset)
+    } else {
+        format!(
+            "WAL segment {}, LSN {}, offset {}",
+            fname.display(),
+            lsn_format(recptr),
+            offset
+        )
+    }
+}
+
 // Validate a page header.
 //
 // Check if 'phdr' is valid as the header of the XLog page at position
 // 'recptr'.
-pub(crate) fn xlog_reader_validate_page_header(
+pub fn xlog_reader_validate_page_header(
     state: &mut XLogReaderState,
     recptr: XLogRecPtr,
 ) -> bool {
@@ -1073,68 +1469,71 @@ pub(crate) fn xlog_reader_validate_page_header(
 
     assert_eq!((recptr % XLOG_BLCKSZ as u64), 0);
 
-    // XLByteToSeg(recptr, segno, state.segcxt.ws_segsize);
-    let offset = xlog_segment_offset(recptr, state.segcxt.ws_segsize);
+    // XLByteToSeg(recptr, segno, state.config.wal_seg_sz);
+    let offset = xlog_segment_offset(recptr, state.config.wal_seg_sz);
 
-    if hdr.xlp_magic != XLOG_PAGE_MAGIC {
-        // char		fname[MAXFNAMELEN];
-
-        // XLogFileName(fname, state.seg.ws_tli, segno, state.segcxt.ws_segsize);
-
-        // report_invalid_record(state,
-        // 					  "invalid magic number %04X in WAL segment %s, LSN %X/%X, offset %u",
-        // 					  hdr.xlp_magic,
-        // 					  fname,
-        // 					  LSN_FORMAT_ARGS(recptr),
-        // 					  offset);
+    if hdr.xlp_magic != state.config.page_magic {
+        state.errmsg = format!(
+            "invalid magic number {:04X} in {}",
+            hdr.xlp_magic,
+            page_location(state, recptr, offset)
+        );
         return false;
     }
 
     if (hdr.xlp_info & !XLP_ALL_FLAGS) != 0 {
-        // char		fname[MAXFNAMELEN];
-
-        // XLogFileName(fname, state.seg.ws_tli, segno, state.segcxt.ws_segsize);
-
-        // report_invalid_record(state,
-        // 					  "invalid info bits %04X in WAL segment %s, LSN %X/%X, offset %u",
-        // 					  hdr.xlp_info,
-        // 					  fname,
-        // 					  LSN_FORMAT_ARGS(recptr),
-        // 					  offset);
+        state.errmsg = format!(
+            "invalid info bits {:04X} in {}",
+            hdr.xlp_info,
+            page_location(state, recptr, offset)
+        );
         return false;
     }
 
     if hdr.xlp_info & XLP_LONG_HEADER == XLP_LONG_HEADER {
         let (buf, longhdr) = first_page_header(&state.read_buf).unwrap();
 
-        // if longhdr.xlp_sysid != state.system_identifier {
-        //     // report_invalid_record(state,
-        //     // 					  "WAL file is from different database system: WAL file database system identifier is %llu, pg_control database system identifier is %llu",
-        //     // 					  (unsigned long long) longhdr.xlp_sysid,
-        //     // 					  (unsigned long long) state.system_identifier);
-        //     return false;
-        // }
-        if longhdr.xlp_seg_size != state.segcxt.ws_segsize {
-            // report_invalid_record(state,
-            // 					  "WAL file is from different database system: incorrect segment size in page header");
+        if state.config.system_identifier != 0
+            && longhdr.xlp_sysid != state.config.system_identifier
+        {
+            let expected = match state.config.system_identifier_source {
+                SystemIdentifierSource::PgControl => format!(
+                    "pg_control database system identifier is {}",
+                    state.config.system_identifier
+                ),
+                SystemIdentifierSource::FirstSegment => format!(
+                    "expected {} from the first segment",
+                    state.config.system_identifier
+                ),
+                SystemIdentifierSource::Server => format!(
+                    "expected {} from the server",
+                    state.config.system_identifier
+                ),
+            };
+            state.errmsg = format!(
+                "WAL file is from different database system: WAL file database system identifier is {}, {}",
+                longhdr.xlp_sysid, expected
+            );
             return false;
-        } else if longhdr.xlp_xlog_blcksz != XLOG_BLCKSZ {
-            // report_invalid_record(state,
-            // 					  "WAL file is from different database system: incorrect XLOG_BLCKSZ in page header");
+        }
+        if longhdr.xlp_seg_size != state.config.wal_seg_sz {
+            state.errmsg = String::from(
+                "WAL file is from different database system: incorrect segment size in page header",
+            );
+            return false;
+        } else if longhdr.xlp_xlog_blcksz != state.config.xlog_blcksz {
+            state.errmsg = String::from(
+                "WAL file is from different database system: incorrect XLOG_BLCKSZ in page header",
+            );
             return false;
         }
     } else if offset == 0 {
-        // char		fname[MAXFNAMELEN];
-
-        // XLogFileName(fname, state.seg.ws_tli, segno, state.segcxt.ws_segsize);
-
-        // /* hmm, first page of file doesn't have a long header? */
-        // report_invalid_record(state,
-        // 					  "invalid info bits %04X in WAL segment %s, LSN %X/%X, offset %u",
-        // 					  hdr.xlp_info,
-        // 					  fname,
-        // 					  LSN_FORMAT_ARGS(recptr),
-        // 					  offset);
+        /* hmm, first page of file doesn't have a long header? */
+        state.errmsg = format!(
+            "invalid info bits {:04X} in {}",
+            hdr.xlp_info,
+            page_location(state, recptr, offset)
+        );
         return false;
     }
 
@@ -1144,16 +1543,16 @@ pub(crate) fn xlog_reader_validate_page_header(
      * yet been overwritten with new data yet.
      */
     if hdr.xlp_pageaddr != recptr {
-        // char		fname[MAXFNAMELEN];
-
-        // XLogFileName(fname, state.seg.ws_tli, segno, state.segcxt.ws_segsize);
-
-        // report_invalid_record(state,
-        // 					  "unexpected pageaddr %X/%X in WAL segment %s, LSN %X/%X, offset %u",
-        // 					  LSN_FORMAT_ARGS(hdr.xlp_pageaddr),
-        // 					  fname,
-        // 					  LSN_FORMAT_ARGS(recptr),
-        // 					  offset);
+        let pageaddr = if state.upstream {
+            lsn_format(hdr.xlp_pageaddr)
+        } else {
+            lsn_out(hdr.xlp_pageaddr)
+        };
+        state.errmsg = format!(
+            "unexpected pageaddr {} in {}",
+            pageaddr,
+            page_location(state, recptr, offset)
+        );
         return false;
     }
 
@@ -1168,17 +1567,12 @@ pub(crate) fn xlog_reader_validate_page_header(
      */
     if recptr > state.latest_page_ptr {
         if hdr.xlp_tli < state.latest_page_tli {
-            // char		fname[MAXFNAMELEN];
-
-            // XLogFileName(fname, state.seg.ws_tli, segno, state.segcxt.ws_segsize);
-
-            // report_invalid_record(state,
-            // 					  "out-of-sequence timeline ID %u (after %u) in WAL segment %s, LSN %X/%X, offset %u",
-            // 					  hdr.xlp_tli,
-            // 					  state.latestPageTLI,
-            // 					  fname,
-            // 					  LSN_FORMAT_ARGS(recptr),
-            // 					  offset);
+            state.errmsg = format!(
+                "out-of-sequence timeline ID {} (after {}) in {}",
+                hdr.xlp_tli,
+                state.latest_page_tli,
+                page_location(state, recptr, offset)
+            );
             return false;
         }
     }
@@ -1204,7 +1598,7 @@ mod tests {
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         ];
         let (_, hdr) = page_header(&input).unwrap();
-        assert_eq!(hdr.xlp_magic, XLOG_PAGE_MAGIC);
+        assert_eq!(hdr.xlp_magic, PgVersion::V15.page_magic());
         assert_eq!(hdr.xlp_info, XLP_LONG_HEADER);
         assert_eq!(hdr.xlp_tli, 1);
         assert_eq!(hdr.xlp_pageaddr, 0x01000000);
@@ -1214,7 +1608,7 @@ mod tests {
     #[test]
     fn wal_file_first_page_header() {
         let (_, hdr) = first_page_header(WAL_FILE).unwrap();
-        assert_eq!(hdr.std.xlp_magic, XLOG_PAGE_MAGIC);
+        assert_eq!(hdr.std.xlp_magic, PgVersion::V15.page_magic());
         assert_eq!(hdr.std.xlp_info, XLP_LONG_HEADER);
         assert_eq!(hdr.std.xlp_tli, 1);
         assert_eq!(hdr.std.xlp_pageaddr, 0x01000000);
@@ -1222,6 +1616,12 @@ mod tests {
         assert_eq!(hdr.xlp_sysid, 0x657d48e8d9177b4b);
         assert_eq!(hdr.xlp_seg_size, 16 * 1024 * 1024);
         assert_eq!(hdr.xlp_xlog_blcksz, 8 * 1024);
+
+        let config = WalConfig::from_first_page(WAL_FILE).unwrap();
+        assert_eq!(config.version, PgVersion::V15);
+        let config = WalConfig::from_first_page_as(WAL_FILE, Some(PgVersion::V16)).unwrap();
+        assert_eq!(config.version, PgVersion::V16);
+        assert_eq!(config.page_magic, PgVersion::V15.page_magic());
     }
 
     #[test]
@@ -1354,4 +1754,79 @@ mod tests {
         let ckp: CheckPoint = unsafe { std::ptr::read(remaining.as_ptr() as *const CheckPoint) };
         println!("{}", ckp);
     }
+    #[test]
+    fn raw_records_are_laid_out_in_parts() {
+        use crate::walsource::MemorySource;
+
+        let config = WalConfig::from_first_page(WAL_FILE).unwrap();
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: 0x1000000,
+            endptr: 0x1004000,
+            ..Default::default()
+        };
+        let source = MemorySource::new(0x1000000, WAL_FILE.to_vec());
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        state.keep_raw = true;
+        xlog_find_next_record(&mut state);
+        let mut parts = Vec::new();
+        while xlog_read_record(&mut state) {
+            let record = state.record().unwrap();
+            assert_eq!(record.raw.len(), record.header.xl_tot_len as usize);
+            // the parts cover the record, one after the other
+            let mut off = 0;
+            for span in &record.layout {
+                assert_eq!(span.off, off, "{}", span.part);
+                off += span.len;
+                parts.push(span.part);
+            }
+            assert_eq!(off, record.header.xl_tot_len);
+            if let Some(data) = record.main_data() {
+                let last = record.layout.last().unwrap();
+                assert_eq!(last.part, RecordPart::MainData);
+                assert_eq!(&record.raw[last.off as usize..], data);
+            }
+        }
+        assert_eq!(parts[0], RecordPart::Header);
+        for part in [
+            RecordPart::BlockHeader(0),
+            RecordPart::ImageHeader(0),
+            RecordPart::RelFileLocator(0),
+            RecordPart::BlockNumber(0),
+            RecordPart::Image(0),
+            RecordPart::DataHeaderShort,
+        ] {
+            assert!(parts.contains(&part), "{}", part);
+        }
+    }
+
+    #[test]
+    fn record_data_is_read_in_place() {
+        let mut state = crate::walsource::tests::bundled_segment_reader();
+        let (mut on_page, mut cross_page) = (0, 0);
+        while xlog_read_record(&mut state) {
+            let record = state.record().unwrap();
+            let Some(data) = record.main_data().or(record.block_image(0)) else {
+                continue;
+            };
+            // a slice of the page, or of the reassembled record
+            let page = state.read_buf.as_ptr_range();
+            let reassembled = state.cross_page_record_buf.as_ptr_range();
+            if page.contains(&data.as_ptr()) {
+                on_page += 1;
+            } else {
+                assert!(reassembled.contains(&data.as_ptr()));
+                cross_page += 1;
+            }
+            assert!(record.raw.is_empty());
+        }
+        assert!(on_page > 0 && cross_page > 0);
+
+        // a record taken from the reader keeps a copy of its bytes
+        let mut state = crate::walsource::tests::bundled_segment_reader();
+        assert!(xlog_read_record(&mut state));
+        let data = state.record().unwrap().main_data().map(|d| d.to_vec());
+        let record = state.take_record().unwrap();
+        assert_eq!(record.view().main_data().map(|d| d.to_vec()), data);
+    }
 }
@@ -0,0 +1,940 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::archive::TarArchive;
+use crate::compress::{find_segment, open_segment, SegmentFile};
+use crate::constant::XLOG_BLCKSZ;
+use crate::pgtypes::*;
+use crate::restore::RestoreCommand;
+use crate::scan::ScanWarning;
+use crate::util::*;
+use crate::waldec::{lsn_out, WalConfig};
+
+// errno reported when a source has no data for the requested segment
+pub const ENOENT: i32 = 2;
+// errno reported when the data isn't available yet, but may be later; the
+// reader gives up on the current record without failing, and the caller
+// can retry once the source has the data.
+pub const EWOULDBLOCK: i32 = 11;
+
+// A WalSource hands raw WAL bytes to the reader: "read buf.len() bytes
+// starting at startptr on timeline tli".  Page validation and record
+// decoding stay in waldec.rs, so anything able to answer that question can
+// be plugged into an XLogReaderState.
+pub trait WalSource: Send {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError>;
+
+    // For a source whose WAL is still being written: wait until the WAL
+    // from startptr up to at least upto is there, and return where it ends.
+    // The reader then reads only that much of the last page rather than
+    // wait for the page to fill up.  An end short of upto means there will
+    // be no more, and reading stops there.  Sources of complete segments
+    // return None.
+    fn wait_for(&mut self, startptr: XLogRecPtr, upto: XLogRecPtr) -> Option<XLogRecPtr> {
+        None
+    }
+}
+
+// Error for a segment that could not be opened.
+fn open_error(errno: i32, segno: XLogSegNo, tli: TimeLineID) -> WALReadError {
+    WALReadError {
+        errno,
+        off: 0,
+        req: 0,
+        read: 0,
+        seg: WALOpenSegment {
+            file: None,
+            segno,
+            tli,
+        },
+        msg: None,
+    }
+}
+
+// Read from WAL stored as segment files, opening the segment holding each
+// part of the request through the open callback.  This is the equivalent of
+// upstream's WALRead() with its segment_open callback.
+fn segment_read<F>(
+    seg: &mut WALOpenSegment,
+    segsize: u32,
+    startptr: XLogRecPtr,
+    tli: TimeLineID,
+    buf: &mut [u8],
+    mut open: F,
+) -> Result<(), WALReadError>
+where
+    F: FnMut(XLogSegNo, TimeLineID) -> Result<SegmentFile, WALReadError>,
+{
+    let mut recptr = startptr;
+    let mut done = 0;
+
+    while done < buf.len() {
+        let startoff = xlog_segment_offset(recptr, segsize);
+
+        // If the data we want is not in a segment we have open, close what we
+        // have (if anything) and open the next one.
+        if seg.file.is_none() || !byte_in_seg(recptr, seg.segno, segsize) || tli != seg.tli {
+            seg.file = None;
+
+            let next_seg_no = byte_to_seg(recptr, segsize);
+            seg.file = Some(open(next_seg_no, tli)?);
+
+            // Update the current segment info.
+            seg.tli = tli;
+            seg.segno = next_seg_no;
+        }
+
+        // How many bytes are within this segment?
+        let segbytes = std::cmp::min(buf.len() - done, (segsize - startoff) as usize);
+
+        let file = seg.file.as_mut().unwrap();
+        let res = file
+            .seek(SeekFrom::Start(startoff as u64))
+            .and_then(|_| file.read_exact(&mut buf[done..done + segbytes]));
+        if let Err(e) = res {
+            return Err(WALReadError {
+                errno: e.raw_os_error().unwrap_or(0),
+                off: startoff,
+                req: segbytes as u32,
+                read: 0,
+                seg: seg.clone(),
+                msg: None,
+            });
+        }
+
+        // Update state for read
+        recptr += segbytes as u64;
+        done += segbytes;
+    }
+
+    Ok(())
+}
+
+// Segment files named after their timeline and segment number, all living
+// in one directory.  This is what pg_waldump reads by default.  Segments
+// compressed by an archive_command (.gz, .zst, .lz4, .bz2) are found too,
+// and those missing from the directory are restored with the restore
+// command, if one is given.
+//
+// Several directories can be searched in priority order, e.g. pg_wal and
+// then the archive.  Each segment is then read from the first directory
+// with a copy whose page header is valid, preferring complete copies over
+// .partial ones, and served from its file.  The other copies are compared
+// with it a chunk at a time, as far as a .partial one was written, and
+// those that differ or are invalid are reported to the warning handler.
+pub struct DirectorySource {
+    dirs: Vec<PathBuf>,
+    segsize: u32,
+    seg: WALOpenSegment,
+    restore: Option<RestoreCommand>,
+    // the copy chosen for each segment already opened from several dirs
+    resolved: BTreeMap<PathBuf, PathBuf>,
+    warn: Box<dyn FnMut(&ScanWarning) + Send>,
+}
+
+impl DirectorySource {
+    pub fn new(dir: PathBuf, segsize: u32) -> Self {
+        Self::from_dirs(vec![dir], segsize)
+    }
+
+    pub fn from_dirs(dirs: Vec<PathBuf>, segsize: u32) -> Self {
+        DirectorySource {
+            dirs,
+            segsize,
+            seg: WALOpenSegment::default(),
+            restore: None,
+            resolved: BTreeMap::new(),
+            warn: Box::new(|w| eprintln!("pg_waldump: warning: {}", w)),
+        }
+    }
+
+    pub fn with_restore_command(mut self, restore: RestoreCommand) -> Self {
+        self.restore = Some(restore);
+        self
+    }
+
+    pub fn with_warning_handler<F>(mut self, warn: F) -> Self
+    where
+        F: FnMut(&ScanWarning) + Send + 'static,
+    {
+        self.warn = Box::new(warn);
+        self
+    }
+
+    // Open segment segno from the first directory with a valid copy.
+    fn open(&mut self, fname: &PathBuf, segno: XLogSegNo) -> std::io::Result<SegmentFile> {
+        if self.dirs.len() == 1 {
+            return open_segment(&self.dirs[0], fname);
+        }
+        if let Some(path) = self.resolved.get(fname) {
+            return SegmentFile::open(path);
+        }
+
+        let mut copies: Vec<PathBuf> = self
+            .dirs
+            .iter()
+            .filter_map(|dir| find_segment(dir, fname))
+            .collect();
+        copies.sort_by_key(|path| is_partial_xlog_filename(path));
+
+        let segstart = segno * self.segsize as u64;
+        let mut chosen: Option<(PathBuf, SegmentFile)> = None;
+        for path in copies {
+            let mut page = vec![0u8; XLOG_BLCKSZ as usize];
+            let file = SegmentFile::open(&path).and_then(|mut file| {
+                file.read_exact(&mut page)?;
+                Ok(file)
+            });
+            let problem = match &file {
+                Ok(_) => check_segment_copy(&page, segstart, self.segsize).err(),
+                Err(e) => Some(format!("could not read file: {}", e)),
+            };
+            if let Some(reason) = problem {
+                (self.warn)(&ScanWarning::HeaderMismatch {
+                    file: path.display().to_string(),
+                    reason,
+                });
+                continue;
+            }
+            let mut file = file.unwrap();
+            match chosen.as_mut() {
+                None => chosen = Some((path, file)),
+                Some((used, first)) => {
+                    let diff = first.rewind().and_then(|_| file.rewind()).and_then(|_| {
+                        first_difference(
+                            first,
+                            is_partial_xlog_filename(used),
+                            &mut file,
+                            is_partial_xlog_filename(&path),
+                        )
+                    });
+                    match diff {
+                        Ok(None) => {}
+                        Ok(Some(offset)) => (self.warn)(&ScanWarning::CopiesDiffer {
+                            segno,
+                            start: segstart,
+                            used: used.display().to_string(),
+                            other: path.display().to_string(),
+                            offset,
+                        }),
+                        Err(e) => (self.warn)(&ScanWarning::HeaderMismatch {
+                            file: path.display().to_string(),
+                            reason: format!("could not read file: {}", e),
+                        }),
+                    }
+                }
+            }
+        }
+
+        match chosen {
+            Some((path, file)) => {
+                self.resolved.insert(fname.clone(), path);
+                Ok(file)
+            }
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
+    }
+}
+
+// Check that data, the start of a segment file at least, is a copy of the
+// segment starting at segstart.
+pub fn check_segment_copy(data: &[u8], segstart: XLogRecPtr, segsize: u32) -> Result<(), String> {
+    if data.len() < XLOG_BLCKSZ as usize {
+        return Err(format!("file is only {} bytes", data.len()));
+    }
+    let config = WalConfig::from_first_page(&data[..XLOG_BLCKSZ as usize])?;
+    if config.wal_seg_sz != segsize {
+        return Err(format!(
+            "WAL segment size in page header is {} bytes, expected {}",
+            config.wal_seg_sz, segsize
+        ));
+    }
+    let hdr = crate::waldec::page_header(data).unwrap().1;
+    if hdr.xlp_pageaddr != segstart {
+        return Err(format!(
+            "page address in page header is {}, expected {} from the file name",
+            lsn_out(hdr.xlp_pageaddr),
+            lsn_out(segstart)
+        ));
+    }
+    Ok(())
+}
+
+// Offset of the first byte where the copies a and b differ, if they do,
+// reading them a chunk at a time.  A .partial copy, e.g. the one
+// pg_receivewal is writing, holds only the WAL up to where it got and zeros
+// after it, so a difference past where either partial copy was written
+// doesn't count.
+fn first_difference<A: Read, B: Read>(
+    a: &mut A,
+    a_partial: bool,
+    b: &mut B,
+    b_partial: bool,
+) -> std::io::Result<Option<u64>> {
+    let mut abuf = vec![0u8; COMPARE_CHUNK];
+    let mut bbuf = vec![0u8; COMPARE_CHUNK];
+    let mut offset = 0;
+    loop {
+        let alen = read_chunk(a, &mut abuf)?;
+        let blen = read_chunk(b, &mut bbuf)?;
+        let n = alen.min(blen);
+        let pos = abuf[..n]
+            .iter()
+            .zip(&bbuf[..n])
+            .position(|(x, y)| x != y)
+            .or((alen != blen).then_some(n));
+        match pos {
+            Some(pos) => {
+                let written = (!a_partial || written_from(&abuf[pos.min(alen)..alen], a)?)
+                    && (!b_partial || written_from(&bbuf[pos.min(blen)..blen], b)?);
+                return Ok(written.then_some(offset + pos as u64));
+            }
+            None if n == 0 => return Ok(None),
+            None => offset += n as u64,
+        }
+    }
+}
+
+const COMPARE_CHUNK: usize = 64 * 1024;
+
+// Fill buf from r as far as r goes; the length read.
+fn read_chunk<R: Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// Whether anything but zeros is left in chunk or after it in r.
+fn written_from<R: Read>(chunk: &[u8], r: &mut R) -> std::io::Result<bool> {
+    if chunk.iter().any(|b| *b != 0) {
+        return Ok(true);
+    }
+    let mut buf = vec![0u8; COMPARE_CHUNK];
+    loop {
+        match read_chunk(r, &mut buf)? {
+            0 => return Ok(false),
+            n if buf[..n].iter().any(|b| *b != 0) => return Ok(true),
+            _ => {}
+        }
+    }
+}
+
+impl WalSource for DirectorySource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let segsize = self.segsize;
+        let mut seg = std::mem::take(&mut self.seg);
+        let res = segment_read(&mut seg, segsize, startptr, tli, buf, |segno, tli| {
+            let fname = xlog_filename(tli, segno, segsize);
+            let mut file = self.open(&fname, segno);
+            if let (Err(_), Some(restore)) = (&file, self.restore.as_mut()) {
+                file = restore
+                    .fetch(fname.to_str().unwrap())
+                    .and_then(|path| SegmentFile::open(&path));
+            }
+            file.map_err(|e| open_error(e.raw_os_error().unwrap_or(ENOENT), segno, tli))
+        });
+        self.seg = seg;
+        res
+    }
+}
+
+// A single segment file whose segment number is known to the caller, e.g.
+// a renamed copy or a file handed over by another program.
+pub struct SingleFileSource {
+    segsize: u32,
+    segno: XLogSegNo,
+    file: File,
+    seg: WALOpenSegment,
+}
+
+impl SingleFileSource {
+    pub fn new(file: File, segno: XLogSegNo, segsize: u32) -> Self {
+        SingleFileSource {
+            segsize,
+            segno,
+            file,
+            seg: WALOpenSegment::default(),
+        }
+    }
+}
+
+impl WalSource for SingleFileSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let file = &self.file;
+        let wanted = self.segno;
+        segment_read(
+            &mut self.seg,
+            self.segsize,
+            startptr,
+            tli,
+            buf,
+            |segno, tli| {
+                let err = |errno| open_error(errno, segno, tli);
+                if segno != wanted {
+                    return Err(err(ENOENT));
+                }
+                file.try_clone()
+                    .map(SegmentFile::from)
+                    .map_err(|e| err(e.raw_os_error().unwrap_or(0)))
+            },
+        )
+    }
+}
+
+// An explicit list of segment files, looked up by the timeline and segment
+// number encoded in their file names, which may carry a compression suffix.
+pub struct FileListSource {
+    segsize: u32,
+    files: BTreeMap<(TimeLineID, XLogSegNo), PathBuf>,
+    seg: WALOpenSegment,
+}
+
+impl FileListSource {
+    pub fn new(paths: Vec<PathBuf>, segsize: u32) -> Result<Self, String> {
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let fname: PathBuf = match path.file_name() {
+                Some(name) if is_xlog_filename(&path) => name.into(),
+                _ => return Err(format!("\"{}\" is not a WAL file name", path.display())),
+            };
+            let mut tli: TimeLineID = 0;
+            let mut segno: XLogSegNo = 0;
+            xlog_from_file_name(&fname, &mut tli, &mut segno, segsize);
+            files.insert((tli, segno), path);
+        }
+        Ok(FileListSource {
+            segsize,
+            files,
+            seg: WALOpenSegment::default(),
+        })
+    }
+}
+
+impl WalSource for FileListSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let files = &self.files;
+        segment_read(
+            &mut self.seg,
+            self.segsize,
+            startptr,
+            tli,
+            buf,
+            |segno, tli| {
+                let err = |errno| open_error(errno, segno, tli);
+                let path = files.get(&(tli, segno)).ok_or_else(|| err(ENOENT))?;
+                SegmentFile::open(path).map_err(|e| err(e.raw_os_error().unwrap_or(0)))
+            },
+        )
+    }
+}
+
+// Segment files inside a tar archive, e.g. a pg_basebackup -Ft base backup,
+// in directory dir of the archive.
+pub struct TarSource {
+    archive: TarArchive,
+    dir: PathBuf,
+    segsize: u32,
+    seg: WALOpenSegment,
+}
+
+impl TarSource {
+    pub fn new(archive: TarArchive, dir: PathBuf, segsize: u32) -> Self {
+        TarSource {
+            archive,
+            dir,
+            segsize,
+            seg: WALOpenSegment::default(),
+        }
+    }
+}
+
+impl WalSource for TarSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let archive = &self.archive;
+        let dir = &self.dir;
+        let segsize = self.segsize;
+        segment_read(&mut self.seg, segsize, startptr, tli, buf, |segno, tli| {
+            let fname = xlog_filename(tli, segno, segsize);
+            archive
+                .open_segment(dir, &fname)
+                .map_err(|e| open_error(e.raw_os_error().unwrap_or(ENOENT), segno, tli))
+        })
+    }
+}
+
+// WAL read from a byte stream such as stdin or a pipe, e.g. `zstdcat
+// seg.zst | pg_waldump -`.  The stream holds a segment, or consecutive
+// segments, from startptr on, and is read as far as the reader gets.  Like
+// StreamSource, the WAL is kept from the start of the segment before the
+// one being read.  The timeline is not checked.
+pub struct ByteStreamSource {
+    input: Box<dyn Read + Send>,
+    segsize: u32,
+    startptr: XLogRecPtr,
+    data: Vec<u8>,
+    eof: bool,
+}
+
+impl ByteStreamSource {
+    // `data` is what has been read from input already, starting at startptr,
+    // such as the first page used to find the configuration.
+    pub fn new(
+        input: Box<dyn Read + Send>,
+        startptr: XLogRecPtr,
+        data: Vec<u8>,
+        segsize: u32,
+    ) -> Self {
+        ByteStreamSource {
+            input,
+            segsize,
+            startptr,
+            data,
+            eof: false,
+        }
+    }
+
+    // start of the WAL still held
+    pub fn startptr(&self) -> XLogRecPtr {
+        self.startptr
+    }
+
+    fn endptr(&self) -> XLogRecPtr {
+        self.startptr + self.data.len() as u64
+    }
+
+    // Read from the input until the WAL up to upto is there, or the input
+    // ends.
+    fn fill(&mut self, upto: XLogRecPtr) -> std::io::Result<()> {
+        let mut chunk = [0u8; 65536];
+        while self.endptr() < upto && !self.eof {
+            match self.input.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.data.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WalSource for ByteStreamSource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let endptr = startptr + buf.len() as u64;
+        let res = self.fill(endptr);
+        if startptr < self.startptr || endptr > self.endptr() || res.is_err() {
+            return Err(WALReadError {
+                errno: res.err().and_then(|e| e.raw_os_error()).unwrap_or(0),
+                off: xlog_segment_offset(startptr, self.segsize),
+                req: buf.len() as u32,
+                read: self.endptr().saturating_sub(startptr).min(buf.len() as u64) as u32,
+                seg: WALOpenSegment {
+                    file: None,
+                    segno: xlog_byte_to_seg(startptr, self.segsize),
+                    tli,
+                },
+                msg: None,
+            });
+        }
+        let off = (startptr - self.startptr) as usize;
+        buf.copy_from_slice(&self.data[off..off + buf.len()]);
+
+        // drop what is before the previous segment
+        let keep = xlog_byte_to_seg(startptr, self.segsize).saturating_sub(1) * self.segsize as u64;
+        if keep > self.startptr {
+            self.data.drain(..(keep - self.startptr) as usize);
+            self.startptr = keep;
+        }
+        Ok(())
+    }
+
+    fn wait_for(&mut self, startptr: XLogRecPtr, upto: XLogRecPtr) -> Option<XLogRecPtr> {
+        // an error is reported by the read that follows
+        self.fill(upto).ok().map(|_| self.endptr())
+    }
+}
+
+// WAL held in memory, starting at startptr.  The timeline is not checked;
+// the caller decides what the bytes are.
+#[derive(Default)]
+pub struct MemorySource {
+    startptr: XLogRecPtr,
+    data: Vec<u8>,
+}
+
+impl MemorySource {
+    pub fn new(startptr: XLogRecPtr, data: Vec<u8>) -> Self {
+        MemorySource { startptr, data }
+    }
+
+    // Append more WAL, e.g. as it arrives from somewhere else.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // end+1 of the WAL available
+    pub fn endptr(&self) -> XLogRecPtr {
+        self.startptr + self.data.len() as u64
+    }
+}
+
+impl WalSource for MemorySource {
+    fn read(
+        &mut self,
+        startptr: XLogRecPtr,
+        tli: TimeLineID,
+        buf: &mut [u8],
+    ) -> Result<(), WALReadError> {
+        let endptr = startptr + buf.len() as u64;
+        if startptr < self.startptr || endptr > self.endptr() {
+            let available = self.endptr().saturating_sub(startptr).min(buf.len() as u64);
+            return Err(WALReadError {
+                errno: 0,
+                off: startptr.saturating_sub(self.startptr) as u32,
+                req: buf.len() as u32,
+                read: available as u32,
+                seg: WALOpenSegment {
+                    file: None,
+                    segno: 0,
+                    tli,
+                },
+                msg: None,
+            });
+        }
+        let off = (startptr - self.startptr) as usize;
+        buf.copy_from_slice(&self.data[off..off + buf.len()]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::constant::*;
+    use crate::pg_control::XLOG_NOOP;
+    use crate::rmgr::RmgrIds;
+    use crate::state::XLogReaderState;
+    use crate::waldec::{xlog_find_next_record, xlog_read_record, WalConfig};
+
+    pub(crate) const SEGSIZE: u32 = 16 * 1024 * 1024;
+
+    // the first record of the bundled segment
+    pub(crate) const BUNDLED_FIRST_RECORD: XLogRecPtr = 0x1000028;
+
+    // A reader over test/000000010000000000000001, the first 16 kB of a
+    // segment written by PostgreSQL 15, positioned at its first record.
+    pub(crate) fn bundled_segment_reader() -> XLogReaderState {
+        let seg = std::fs::read("test/000000010000000000000001").unwrap();
+        let config = WalConfig::from_first_page(&seg).unwrap();
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: 0x1000000,
+            endptr: 0x1004000,
+            ..Default::default()
+        };
+        let source = MemorySource::new(0x1000000, seg);
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        assert_eq!(xlog_find_next_record(&mut state), BUNDLED_FIRST_RECORD);
+        state
+    }
+
+    // Build the first page of a segment holding XLOG NOOP records, each with
+    // eight bytes of main data.  Returns the page and the record LSNs.
+    pub(crate) fn synthetic_page(
+        segstart: XLogRecPtr,
+        segsize: u32,
+        nrecords: usize,
+    ) -> (Vec<u8>, Vec<XLogRecPtr>) {
+        let mut page = Vec::with_capacity(XLOG_BLCKSZ as usize);
+        page.extend_from_slice(&XLOG_PAGE_MAGIC.to_le_bytes());
+        page.extend_from_slice(&XLP_LONG_HEADER.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes());
+        page.extend_from_slice(&segstart.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.extend_from_slice(&42u64.to_le_bytes());
+        page.extend_from_slice(&segsize.to_le_bytes());
+        page.extend_from_slice(&XLOG_BLCKSZ.to_le_bytes());
+
+        let mut lsns = Vec::new();
+        let mut prev: XLogRecPtr = 0;
+        for _ in 0..nrecords {
+            let lsn = segstart + page.len() as u64;
+            page.extend_from_slice(&34u32.to_le_bytes());
+            page.extend_from_slice(&0u32.to_le_bytes());
+            page.extend_from_slice(&prev.to_le_bytes());
+            page.push(XLOG_NOOP);
+            page.push(RmgrIds::XLOG as u8);
+            page.extend_from_slice(&[0; 2]);
+            page.extend_from_slice(&0u32.to_le_bytes());
+            page.push(XLR_BLOCK_ID_DATA_SHORT);
+            page.push(8);
+            page.extend_from_slice(&[0xAB; 8]);
+            page.resize(crate::util::max_align(page.len() as u32) as usize, 0);
+            lsns.push(lsn);
+            prev = lsn;
+        }
+        page.resize(XLOG_BLCKSZ as usize, 0);
+        (page, lsns)
+    }
+
+    #[test]
+    fn memory_source_feeds_reader() {
+        let segstart = SEGSIZE as u64;
+        let (page, lsns) = synthetic_page(segstart, SEGSIZE, 3);
+        let config = WalConfig::from_first_page(&page).unwrap();
+
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: segstart,
+            endptr: lsns[2] + 40,
+            ..Default::default()
+        };
+        let source = MemorySource::new(segstart, page);
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+
+        assert_eq!(xlog_find_next_record(&mut state), lsns[0]);
+        for lsn in &lsns {
+            assert!(xlog_read_record(&mut state));
+            let record = state.record.as_ref().unwrap();
+            assert_eq!(record.lsn, *lsn);
+            assert_eq!(record.header.xl_rmid, RmgrIds::XLOG as u8);
+            assert_eq!(record.main_data_len, 8);
+        }
+        assert!(!xlog_read_record(&mut state));
+    }
+
+    #[test]
+    fn readers_with_different_segment_sizes() {
+        // segment 3 starts at a different LSN depending on the segment size
+        let mut readers = Vec::new();
+        for segsize in [SEGSIZE, 1024 * 1024] {
+            let segstart = 3 * segsize as u64;
+            let (page, lsns) = synthetic_page(segstart, segsize, 2);
+            let config = WalConfig::from_first_page(&page).unwrap();
+            assert_eq!(config.wal_seg_sz, segsize);
+            assert_eq!(config.system_identifier, 42);
+
+            let private = XLogDumpPrivate {
+                timeline: 1,
+                startptr: segstart,
+                endptr: lsns[1] + 40,
+                ..Default::default()
+            };
+            let source = MemorySource::new(segstart, page);
+            let state = XLogReaderState::new(config, Box::new(source), private);
+            readers.push((state, lsns));
+        }
+
+        // interleave the readers; neither may disturb the other
+        for (state, lsns) in readers.iter_mut() {
+            assert_eq!(xlog_find_next_record(state), lsns[0]);
+        }
+        for i in 0..2 {
+            for (state, lsns) in readers.iter_mut() {
+                assert!(xlog_read_record(state));
+                assert_eq!(state.record.as_ref().unwrap().lsn, lsns[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_system_identifier_is_rejected() {
+        let segstart = SEGSIZE as u64;
+        let (page, lsns) = synthetic_page(segstart, SEGSIZE, 1);
+        let mut config = WalConfig::from_first_page(&page).unwrap();
+        config.system_identifier = 7;
+
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: segstart,
+            endptr: lsns[0] + 40,
+            ..Default::default()
+        };
+        let source = MemorySource::new(segstart, page);
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        assert_eq!(xlog_find_next_record(&mut state), INVALID_XLOG_RECPTR);
+        let msg = &state.errmsg;
+        assert!(
+            msg.starts_with("WAL file is from different database"),
+            "{}",
+            msg
+        );
+        assert!(
+            msg.ends_with("is 42, expected 7 from the first segment"),
+            "{}",
+            msg
+        );
+    }
+
+    // hands out at most 1000 bytes per read, like a pipe
+    struct Trickle(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(1000);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    #[test]
+    fn byte_stream_source_reads_until_input_ends() {
+        let seg = std::fs::read("test/000000010000000000000001").unwrap();
+        let config = WalConfig::from_first_page(&seg).unwrap();
+        let segstart = config.wal_seg_sz as u64;
+        let page = seg[..XLOG_BLCKSZ as usize].to_vec();
+        let input = Trickle(std::io::Cursor::new(seg[XLOG_BLCKSZ as usize..].to_vec()));
+        let source = ByteStreamSource::new(Box::new(input), segstart, page, config.wal_seg_sz);
+
+        let private = XLogDumpPrivate {
+            timeline: 1,
+            startptr: segstart,
+            ..Default::default()
+        };
+        let mut state = XLogReaderState::new(config, Box::new(source), private);
+        assert_eq!(xlog_find_next_record(&mut state), 0x1000028);
+        let mut n = 0;
+        while xlog_read_record(&mut state) {
+            n += 1;
+        }
+        // the end of the input is the end of the WAL, not an error
+        assert_eq!(n, 85);
+        assert!(state.errmsg.is_empty());
+        assert!(state.private_data.endptr_reached);
+    }
+
+    #[test]
+    fn memory_source_rejects_reads_outside_buffer() {
+        let mut source = MemorySource::new(0x1000, vec![0; 16]);
+        let mut buf = [0u8; 8];
+        assert!(source.read(0x1008, 1, &mut buf).is_ok());
+        let err = source.read(0x100C, 1, &mut buf).unwrap_err();
+        assert_eq!(err.read, 4);
+        assert!(source.read(0x0FF8, 1, &mut buf).is_err());
+    }
+
+    #[test]
+    fn copies_are_read_from_the_first_valid_directory() {
+        let seg = std::fs::read("test/000000010000000000000001").unwrap();
+        let config = WalConfig::from_first_page(&seg).unwrap();
+        let fname = "000000010000000000000001";
+
+        // a has a copy with a broken page address, b a good one, c one
+        // that differs from it past the first page and d a .partial one
+        // written up to the second page and zeroed after it
+        let top = std::env::temp_dir().join(format!("pg_waldump_dirs_{}", std::process::id()));
+        let mut broken = seg.clone();
+        broken[8] ^= 1;
+        let mut differing = seg.clone();
+        differing[XLOG_BLCKSZ as usize + 100] ^= 1;
+        let mut partial = seg.clone();
+        partial[XLOG_BLCKSZ as usize + 200..].fill(0);
+        let mut dirs = Vec::new();
+        for (name, file, data) in [
+            ("a", fname.to_string(), &broken),
+            ("b", fname.to_string(), &seg),
+            ("c", fname.to_string(), &differing),
+            ("d", format!("{}.partial", fname), &partial),
+        ] {
+            let dir = top.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(file), data).unwrap();
+            dirs.push(dir);
+        }
+
+        let warnings = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = warnings.clone();
+        let mut source = DirectorySource::from_dirs(dirs.clone(), config.wal_seg_sz)
+            .with_warning_handler(move |w| collected.lock().unwrap().push(w.clone()));
+        let mut buf = vec![0u8; seg.len()];
+        source.read(config.wal_seg_sz as u64, 1, &mut buf).unwrap();
+        assert!(buf == seg);
+        // served from the file, not from memory
+        assert!(matches!(source.seg.file, Some(SegmentFile::Plain(_))));
+
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(&warnings[0],
+            ScanWarning::HeaderMismatch { file, .. } if file.ends_with("a/000000010000000000000001")));
+        assert!(matches!(&warnings[1],
+            ScanWarning::CopiesDiffer { used, other, offset, .. }
+                if used.ends_with("b/000000010000000000000001")
+                    && other.ends_with("c/000000010000000000000001")
+                    && *offset == XLOG_BLCKSZ as u64 + 100));
+        std::fs::remove_dir_all(&top).unwrap();
+    }
+
+    #[test]
+    fn copies_are_compared_across_chunks() {
+        let a: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8 + 1).collect();
+        let mut b = a.clone();
+        b[150_000] ^= 1;
+        let mut partial = a.clone();
+        partial[100_000..].fill(0);
+        let diff = |x: &[u8], xp, y: &[u8], yp| {
+            first_difference(&mut &x[..], xp, &mut &y[..], yp).unwrap()
+        };
+
+        assert_eq!(diff(&a, false, &a, false), None);
+        assert_eq!(diff(&a, false, &b, false), Some(150_000));
+        assert_eq!(diff(&a, false, &a[..120_000], false), Some(120_000));
+        // past where the partial copy was written
+        assert_eq!(diff(&b, false, &partial, true), None);
+        assert_eq!(diff(&partial, true, &a[..100_000], false), None);
+        assert_eq!(diff(&b, false, &partial, false), Some(100_000));
+    }
+
+    #[test]
+    fn file_list_source_checks_names() {
+        let seg = PathBuf::from("test/000000010000000000000001");
+        let mut source = FileListSource::new(vec![seg.clone()], SEGSIZE).unwrap();
+        let mut buf = [0u8; 8];
+        assert!(source.read(SEGSIZE as u64, 1, &mut buf).is_ok());
+
+        for bad in ["test/README", "test/0000000100000000", "/"] {
+            let err = FileListSource::new(vec![seg.clone(), PathBuf::from(bad)], SEGSIZE)
+                .err()
+                .unwrap();
+            assert!(err.contains("is not a WAL file name"), "{}", err);
+        }
+    }
+}
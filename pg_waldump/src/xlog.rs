@@ -13,7 +13,9 @@ impl From<WalLevel> for i32 {
 }
 
 // End of recovery mark, when we don't do an END_OF_RECOVERY checkpoint
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XlEndOfRecovery {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_util::timestamptz"))]
     pub end_time: TimestampTz,
     // new TLI
     pub this_timeline_id: TimeLineID,
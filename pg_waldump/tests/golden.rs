@@ -0,0 +1,153 @@
+// Output of --compat=upstream against that of pg_waldump itself.
+//
+// tests/golden/<major>/ holds WAL segments written by that PostgreSQL
+// version, gzipped in wal/, and a cases file with one case per line,
+//
+//     name: arguments
+//
+// run against the segments by both tools; <name>.out and <name>.err are
+// what pg_waldump printed.  To regenerate them, point PG_WALDUMP_UPSTREAM at
+// the pg_waldump of the version; the other versions' are only compared:
+//
+//     PG_WALDUMP_UPSTREAM=/usr/lib/postgresql/15/bin/pg_waldump cargo test --test golden
+//
+// golden/make-fixtures.sh writes the segments and cases of another version.
+use pg_waldump::version::PgVersion;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// Gunzip the segments of the version into a directory of their own.
+fn unpack_segments(version: &Path) -> PathBuf {
+    let name = version.file_name().unwrap().to_string_lossy();
+    let dir =
+        std::env::temp_dir().join(format!("pg_waldump_golden_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for entry in std::fs::read_dir(version.join("wal")).unwrap() {
+        let path = entry.unwrap().path();
+        let mut seg = Vec::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap())
+            .read_to_end(&mut seg)
+            .unwrap();
+        std::fs::write(dir.join(path.file_stem().unwrap()), seg).unwrap();
+    }
+    dir
+}
+
+fn run(program: &Path, upstream: bool, waldir: &Path, args: &[&str]) -> Output {
+    let mut cmd = Command::new(program);
+    if !upstream {
+        cmd.arg("--compat=upstream");
+    }
+    cmd.arg("-p")
+        .arg(waldir)
+        .args(args)
+        // relative paths are looked for in the current directory first
+        .current_dir(waldir)
+        .output()
+        .unwrap()
+}
+
+// The version directories, without make-fixtures.sh.
+fn versions(golden: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(golden)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect()
+}
+
+// The major version of a pg_waldump, from "pg_waldump (PostgreSQL) 15.4".
+fn upstream_major(program: &Path) -> String {
+    let output = Command::new(program).arg("--version").output().unwrap();
+    let version = String::from_utf8_lossy(&output.stdout);
+    let version = version.split_whitespace().last().unwrap();
+    version.split('.').next().unwrap().to_string()
+}
+
+#[test]
+fn output_matches_upstream() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let ours = Path::new(env!("CARGO_BIN_EXE_pg_waldump"));
+    let upstream = std::env::var_os("PG_WALDUMP_UPSTREAM")
+        .map(PathBuf::from)
+        .map(|program| {
+            let major = upstream_major(&program);
+            (program, major)
+        });
+    let mut failed = Vec::new();
+
+    for version in versions(&golden) {
+        let regenerate = upstream
+            .as_ref()
+            .filter(|(_, major)| version.ends_with(major))
+            .map(|(program, _)| program);
+        let waldir = unpack_segments(&version);
+        let cases = std::fs::read_to_string(version.join("cases")).unwrap();
+        for line in cases.lines().filter(|l| !l.trim().is_empty()) {
+            let (name, args) = line.split_once(':').unwrap();
+            let args: Vec<&str> = args.split_whitespace().collect();
+            let out = version.join(format!("{}.out", name));
+            let err = version.join(format!("{}.err", name));
+            if let Some(upstream) = regenerate {
+                let output = run(upstream, true, &waldir, &args);
+                std::fs::write(&out, &output.stdout).unwrap();
+                std::fs::write(&err, &output.stderr).unwrap();
+            }
+
+            let output = run(ours, false, &waldir, &args);
+            let expected_out = std::fs::read(&out).unwrap();
+            let expected_err = std::fs::read(&err).unwrap();
+            if output.stdout != expected_out
+                || output.stderr != expected_err
+                || output.status.success() != expected_err.is_empty()
+            {
+                // the first line that differs, if any does
+                let got = String::from_utf8_lossy(&output.stdout).into_owned()
+                    + &String::from_utf8_lossy(&output.stderr);
+                let want = String::from_utf8_lossy(&expected_out).into_owned()
+                    + &String::from_utf8_lossy(&expected_err);
+                let (n, (want, got)) = want
+                    .lines()
+                    .chain(std::iter::repeat(""))
+                    .zip(got.lines().chain(std::iter::repeat("")))
+                    .enumerate()
+                    .take(want.lines().count().max(got.lines().count()))
+                    .find(|(_, (want, got))| want != got)
+                    .unwrap_or((0, ("", "")));
+                failed.push(format!(
+                    "{}/{}: pg_waldump {}\n  line {}: expected {:?}\n  line {}: got      {:?}",
+                    version.file_name().unwrap().to_string_lossy(),
+                    name,
+                    args.join(" "),
+                    n + 1,
+                    want,
+                    n + 1,
+                    got
+                ));
+            }
+        }
+        std::fs::remove_dir_all(&waldir).unwrap();
+    }
+    assert!(
+        failed.is_empty(),
+        "differ from pg_waldump:\n{}",
+        failed.join("\n")
+    );
+}
+
+// --compat=upstream accepts the WAL of exactly the versions checked here.
+#[test]
+fn compat_checked_versions_have_golden_output() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut majors: Vec<String> = versions(&golden)
+        .iter()
+        .map(|v| v.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    majors.sort();
+    let checked: Vec<String> = PgVersion::COMPAT_CHECKED
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    assert_eq!(majors, checked);
+}
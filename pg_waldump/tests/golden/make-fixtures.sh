@@ -0,0 +1,106 @@
+#!/bin/sh
+# Write tests/golden/<major>/ for the PostgreSQL whose binaries are in
+# BINDIR: the WAL segments of a scratch cluster running the workload below
+# and the cases file.  The expected output is then taken from that
+# version's pg_waldump:
+#
+#     tests/golden/make-fixtures.sh /usr/lib/postgresql/16/bin
+#     PG_WALDUMP_UPSTREAM=/usr/lib/postgresql/16/bin/pg_waldump cargo test --test golden
+#
+# The cluster has 1 MB segments, and each part of the workload gets
+# segments of its own, switched to with pg_switch_wal().  initdb refuses
+# to run as root.
+set -e
+
+bindir=${1:?usage: $0 BINDIR [OUTDIR]}
+major=$("$bindir/postgres" --version | sed 's/^[^0-9]*\([0-9]*\).*/\1/')
+out=${2:-$(dirname "$0")/$major}
+tmp=$(mktemp -d)
+trap '"$bindir/pg_ctl" -D "$tmp/data" -m immediate stop >/dev/null 2>&1 || :; rm -rf "$tmp"' EXIT
+
+"$bindir/initdb" -D "$tmp/data" -U postgres -A trust --wal-segsize=1 >/dev/null
+"$bindir/pg_ctl" -D "$tmp/data" -l "$tmp/log" -w \
+    -o "-c listen_addresses= -k $tmp -c fsync=off -c autovacuum=off -c wal_keep_size=1GB" start >/dev/null
+
+sql() { "$bindir/psql" -h "$tmp" -U postgres -X -q -At -v ON_ERROR_STOP=1 "$@"; }
+# switch to a fresh segment and print its name
+switch() {
+    sql -c "SELECT pg_switch_wal()" >/dev/null
+    sql -c "SELECT pg_walfile_name(pg_current_wal_insert_lsn())"
+}
+
+sql -c "CHECKPOINT"
+heap=$(switch)
+zstart=$(sql -c "SELECT pg_current_wal_insert_lsn()")
+sql >/dev/null <<'EOF'
+CREATE TABLE t (a int, b text);
+INSERT INTO t SELECT i, repeat('x', i % 50) FROM generate_series(1, 300) i;
+UPDATE t SET b = 'y' WHERE a % 7 = 0;
+UPDATE t SET a = -a WHERE a % 11 = 0;
+DELETE FROM t WHERE a % 5 = 0;
+TRUNCATE t;
+INSERT INTO t VALUES (1, 'one');
+BEGIN; INSERT INTO t VALUES (2, 'two'); SAVEPOINT s; INSERT INTO t VALUES (3, 'three'); ROLLBACK TO s; COMMIT;
+BEGIN; INSERT INTO t VALUES (4, 'four'); ROLLBACK;
+SELECT * FROM t FOR UPDATE;
+VACUUM t;
+EOF
+sql -c "CHECKPOINT"
+
+btree=$(switch)
+start=$(sql -c "SELECT pg_current_wal_insert_lsn()")
+sql <<'EOF'
+CREATE TABLE u (k int PRIMARY KEY, v text);
+CREATE INDEX ON u (v);
+INSERT INTO u SELECT i, md5(i::text) FROM generate_series(1, 2000) i;
+DELETE FROM u WHERE k % 3 = 0;
+VACUUM u;
+EOF
+
+splits=$(switch)
+sql <<'EOF'
+INSERT INTO u SELECT i, md5(i::text) FROM generate_series(2001, 6000) i;
+DELETE FROM u WHERE k > 1000;
+VACUUM u;
+EOF
+
+sql -c "CHECKPOINT"
+fpw=$(switch)
+sql -c "UPDATE u SET v = v || '!' WHERE k < 40"
+
+backup=$(switch)
+sql -c "SELECT pg_backup_start('golden')" -c "SELECT pg_backup_stop()" >/dev/null 2>&1 ||
+    sql -c "SELECT pg_start_backup('golden')" -c "SELECT pg_stop_backup()" >/dev/null
+
+db=$(switch)
+zend=$(sql -c "SELECT pg_current_wal_insert_lsn()")
+sql -c "CREATE DATABASE golden" -c "DROP DATABASE golden"
+sql -c "SELECT pg_switch_wal()" >/dev/null
+"$bindir/pg_ctl" -D "$tmp/data" -w stop >/dev/null
+
+mkdir -p "$out/wal"
+rm -f "$out"/wal/* "$out"/*.out "$out"/*.err
+# every segment from the first case's to the last one's, for zrange
+for path in "$tmp"/data/pg_wal/0*; do
+    seg=${path##*/}
+    if [ "$seg" \< "$heap" ] || [ "$seg" \> "$db" ]; then
+        continue
+    fi
+    gzip -9 -c "$path" > "$out/wal/$seg.gz"
+done
+
+# the rmgrs whose records are described
+r="-r XLOG -r Transaction -r Database -r Heap2 -r Heap -r Btree"
+cat > "$out/cases" <<EOF
+heap: $r $heap
+btree: $r $btree
+fpw: $r -b $fpw
+db: $r -b $db
+start: $r -s $start -n 20 -b
+zrange: -z -s $zstart -e $zend
+recstats: $r --stats=record $splits
+fpw-short: $r $fpw
+backup: $r $backup
+splits: $r -b $splits
+EOF
+echo "wrote $out; now run PG_WALDUMP_UPSTREAM=$bindir/pg_waldump cargo test --test golden"